@@ -0,0 +1,36 @@
+/* Looking for a quarto checks every line of the board, after every move of every
+   search. The allocations this no longer makes are counted in
+   tests/allocations.rs.
+
+   The crate has no library target, so the engine is built in here from its
+   sources. Their test modules come along without their tests.
+*/
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src/intersperse.rs"]
+mod intersperse;
+#[path = "../src/notation.rs"]
+mod notation;
+#[path = "../src/quarto.rs"]
+mod quarto;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use quarto::Quarto;
+use std::convert::TryFrom;
+
+fn board(text: &str) -> Quarto {
+    Quarto::try_from(&text.to_string()).unwrap()
+}
+
+fn lines(c: &mut Criterion) {
+    let won = board("BSCF BSCH BSSF WTSH\n---- ---- ---- ----\n---- ---- ---- ----\nBTCF BTCH BTSF BTSH");
+    let open = board("BSCF BSCH BSSF WTSH\n---- WSCF ---- ----\n---- ---- WTCH ----\nBTCF BTCH BTSF ----");
+    c.bench_function("is_quarto won", |b| b.iter(|| black_box(&won).is_quarto()));
+    c.bench_function("is_quarto open", |b| b.iter(|| black_box(&open).is_quarto()));
+    c.bench_function("winning_lines", |b| {
+        b.iter(|| black_box(&won).winning_lines())
+    });
+}
+
+criterion_group!(benches, lines);
+criterion_main!(benches);
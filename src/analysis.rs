@@ -0,0 +1,405 @@
+use crate::clock::parse_amount;
+use crate::quarto::{GameRng, Move, Quarto, QuartoError};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+/* Analysis for `play`'s hint, run on a blocking tokio task in the background.
+   Each position is analysed at depth 1, 2, ... MAX_DEPTH in turn, and `hint`
+   takes the deepest result finished so far instead of waiting for a search.
+   When the position changes the running analysis is told to stop and its
+   results are dropped, so a hint is always about the position on the board.
+*/
+pub const MAX_DEPTH: u32 = 8;
+
+/* What the analysis runs at each depth. An engine which sees `stop` set may give
+   up and return None; its result would be dropped anyway.
+*/
+pub trait Engine: Send + Sync {
+    /* The best move at `depth` with its score for the player to move, None when
+       the game is over
+    */
+    fn analyze(&self, quarto: &Quarto, depth: u32, stop: &AtomicBool) -> Option<(Move, f64)>;
+}
+
+/* best_move, doubling the playouts with each depth: 32 at depth 1, 4096 at 8.
+   Each position is searched with the game's seed at that move, as `hint` searches
+   it, or 0 for a game without one. A search is not interrupted; once stopped its
+   result is only dropped.
+*/
+pub struct Playouts {
+    pub rng: Option<GameRng>,
+}
+
+impl Engine for Playouts {
+    fn analyze(&self, quarto: &Quarto, depth: u32, _stop: &AtomicBool) -> Option<(Move, f64)> {
+        let seed = self.rng.map_or(0, |rng| rng.seed_for(quarto));
+        quarto.best_move(16 << depth, seed)
+    }
+}
+
+/* The share of one core the analysis may keep busy, written like 50%. After
+   each depth it idles long enough to stay within the share.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuShare(u8);
+
+impl Default for CpuShare {
+    fn default() -> CpuShare {
+        CpuShare(100)
+    }
+}
+
+impl CpuShare {
+    /* How long to idle after working for `busy` */
+    pub fn idle(&self, busy: Duration) -> Duration {
+        busy * u32::from(100 - self.0) / u32::from(self.0)
+    }
+}
+
+impl FromStr for CpuShare {
+    type Err = QuartoError;
+    fn from_str(s: &str) -> Result<CpuShare, QuartoError> {
+        s.strip_suffix('%')
+            .unwrap_or(s)
+            .parse::<u8>()
+            .ok()
+            .filter(|percent| (1..=100).contains(percent))
+            .map(CpuShare)
+            .ok_or_else(|| QuartoError::InvalidCpuShare(s.to_string()))
+    }
+}
+
+impl fmt::Display for CpuShare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}%", self.0)
+    }
+}
+
+/* A finished depth of analysis */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Analysis {
+    pub depth: u32,
+    pub mv: Move,
+    pub score: f64,
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "depth {}: {} (score {:.3})",
+            self.depth, self.mv, self.score
+        )
+    }
+}
+
+/* `generation` counts the positions followed; a task only writes results while
+   its generation is the current one
+*/
+#[derive(Default)]
+struct State {
+    generation: u64,
+    position: Option<Quarto>,
+    best: Option<Analysis>,
+    done: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("analysis state poisoned")
+    }
+}
+
+pub struct Scheduler {
+    shared: Arc<Shared>,
+    engine: Arc<dyn Engine>,
+    cpu: CpuShare,
+    handle: Handle,
+    stop: Arc<AtomicBool>,
+}
+
+impl Scheduler {
+    pub fn new(handle: Handle, engine: Arc<dyn Engine>, cpu: CpuShare) -> Scheduler {
+        Scheduler {
+            shared: Arc::default(),
+            engine,
+            cpu,
+            handle,
+            stop: Arc::default(),
+        }
+    }
+
+    /* Starts analysing `quarto` unless it is the position being analysed already */
+    pub fn follow(&mut self, quarto: &Quarto) {
+        let generation = {
+            let mut state = self.shared.lock();
+            if state.position.as_ref() == Some(quarto) {
+                return;
+            }
+            state.generation += 1;
+            state.position = Some(quarto.clone());
+            state.best = None;
+            state.done = false;
+            state.generation
+        };
+        self.stop.store(true, Ordering::Relaxed);
+        self.stop = Arc::default();
+        // Wakes the previous task if it is idling, so it sees it is stale
+        self.shared.changed.notify_all();
+        let task = Task {
+            shared: self.shared.clone(),
+            engine: self.engine.clone(),
+            cpu: self.cpu,
+            stop: self.stop.clone(),
+            generation,
+        };
+        let quarto = quarto.clone();
+        self.handle.spawn_blocking(move || task.run(&quarto));
+    }
+
+    /* The deepest finished result for the position followed */
+    pub fn best(&self) -> Option<Analysis> {
+        self.shared.lock().best.clone()
+    }
+
+    /* The deepest result after waiting up to `timeout`, or less when the analysis
+       finishes first
+    */
+    pub fn wait(&self, timeout: Duration) -> Option<Analysis> {
+        let state = self.shared.lock();
+        let (state, _) = self
+            .shared
+            .changed
+            .wait_timeout_while(state, timeout, |state| !state.done)
+            .expect("analysis state poisoned");
+        state.best.clone()
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.shared.lock().generation += 1;
+        self.shared.changed.notify_all();
+    }
+}
+
+/* One position's analysis, from depth 1 until it is done or stale */
+struct Task {
+    shared: Arc<Shared>,
+    engine: Arc<dyn Engine>,
+    cpu: CpuShare,
+    stop: Arc<AtomicBool>,
+    generation: u64,
+}
+
+impl Task {
+    fn run(&self, quarto: &Quarto) {
+        for depth in 1..=MAX_DEPTH {
+            let started = Instant::now();
+            let result = self.engine.analyze(quarto, depth, &self.stop);
+            let busy = started.elapsed();
+            let mut state = self.shared.lock();
+            if state.generation != self.generation {
+                return;
+            }
+            match result {
+                Some((mv, score)) => state.best = Some(Analysis { depth, mv, score }),
+                None => state.done = true,
+            }
+            state.done |= depth == MAX_DEPTH;
+            self.shared.changed.notify_all();
+            if state.done {
+                return;
+            }
+            let resume = Instant::now() + self.cpu.idle(busy);
+            while state.generation == self.generation {
+                let Some(left) = resume.checked_duration_since(Instant::now()) else {
+                    break;
+                };
+                state = self
+                    .shared
+                    .changed
+                    .wait_timeout(state, left)
+                    .expect("analysis state poisoned")
+                    .0;
+            }
+            if state.generation != self.generation {
+                return;
+            }
+        }
+    }
+}
+
+/* `hint --wait 5s`: how long to wait for a deeper result, None for plain `hint` */
+pub fn parse_wait(args: &[&str]) -> Result<Option<Duration>, String> {
+    match args {
+        [] => Ok(None),
+        ["--wait", amount] => parse_amount(amount)
+            .map(Some)
+            .ok_or_else(|| format!("invalid wait: {}", amount)),
+        _ => Err("usage: hint [--wait <amount>]".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::quarto::GameStatus;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+
+    /* Takes `delay` per depth, checking `stop` as it goes, and scores each move
+       by its depth so the depth of a result can be seen from it
+    */
+    #[derive(Default)]
+    struct SlowEngine {
+        delay: Duration,
+        started: AtomicUsize,
+        stopped: AtomicUsize,
+    }
+
+    impl SlowEngine {
+        fn new(delay: Duration) -> Arc<SlowEngine> {
+            Arc::new(SlowEngine {
+                delay,
+                ..SlowEngine::default()
+            })
+        }
+    }
+
+    impl Engine for SlowEngine {
+        fn analyze(&self, quarto: &Quarto, depth: u32, stop: &AtomicBool) -> Option<(Move, f64)> {
+            self.started.fetch_add(1, Ordering::Relaxed);
+            let deadline = Instant::now() + self.delay;
+            while Instant::now() < deadline {
+                if stop.load(Ordering::Relaxed) {
+                    self.stopped.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+            let mv = quarto.legal_moves().into_iter().next()?;
+            Some((mv, f64::from(depth)))
+        }
+    }
+
+    fn scheduler(engine: Arc<SlowEngine>) -> Scheduler {
+        Scheduler::new(Handle::current(), engine, CpuShare::default())
+    }
+
+    fn after(quarto: &Quarto) -> Quarto {
+        let mut next = quarto.clone();
+        let mv = next.legal_moves()[0];
+        next.apply_move(&mv);
+        next
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_hint_returns_deepest_finished() {
+        let engine = SlowEngine::new(Duration::from_millis(20));
+        let mut analysis = scheduler(engine.clone());
+        let quarto = Quarto::new();
+        analysis.follow(&quarto);
+        assert_eq!(analysis.best(), None);
+
+        let first = analysis.wait(Duration::from_millis(70)).unwrap();
+        assert!((1..MAX_DEPTH).contains(&first.depth));
+        assert_eq!(first.score, f64::from(first.depth));
+        assert_eq!(first.mv, quarto.legal_moves()[0]);
+
+        let last = analysis.wait(Duration::from_secs(5)).unwrap();
+        assert_eq!(last.depth, MAX_DEPTH);
+        assert_eq!(analysis.best(), Some(last));
+        assert_eq!(engine.started.load(Ordering::Relaxed), MAX_DEPTH as usize);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_new_position_cancels_analysis() {
+        let engine = SlowEngine::new(Duration::from_millis(50));
+        let mut analysis = scheduler(engine.clone());
+        let quarto = Quarto::new();
+        analysis.follow(&quarto);
+        thread::sleep(Duration::from_millis(10));
+
+        let next = after(&quarto);
+        analysis.follow(&next);
+        assert_eq!(analysis.best(), None);
+        let best = analysis.wait(Duration::from_millis(120)).unwrap();
+        assert_eq!(best.mv, next.legal_moves()[0]);
+        assert_eq!(engine.stopped.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_same_position_keeps_analysis() {
+        let engine = SlowEngine::new(Duration::from_millis(5));
+        let mut analysis = scheduler(engine.clone());
+        let quarto = Quarto::new();
+        analysis.follow(&quarto);
+        analysis.wait(Duration::from_secs(5)).unwrap();
+        analysis.follow(&quarto);
+        assert_eq!(analysis.best().unwrap().depth, MAX_DEPTH);
+        assert_eq!(engine.stopped.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_finished_game_has_no_hint() {
+        let engine = SlowEngine::new(Duration::ZERO);
+        let mut analysis = scheduler(engine.clone());
+        let mut quarto = Quarto::new();
+        while quarto.status() == GameStatus::InProgress {
+            quarto = after(&quarto);
+        }
+        analysis.follow(&quarto);
+        assert_eq!(analysis.wait(Duration::from_secs(5)), None);
+        assert_eq!(engine.started.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_playouts_search_with_the_game_seed() {
+        let stop = AtomicBool::new(false);
+        let quarto = after(&after(&Quarto::new()));
+        let rng = GameRng(7);
+        assert_eq!(
+            Playouts { rng: Some(rng) }.analyze(&quarto, 1, &stop),
+            quarto.best_move(32, rng.seed_for(&quarto))
+        );
+        assert_eq!(
+            Playouts { rng: None }.analyze(&quarto, 1, &stop),
+            quarto.best_move(32, 0)
+        );
+    }
+
+    #[test]
+    fn test_cpu_share() {
+        assert_eq!("50%".parse::<CpuShare>().unwrap(), CpuShare(50));
+        assert_eq!("25".parse::<CpuShare>().unwrap().to_string(), "25%");
+        assert!("0%".parse::<CpuShare>().is_err());
+        assert!("150%".parse::<CpuShare>().is_err());
+        let second = Duration::from_secs(1);
+        assert_eq!(CpuShare(100).idle(second), Duration::ZERO);
+        assert_eq!(CpuShare(50).idle(second), second);
+        assert_eq!(CpuShare(25).idle(second), 3 * second);
+    }
+
+    #[test]
+    fn test_parse_wait() {
+        assert_eq!(parse_wait(&[]), Ok(None));
+        assert_eq!(
+            parse_wait(&["--wait", "5s"]),
+            Ok(Some(Duration::from_secs(5)))
+        );
+        assert!(parse_wait(&["--wait", "soon"]).is_err());
+        assert!(parse_wait(&["5s"]).is_err());
+    }
+}
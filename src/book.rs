@@ -0,0 +1,772 @@
+use crate::progress::Progress;
+use crate::quarto::{
+    square, BoardState, Coord, GameStatus, Move, Piece, Quarto, QuartoError, Rules, Symmetry,
+};
+use clap::ValueEnum;
+use sqlx::sqlite::SqliteQueryResult;
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite};
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use tracing::warn;
+
+/* Opening moves keyed by canonical position. The eight board symmetries map a
+   position to the same key, so one entry covers every orientation; moves are kept
+   in the orientation of the key and turned back on lookup. Only board symmetries
+   are reduced, pieces are not relabelled.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct BookEntry {
+    pub mv: Move,
+    /* Expected score for the player to move, win = 1 and draw = 1/2 */
+    pub score: f64,
+    /* Games the move was played in, or playouts per move for the solver */
+    pub weight: i64,
+    /* "games" or "solver" */
+    pub source: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Book {
+    entries: BTreeMap<String, BookEntry>,
+}
+
+/* Quarto::canonical with the key prefixed by the variant */
+pub fn canonical(quarto: &Quarto) -> (String, Symmetry) {
+    let (key, symmetry) = quarto.canonical();
+    (format!("{}\n{}", quarto.rules.variant, key), symmetry)
+}
+
+fn to_canonical(mv: Move, symmetry: Symmetry) -> Move {
+    Move {
+        place: mv.place.map(|at| {
+            let (x, y) = at.xy();
+            square(symmetry.map(x, y))
+        }),
+        ..mv
+    }
+}
+
+fn from_canonical(mv: Move, symmetry: Symmetry) -> Move {
+    let cell = |at: Coord| {
+        (0..16)
+            .map(|i| (i / 4, i % 4))
+            .find(|(x, y)| symmetry.map(*x, *y) == at.xy())
+            .map(square)
+            .unwrap()
+    };
+    Move {
+        place: mv.place.map(cell),
+        ..mv
+    }
+}
+
+impl Book {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /* `mv` is a move in the position as given */
+    pub fn insert(&mut self, quarto: &Quarto, mv: Move, score: f64, weight: i64, source: &str) {
+        let (key, symmetry) = canonical(quarto);
+        self.entries.insert(
+            key,
+            BookEntry {
+                mv: to_canonical(mv, symmetry),
+                score,
+                weight,
+                source: source.to_string(),
+            },
+        );
+    }
+
+    /* The book move for the position as given, if it is still a legal one there */
+    pub fn lookup(&self, quarto: &Quarto) -> Option<BookEntry> {
+        let (key, symmetry) = canonical(quarto);
+        let entry = self.entries.get(&key)?;
+        let mv = from_canonical(entry.mv, symmetry);
+        quarto.legal_moves().contains(&mv).then(|| BookEntry {
+            mv,
+            ..entry.clone()
+        })
+    }
+
+    /* The move scoring best for its player over the first `depth` plies of finished
+       games, ties going to the move played more often. Unfinished games are skipped.
+    */
+    pub fn from_games(rules: Rules, games: &[Vec<Move>], depth: usize) -> Book {
+        // canonical position -> canonical move -> (total score, games)
+        let mut tally: BTreeMap<String, BTreeMap<String, (Move, f64, i64)>> = BTreeMap::new();
+        for game in games {
+            let mut quarto = Quarto::with_rules(rules);
+            let mut plies = Vec::new();
+            for mv in game {
+                let (key, symmetry) = canonical(&quarto);
+                if !quarto.apply_move(mv) {
+                    break;
+                }
+                plies.push((key, to_canonical(*mv, symmetry)));
+            }
+            let last = plies.len().wrapping_sub(1) % 2;
+            let score = |ply: usize| match quarto.status() {
+                GameStatus::Won if ply % 2 == last => Some(1.0),
+                GameStatus::Won => Some(0.0),
+                GameStatus::Drawn => Some(0.5),
+                GameStatus::Adjudicated(a) if ply % 2 == last => Some(a.placer_score()),
+                GameStatus::Adjudicated(a) => Some(1.0 - a.placer_score()),
+                GameStatus::InProgress => None,
+            };
+            for (ply, (key, mv)) in plies.into_iter().enumerate().take(depth) {
+                let Some(score) = score(ply) else {
+                    break;
+                };
+                let entry = tally
+                    .entry(key)
+                    .or_default()
+                    .entry(mv.to_string())
+                    .or_insert((mv, 0.0, 0));
+                entry.1 += score;
+                entry.2 += 1;
+            }
+        }
+        let mut book = Book::default();
+        for (key, moves) in tally {
+            let mut best: Option<BookEntry> = None;
+            for (mv, total, games) in moves.into_values() {
+                let score = total / games as f64;
+                if !matches!(&best, Some(b) if (b.score, b.weight) >= (score, games)) {
+                    best = Some(BookEntry {
+                        mv,
+                        score,
+                        weight: games,
+                        source: "games".to_string(),
+                    });
+                }
+            }
+            book.entries.extend(best.map(|entry| (key.clone(), entry)));
+        }
+        book
+    }
+
+    /* Searches every distinct position of the first `depth` plies with best_move.
+       The number of positions grows quickly: depth 2 is the opening hand and the
+       sixteen positions after it, depth 3 already runs to hundreds. Each ply is a
+       phase of `progress`.
+    */
+    pub fn from_solver(
+        rules: Rules,
+        depth: usize,
+        playouts: u32,
+        seed: u64,
+        progress: &dyn Progress,
+    ) -> Book {
+        let mut book = Book::default();
+        let mut seen = HashSet::new();
+        let mut frontier = vec![Quarto::with_rules(rules)];
+        for ply in 1..=depth {
+            progress.phase(&format!("ply {}", ply), Some(frontier.len() as u64));
+            let mut next = Vec::new();
+            for quarto in frontier {
+                if let Some((mv, score)) = quarto.best_move(playouts, seed) {
+                    book.insert(&quarto, mv, score, playouts.into(), "solver");
+                }
+                for mv in quarto.legal_moves() {
+                    let mut after = quarto.clone();
+                    after.apply_move(&mv);
+                    if after.status() == GameStatus::InProgress && seen.insert(canonical(&after).0)
+                    {
+                        next.push(after);
+                    }
+                }
+                progress.advance(1);
+            }
+            frontier = next;
+        }
+        progress.finish();
+        book
+    }
+}
+
+/* One game per line as moves separated by `;`, each written like the arguments of
+   `quarto move`. `#` starts a comment. Every move is checked by playing it.
+*/
+pub fn parse_games(rules: Rules, text: &str) -> Result<Vec<Vec<Move>>, QuartoError> {
+    let mut games = Vec::new();
+    for (n, raw) in text.lines().enumerate() {
+        let content = raw.split('#').next().unwrap_or_default();
+        if content.trim().is_empty() {
+            continue;
+        }
+        let mut quarto = Quarto::with_rules(rules);
+        let mut game = Vec::new();
+        let mut offset = 0;
+        for text in content.split(';') {
+            let column = offset + text.len() - text.trim_start().len() + 1;
+            offset += text.len() + 1;
+            let error = |reason: String| QuartoError::ParseError {
+                line: n + 1,
+                column,
+                reason,
+            };
+            let mv: Move = text
+                .parse()
+                .map_err(|_| error(format!("invalid move: {}", text.trim())))?;
+            if !quarto.apply_move(&mv) {
+                return Err(error(format!("illegal move: {}", mv)));
+            }
+            game.push(mv);
+        }
+        games.push(game);
+    }
+    Ok(games)
+}
+
+/* Books shared as text, one entry per line with tab-separated fields:
+
+       # quarto book 1
+       AQAAAAAAAAAAAAAAAQ4C\ta4 WSCF\t2\t1.000\tgames
+
+   the position's share code, the move in that position written like the arguments
+   of `quarto move`, the weight, the score and the source. Positions are written as
+   their key's orientation; on reading they may be in any. `#` starts a comment.
+*/
+pub const TEXT_HEADER: &str = "# quarto book 1";
+
+/* How an imported entry is combined with one the book has for its position. A
+   different move, or the same one from another source, is a conflict: the entry
+   with the greater weight stays, the one already in the book on a tie.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Merge {
+    /* The same move adds the weights, its score averaged over both */
+    Sum,
+    /* The same move keeps the entry with the greater weight */
+    Max,
+}
+
+/* The position a key was made from, in the key's orientation */
+fn position(key: &str) -> Result<Quarto, QuartoError> {
+    let corrupt = || QuartoError::CorruptRecord(format!("unreadable book position {:?}", key));
+    let (variant, rest) = key.split_once('\n').ok_or_else(corrupt)?;
+    let (board, hand) = rest.rsplit_once('\n').ok_or_else(corrupt)?;
+    let board = BoardState::try_from(&board.to_string())?;
+    let hand = match hand.trim() {
+        "" => None,
+        code => Some(Piece::try_from(code.to_string()).map_err(|_| corrupt())?),
+    };
+    let rules = Rules {
+        variant: variant.parse()?,
+        ..Rules::default()
+    };
+    Quarto::from_parts(board, hand, rules)
+}
+
+impl Book {
+    /* The book in the text format; entries whose position cannot be read back are
+       left out
+    */
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", TEXT_HEADER);
+        for (key, entry) in &self.entries {
+            match position(key) {
+                Ok(quarto) => text.push_str(&format!(
+                    "{}\t{}\t{}\t{:.3}\t{}\n",
+                    quarto.to_share_code(),
+                    entry.mv,
+                    entry.weight,
+                    entry.score,
+                    entry.source
+                )),
+                Err(e) => warn!(?e, "skipping unreadable book entry"),
+            }
+        }
+        text
+    }
+
+    /* Reads the text format, checking each move is legal in its position */
+    pub fn from_text(text: &str) -> Result<Book, QuartoError> {
+        let mut book = Book::default();
+        for (n, raw) in text.lines().enumerate() {
+            let content = raw.split('#').next().unwrap_or_default();
+            if content.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = content.split('\t').collect();
+            let column =
+                |field: usize| fields[..field].iter().map(|f| f.len() + 1).sum::<usize>() + 1;
+            let error = |field: usize, reason: String| QuartoError::ParseError {
+                line: n + 1,
+                column: column(field),
+                reason,
+            };
+            let [code, mv, weight, score, source] = fields[..] else {
+                return Err(error(
+                    0,
+                    format!("expected 5 tab-separated fields, found {}", fields.len()),
+                ));
+            };
+            let quarto = Quarto::from_share_code(code)
+                .map_err(|_| error(0, format!("invalid position: {}", code.trim())))?;
+            let mv: Move = mv
+                .parse()
+                .map_err(|_| error(1, format!("invalid move: {}", mv.trim())))?;
+            if !quarto.legal_moves().contains(&mv) {
+                return Err(error(1, format!("illegal move in this position: {}", mv)));
+            }
+            let weight = weight
+                .trim()
+                .parse()
+                .map_err(|_| error(2, format!("invalid weight: {}", weight.trim())))?;
+            let score = score
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|score| (0.0..=1.0).contains(score))
+                .ok_or_else(|| error(3, format!("invalid score: {}", score.trim())))?;
+            book.insert(&quarto, mv, score, weight, source.trim());
+        }
+        Ok(book)
+    }
+
+    /* Adds `other`'s entries, combining those for positions both have by `how` */
+    pub fn merge(&mut self, other: Book, how: Merge) {
+        for (key, theirs) in other.entries {
+            let Some(ours) = self.entries.get_mut(&key) else {
+                self.entries.insert(key, theirs);
+                continue;
+            };
+            let same = ours.mv == theirs.mv && ours.source == theirs.source;
+            if same && how == Merge::Sum {
+                let weight = ours.weight + theirs.weight;
+                if weight > 0 {
+                    ours.score = (ours.score * ours.weight as f64
+                        + theirs.score * theirs.weight as f64)
+                        / weight as f64;
+                }
+                ours.weight = weight;
+            } else if theirs.weight > ours.weight {
+                *ours = theirs;
+            }
+        }
+    }
+}
+
+/* What `hint` suggests, with where it came from */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Hint {
+    Book(BookEntry),
+    Engine { mv: Move, score: f64, playouts: u32 },
+}
+
+impl Hint {
+    pub fn mv(&self) -> Move {
+        match self {
+            Hint::Book(entry) => entry.mv,
+            Hint::Engine { mv, .. } => *mv,
+        }
+    }
+}
+
+impl fmt::Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Hint::Book(entry) if entry.source == "games" => write!(
+                f,
+                "book: {} (score {:.3} over {} games)",
+                entry.mv, entry.score, entry.weight
+            ),
+            Hint::Book(entry) => write!(
+                f,
+                "book: {} (score {:.3}, {} playouts per move)",
+                entry.mv, entry.score, entry.weight
+            ),
+            Hint::Engine {
+                mv,
+                score,
+                playouts,
+            } => write!(
+                f,
+                "engine: {} (score {:.3}, {} playouts per move)",
+                mv, score, playouts
+            ),
+        }
+    }
+}
+
+/* The book move when there is one, otherwise a search */
+pub fn hint(quarto: &Quarto, book: Option<&Book>, playouts: u32, seed: u64) -> Option<Hint> {
+    if let Some(entry) = book.and_then(|book| book.lookup(quarto)) {
+        return Some(Hint::Book(entry));
+    }
+    quarto
+        .best_move(playouts, seed)
+        .map(|(mv, score)| Hint::Engine {
+            mv,
+            score,
+            playouts,
+        })
+}
+
+/* Prose for `hint --teach` about the double threat motif: how the move sets one up,
+   or which cell would have; None when the piece in hand makes no double threat
+*/
+pub fn teach(quarto: &Quarto, mv: &Move) -> Option<String> {
+    let p = quarto.next_piece?;
+    let cells = quarto.double_threats(&p);
+    let (x, y) = match mv.place {
+        Some(at) if cells.contains(&at.xy()) => at.xy(),
+        _ => {
+            return cells.first().map(|cell| {
+                format!(
+                    "{} on {} would set up a double threat",
+                    String::from(p),
+                    square(*cell)
+                )
+            })
+        }
+    };
+    let mut threats = quarto.threats_after(x, y, &p);
+    threats.dedup_by_key(|threat| threat.cell);
+    let threats: Vec<String> = threats.iter().map(|threat| threat.to_string()).collect();
+    let mut text = format!(
+        "{} sets up a double threat, winning on {}",
+        square((x, y)),
+        threats.join(" and on ")
+    );
+    if let Some(hand) = mv.hand {
+        text += &format!(". {} completes none of them", String::from(hand));
+    }
+    text += ". Your opponent can block only one, then has to hand you a piece for another";
+    Some(text)
+}
+
+pub async fn init_book(db: &Pool<Sqlite>) -> Result<SqliteQueryResult, SqlxError> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS book
+        (
+              position VARCHAR NOT NULL PRIMARY KEY,
+              best_move VARCHAR NOT NULL,
+              score REAL NOT NULL,
+              weight INTEGER NOT NULL,
+              source VARCHAR NOT NULL
+        );"#,
+    )
+    .execute(db)
+    .await
+}
+
+/* Entries already in the table are replaced by the ones built last */
+#[tracing::instrument(level = "debug", skip(db, book))]
+pub async fn save(db: &Pool<Sqlite>, book: &Book) -> Result<u64, SqlxError> {
+    let mut saved = 0;
+    for (position, entry) in &book.entries {
+        saved += sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO book (position, best_move, score, weight, source)
+            VALUES (?1, ?2, ?3, ?4, ?5);
+            "#,
+        )
+        .bind(position)
+        .bind(entry.mv.to_string())
+        .bind(entry.score)
+        .bind(entry.weight)
+        .bind(&entry.source)
+        .execute(db)
+        .await?
+        .rows_affected();
+    }
+    Ok(saved)
+}
+
+/* For `book import --replace` */
+pub async fn clear(db: &Pool<Sqlite>) -> Result<SqliteQueryResult, SqlxError> {
+    sqlx::query("DELETE FROM book;").execute(db).await
+}
+
+#[tracing::instrument(level = "debug", skip(db))]
+pub async fn load(db: &Pool<Sqlite>) -> Result<Book, SqlxError> {
+    let rows = sqlx::query_as::<_, (String, String, f64, i64, String)>(
+        "SELECT position, best_move, score, weight, source FROM book",
+    )
+    .fetch_all(db)
+    .await?;
+    let mut book = Book::default();
+    for (position, best_move, score, weight, source) in rows {
+        match best_move.parse() {
+            Ok(mv) => {
+                book.entries.insert(
+                    position,
+                    BookEntry {
+                        mv,
+                        score,
+                        weight,
+                        source,
+                    },
+                );
+            }
+            Err(_) => warn!(best_move = %best_move, "skipping unreadable book entry"),
+        }
+    }
+    Ok(book)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::progress::test::Recording;
+    use crate::quarto::Symmetry;
+    use strum::IntoEnumIterator;
+
+    /* Both games open alike and the 2nd player answers BSCF in a corner in one and
+       in the centre in the other. The corner game is won by the 2nd player, the
+       centre one by the 1st. The third game is unfinished and does not count.
+    */
+    const GAMES: &str = "\
+        BSCF; 0 0 WSCF; 1 0 BSCH; 0 1 WSCH; 1 1 BSSF; 0 2 WSSF; 1 2 BTSH; 0 3\n\
+        BSCF; 1 1 WSCF; 0 0 BSCH; 3 0 WSCH; 0 1 BSSF; 3 1 WSSF; 0 2 BTSH; 3 2 WTSH; 0 3\n\
+        BSCF; 2 2 WSCF # abandoned\n";
+
+    fn book() -> Book {
+        let games = parse_games(Rules::default(), GAMES).unwrap();
+        assert_eq!(games.len(), 3);
+        Book::from_games(Rules::default(), &games, 2)
+    }
+
+    fn after(moves: &[&str]) -> Quarto {
+        let mut quarto = Quarto::new();
+        for mv in moves {
+            assert!(quarto.apply_move(&mv.parse().unwrap()), "{}", mv);
+        }
+        quarto
+    }
+
+    #[test]
+    fn test_book_from_games() {
+        let book = book();
+        // The empty board and the position after BSCF
+        assert_eq!(book.len(), 2);
+        let opening = book.lookup(&Quarto::new()).unwrap();
+        assert_eq!(opening.mv.to_string(), "BSCF");
+        assert_eq!((opening.score, opening.weight), (0.5, 2));
+        // The answer which won is preferred over the one which lost
+        let answer = book.lookup(&after(&["BSCF"])).unwrap();
+        assert_eq!(answer.mv.to_string(), "a4 WSCF");
+        assert_eq!((answer.score, answer.weight), (1.0, 1));
+        // Past the book depth
+        assert_eq!(book.lookup(&after(&["BSCF", "a4 WSCF"])), None);
+
+        for (text, line, column) in [
+            ("BSCF; 0 0", 1, 7),
+            ("BSCF\n\nBSCF; 0 0 BSCF", 3, 7),
+            ("BSCF;; 0 0 WSCF", 1, 6),
+        ] {
+            let e = parse_games(Rules::default(), text).unwrap_err();
+            assert!(
+                matches!(e, QuartoError::ParseError { line: l, column: c, .. }
+                    if (l, c) == (line, column)),
+                "{}: {:?}",
+                text,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn test_book_covers_symmetric_positions() {
+        let mut book = Book::default();
+        let quarto = after(&["BSCF", "b4 WSCF"]);
+        book.insert(&quarto, "c4 BSCH".parse().unwrap(), 0.5, 1, "games");
+        for symmetry in Symmetry::iter() {
+            let turned = quarto.transformed(symmetry);
+            assert_eq!(
+                book.lookup(&turned).unwrap().mv.to_string(),
+                format!("{} BSCH", square(symmetry.map(0, 2))),
+                "{:?}",
+                symmetry
+            );
+        }
+        // Other rules are another position
+        let mut advanced = quarto.clone();
+        advanced.rules.variant = crate::quarto::Variant::Advanced;
+        assert_eq!(book.lookup(&advanced), None);
+    }
+
+    #[test]
+    fn test_hint_prefers_book_moves() {
+        let book = book();
+        let quarto = after(&["BSCF"]);
+        let hint = hint(&quarto, Some(&book), 20, 0).unwrap();
+        assert_eq!(hint.mv().to_string(), "a4 WSCF");
+        assert_eq!(hint.to_string(), "book: a4 WSCF (score 1.000 over 1 games)");
+
+        // Without the book, or off it, the engine searches
+        let searched = super::hint(&quarto, None, 20, 0).unwrap();
+        assert!(matches!(searched, Hint::Engine { playouts: 20, .. }));
+        assert!(searched.to_string().starts_with("engine: "));
+        let off_book = after(&["BSCF", "c2 WSCF"]);
+        assert!(matches!(
+            super::hint(&off_book, Some(&book), 20, 0),
+            Some(Hint::Engine { .. })
+        ));
+    }
+
+    #[test]
+    fn test_teach_double_threat() {
+        let quarto = after(&["BTCF", "a3 BTSH", "a2 WSSH", "b1 WSSF", "c1 WTCF"]);
+        assert_eq!(
+            teach(&quarto, &"a1 BSCF".parse().unwrap()).unwrap(),
+            "a1 sets up a double threat, winning on d1 with any white piece and on a4 \
+             with any tall piece. BSCF completes none of them. Your opponent can block \
+             only one, then has to hand you a piece for another"
+        );
+        assert_eq!(
+            teach(&quarto, &"d4 BSCF".parse().unwrap()).unwrap(),
+            "WTCF on a1 would set up a double threat"
+        );
+        assert_eq!(teach(&after(&["BSCF"]), &"a1 WSCF".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_book_text_round_trips() {
+        let mut book = book();
+        let turned = after(&["BSCF", "b4 WSCF"]).transformed(Symmetry::Rotate90);
+        book.insert(&turned, "c1 BSCH".parse().unwrap(), 0.25, 40, "solver");
+        let text = book.to_text();
+        assert!(text.starts_with(&format!("{}\n", TEXT_HEADER)));
+        assert_eq!(text.lines().count(), 4);
+        assert_eq!(Book::from_text(&text).unwrap(), book);
+
+        // Positions may be given in any orientation, and comments are skipped
+        let quarto = after(&["BSCF", "b4 WSCF"]);
+        let mirrored = quarto.transformed(Symmetry::MirrorVertical);
+        let text = format!(
+            "# shared\n{}\t{} BSCH\t3\t0.5\tgames # mirrored\n",
+            mirrored.to_share_code(),
+            square(Symmetry::MirrorVertical.map(0, 2))
+        );
+        let read = Book::from_text(&text).unwrap();
+        let entry = read.lookup(&quarto).unwrap();
+        assert_eq!(entry.mv.to_string(), "c4 BSCH");
+        assert_eq!((entry.score, entry.weight), (0.5, 3));
+    }
+
+    #[test]
+    fn test_book_text_rejects_bad_entries() {
+        let opening = Quarto::new().to_share_code();
+        let handed = after(&["BSCF", "a4 WSCF"]).to_share_code();
+        for (text, column, reason) in [
+            // BSCF is already on the board
+            (
+                format!("{}\tb4 BSCF\t1\t0.5\tgames", handed),
+                22,
+                "illegal move in this position: b4 BSCF",
+            ),
+            (
+                format!("{}\ta4 WSCF\t1\t0.5\tgames", opening),
+                22,
+                "illegal move in this position: a4 WSCF",
+            ),
+            (
+                format!("{}\tWSCF\tmany\t0.5\tgames", opening),
+                27,
+                "invalid weight: many",
+            ),
+            (
+                format!("{}\tWSCF\t1\t1.5\tgames", opening),
+                29,
+                "invalid score: 1.5",
+            ),
+            (
+                format!("{}\tWSCF\t1", opening),
+                1,
+                "expected 5 tab-separated fields, found 3",
+            ),
+            (
+                "AQAA\tWSCF\t1\t0.5\tgames".to_string(),
+                1,
+                "invalid position: AQAA",
+            ),
+        ] {
+            let e = Book::from_text(&format!("{}\n\n{}\n", TEXT_HEADER, text)).unwrap_err();
+            assert!(
+                matches!(&e, QuartoError::ParseError { line: 3, column: c, reason: r }
+                    if (*c, r.as_str()) == (column, reason)),
+                "{}: {:?}",
+                text,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn test_book_merges() {
+        let entry = |mv: &str, score, weight, source: &str| {
+            let mut book = Book::default();
+            book.insert(&Quarto::new(), mv.parse().unwrap(), score, weight, source);
+            book
+        };
+        let merged = |how, theirs: Book| {
+            let mut book = entry("BSCF", 1.0, 3, "games");
+            book.insert(
+                &after(&["BSCF"]),
+                "a4 WSCF".parse().unwrap(),
+                0.5,
+                1,
+                "games",
+            );
+            book.merge(theirs, how);
+            assert_eq!(book.len(), 2);
+            let entry = book.lookup(&Quarto::new()).unwrap();
+            (
+                entry.mv.to_string(),
+                entry.score,
+                entry.weight,
+                entry.source,
+            )
+        };
+        let games = |mv: &str| (mv.to_string(), 1.0, 3, "games".to_string());
+
+        // The same move adds up or keeps the heavier entry
+        let same = entry("BSCF", 0.0, 1, "games");
+        assert_eq!(
+            merged(Merge::Sum, same.clone()),
+            ("BSCF".to_string(), 0.75, 4, "games".to_string())
+        );
+        assert_eq!(merged(Merge::Max, same), games("BSCF"));
+        let heavier = entry("BSCF", 0.5, 10, "games");
+        assert_eq!(
+            merged(Merge::Max, heavier),
+            ("BSCF".to_string(), 0.5, 10, "games".to_string())
+        );
+
+        // Conflicting moves or sources keep the greater weight, the book's on a tie
+        for how in [Merge::Sum, Merge::Max] {
+            assert_eq!(merged(how, entry("WTCH", 0.0, 3, "games")), games("BSCF"));
+            assert_eq!(
+                merged(how, entry("WTCH", 0.0, 5, "games")),
+                ("WTCH".to_string(), 0.0, 5, "games".to_string())
+            );
+            assert_eq!(
+                merged(how, entry("BSCF", 0.5, 9, "solver")),
+                ("BSCF".to_string(), 0.5, 9, "solver".to_string())
+            );
+        }
+        // Positions the book lacks are added
+        let mut book = Book::default();
+        book.merge(entry("BSCF", 1.0, 3, "games"), Merge::Max);
+        assert_eq!(book.len(), 1);
+    }
+
+    #[test]
+    fn test_book_from_solver() {
+        let progress = Recording::default();
+        let book = Book::from_solver(Rules::default(), 1, 4, 0, &progress);
+        assert_eq!(book.len(), 1);
+        assert_eq!(progress.phases(), vec![("ply 1".to_string(), Some(1), 1)]);
+        let entry = book.lookup(&Quarto::new()).unwrap();
+        assert_eq!(entry.source, "solver");
+        assert!(entry.mv.place.is_none() && entry.mv.hand.is_some());
+    }
+}
@@ -0,0 +1,215 @@
+use crate::book;
+use crate::db_policy;
+use crate::quarto::Quarto;
+use sqlx::sqlite::SqliteQueryResult;
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite};
+use tracing::debug;
+
+/* Analysis results keyed by position and engine.
+   A result is reusable when it was computed at least as deep as requested.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct CachedAnalysis {
+    pub engine: String,
+    pub depth: i64,
+    pub score: f64,
+    pub best_move: Option<String>,
+}
+
+/* Positions are keyed as the book keys them, so every orientation of a position
+   shares one entry
+*/
+pub fn position_key(quarto: &Quarto) -> String {
+    book::canonical(quarto).0
+}
+
+/* Playouts drawn from another seed give another estimate */
+pub fn playout_engine(seed: u64) -> String {
+    format!("playout:{}", seed)
+}
+
+pub async fn init_cache(db: &Pool<Sqlite>) -> Result<SqliteQueryResult, SqlxError> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS analysis
+        (
+              position VARCHAR NOT NULL,
+              engine VARCHAR NOT NULL,
+              depth INTEGER NOT NULL,
+              score REAL NOT NULL,
+              best_move VARCHAR,
+              PRIMARY KEY (position, engine)
+        );"#,
+    )
+    .execute(db)
+    .await
+}
+
+#[tracing::instrument(level = "debug", skip(db))]
+pub async fn lookup(
+    db: &Pool<Sqlite>,
+    position: &str,
+    engine: &str,
+    min_depth: i64,
+) -> Result<Option<CachedAnalysis>, SqlxError> {
+    let row = sqlx::query_as::<_, (String, i64, f64, Option<String>)>(
+        r#"
+        SELECT engine, depth, score, best_move
+        FROM analysis
+        WHERE position = ?1 AND engine = ?2 AND depth >= ?3
+        "#,
+    )
+    .bind(position)
+    .bind(engine)
+    .bind(min_depth)
+    .fetch_optional(db)
+    .await?;
+    Ok(row.map(|(engine, depth, score, best_move)| CachedAnalysis {
+        engine,
+        depth,
+        score,
+        best_move,
+    }))
+}
+
+/* Shallower results never replace deeper ones. A read-only database keeps nothing,
+   and analysing in one goes on without the cache.
+*/
+#[tracing::instrument(level = "debug", skip(db))]
+pub async fn store(
+    db: &Pool<Sqlite>,
+    position: &str,
+    analysis: &CachedAnalysis,
+) -> Result<SqliteQueryResult, SqlxError> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO analysis (position, engine, depth, score, best_move)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT (position, engine) DO UPDATE
+        SET depth = excluded.depth, score = excluded.score, best_move = excluded.best_move
+        WHERE excluded.depth >= analysis.depth;
+        "#,
+    )
+    .bind(position)
+    .bind(&analysis.engine)
+    .bind(analysis.depth)
+    .bind(analysis.score)
+    .bind(&analysis.best_move)
+    .execute(db)
+    .await;
+    match result {
+        Err(e) if db_policy::is_read_only(&e) => {
+            debug!("database is read-only, analysis not cached");
+            Ok(SqliteQueryResult::default())
+        }
+        result => result,
+    }
+}
+
+#[tracing::instrument(level = "debug", skip(db))]
+pub async fn stats(db: &Pool<Sqlite>) -> Result<Vec<(String, i64, i64)>, SqlxError> {
+    sqlx::query_as::<_, (String, i64, i64)>(
+        r#"
+        SELECT engine, COUNT(*), MAX(depth)
+        FROM analysis
+        GROUP BY engine
+        ORDER BY engine
+        "#,
+    )
+    .fetch_all(db)
+    .await
+}
+
+#[tracing::instrument(level = "debug", skip(db))]
+pub async fn clear(db: &Pool<Sqlite>) -> Result<u64, SqlxError> {
+    let result = sqlx::query("DELETE FROM analysis;").execute(db).await?;
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::quarto::{Piece, Symmetry};
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn memory_db() -> Pool<Sqlite> {
+        // A single connection keeps the in-memory database alive for the test.
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_cache(&db).await.unwrap();
+        db
+    }
+
+    fn playout(depth: i64, score: f64) -> CachedAnalysis {
+        CachedAnalysis {
+            engine: "playout".to_string(),
+            depth,
+            score,
+            best_move: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cache_hit_after_store() {
+        let db = memory_db().await;
+        assert_eq!(lookup(&db, "pos", "playout", 100).await.unwrap(), None);
+
+        store(&db, "pos", &playout(1000, 0.75)).await.unwrap();
+        let hit = lookup(&db, "pos", "playout", 100).await.unwrap();
+        assert_eq!(hit, Some(playout(1000, 0.75)));
+        // Not deep enough for the request
+        assert_eq!(lookup(&db, "pos", "playout", 5000).await.unwrap(), None);
+        assert_eq!(lookup(&db, "pos", "minimax", 1).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_cache_deeper_supersedes_shallower() {
+        let db = memory_db().await;
+        store(&db, "pos", &playout(100, 0.5)).await.unwrap();
+        store(&db, "pos", &playout(1000, 0.75)).await.unwrap();
+        assert_eq!(
+            lookup(&db, "pos", "playout", 0).await.unwrap(),
+            Some(playout(1000, 0.75))
+        );
+
+        // A stale shallower result does not overwrite the deeper one
+        store(&db, "pos", &playout(10, 0.0)).await.unwrap();
+        assert_eq!(
+            lookup(&db, "pos", "playout", 0).await.unwrap(),
+            Some(playout(1000, 0.75))
+        );
+
+        assert_eq!(
+            stats(&db).await.unwrap(),
+            vec![("playout".to_string(), 1, 1000)]
+        );
+        assert_eq!(clear(&db).await.unwrap(), 1);
+        assert_eq!(stats(&db).await.unwrap(), vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_cache_is_shared_by_symmetric_positions_of_one_seed() {
+        let db = memory_db().await;
+        let mut quarto = Quarto::new();
+        assert!(quarto.pick_piece(&Piece::try_from("BSCF".to_string()).unwrap()));
+        assert!(quarto.move_piece(0, 1));
+        assert!(quarto.pick_piece(&Piece::try_from("WSCF".to_string()).unwrap()));
+        let turned = quarto.transformed(Symmetry::Rotate90);
+        assert_ne!(quarto.position_key(), turned.position_key());
+        assert_eq!(position_key(&quarto), position_key(&turned));
+
+        let analysis = CachedAnalysis {
+            engine: playout_engine(7),
+            ..playout(1000, 0.75)
+        };
+        store(&db, &position_key(&quarto), &analysis).await.unwrap();
+        let hit = lookup(&db, &position_key(&turned), &playout_engine(7), 1000).await;
+        assert_eq!(hit.unwrap(), Some(analysis));
+        let other_seed = lookup(&db, &position_key(&turned), &playout_engine(8), 1000).await;
+        assert_eq!(other_seed.unwrap(), None);
+    }
+}
@@ -0,0 +1,197 @@
+use crate::quarto::Quarto;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/* `count-positions` walks every line of play from the empty board, depth first,
+   and counts the distinct positions at each ply. A ply is one move: the first only
+   hands a piece over, the others place the piece in hand and hand on the next.
+   Positions are told apart by position_key, or by canonical_key when counting up
+   to the board's symmetries; a position met again is not walked a second time.
+
+   The exact sets hold every key. With a Bloom filter instead the memory is fixed,
+   but a false positive skips a position and everything after it, so the counts
+   are lower bounds.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Census {
+    /* Distinct positions by ply, from the empty board at ply 0 */
+    pub per_ply: Vec<u64>,
+    pub canonical: bool,
+    /* Bytes held by the set of positions seen */
+    pub memory: usize,
+    /* Whether a Bloom filter stood in for the exact set */
+    pub approximate: bool,
+}
+
+impl Census {
+    pub fn total(&self) -> u64 {
+        self.per_ply.iter().sum()
+    }
+}
+
+impl fmt::Display for Census {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bound = if self.approximate { "at least " } else { "" };
+        for (ply, count) in self.per_ply.iter().enumerate() {
+            writeln!(f, "ply {:>2}: {}{}", ply, bound, count)?;
+        }
+        let how = if self.canonical {
+            "up to symmetry"
+        } else {
+            "exactly"
+        };
+        writeln!(f, "{}{} positions, counted {}", bound, self.total(), how)?;
+        write!(f, "memory: {} KiB", self.memory.div_ceil(1024))
+    }
+}
+
+/* The positions met so far */
+enum Seen {
+    Exact(HashSet<String>),
+    Bloom(Bloom),
+}
+
+impl Seen {
+    /* Remembers `key`; false when it had been met before */
+    fn insert(&mut self, key: String) -> bool {
+        match self {
+            Seen::Exact(keys) => keys.insert(key),
+            Seen::Bloom(bloom) => bloom.insert(&key),
+        }
+    }
+
+    fn memory(&self) -> usize {
+        match self {
+            Seen::Exact(keys) => {
+                keys.capacity() * std::mem::size_of::<String>()
+                    + keys.iter().map(String::capacity).sum::<usize>()
+            }
+            Seen::Bloom(bloom) => bloom.bits.len() * std::mem::size_of::<u64>(),
+        }
+    }
+}
+
+/* A Bloom filter with `HASHES` probes per key, derived from two hashes of it */
+struct Bloom {
+    bits: Vec<u64>,
+}
+
+const HASHES: u64 = 7;
+
+impl Bloom {
+    fn with_bytes(bytes: usize) -> Bloom {
+        Bloom {
+            bits: vec![0; (bytes / 8).max(1)],
+        }
+    }
+
+    fn insert(&mut self, key: &str) -> bool {
+        let hash = |seed: u64| {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            key.hash(&mut hasher);
+            hasher.finish()
+        };
+        let (first, second) = (hash(0), hash(1) | 1);
+        let size = self.bits.len() as u64 * 64;
+        let mut added = false;
+        for i in 0..HASHES {
+            let bit = first.wrapping_add(i.wrapping_mul(second)) % size;
+            let (word, mask) = ((bit / 64) as usize, 1 << (bit % 64));
+            added |= self.bits[word] & mask == 0;
+            self.bits[word] |= mask;
+        }
+        added
+    }
+}
+
+/* Counts the positions reachable within `plies` moves of the empty board. With
+   `bloom_bytes` the positions seen are kept in a Bloom filter of that size.
+*/
+pub fn count_positions(plies: u8, canonical: bool, bloom_bytes: Option<usize>) -> Census {
+    let mut seen = match bloom_bytes {
+        Some(bytes) => Seen::Bloom(Bloom::with_bytes(bytes)),
+        None => Seen::Exact(HashSet::new()),
+    };
+    let mut per_ply = vec![0; usize::from(plies) + 1];
+    walk(&Quarto::new(), 0, canonical, &mut seen, &mut per_ply);
+    Census {
+        per_ply,
+        canonical,
+        memory: seen.memory(),
+        approximate: bloom_bytes.is_some(),
+    }
+}
+
+fn walk(quarto: &Quarto, ply: usize, canonical: bool, seen: &mut Seen, per_ply: &mut [u64]) {
+    let key = if canonical {
+        quarto.canonical_key()
+    } else {
+        quarto.position_key()
+    };
+    if !seen.insert(key) {
+        return;
+    }
+    per_ply[ply] += 1;
+    if ply + 1 == per_ply.len() {
+        return;
+    }
+    for mv in quarto.legal_moves() {
+        let mut next = quarto.clone();
+        next.play_legal(&mv);
+        walk(&next, ply + 1, canonical, seen, per_ply);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /* Every line of play to `plies` moves, with the distinct keys at each ply */
+    fn brute_force(plies: usize, key: fn(&Quarto) -> String) -> Vec<u64> {
+        let mut layers = vec![vec![Quarto::new()]];
+        for _ in 0..plies {
+            let next = layers
+                .last()
+                .unwrap()
+                .iter()
+                .flat_map(|quarto| {
+                    quarto.legal_moves().into_iter().map(|mv| {
+                        let mut next = quarto.clone();
+                        next.play_legal(&mv);
+                        next
+                    })
+                })
+                .collect();
+            layers.push(next);
+        }
+        layers
+            .iter()
+            .map(|layer| layer.iter().map(key).collect::<HashSet<_>>().len() as u64)
+            .collect()
+    }
+
+    #[test]
+    fn test_counts_agree_with_brute_force() {
+        let exact = count_positions(2, false, None);
+        assert_eq!(exact.per_ply, brute_force(2, Quarto::position_key));
+        // Any of the 16 pieces, then any cell for it and any of the 15 others
+        assert_eq!(exact.per_ply, vec![1, 16, 16 * 16 * 15]);
+        assert!(exact.memory > 0);
+
+        let canonical = count_positions(2, true, None);
+        assert_eq!(canonical.per_ply, brute_force(2, Quarto::canonical_key));
+        // The cells fall into three classes: corners, edges and the middle four
+        assert_eq!(canonical.per_ply, vec![1, 16, 3 * 16 * 15]);
+
+        // A filter with room to spare misses nothing
+        let bloom = count_positions(2, true, Some(1 << 16));
+        assert_eq!(bloom.per_ply, canonical.per_ply);
+        assert_eq!(bloom.memory, 1 << 16);
+        assert!(bloom
+            .to_string()
+            .contains("at least 737 positions, counted up to symmetry"));
+    }
+}
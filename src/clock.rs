@@ -0,0 +1,355 @@
+use crate::quarto::{Quarto, QuartoError};
+use sqlx::sqlite::SqliteQueryResult;
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite, SqliteConnection};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/* Budget per player and increment added after each of their moves, written 10m+5s.
+   Amounts combine h, m and s units, e.g. 1h30m or 90s.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeControl {
+    pub budget: Duration,
+    pub increment: Duration,
+}
+
+pub(crate) fn parse_amount(s: &str) -> Option<Duration> {
+    let mut total = 0u64;
+    let mut digits = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        let n: u64 = digits.parse().ok()?;
+        total = total.checked_add(n.checked_mul(unit)?)?;
+        digits.clear();
+    }
+    if !digits.is_empty() || s.is_empty() {
+        return None;
+    }
+    Some(Duration::from_secs(total))
+}
+
+pub(crate) fn format_amount(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0 => "0s".to_string(),
+        _ if secs.is_multiple_of(3600) => format!("{}h", secs / 3600),
+        _ if secs.is_multiple_of(60) => format!("{}m", secs / 60),
+        _ => format!("{}s", secs),
+    }
+}
+
+impl FromStr for TimeControl {
+    type Err = QuartoError;
+    fn from_str(s: &str) -> Result<TimeControl, QuartoError> {
+        let invalid = || QuartoError::InvalidTimeControl(s.to_string());
+        let (budget, increment) = match s.split_once('+') {
+            Some((budget, increment)) => (budget, Some(increment)),
+            None => (s, None),
+        };
+        let budget = parse_amount(budget).ok_or_else(invalid)?;
+        let increment = match increment {
+            Some(increment) => parse_amount(increment).ok_or_else(invalid)?,
+            None => Duration::ZERO,
+        };
+        if budget.is_zero() {
+            return Err(invalid());
+        }
+        Ok(TimeControl { budget, increment })
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}+{}",
+            format_amount(self.budget),
+            format_amount(self.increment)
+        )
+    }
+}
+
+/* Seats are 0 for the 1st player and 1 for the 2nd */
+pub fn seat_to_move(quarto: &Quarto) -> usize {
+    quarto.turn() - 1
+}
+
+pub fn seat_name(seat: usize) -> &'static str {
+    ["1st", "2nd"][seat]
+}
+
+/* A player named on the command line, written like seat_name */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Seat(pub usize);
+
+impl FromStr for Seat {
+    type Err = QuartoError;
+    fn from_str(s: &str) -> Result<Seat, QuartoError> {
+        match s {
+            "1st" => Ok(Seat(0)),
+            "2nd" => Ok(Seat(1)),
+            _ => Err(QuartoError::InvalidSeat(s.to_string())),
+        }
+    }
+}
+
+pub const THINK_SLACK_MS: i64 = 1_000;
+
+/* Times are milliseconds; last_move_at is since the Unix epoch */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Clock {
+    pub control: TimeControl,
+    pub remaining: [i64; 2],
+    pub last_move_at: i64,
+    pub flagged: Option<usize>,
+}
+
+impl Clock {
+    /* Starts after the opening hand-over, which costs nothing */
+    pub fn start(control: TimeControl, now: i64) -> Clock {
+        let budget = control.budget.as_millis() as i64;
+        Clock {
+            control,
+            remaining: [budget, budget],
+            last_move_at: now,
+            flagged: None,
+        }
+    }
+
+    /* Time left for seat at now, counting the running turn when it is seat's */
+    pub fn remaining_at(&self, seat: usize, to_move: usize, now: i64) -> i64 {
+        if seat == to_move && self.flagged.is_none() {
+            (self.remaining[seat] - (now - self.last_move_at).max(0)).max(0)
+        } else {
+            self.remaining[seat]
+        }
+    }
+
+    /* What the running turn has cost the seat to move by now */
+    pub fn charge_at(&self, now: i64) -> i64 {
+        (now - self.last_move_at).max(0)
+    }
+
+    /* Whether a move's think time, measured from the events, agrees with what the
+       clock charges for it. The clock is started and saved in statements of its
+       own, so the two may be THINK_SLACK_MS apart.
+    */
+    pub fn agrees_with(&self, think_ms: i64, now: i64) -> bool {
+        (self.charge_at(now) - think_ms).abs() <= THINK_SLACK_MS
+    }
+
+    /* Flags the seat to move once its budget is used up; true when the game is lost on time */
+    pub fn check_flag(&mut self, to_move: usize, now: i64) -> bool {
+        if self.flagged.is_none() && self.remaining_at(to_move, to_move, now) == 0 {
+            self.remaining[to_move] = 0;
+            self.flagged = Some(to_move);
+        }
+        self.flagged.is_some()
+    }
+
+    /* Charges the seat to move for its turn and adds the increment */
+    pub fn complete_move(&mut self, to_move: usize, now: i64) -> Result<(), QuartoError> {
+        if self.check_flag(to_move, now) {
+            return Err(QuartoError::LostOnTime);
+        }
+        self.remaining[to_move] =
+            self.remaining_at(to_move, to_move, now) + self.control.increment.as_millis() as i64;
+        self.last_move_at = now;
+        Ok(())
+    }
+}
+
+pub fn format_clock(ms: i64) -> String {
+    let secs = ms / 1000;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/* A think time to a tenth of a second, e.g. 4.2s or 1:05.3 */
+pub fn format_think(ms: i64) -> String {
+    let tenths = ms / 100;
+    match tenths / 600 {
+        0 => format!("{}.{}s", tenths / 10, tenths % 10),
+        _ => format!("{}.{}", format_clock(ms), tenths % 10),
+    }
+}
+
+/* Games without a time control have no clock */
+pub async fn load(db: &Pool<Sqlite>, uuid: &str) -> Result<Option<Clock>, SqlxError> {
+    let row = sqlx::query_as::<
+        _,
+        (
+            Option<String>,
+            Option<i64>,
+            Option<i64>,
+            Option<i64>,
+            Option<i64>,
+        ),
+    >(
+        r#"
+        SELECT time_control, clock_1st, clock_2nd, last_move_at, flagged
+        FROM game
+        WHERE uuid = ?1
+        "#,
+    )
+    .bind(uuid)
+    .fetch_optional(db)
+    .await?;
+    match row {
+        Some((Some(control), Some(clock_1st), Some(clock_2nd), Some(last_move_at), flagged)) => {
+            let control = control
+                .parse::<TimeControl>()
+                .map_err(|e| SqlxError::Decode(Box::new(e)))?;
+            Ok(Some(Clock {
+                control,
+                remaining: [clock_1st, clock_2nd],
+                last_move_at,
+                flagged: flagged.map(|seat| seat as usize - 1),
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+pub async fn save(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    clock: &Clock,
+) -> Result<SqliteQueryResult, SqlxError> {
+    sqlx::query(
+        r#"
+        UPDATE game
+        SET time_control = ?2, clock_1st = ?3, clock_2nd = ?4, last_move_at = ?5, flagged = ?6
+        WHERE uuid = ?1;
+        "#,
+    )
+    .bind(uuid)
+    .bind(clock.control.to_string())
+    .bind(clock.remaining[0])
+    .bind(clock.remaining[1])
+    .bind(clock.last_move_at)
+    .bind(clock.flagged.map(|seat| seat as i64 + 1))
+    .execute(conn)
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::quarto::Piece;
+    use std::convert::TryFrom;
+
+    fn control(s: &str) -> TimeControl {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_time_control() {
+        assert_eq!(
+            control("10m+5s"),
+            TimeControl {
+                budget: Duration::from_secs(600),
+                increment: Duration::from_secs(5),
+            }
+        );
+        assert_eq!(control("1h30m").budget, Duration::from_secs(5400));
+        assert_eq!(control("90s").increment, Duration::ZERO);
+        assert_eq!(control("10m+5s").to_string(), "10m+5s");
+        assert_eq!(control("90s").to_string(), "90s+0s");
+        assert_eq!(control("120s+0s").to_string(), "2m+0s");
+        for invalid in [
+            "",
+            "10",
+            "m",
+            "10x",
+            "0s",
+            "10m+",
+            "+5s",
+            "-5m",
+            "10m+5s+1s",
+        ] {
+            assert!(invalid.parse::<TimeControl>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_seat_to_move() {
+        let mut quarto = Quarto::new();
+        // The opening hand-over is the 1st player's, so the 2nd places first
+        quarto.pick_piece(&Piece::try_from("BSCF".to_string()).unwrap());
+        assert_eq!(seat_to_move(&quarto), 1);
+        quarto.move_piece(0, 0);
+        quarto.pick_piece(&Piece::try_from("WSCF".to_string()).unwrap());
+        assert_eq!(seat_to_move(&quarto), 0);
+        assert_eq!(seat_name("2nd".parse::<Seat>().unwrap().0), "2nd");
+        assert!(matches!(
+            "first".parse::<Seat>(),
+            Err(QuartoError::InvalidSeat(_))
+        ));
+    }
+
+    #[test]
+    fn test_clock_charges_only_the_seat_to_move() {
+        let mut clock = Clock::start(control("1m+5s"), 1_000);
+        assert_eq!(clock.remaining_at(1, 1, 21_000), 40_000);
+        assert_eq!(clock.remaining_at(0, 1, 21_000), 60_000);
+
+        clock.complete_move(1, 21_000).unwrap();
+        assert_eq!(clock.remaining, [60_000, 45_000]);
+        clock.complete_move(0, 31_000).unwrap();
+        assert_eq!(clock.remaining, [55_000, 45_000]);
+        assert_eq!(clock.last_move_at, 31_000);
+        // A clock that runs backwards never adds time
+        assert_eq!(clock.remaining_at(1, 1, 0), 45_000);
+    }
+
+    #[test]
+    fn test_clock_flags_on_time() {
+        let mut clock = Clock::start(control("1m"), 0);
+        assert!(!clock.check_flag(1, 59_999));
+        assert!(matches!(
+            clock.complete_move(1, 60_000),
+            Err(QuartoError::LostOnTime)
+        ));
+        assert_eq!(clock.flagged, Some(1));
+        assert_eq!(clock.remaining, [60_000, 0]);
+        // Once flagged the clock stops for good
+        assert!(clock.check_flag(0, 1_000_000));
+        assert_eq!(clock.flagged, Some(1));
+        assert_eq!(clock.remaining_at(0, 0, 1_000_000), 60_000);
+    }
+
+    #[test]
+    fn test_format_clock() {
+        assert_eq!(format_clock(0), "0:00");
+        assert_eq!(format_clock(599_999), "9:59");
+        assert_eq!(format_clock(3_600_000), "1:00:00");
+        assert_eq!(format_think(0), "0.0s");
+        assert_eq!(format_think(4_250), "4.2s");
+        assert_eq!(format_think(65_300), "1:05.3");
+    }
+
+    #[test]
+    fn test_think_time_agrees_with_the_clock() {
+        let clock = Clock::start(control("1m"), 1_000);
+        assert_eq!(clock.charge_at(21_000), 20_000);
+        assert_eq!(clock.charge_at(0), 0);
+        assert!(clock.agrees_with(20_000, 21_000));
+        assert!(clock.agrees_with(19_200, 21_000));
+        assert!(!clock.agrees_with(15_000, 21_000));
+    }
+}
@@ -0,0 +1,430 @@
+use crate::game_id;
+use crate::quarto::{self, BoardState, ParseOptions, ParseWarning, QuartoError};
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite};
+use tracing::info;
+
+/* How the board_state column of a game row is written, recorded in board_format.
+   Rows from before that column leave it NULL, and their format is inferred from
+   the text.
+
+   1   four lines of four cells separated by spaces: pieces as BSCF codes, empty
+       cells as four spaces, ---- or ....
+
+   Every format with a reader below keeps loading; `migrate-board-format`
+   rewrites older rows in BOARD_FORMAT, which is what new rows are written in.
+   Rows are loaded leniently. `doctor` reads rows in BOARD_FORMAT strictly as
+   well, since anything written since is canonical, and flags the rest for
+   re-normalization. Stray whitespace, as left by tools which sync the database
+   file, is put right when a row is loaded and written out on its next save.
+*/
+pub const BOARD_FORMAT: i64 = 1;
+
+/* A board as read, with what lenient reading put right */
+type Read = Result<(BoardState, Vec<ParseWarning>), QuartoError>;
+
+/* Reads board_state for the board_format values it accepts */
+pub struct Reader {
+    pub name: &'static str,
+    pub accepts: &'static [i64],
+    read: fn(&str, ParseOptions) -> Read,
+}
+
+pub const READERS: [Reader; 1] = [Reader {
+    name: "board text",
+    accepts: &[1],
+    read: read_text,
+}];
+
+fn read_text(text: &str, options: ParseOptions) -> Read {
+    BoardState::parse(text, options)
+}
+
+/* The format of a row written before board_format existed */
+pub fn infer(board_state: &str) -> Option<i64> {
+    (board_state.lines().count() == 4).then_some(1)
+}
+
+/* The row's format: as stored, or inferred for legacy rows */
+pub fn format_of(board_format: Option<i64>, board_state: &str) -> Result<i64, QuartoError> {
+    board_format
+        .or_else(|| infer(board_state))
+        .ok_or_else(|| QuartoError::CorruptRecord("board_state: unknown format".to_string()))
+}
+
+pub fn reader(format: i64) -> Result<&'static Reader, QuartoError> {
+    READERS
+        .iter()
+        .find(|reader| reader.accepts.contains(&format))
+        .ok_or_else(|| QuartoError::CorruptRecord(format!("board_format {} has no reader", format)))
+}
+
+/* Reads board_state with the reader for its format */
+pub fn read_board(board_format: Option<i64>, board_state: &str) -> Result<BoardState, QuartoError> {
+    read_with(board_format, board_state, ParseOptions::default()).map(|(board, _)| board)
+}
+
+/* read_board, with the stray whitespace it put right */
+pub fn read_board_noting_whitespace(board_format: Option<i64>, board_state: &str) -> Read {
+    let (board, warnings) = read_with(board_format, board_state, ParseOptions::default())?;
+    let whitespace = warnings
+        .into_iter()
+        .filter(|warning| warning.kind.is_whitespace())
+        .collect();
+    Ok((board, whitespace))
+}
+
+fn read_with(board_format: Option<i64>, board_state: &str, options: ParseOptions) -> Read {
+    let reader = reader(format_of(board_format, board_state)?)?;
+    (reader.read)(board_state, options)
+        .map_err(|e| QuartoError::CorruptRecord(format!("board_state: {:?}", e)))
+}
+
+/* Why a row in BOARD_FORMAT is not canonical, if it is not */
+fn renormalize(board_format: Option<i64>, board_state: &str) -> Option<String> {
+    if board_format != Some(BOARD_FORMAT) {
+        return None;
+    }
+    read_with(board_format, board_state, ParseOptions { strict: true })
+        .err()
+        .map(|e| format!("{:?}", e))
+}
+
+/* Columns of the game table which older databases lack, with their types */
+const ADDED_COLUMNS: [(&str, &str); 7] = [
+    ("board_format", "INTEGER"),
+    ("seed", "INTEGER"),
+    ("aborted", "INTEGER"),
+    ("abort_requested_by", "INTEGER"),
+    ("engine_version", "VARCHAR"),
+    ("player_1st", "VARCHAR"),
+    ("player_2nd", "VARCHAR"),
+];
+
+/* Adds ADDED_COLUMNS to game tables created before them, NULL in existing rows.
+   Run by `init --force`.
+*/
+pub async fn upgrade_schema(db: &Pool<Sqlite>) -> Result<(), SqlxError> {
+    let columns = sqlx::query_as::<_, (String,)>("SELECT name FROM pragma_table_info('game');")
+        .fetch_all(db)
+        .await?;
+    for (added, kind) in ADDED_COLUMNS {
+        if !columns.iter().any(|(name,)| name == added) {
+            sqlx::query(&format!("ALTER TABLE game ADD COLUMN {} {};", added, kind))
+                .execute(db)
+                .await?;
+            info!("added game.{}", added);
+        }
+    }
+    Ok(())
+}
+
+/* Why the game may not replay as it was played under this build's rules, read
+   from the engine version its row was stamped with
+*/
+pub async fn revision_mismatch(db: &Pool<Sqlite>, uuid: &str) -> Result<Option<String>, SqlxError> {
+    let stamped =
+        sqlx::query_as::<_, (Option<String>,)>("SELECT engine_version FROM game WHERE uuid = ?1;")
+            .bind(uuid)
+            .fetch_optional(db)
+            .await?
+            .and_then(|(stamped,)| stamped);
+    Ok(quarto::revision_mismatch(
+        stamped.as_deref().and_then(quarto::rules_revision),
+    ))
+}
+
+/* A game row which is not in BOARD_FORMAT, not canonical in it, or cannot be
+   read at all
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub id: i64,
+    pub uuid: String,
+    pub board_format: Option<i64>,
+    /* The name of the reader which reads the row, or why none can */
+    pub read_as: Result<&'static str, String>,
+    /* What strict reading rejects in a readable row in BOARD_FORMAT */
+    pub renormalize: Option<String>,
+    /* Whether loading puts right stray whitespace in the row */
+    pub stray_whitespace: bool,
+}
+
+impl Finding {
+    /* e.g. 6b1f2a3c: board_format unset, read as board text; current is 1
+            6b1f2a3c: board_format 1, read as board text; needs re-normalization, ...
+       with the uuid shortened among `known`
+    */
+    pub fn line(&self, known: &[String]) -> String {
+        let uuid = game_id::short(&self.uuid, known);
+        let name = match &self.read_as {
+            Ok(name) => name,
+            Err(problem) => return format!("{}: unreadable, {}", uuid, problem),
+        };
+        let format = match self.board_format {
+            None => "board_format unset".to_string(),
+            Some(format) => format!("board_format {}", format),
+        };
+        match &self.renormalize {
+            Some(reason) => format!(
+                "{}: {}, read as {}; needs re-normalization, {}",
+                uuid, format, name, reason
+            ),
+            None => format!(
+                "{}: {}, read as {}; current is {}",
+                uuid, format, name, BOARD_FORMAT
+            ),
+        }
+    }
+}
+
+type Row = (i64, Option<String>, Option<i64>, Option<String>);
+
+const ROWS: &str = "SELECT id, uuid, board_format, board_state FROM game ORDER BY id;";
+
+/* Rows get their board with the first move, so one without is left out */
+fn finding((id, uuid, board_format, board_state): &Row) -> Option<Finding> {
+    let board_state = board_state.as_ref()?;
+    let read_as = read_board(*board_format, board_state)
+        .and_then(|_| Ok(reader(format_of(*board_format, board_state)?)?.name))
+        .map_err(|e| format!("{:?}", e));
+    let renormalize = read_as
+        .is_ok()
+        .then(|| renormalize(*board_format, board_state))
+        .flatten();
+    let stray_whitespace = read_board_noting_whitespace(*board_format, board_state)
+        .is_ok_and(|(_, whitespace)| !whitespace.is_empty());
+    (read_as.is_err() || *board_format != Some(BOARD_FORMAT) || renormalize.is_some()).then(|| {
+        Finding {
+            id: *id,
+            uuid: uuid.clone().unwrap_or_default(),
+            board_format: *board_format,
+            read_as,
+            renormalize,
+            stray_whitespace,
+        }
+    })
+}
+
+/* Game rows in older formats, not canonical or unreadable, for `doctor` */
+pub async fn survey(db: &Pool<Sqlite>) -> Result<Vec<Finding>, SqlxError> {
+    let rows = sqlx::query_as::<_, Row>(ROWS).fetch_all(db).await?;
+    Ok(rows.iter().filter_map(finding).collect())
+}
+
+/* Rewrites every readable row in an older format or not canonical in
+   BOARD_FORMAT and returns how many were rewritten; unreadable rows are left as
+   they are
+*/
+pub async fn migrate(db: &Pool<Sqlite>) -> Result<usize, SqlxError> {
+    let mut tx = db.begin().await?;
+    let rows = sqlx::query_as::<_, Row>(ROWS).fetch_all(&mut *tx).await?;
+    let mut migrated = 0;
+    for row in &rows {
+        let (Some(found), Some(board_state)) = (finding(row), &row.3) else {
+            continue;
+        };
+        let Ok(board) = read_board(found.board_format, board_state) else {
+            continue;
+        };
+        sqlx::query("UPDATE game SET board_state = ?2, board_format = ?3 WHERE id = ?1;")
+            .bind(found.id)
+            .bind(String::from(board))
+            .bind(BOARD_FORMAT)
+            .execute(&mut *tx)
+            .await?;
+        migrated += 1;
+    }
+    tx.commit().await?;
+    if migrated > 0 {
+        info!(migrated, "rewrote boards in format {}", BOARD_FORMAT);
+    }
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event;
+    use crate::file_store::test::TempDir;
+    use crate::quarto::GameStatus;
+    use crate::store;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+    /* A database as written before board_format existed: blank and dashed empty
+       cells, lines with their trailing blanks stripped, NULL next_piece and
+       clock columns, a game with events and games without
+    */
+    const FIXTURE: &str = include_str!("../tests/fixtures/games-before-board-format.sql");
+
+    async fn fixture_db(dir: &TempDir) -> Pool<Sqlite> {
+        load_fixture(dir, FIXTURE).await
+    }
+
+    async fn load_fixture(dir: &TempDir, fixture: &str) -> Pool<Sqlite> {
+        let options = SqliteConnectOptions::new()
+            .filename(dir.0.join("games.sqlite"))
+            .create_if_missing(true);
+        let db = SqlitePoolOptions::new()
+            .connect_with(options)
+            .await
+            .unwrap();
+        for statement in fixture.split(";\n").filter(|s| !s.trim().is_empty()) {
+            sqlx::query(statement).execute(&db).await.unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn test_formats_are_inferred_and_dispatched() {
+        let text = "BSCF ---- ---- ----\n\n\n";
+        assert_eq!(infer(text), None);
+        let text = "BSCF ---- ---- ----\n\n\n ";
+        assert_eq!(format_of(None, text).unwrap(), 1);
+        assert_eq!(format_of(Some(1), text).unwrap(), 1);
+        assert_eq!(reader(1).unwrap().name, "board text");
+        assert_eq!(
+            read_board(None, text).unwrap().cells()[0][0].map(String::from),
+            Some("BSCF".into())
+        );
+        assert!(matches!(
+            format_of(None, "BSCF"),
+            Err(QuartoError::CorruptRecord(_))
+        ));
+        assert!(matches!(
+            read_board(Some(9), text),
+            Err(QuartoError::CorruptRecord(reason)) if reason == "board_format 9 has no reader"
+        ));
+        for reader in &READERS {
+            assert!(reader.accepts.iter().all(|format| *format <= BOARD_FORMAT));
+        }
+    }
+
+    #[test]
+    fn test_rows_not_canonical_need_renormalization() {
+        let canonical = String::from(read_board(Some(1), "BSCF\n\n\n ").unwrap());
+        let row = |board_state: &str| {
+            (
+                1,
+                Some("a".to_string()),
+                Some(BOARD_FORMAT),
+                Some(board_state.to_string()),
+            )
+        };
+        assert_eq!(finding(&row(&canonical)), None);
+        for text in [
+            "BSCF ---- ---- ----\n\n\n ",
+            "bscf\n\n\n ",
+            &canonical.replace('\n', "\r\n"),
+        ] {
+            let found = finding(&row(text)).unwrap();
+            assert_eq!(found.read_as, Ok("board text"));
+            let reason = found.renormalize.clone().unwrap();
+            assert!(reason.contains("in strict mode"), "{}", reason);
+            assert!(
+                found
+                    .line(&[])
+                    .starts_with("a: board_format 1, read as board text; needs re-normalization, "),
+                "{}",
+                found.line(&[])
+            );
+            assert_eq!(String::from(read_board(Some(1), text).unwrap()), canonical);
+        }
+        // Legacy rows are reported for their format, not for their spelling
+        let legacy = finding(&(1, None, None, Some("BSCF ----\n\n\n ".to_string()))).unwrap();
+        assert_eq!(legacy.renormalize, None);
+    }
+
+    #[tokio::test]
+    async fn test_older_rules_revisions_are_reported() {
+        let dir = TempDir::new();
+        let fixture = include_str!("../tests/fixtures/game-rules-revision-0.sql");
+        let db = load_fixture(&dir, fixture).await;
+        let older = revision_mismatch(&db, "e1a2b3c4-0000-4000-8000-000000000000")
+            .await
+            .unwrap();
+        assert_eq!(
+            older.unwrap(),
+            format!(
+                "recorded under rules revision 0, replaying under {}",
+                quarto::RULES_REVISION
+            )
+        );
+        for unstamped in ["e1a2b3c4-0000-4000-8000-00000000000f", "unknown"] {
+            assert_eq!(revision_mismatch(&db, unstamped).await.unwrap(), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_database_before_board_format_loads_and_replays() {
+        let dir = TempDir::new();
+        let db = fixture_db(&dir).await;
+        // What `init --force` does to an older database
+        upgrade_schema(&db).await.unwrap();
+        upgrade_schema(&db).await.unwrap();
+        event::init_events(&db).await.unwrap();
+        event::backfill(&db).await.unwrap();
+
+        let findings = survey(&db).await.unwrap();
+        assert_eq!(findings.len(), 4);
+        assert!(findings
+            .iter()
+            .all(|f| f.read_as.is_ok() && f.board_format.is_none()));
+        assert!(
+            findings[0]
+                .line(&[])
+                .ends_with(": board_format unset, read as board text; current is 1"),
+            "{}",
+            findings[0].line(&[])
+        );
+
+        let rows = sqlx::query_as::<_, (String, Option<String>, Option<String>, bool, Option<i64>)>(
+            "SELECT uuid, board_state, next_piece, advanced, board_format FROM game ORDER BY id;",
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        let mut loaded = Vec::new();
+        for (uuid, board_state, next_piece, advanced, board_format) in rows {
+            // The game which never got a board cannot be read, and has no events
+            if board_state.is_none() {
+                assert!(matches!(
+                    store::from_row(&board_state, &next_piece, advanced, board_format),
+                    Err(QuartoError::CorruptRecord(reason)) if reason == "board_state is missing"
+                ));
+                assert!(event::load(&db, &uuid, 0).await.unwrap().is_empty());
+                continue;
+            }
+            let quarto =
+                store::from_row(&board_state, &next_piece, advanced, board_format).unwrap();
+            // The events rebuild every stored game
+            let replayed = event::replay(&event::load(&db, &uuid, 0).await.unwrap()).unwrap();
+            assert_eq!(replayed.as_ref(), Some(&quarto), "{}", uuid);
+            loaded.push(quarto);
+        }
+        assert_eq!(loaded[1].status(), GameStatus::Won);
+        assert_eq!(loaded[1].next_piece, None);
+        assert_eq!(loaded.len(), 4);
+
+        assert_eq!(migrate(&db).await.unwrap(), 4);
+        assert!(survey(&db).await.unwrap().is_empty());
+        assert_eq!(migrate(&db).await.unwrap(), 0);
+        let rows = sqlx::query_as::<_, (Option<String>, Option<String>, bool, Option<i64>)>(
+            r#"
+            SELECT board_state, next_piece, advanced, board_format
+            FROM game
+            WHERE board_state IS NOT NULL
+            ORDER BY id;
+            "#,
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        for ((board_state, next_piece, advanced, board_format), before) in rows.iter().zip(&loaded)
+        {
+            assert_eq!(*board_format, Some(BOARD_FORMAT));
+            let after = store::from_row(board_state, next_piece, *advanced, *board_format).unwrap();
+            assert_eq!(&after, before);
+        }
+    }
+}
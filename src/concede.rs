@@ -0,0 +1,508 @@
+use crate::clock::seat_name;
+use crate::event::{self, Event};
+use crate::game_id;
+use crate::quarto::{GameStatus, Quarto, QuartoError};
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite};
+use std::error::Error;
+use tracing::info;
+
+/* Games ended by the players rather than on the board: one resigns and the other
+   wins, or one offers a draw and the other accepts it. An offer is open only on the
+   board it was made on, so the next placement withdraws it. Seats are stored
+   plus one, like forfeited.
+
+   A game can also be aborted: called off with no result, so that it counts for
+   nobody. Either player may abort it alone before ABORT_BEFORE plies have been
+   played; after that an abort is a request which stands until the other player
+   aborts too.
+*/
+pub const ABORT_BEFORE: usize = 2;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Concession {
+    pub resigned: Option<usize>,
+    pub draw_offer: Option<DrawOffer>,
+    pub draw_agreed: bool,
+    /* Lost on time or forfeited; nothing can be conceded any more */
+    pub decided: bool,
+    /* By the seat which aborted it, alone or agreeing to the other's request */
+    pub aborted: Option<usize>,
+    pub abort_request: Option<usize>,
+}
+
+/* What `abort` does for a seat */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Abort {
+    /* Early enough to abort alone */
+    Alone,
+    /* The other player asked first */
+    Agreed,
+    /* Too late to abort alone, so ask the other player */
+    Request,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DrawOffer {
+    pub seat: usize,
+    /* Pieces on the board when the offer was made */
+    pub placed: usize,
+}
+
+impl Concession {
+    pub fn is_over(&self) -> bool {
+        self.resigned.is_some() || self.draw_agreed || self.decided || self.aborted.is_some()
+    }
+
+    /* `before` is the number of plies from which aborting takes both players */
+    pub fn check_abort(
+        &self,
+        quarto: &Quarto,
+        seat: usize,
+        before: usize,
+    ) -> Result<Abort, QuartoError> {
+        self.check_open(quarto)?;
+        if quarto.placed_pieces() < before {
+            return Ok(Abort::Alone);
+        }
+        match self.abort_request {
+            Some(requested) if requested != seat => Ok(Abort::Agreed),
+            _ => Ok(Abort::Request),
+        }
+    }
+
+    /* Who offered the draw still open on this board */
+    pub fn pending_offer(&self, quarto: &Quarto) -> Option<usize> {
+        self.draw_offer
+            .filter(|offer| offer.placed == quarto.placed_pieces())
+            .map(|offer| offer.seat)
+    }
+
+    /* The game must still be going on the board and between the players */
+    pub fn check_open(&self, quarto: &Quarto) -> Result<(), QuartoError> {
+        if self.is_over() || quarto.status() != GameStatus::InProgress {
+            return Err(QuartoError::GameOver);
+        }
+        Ok(())
+    }
+
+    /* Only the other player's open offer can be accepted */
+    pub fn check_accept(&self, quarto: &Quarto, seat: usize) -> Result<(), QuartoError> {
+        self.check_open(quarto)?;
+        match self.pending_offer(quarto) {
+            Some(offered) if offered != seat => Ok(()),
+            _ => Err(QuartoError::NoDrawOffer),
+        }
+    }
+
+    pub fn describe(&self, quarto: &Quarto) -> Option<String> {
+        if let Some(seat) = self.resigned {
+            return Some(format!(
+                "{} resigned; {} wins",
+                seat_name(seat),
+                seat_name(1 - seat)
+            ));
+        }
+        if self.draw_agreed {
+            return Some("drawn by agreement".to_string());
+        }
+        if let Some(seat) = self.aborted {
+            return Some(format!("aborted by {}", seat_name(seat)));
+        }
+        let offer = self
+            .pending_offer(quarto)
+            .map(|seat| format!("{} offers a draw", seat_name(seat)));
+        let request = self
+            .abort_request
+            .map(|seat| format!("{} asks to abort", seat_name(seat)));
+        match (offer, request) {
+            (Some(offer), Some(request)) => Some(format!("{}; {}", offer, request)),
+            (offer, request) => offer.or(request),
+        }
+    }
+}
+
+type ConcessionRow = (
+    Option<i64>,
+    Option<i64>,
+    Option<i64>,
+    bool,
+    bool,
+    Option<i64>,
+    Option<i64>,
+);
+
+pub async fn load(db: &Pool<Sqlite>, uuid: &str) -> Result<Concession, SqlxError> {
+    let row = sqlx::query_as::<_, ConcessionRow>(
+        r#"
+        SELECT resigned, draw_offered_by, draw_offered_at, draw_agreed,
+               flagged IS NOT NULL OR forfeited IS NOT NULL, aborted, abort_requested_by
+        FROM game
+        WHERE uuid = ?1
+        "#,
+    )
+    .bind(uuid)
+    .fetch_optional(db)
+    .await?;
+    let Some((resigned, offered_by, offered_at, draw_agreed, decided, aborted, abort_request)) =
+        row
+    else {
+        return Ok(Concession::default());
+    };
+    Ok(Concession {
+        resigned: resigned.map(|seat| seat as usize - 1),
+        draw_offer: offered_by.zip(offered_at).map(|(seat, placed)| DrawOffer {
+            seat: seat as usize - 1,
+            placed: placed as usize,
+        }),
+        draw_agreed,
+        decided,
+        aborted: aborted.map(|seat| seat as usize - 1),
+        abort_request: abort_request.map(|seat| seat as usize - 1),
+    })
+}
+
+/* Applies `update` unless the game has been ended since it was loaded, and records
+   `event` with it. False when nothing was changed.
+*/
+async fn conclude(
+    db: &Pool<Sqlite>,
+    uuid: &str,
+    update: &str,
+    values: &[i64],
+    event: &Event,
+    now: i64,
+) -> Result<bool, Box<dyn Error>> {
+    let mut tx = db.begin().await?;
+    let mut query = sqlx::query(update).bind(uuid);
+    for value in values {
+        query = query.bind(*value);
+    }
+    let result = query.execute(&mut *tx).await?;
+    if result.rows_affected() != 1 {
+        return Ok(false);
+    }
+    event::append(&mut tx, uuid, event, now).await?;
+    tx.commit().await?;
+    info!(uuid = %game_id::logged(&uuid), kind = event.kind(), "recorded");
+    Ok(true)
+}
+
+const STILL_OPEN: &str = "resigned IS NULL AND draw_agreed = false AND flagged IS NULL \
+                          AND forfeited IS NULL AND aborted IS NULL";
+
+pub async fn resign(
+    db: &Pool<Sqlite>,
+    uuid: &str,
+    seat: usize,
+    now: i64,
+) -> Result<bool, Box<dyn Error>> {
+    let update = format!(
+        "UPDATE game SET resigned = ?2, draw_offered_by = NULL, draw_offered_at = NULL \
+         WHERE uuid = ?1 AND {};",
+        STILL_OPEN
+    );
+    let event = Event::Resigned { seat };
+    conclude(db, uuid, &update, &[seat as i64 + 1], &event, now).await
+}
+
+/* Replaces any earlier offer, from either player */
+pub async fn offer_draw(
+    db: &Pool<Sqlite>,
+    uuid: &str,
+    seat: usize,
+    placed: usize,
+    now: i64,
+) -> Result<bool, Box<dyn Error>> {
+    let update = format!(
+        "UPDATE game SET draw_offered_by = ?2, draw_offered_at = ?3 WHERE uuid = ?1 AND {};",
+        STILL_OPEN
+    );
+    let event = Event::DrawOffered { seat };
+    let values = [seat as i64 + 1, placed as i64];
+    conclude(db, uuid, &update, &values, &event, now).await
+}
+
+/* Succeeds only while the other player's offer made on this board is still stored */
+pub async fn accept_draw(
+    db: &Pool<Sqlite>,
+    uuid: &str,
+    seat: usize,
+    placed: usize,
+    now: i64,
+) -> Result<bool, Box<dyn Error>> {
+    let update = format!(
+        "UPDATE game SET draw_agreed = true \
+         WHERE uuid = ?1 AND draw_offered_by = ?2 AND draw_offered_at = ?3 AND {};",
+        STILL_OPEN
+    );
+    let event = Event::DrawAgreed { seat };
+    // The offer is the other seat's, stored plus one
+    let values = [2 - seat as i64, placed as i64];
+    conclude(db, uuid, &update, &values, &event, now).await
+}
+
+/* Stands until the game ends; the other player agrees by aborting too */
+pub async fn request_abort(
+    db: &Pool<Sqlite>,
+    uuid: &str,
+    seat: usize,
+    now: i64,
+) -> Result<bool, Box<dyn Error>> {
+    let update = format!(
+        "UPDATE game SET abort_requested_by = ?2 WHERE uuid = ?1 AND {};",
+        STILL_OPEN
+    );
+    let event = Event::AbortRequested { seat };
+    conclude(db, uuid, &update, &[seat as i64 + 1], &event, now).await
+}
+
+/* With `agreed`, succeeds only while the other player's request is still stored */
+pub async fn abort(
+    db: &Pool<Sqlite>,
+    uuid: &str,
+    seat: usize,
+    agreed: bool,
+    now: i64,
+) -> Result<bool, Box<dyn Error>> {
+    // The request is the other seat's, stored plus one
+    let (requested, values) = if agreed {
+        (
+            "AND abort_requested_by = ?3",
+            vec![seat as i64 + 1, 2 - seat as i64],
+        )
+    } else {
+        ("", vec![seat as i64 + 1])
+    };
+    let update = format!(
+        "UPDATE game SET aborted = ?2, draw_offered_by = NULL, draw_offered_at = NULL \
+         WHERE uuid = ?1 {} AND {};",
+        requested, STILL_OPEN
+    );
+    let event = Event::Aborted { seat };
+    conclude(db, uuid, &update, &values, &event, now).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory_db;
+    use crate::quarto::Piece;
+
+    fn piece(code: &str) -> Piece {
+        Piece::try_from(code.to_string()).unwrap()
+    }
+
+    fn opening() -> Quarto {
+        let mut quarto = Quarto::new();
+        quarto.pick_piece(&piece("BSCF"));
+        quarto
+    }
+
+    fn after_one_move() -> Quarto {
+        let mut quarto = opening();
+        quarto.move_piece(0, 0);
+        quarto.pick_piece(&piece("WSCF"));
+        quarto
+    }
+
+    #[test]
+    fn test_stale_draw_offer_cannot_be_accepted() {
+        let offered = Concession {
+            draw_offer: Some(DrawOffer { seat: 0, placed: 0 }),
+            ..Concession::default()
+        };
+        assert_eq!(offered.pending_offer(&opening()), Some(0));
+        assert_eq!(
+            offered.describe(&opening()).as_deref(),
+            Some("1st offers a draw")
+        );
+        assert!(offered.check_accept(&opening(), 1).is_ok());
+        // Not one's own offer
+        assert!(matches!(
+            offered.check_accept(&opening(), 0),
+            Err(QuartoError::NoDrawOffer)
+        ));
+
+        // A placement since the offer withdrew it
+        let board = after_one_move();
+        assert_eq!(offered.pending_offer(&board), None);
+        assert_eq!(offered.describe(&board), None);
+        assert!(matches!(
+            offered.check_accept(&board, 1),
+            Err(QuartoError::NoDrawOffer)
+        ));
+        assert!(matches!(
+            Concession::default().check_accept(&board, 1),
+            Err(QuartoError::NoDrawOffer)
+        ));
+    }
+
+    #[test]
+    fn test_concluded_games_stay_over() {
+        let resigned = Concession {
+            resigned: Some(1),
+            ..Concession::default()
+        };
+        assert_eq!(
+            resigned.describe(&opening()).as_deref(),
+            Some("2nd resigned; 1st wins")
+        );
+        let agreed = Concession {
+            draw_agreed: true,
+            ..Concession::default()
+        };
+        assert_eq!(
+            agreed.describe(&opening()).as_deref(),
+            Some("drawn by agreement")
+        );
+        let forfeited = Concession {
+            decided: true,
+            ..Concession::default()
+        };
+        let aborted = Concession {
+            aborted: Some(0),
+            ..Concession::default()
+        };
+        assert_eq!(
+            aborted.describe(&opening()).as_deref(),
+            Some("aborted by 1st")
+        );
+        for concession in [resigned, agreed, forfeited, aborted] {
+            assert!(concession.is_over());
+            assert!(matches!(
+                concession.check_open(&opening()),
+                Err(QuartoError::GameOver)
+            ));
+        }
+
+        // Decided on the board
+        let mut won = Quarto::new();
+        for (y, code) in ["BSCF", "BSCH", "BSSF", "BTSH"].iter().enumerate() {
+            won.pick_piece(&piece(code));
+            won.move_piece(0, y);
+        }
+        assert!(matches!(
+            Concession::default().check_open(&won),
+            Err(QuartoError::GameOver)
+        ));
+        assert!(Concession::default().check_open(&opening()).is_ok());
+    }
+
+    #[test]
+    fn test_abort_alone_only_before_the_threshold() {
+        let open = Concession::default();
+        assert_eq!(
+            open.check_abort(&opening(), 0, ABORT_BEFORE).unwrap(),
+            Abort::Alone
+        );
+        assert_eq!(
+            open.check_abort(&after_one_move(), 1, ABORT_BEFORE)
+                .unwrap(),
+            Abort::Alone
+        );
+        let mut later = after_one_move();
+        later.move_piece(1, 1);
+        later.pick_piece(&piece("BTCF"));
+        assert_eq!(
+            open.check_abort(&later, 0, ABORT_BEFORE).unwrap(),
+            Abort::Request
+        );
+        // A threshold of 0 always takes both players
+        assert_eq!(open.check_abort(&opening(), 0, 0).unwrap(), Abort::Request);
+        assert_eq!(open.check_abort(&later, 0, 3).unwrap(), Abort::Alone);
+
+        let requested = Concession {
+            abort_request: Some(0),
+            ..Concession::default()
+        };
+        assert_eq!(
+            requested.check_abort(&later, 1, ABORT_BEFORE).unwrap(),
+            Abort::Agreed
+        );
+        // Asking twice does not agree with oneself
+        assert_eq!(
+            requested.check_abort(&later, 0, ABORT_BEFORE).unwrap(),
+            Abort::Request
+        );
+        assert_eq!(
+            requested.describe(&later).as_deref(),
+            Some("1st asks to abort")
+        );
+    }
+
+    async fn games(uuids: &[&str]) -> Pool<Sqlite> {
+        let db = memory_db().await;
+        for uuid in uuids {
+            sqlx::query("INSERT INTO game (uuid) VALUES (?1);")
+                .bind(uuid)
+                .execute(&db)
+                .await
+                .unwrap();
+        }
+
+        db
+    }
+
+    #[tokio::test]
+    async fn test_draw_by_agreement_and_resignation() {
+        let db = games(&["drawn", "resigned"]).await;
+        // The offer made before the last placement has gone stale
+        assert!(offer_draw(&db, "drawn", 0, 0, 1).await.unwrap());
+        assert!(!accept_draw(&db, "drawn", 1, 1, 2).await.unwrap());
+        assert!(!load(&db, "drawn").await.unwrap().draw_agreed);
+        assert!(offer_draw(&db, "drawn", 0, 1, 3).await.unwrap());
+        assert!(accept_draw(&db, "drawn", 1, 1, 4).await.unwrap());
+        let drawn = load(&db, "drawn").await.unwrap();
+        assert!(drawn.draw_agreed && drawn.is_over());
+        assert!(!resign(&db, "drawn", 0, 5).await.unwrap());
+
+        assert!(offer_draw(&db, "resigned", 1, 0, 1).await.unwrap());
+        assert!(resign(&db, "resigned", 0, 2).await.unwrap());
+        let resigned = load(&db, "resigned").await.unwrap();
+        assert_eq!((resigned.resigned, resigned.draw_offer), (Some(0), None));
+        assert!(!accept_draw(&db, "resigned", 0, 0, 3).await.unwrap());
+
+        let kinds = |events: Vec<event::Record>| {
+            events
+                .into_iter()
+                .map(|r| r.event.kind())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            kinds(event::load(&db, "drawn", 0).await.unwrap()),
+            vec!["draw_offered", "draw_offered", "draw_agreed"]
+        );
+        assert_eq!(
+            kinds(event::load(&db, "resigned", 0).await.unwrap()),
+            vec!["draw_offered", "resigned"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_abort_by_agreement() {
+        let db = games(&["early", "agreed"]).await;
+        assert!(abort(&db, "early", 1, false, 1).await.unwrap());
+        assert_eq!(load(&db, "early").await.unwrap().aborted, Some(1));
+        assert!(!resign(&db, "early", 0, 2).await.unwrap());
+        assert!(!abort(&db, "early", 0, false, 3).await.unwrap());
+
+        // Nothing to agree to until the other player asks
+        assert!(!abort(&db, "agreed", 1, true, 1).await.unwrap());
+        assert!(request_abort(&db, "agreed", 0, 2).await.unwrap());
+        assert!(!abort(&db, "agreed", 0, true, 3).await.unwrap());
+        assert!(abort(&db, "agreed", 1, true, 4).await.unwrap());
+        let agreed = load(&db, "agreed").await.unwrap();
+        assert!(agreed.is_over());
+        assert_eq!((agreed.aborted, agreed.abort_request), (Some(1), Some(0)));
+        assert!(!request_abort(&db, "agreed", 0, 5).await.unwrap());
+
+        let kinds: Vec<&str> = event::load(&db, "agreed", 0)
+            .await
+            .unwrap()
+            .iter()
+            .map(|r| r.event.kind())
+            .collect();
+        assert_eq!(kinds, vec!["abort_requested", "aborted"]);
+    }
+}
@@ -0,0 +1,328 @@
+use crate::clock::{format_amount, parse_amount};
+use crate::quarto::QuartoError;
+use sqlx::Error as SqlxError;
+use std::error::Error;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::io;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/* How long a single database operation may take, written like 5s or 1m */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DbTimeout(pub Duration);
+
+impl Default for DbTimeout {
+    fn default() -> DbTimeout {
+        DbTimeout(Duration::from_secs(5))
+    }
+}
+
+impl FromStr for DbTimeout {
+    type Err = QuartoError;
+    fn from_str(s: &str) -> Result<DbTimeout, QuartoError> {
+        parse_amount(s)
+            .filter(|d| !d.is_zero())
+            .map(DbTimeout)
+            .ok_or_else(|| QuartoError::InvalidTimeout(s.to_string()))
+    }
+}
+
+impl fmt::Display for DbTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&format_amount(self.0))
+    }
+}
+
+/* Every database operation runs under a timeout, and one failing because the
+   database was busy is tried again a few times after a growing, jittered wait.
+   Other errors, such as a uuid that is already taken, are returned at once, and
+   so is a timeout, since the operation may still be going on.
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DbPolicy {
+    pub timeout: DbTimeout,
+    pub retries: u32,
+    /* The first wait; it doubles with every retry */
+    pub backoff: Duration,
+    /* Commands that would write fail before they start; see read_only_url */
+    pub read_only: bool,
+}
+
+impl Default for DbPolicy {
+    fn default() -> DbPolicy {
+        DbPolicy {
+            timeout: DbTimeout::default(),
+            retries: 3,
+            backoff: Duration::from_millis(50),
+            read_only: false,
+        }
+    }
+}
+
+/* SQLITE_BUSY and SQLITE_LOCKED with their extended codes, and a pool without a free
+   connection. sqlx reports SQLite's extended code, whose low byte is the primary one.
+*/
+pub fn is_transient(e: &(dyn Error + 'static)) -> bool {
+    match e.downcast_ref::<SqlxError>() {
+        Some(SqlxError::PoolTimedOut) => true,
+        Some(SqlxError::Database(e)) => e
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(|code| matches!(code & 0xff, 5 | 6)),
+        _ => false,
+    }
+}
+
+/* SQLITE_READONLY with its extended codes: a write to a database opened with mode=ro
+   or to a file that cannot be written
+*/
+pub fn is_read_only(e: &SqlxError) -> bool {
+    match e {
+        SqlxError::Database(e) => e
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(|code| code & 0xff == 8),
+        _ => false,
+    }
+}
+
+fn split_url(db_url: &str) -> (&str, Option<&str>) {
+    match db_url.split_once('?') {
+        Some((path, params)) => (path, Some(params)),
+        None => (db_url, None),
+    }
+}
+
+/* Whether a sqlite: URL asks for the database with mode=ro */
+pub fn opens_read_only(db_url: &str) -> bool {
+    let (_, params) = split_url(db_url);
+    params.is_some_and(|params| params.split('&').any(|param| param == "mode=ro"))
+}
+
+/* False when the database file exists but cannot be opened for writing; a file yet
+   to be created and an in-memory database count as writable
+*/
+pub fn is_writable(db_url: &str) -> bool {
+    let (path, _) = split_url(db_url);
+    let file = path.trim_start_matches("sqlite:").trim_start_matches("//");
+    if file.is_empty() || file == ":memory:" {
+        return true;
+    }
+    !matches!(
+        OpenOptions::new().write(true).open(file),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied
+    )
+}
+
+/* The URL with mode=ro, so that SQLite itself refuses any write a read-only run
+   still tries
+*/
+pub fn read_only_url(db_url: &str) -> String {
+    match split_url(db_url) {
+        _ if opens_read_only(db_url) => db_url.to_string(),
+        (_, Some(_)) => format!("{}&mode=ro", db_url),
+        (_, None) => format!("{}?mode=ro", db_url),
+    }
+}
+
+/* A fraction in [0, 1) that differs between processes retrying at the same time */
+fn jitter() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |d| f64::from(d.subsec_nanos()) / 1e9)
+}
+
+impl DbPolicy {
+    /* The wait before retry `attempt`, counted from 1 */
+    pub fn delay(&self, attempt: u32, jitter: f64) -> Duration {
+        self.backoff * 2u32.saturating_pow(attempt - 1) + self.backoff.mul_f64(jitter)
+    }
+
+    /* One try under the timeout, for operations which cannot simply be repeated */
+    pub async fn once<T, E, Fut>(&self, operation: &str, f: Fut) -> Result<T, Box<dyn Error>>
+    where
+        E: Into<Box<dyn Error>>,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        match tokio::time::timeout(self.timeout.0, f).await {
+            Ok(result) => result.map_err(Into::into),
+            Err(_) => {
+                warn!(operation, timeout = %self.timeout, "database operation timed out");
+                Err(QuartoError::Timeout(operation.to_string()).into())
+            }
+        }
+    }
+
+    /* `f` starts the operation afresh for every try */
+    pub async fn run<T, E, F, Fut>(&self, operation: &str, mut f: F) -> Result<T, Box<dyn Error>>
+    where
+        E: Into<Box<dyn Error>>,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match tokio::time::timeout(self.timeout.0, f()).await {
+                Ok(Ok(value)) => return Ok(value),
+                Ok(Err(e)) => {
+                    let e = e.into();
+                    if attempt >= self.retries || !is_transient(&*e) {
+                        return Err(e);
+                    }
+                    attempt += 1;
+                    let delay = self.delay(attempt, jitter());
+                    warn!(operation, attempt, ?delay, "database busy, trying again");
+                    tokio::time::sleep(delay).await;
+                }
+                Err(_) => {
+                    warn!(operation, timeout = %self.timeout, "database operation timed out");
+                    return Err(QuartoError::Timeout(operation.to_string()).into());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+
+    fn policy() -> DbPolicy {
+        DbPolicy {
+            timeout: DbTimeout(Duration::from_millis(50)),
+            retries: 2,
+            backoff: Duration::from_millis(1),
+            read_only: false,
+        }
+    }
+
+    /* A store call which answers with each result in turn, taking `latency` */
+    struct ScriptedStore {
+        latency: Duration,
+        calls: Cell<usize>,
+        results: Vec<Result<i64, SqlxError>>,
+    }
+
+    impl ScriptedStore {
+        async fn load(&self) -> Result<i64, SqlxError> {
+            let call = self.calls.get();
+            self.calls.set(call + 1);
+            tokio::time::sleep(self.latency).await;
+            match &self.results[call.min(self.results.len() - 1)] {
+                Ok(n) => Ok(*n),
+                Err(SqlxError::PoolTimedOut) => Err(SqlxError::PoolTimedOut),
+                Err(_) => Err(SqlxError::RowNotFound),
+            }
+        }
+    }
+
+    fn store(latency: Duration, results: Vec<Result<i64, SqlxError>>) -> ScriptedStore {
+        ScriptedStore {
+            latency,
+            calls: Cell::new(0),
+            results,
+        }
+    }
+
+    #[test]
+    fn test_parse_timeout_and_delay() {
+        assert_eq!(
+            "10s".parse::<DbTimeout>().unwrap().0,
+            Duration::from_secs(10)
+        );
+        assert_eq!(DbTimeout::default().to_string(), "5s");
+        for invalid in ["", "0s", "10", "fast"] {
+            assert!(
+                matches!(
+                    invalid.parse::<DbTimeout>(),
+                    Err(QuartoError::InvalidTimeout(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+
+        let policy = DbPolicy::default();
+        assert_eq!(policy.delay(1, 0.0), Duration::from_millis(50));
+        assert_eq!(policy.delay(3, 0.0), Duration::from_millis(200));
+        assert!(policy.delay(3, 0.999) < Duration::from_millis(250));
+        assert!(is_transient(&SqlxError::PoolTimedOut));
+        assert!(!is_transient(&SqlxError::RowNotFound));
+        assert!(!is_transient(&QuartoError::DuplicateGame("x".to_string())));
+    }
+
+    #[test]
+    fn test_read_only_urls() {
+        assert!(opens_read_only("sqlite:games.db?mode=ro"));
+        assert!(opens_read_only("sqlite://games.db?cache=shared&mode=ro"));
+        assert!(!opens_read_only("sqlite:games.db?mode=rwc"));
+        assert!(!opens_read_only("sqlite:mode=ro.db"));
+        assert_eq!(read_only_url("sqlite:games.db"), "sqlite:games.db?mode=ro");
+        assert_eq!(
+            read_only_url("sqlite:games.db?cache=shared"),
+            "sqlite:games.db?cache=shared&mode=ro"
+        );
+        assert_eq!(
+            read_only_url("sqlite:games.db?mode=ro"),
+            "sqlite:games.db?mode=ro"
+        );
+        assert!(is_writable("sqlite::memory:"));
+        assert!(is_writable("sqlite:///nonexistent/games.db"));
+    }
+
+    #[tokio::test]
+    async fn test_slow_store_times_out_with_the_operation() {
+        let slow = store(Duration::from_secs(10), vec![Ok(1)]);
+        let e = policy().run("load game", || slow.load()).await.unwrap_err();
+        assert!(
+            matches!(
+                e.downcast_ref::<QuartoError>(),
+                Some(QuartoError::Timeout(operation)) if operation == "load game"
+            ),
+            "{:?}",
+            e
+        );
+        // Timeouts are not retried
+        assert_eq!(slow.calls.get(), 1);
+
+        let e = policy().once("insert game", slow.load()).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<QuartoError>(),
+            Some(QuartoError::Timeout(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_busy_is_retried_and_other_errors_are_not() {
+        let busy = store(
+            Duration::ZERO,
+            vec![
+                Err(SqlxError::PoolTimedOut),
+                Err(SqlxError::PoolTimedOut),
+                Ok(7),
+            ],
+        );
+        assert_eq!(policy().run("load game", || busy.load()).await.unwrap(), 7);
+        assert_eq!(busy.calls.get(), 3);
+
+        // Retries are bounded
+        let stuck = store(Duration::ZERO, vec![Err(SqlxError::PoolTimedOut)]);
+        let e = policy()
+            .run("load game", || stuck.load())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<SqlxError>(),
+            Some(SqlxError::PoolTimedOut)
+        ));
+        assert_eq!(stuck.calls.get(), 3);
+
+        let missing = store(Duration::ZERO, vec![Err(SqlxError::RowNotFound)]);
+        assert!(policy().run("load game", || missing.load()).await.is_err());
+        assert_eq!(missing.calls.get(), 1);
+    }
+}
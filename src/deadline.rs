@@ -0,0 +1,263 @@
+use crate::clock::{format_amount, parse_amount, seat_name, seat_to_move};
+use crate::compat;
+use crate::event::{self, Event};
+use crate::game_id;
+use crate::quarto::{Quarto, QuartoError, Rules};
+use sqlx::sqlite::SqliteQueryResult;
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite, SqliteConnection};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/* Correspondence limit for each move, written like 72h or 1h30m.
+   Unlike a clock it does not carry over: every move gets the full limit.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deadline(pub Duration);
+
+impl FromStr for Deadline {
+    type Err = QuartoError;
+    fn from_str(s: &str) -> Result<Deadline, QuartoError> {
+        match parse_amount(s) {
+            Some(limit) if !limit.is_zero() => Ok(Deadline(limit)),
+            _ => Err(QuartoError::InvalidDeadline(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Deadline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&format_amount(self.0))
+    }
+}
+
+/* due_at is milliseconds since the Unix epoch */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MoveDeadline {
+    pub deadline: Deadline,
+    pub due_at: i64,
+    pub forfeited: Option<usize>,
+    pub reason: Option<String>,
+}
+
+/* Pass the transaction which creates the game */
+pub async fn start(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    deadline: Deadline,
+    now: i64,
+) -> Result<SqliteQueryResult, SqlxError> {
+    sqlx::query("UPDATE game SET deadline_secs = ?2, due_at = ?3 WHERE uuid = ?1;")
+        .bind(uuid)
+        .bind(deadline.0.as_secs() as i64)
+        .bind(now + deadline.0.as_millis() as i64)
+        .execute(conn)
+        .await
+}
+
+/* The player to move gets a fresh limit; games without a deadline are left alone.
+   Pass the transaction which saves the move.
+*/
+pub async fn renew(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    now: i64,
+) -> Result<SqliteQueryResult, SqlxError> {
+    sqlx::query(
+        r#"
+        UPDATE game SET due_at = ?2 + deadline_secs * 1000
+        WHERE uuid = ?1 AND deadline_secs IS NOT NULL;
+        "#,
+    )
+    .bind(uuid)
+    .bind(now)
+    .execute(conn)
+    .await
+}
+
+/* A finished game waits on nobody, so a later sweep cannot forfeit it. Pass the
+   transaction which finishes the game.
+*/
+pub async fn stop(conn: &mut SqliteConnection, uuid: &str) -> Result<SqliteQueryResult, SqlxError> {
+    sqlx::query("UPDATE game SET due_at = NULL WHERE uuid = ?1;")
+        .bind(uuid)
+        .execute(conn)
+        .await
+}
+
+pub async fn load(db: &Pool<Sqlite>, uuid: &str) -> Result<Option<MoveDeadline>, SqlxError> {
+    let row = sqlx::query_as::<_, (Option<i64>, Option<i64>, Option<i64>, Option<String>)>(
+        r#"
+        SELECT deadline_secs, due_at, forfeited, forfeit_reason
+        FROM game
+        WHERE uuid = ?1
+        "#,
+    )
+    .bind(uuid)
+    .fetch_optional(db)
+    .await?;
+    match row {
+        Some((Some(secs), Some(due_at), forfeited, reason)) => Ok(Some(MoveDeadline {
+            deadline: Deadline(Duration::from_secs(secs as u64)),
+            due_at,
+            forfeited: forfeited.map(|seat| seat as usize - 1),
+            reason,
+        })),
+        _ => Ok(None),
+    }
+}
+
+/* Forfeits every in-progress game whose player to move is past due.
+   Each update re-checks that the game is still unforfeited, so overlapping sweeps
+   forfeit a game once. Returns the games forfeited by this sweep and by whom.
+*/
+pub async fn sweep(db: &Pool<Sqlite>, now: i64) -> Result<Vec<(String, usize)>, Box<dyn Error>> {
+    let mut tx = db.begin().await?;
+    let overdue = sqlx::query_as::<_, (String, String, Option<i64>)>(
+        r#"
+        SELECT uuid, board_state, board_format
+        FROM game
+        WHERE due_at <= ?1 AND forfeited IS NULL AND flagged IS NULL AND board_state IS NOT NULL
+        ORDER BY due_at
+        "#,
+    )
+    .bind(now)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut forfeited = Vec::new();
+    for (uuid, board_state, board_format) in overdue {
+        let board = compat::read_board(board_format, &board_state);
+        let quarto = match board.and_then(|board| Quarto::from_parts(board, None, Rules::default()))
+        {
+            Ok(quarto) => quarto,
+            Err(e) => {
+                warn!(uuid = %game_id::logged(&uuid), ?e, "skipping unreadable game");
+                continue;
+            }
+        };
+        if quarto.is_quarto() {
+            continue;
+        }
+        let seat = seat_to_move(&quarto);
+        let reason = format!("{} did not move before the deadline", seat_name(seat));
+        let result = sqlx::query(
+            r#"
+            UPDATE game SET forfeited = ?2, forfeit_reason = ?3
+            WHERE uuid = ?1 AND forfeited IS NULL;
+            "#,
+        )
+        .bind(&uuid)
+        .bind(seat as i64 + 1)
+        .bind(&reason)
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 1 {
+            let event = Event::Forfeited { seat, reason };
+            event::append(&mut tx, &uuid, &event, now).await?;
+            info!(uuid = %game_id::logged(&uuid), seat = seat_name(seat), "forfeited");
+            forfeited.push((uuid, seat));
+        }
+    }
+    tx.commit().await?;
+    Ok(forfeited)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory_db;
+    use crate::quarto::Piece;
+
+    async fn insert(db: &Pool<Sqlite>, uuid: &str, quarto: &Quarto) {
+        let board_state: String = quarto.board_state.clone().into();
+        sqlx::query("INSERT INTO game (uuid, board_state) VALUES (?1, ?2);")
+            .bind(uuid)
+            .bind(board_state)
+            .execute(db)
+            .await
+            .unwrap();
+    }
+
+    fn piece(code: &str) -> Piece {
+        Piece::try_from(code.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_deadline() {
+        assert_eq!(
+            "72h".parse::<Deadline>().unwrap(),
+            Deadline(Duration::from_secs(72 * 3600))
+        );
+        assert_eq!("90m".parse::<Deadline>().unwrap().to_string(), "90m");
+        for invalid in ["", "0h", "72", "3d", "1h+5s"] {
+            assert!(invalid.parse::<Deadline>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sweep_forfeits_overdue_games_once() {
+        let db = memory_db().await;
+        let hour: Deadline = "1h".parse().unwrap();
+
+        let mut opening = Quarto::new();
+        opening.pick_piece(&piece("BSCF"));
+        insert(&db, "overdue", &opening).await;
+        start(&mut db.acquire().await.unwrap(), "overdue", hour, 0)
+            .await
+            .unwrap();
+
+        let mut later = opening.clone();
+        later.move_piece(0, 0);
+        later.pick_piece(&piece("WSCF"));
+        insert(&db, "renewed", &later).await;
+        start(&mut db.acquire().await.unwrap(), "renewed", hour, 0)
+            .await
+            .unwrap();
+        renew(&mut db.acquire().await.unwrap(), "renewed", 3_000_000)
+            .await
+            .unwrap();
+
+        // Already decided on the board
+        let mut won = Quarto::new();
+        for (y, code) in ["BSCF", "BSCH", "BSSF", "BTSH"].iter().enumerate() {
+            won.pick_piece(&piece(code));
+            won.move_piece(0, y);
+        }
+        insert(&db, "won", &won).await;
+        start(&mut db.acquire().await.unwrap(), "won", hour, 0)
+            .await
+            .unwrap();
+
+        insert(&db, "untimed", &opening).await;
+
+        let now = 3_600_000;
+        assert_eq!(
+            sweep(&db, now).await.unwrap(),
+            vec![("overdue".to_string(), 1)]
+        );
+        assert!(sweep(&db, now).await.unwrap().is_empty());
+        let events = event::load(&db, "overdue", 0).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(events[0].event, Event::Forfeited { seat: 1, .. }));
+
+        let overdue = load(&db, "overdue").await.unwrap().unwrap();
+        assert_eq!(overdue.forfeited, Some(1));
+        assert_eq!(
+            overdue.reason.as_deref(),
+            Some("2nd did not move before the deadline")
+        );
+        let renewed = load(&db, "renewed").await.unwrap().unwrap();
+        assert_eq!((renewed.due_at, renewed.forfeited), (6_600_000, None));
+        assert_eq!(load(&db, "untimed").await.unwrap(), None);
+
+        // The renewed game runs out later, with the 1st player to move
+        assert_eq!(
+            sweep(&db, 6_600_000).await.unwrap(),
+            vec![("renewed".to_string(), 0)]
+        );
+    }
+}
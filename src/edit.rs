@@ -0,0 +1,301 @@
+use crate::clock::{seat_name, seat_to_move};
+use crate::file_store;
+use crate::intersperse::intersperse;
+use crate::notation;
+use crate::play::{self, Store};
+use crate::quarto::{Coord, Piece, Quarto};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+/* Composing positions by hand. Pieces go straight onto the board with
+   place_arbitrary, so turn order is not enforced while editing, but every piece is
+   still used once. Whether the result could come up in a game is checked on save.
+*/
+pub struct Editor {
+    pub quarto: Quarto,
+    /* Seat to move as set with `turn`, 0 for the 1st player */
+    pub turn: Option<usize>,
+}
+
+const HELP: &str = "commands: put <piece> <cell>, remove <cell>, hand <piece|none>, clear, \
+                    turn <1|2>, show, save --as-board <file>, save --as-game, help, quit";
+
+fn parse_cell(name: &str) -> Result<Coord, String> {
+    name.parse().map_err(|_| format!("invalid cell: {}", name))
+}
+
+/* A code, or words such as white tall circle hole */
+fn parse_piece(words: &[&str]) -> Result<Piece, String> {
+    notation::piece_argument(&words.join(" "))
+        .map_err(|reason| format!("invalid piece: {}", reason))
+}
+
+impl Editor {
+    pub fn new(quarto: Quarto) -> Editor {
+        Editor { quarto, turn: None }
+    }
+
+    pub fn put(&mut self, piece: Piece, at: Coord) -> Result<(), String> {
+        let code = notation::current().piece(&piece);
+        let (x, y) = at.xy();
+        if self.quarto.board_state.cells()[x][y].is_some() {
+            return Err(format!("cell is taken: {}", at));
+        }
+        if self.quarto.next_piece == Some(piece) {
+            return Err(format!("piece is in hand: {}", code));
+        }
+        if !self.quarto.place_arbitrary(&piece, x, y) {
+            return Err(format!("piece is already on the board: {}", code));
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, at: Coord) -> Result<Piece, String> {
+        let (x, y) = at.xy();
+        self.quarto
+            .remove_arbitrary(x, y)
+            .ok_or_else(|| "cell is empty".to_string())
+    }
+
+    pub fn hand(&mut self, piece: Option<Piece>) -> Result<(), String> {
+        if !self.quarto.set_hand(piece) {
+            return Err(format!(
+                "piece is already on the board: {}",
+                notation::current().hand(piece)
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.quarto = Quarto::with_rules(self.quarto.rules);
+        self.turn = None;
+    }
+
+    /* Whether the position could come up in a game */
+    pub fn validate(&self) -> Result<(), String> {
+        let quarto = &self.quarto;
+        quarto
+            .validate()
+            .map_err(|violations| intersperse(&violations, "; ").to_string())?;
+        let won = quarto.is_quarto();
+        let placed = quarto.placed_pieces();
+        if won && quarto.next_piece.is_some() {
+            return Err("a won position has no piece in hand".to_string());
+        }
+        if !won && placed < 16 && quarto.next_piece.is_none() {
+            return Err("a position in progress needs a piece in hand".to_string());
+        }
+        // The game ends at the first quarto, so every winning line holds the last piece
+        let lines = quarto.winning_lines();
+        let mut shared: HashSet<(usize, usize)> =
+            lines.first().into_iter().flatten().copied().collect();
+        for line in lines.iter().skip(1) {
+            shared.retain(|cell| line.contains(cell));
+        }
+        if won && shared.is_empty() {
+            return Err("the winning lines share no cell, so the game ended earlier".to_string());
+        }
+        if let Some(seat) = self.turn {
+            if seat != seat_to_move(quarto) {
+                let parity = if seat == 0 { "an odd" } else { "an even" };
+                return Err(format!(
+                    "{} to move needs {} number of pieces placed",
+                    seat_name(seat),
+                    parity
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn prompt(&self) -> String {
+        format!(
+            "edit {} placed, {}> ",
+            self.quarto.placed_pieces(),
+            play::turn(&self.quarto)
+        )
+    }
+}
+
+/* Reads editor commands until `quit` or end of input. Nothing is written unless
+   the position passes validate.
+*/
+pub fn run<R: BufRead, W: Write>(
+    editor: &mut Editor,
+    store: &mut dyn Store,
+    input: R,
+    mut output: W,
+) -> io::Result<()> {
+    let mut lines = input.lines();
+    loop {
+        write!(output, "{}", editor.prompt())?;
+        output.flush()?;
+        let Some(line) = lines.next() else {
+            writeln!(output)?;
+            break;
+        };
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words.as_slice() {
+            [] => Ok(()),
+            ["put", piece @ .., cell] if !piece.is_empty() => parse_piece(piece)
+                .and_then(|piece| Ok((piece, parse_cell(cell)?)))
+                .and_then(|(piece, at)| editor.put(piece, at)),
+            ["remove", cell] => parse_cell(cell)
+                .and_then(|at| editor.remove(at))
+                .map(|_| ()),
+            ["hand", "none"] => editor.hand(None),
+            ["hand", piece @ ..] if !piece.is_empty() => {
+                parse_piece(piece).and_then(|piece| editor.hand(Some(piece)))
+            }
+            ["clear"] => {
+                editor.clear();
+                Ok(())
+            }
+            ["turn", player @ ("1" | "2")] => {
+                editor.turn = Some(if *player == "1" { 0 } else { 1 });
+                Ok(())
+            }
+            ["show"] => Ok(()),
+            ["save", "--as-board", path] => match editor.validate() {
+                Ok(()) => match fs::write(path, file_store::encode(&editor.quarto)) {
+                    Ok(()) => {
+                        writeln!(output, "saved {}", path)?;
+                        continue;
+                    }
+                    Err(e) => Err(format!("cannot save: {}", e)),
+                },
+                Err(e) => Err(format!("cannot save: {}", e)),
+            },
+            ["save", "--as-game"] => match editor.validate() {
+                Ok(()) if editor.quarto.next_piece.is_none() => {
+                    Err("cannot save: a finished position is not a game to play".to_string())
+                }
+                Ok(()) => match store.create_from(&editor.quarto) {
+                    Ok(game) => {
+                        writeln!(output, "{}", game)?;
+                        continue;
+                    }
+                    Err(e) => Err(format!("cannot save: {}", e)),
+                },
+                Err(e) => Err(format!("cannot save: {}", e)),
+            },
+            ["help"] => {
+                writeln!(output, "{}", HELP)?;
+                continue;
+            }
+            ["quit"] | ["exit"] => break,
+            _ => Err(format!("unknown command: {}", line.trim())),
+        };
+        match result {
+            Ok(()) => play::show(&mut output, &editor.quarto)?,
+            Err(e) => writeln!(output, "{}", e)?,
+        }
+    }
+    output.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file_store::test::TempDir;
+    use crate::play::test::MemoryStore;
+
+    fn drive(editor: &mut Editor, store: &mut MemoryStore, input: &str) -> String {
+        let mut output = Vec::new();
+        run(editor, store, input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_editor_composes_and_saves() {
+        let dir = TempDir::new();
+        let board = dir.0.join("puzzle.qrt");
+        let mut store = MemoryStore::default();
+        let mut editor = Editor::new(Quarto::new());
+        let output = drive(
+            &mut editor,
+            &mut store,
+            &format!(
+                "put BSCF a1\nput BSCH b2\nput WTCH d4\nremove d4\nhand WTSH\nturn 1\n\
+                 save --as-game\nturn 2\nsave --as-game\nsave --as-board {}\n",
+                board.display()
+            ),
+        );
+        assert!(
+            output.contains("cannot save: 1st to move needs an odd number of pieces placed"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("edit 2 placed, 2nd to move> "),
+            "{}",
+            output
+        );
+        assert_eq!(store.games.len(), 1);
+        let saved = &store.games[0].1;
+        assert_eq!(saved, &editor.quarto);
+        assert_eq!(saved.placed_pieces(), 2);
+        assert_eq!(saved.next_piece.map(String::from).as_deref(), Some("WTSH"));
+
+        let text = fs::read_to_string(&board).unwrap();
+        assert_eq!(file_store::decode(&text).unwrap(), editor.quarto);
+        // WTCH went back among the free pieces when it was removed
+        assert!(editor
+            .put(parse_piece(&["WTCH"]).unwrap(), parse_cell("d4").unwrap())
+            .is_ok());
+
+        // Editing a loaded board and starting over
+        let mut reopened = Editor::new(file_store::decode(&text).unwrap());
+        let output = drive(&mut reopened, &mut store, "clear\nshow\n");
+        assert!(
+            output.contains("edit 0 placed, 2nd to move> "),
+            "{}",
+            output
+        );
+        assert_eq!(reopened.quarto.placed_pieces(), 0);
+    }
+
+    #[test]
+    fn test_editor_rejects_illegal_compositions() {
+        let mut store = MemoryStore::default();
+        let mut editor = Editor::new(Quarto::new());
+        let output = drive(
+            &mut editor,
+            &mut store,
+            "put BSCF a1\nput BSCF b1\nput WSCF a1\nput WSCF e5\nput XXXX b1\nremove c3\n\
+             hand BSCF\nsave --as-game\n",
+        );
+        for rejection in [
+            "piece is already on the board: BSCF",
+            "cell is taken: a1",
+            "invalid cell: e5",
+            "invalid piece: XXXX",
+            "cell is empty",
+            "cannot save: a position in progress needs a piece in hand",
+        ] {
+            assert!(output.contains(rejection), "{}: {}", rejection, output);
+        }
+        assert!(store.games.is_empty());
+
+        // Brown along the first line and white along the last: two separate quartos
+        let mut editor = Editor::new(Quarto::new());
+        let output = drive(
+            &mut editor,
+            &mut store,
+            "put BSCF a1\nput BSCH b1\nput BSSF c1\nput BSSH d1\nsave --as-game\n\
+             put WSCF a4\nput WSCH b4\nput WSSF c4\nput WSSH d4\nhand WTCF\nsave --as-game\n\
+             hand none\nsave --as-game\n",
+        );
+        for rejection in [
+            "cannot save: a finished position is not a game to play",
+            "cannot save: a won position has no piece in hand",
+            "cannot save: the winning lines share no cell, so the game ended earlier",
+        ] {
+            assert!(output.contains(rejection), "{}: {}", rejection, output);
+        }
+        assert!(store.games.is_empty());
+    }
+}
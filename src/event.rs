@@ -0,0 +1,636 @@
+use crate::clock;
+use crate::game_id;
+use crate::index;
+use crate::quarto::{Move, Quarto, QuartoError};
+use crate::store;
+use serde::{Deserialize, Serialize};
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite, SqliteConnection};
+use std::error::Error;
+use tracing::{info, warn};
+
+/* What happened to a game, in the order it happened. Each event is appended in
+   the transaction that changes the game, numbered from 1 per game, and never
+   changed afterwards. The payload is the event as JSON, tagged with its kind:
+
+       {"kind":"move","ply":{"place":"a4","hand":"WSCF"}}
+
+   Moves recorded with the cell as an [x, y] pair still read. A move also keeps its
+   think time: how long since the board was last changed, which is when it became
+   the mover's turn, and the seat which handed its piece over. The board events
+   give that seat too; it is kept so queries over hand-offs need not replay games.
+
+   The board events must rebuild the stored board: start from the last created or
+   position event and play each later move the way `quarto move` does.
+*/
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    /* A new game, with its opening position as a share code */
+    Created { position: String },
+    Move { ply: Move },
+    /* The game was set to a position as a whole: a `play` commit, or the board
+       of a game recorded before events were
+    */
+    Position { position: String },
+    Forfeited { seat: usize, reason: String },
+    Resigned { seat: usize },
+    DrawOffered { seat: usize },
+    /* By the seat accepting the offer */
+    DrawAgreed { seat: usize },
+    AbortRequested { seat: usize },
+    /* By the seat aborting alone, or agreeing to the other's request */
+    Aborted { seat: usize },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub seq: i64,
+    pub event: Event,
+    pub created_at: i64,
+    /* Moves only, and not those recorded before think times were */
+    pub think_ms: Option<i64>,
+    /* Moves which hand a piece over; None for those recorded before the column was
+       and not yet backfilled
+    */
+    pub handed_by: Option<usize>,
+}
+
+impl Event {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Created { .. } => "created",
+            Event::Move { .. } => "move",
+            Event::Position { .. } => "position",
+            Event::Forfeited { .. } => "forfeited",
+            Event::Resigned { .. } => "resigned",
+            Event::DrawOffered { .. } => "draw_offered",
+            Event::DrawAgreed { .. } => "draw_agreed",
+            Event::AbortRequested { .. } => "abort_requested",
+            Event::Aborted { .. } => "aborted",
+        }
+    }
+}
+
+pub async fn init_events(db: &Pool<Sqlite>) -> Result<(), SqlxError> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS event
+        (
+              uuid VARCHAR NOT NULL,
+              seq INTEGER NOT NULL,
+              kind VARCHAR NOT NULL,
+              payload VARCHAR NOT NULL,
+              created_at INTEGER NOT NULL,
+              think_ms INTEGER,
+              handed_by INTEGER,
+              PRIMARY KEY (uuid, seq)
+        );"#,
+    )
+    .execute(db)
+    .await?;
+    let columns = sqlx::query_as::<_, (String,)>("SELECT name FROM pragma_table_info('event');")
+        .fetch_all(db)
+        .await?;
+    if !columns.iter().any(|(name,)| name == "think_ms") {
+        sqlx::query("ALTER TABLE event ADD COLUMN think_ms INTEGER;")
+            .execute(db)
+            .await?;
+        info!("added event.think_ms");
+    }
+    if !columns.iter().any(|(name,)| name == "handed_by") {
+        sqlx::query("ALTER TABLE event ADD COLUMN handed_by INTEGER;")
+            .execute(db)
+            .await?;
+        info!("added event.handed_by");
+    }
+    Ok(())
+}
+
+/* Gives every game without events a position event holding its stored board, so
+   each game's events rebuild it. Run by `init --force` on older databases.
+*/
+pub async fn backfill(db: &Pool<Sqlite>) -> Result<(), Box<dyn Error>> {
+    let mut tx = db.begin().await?;
+    let games = sqlx::query_as::<_, (String, Option<String>, Option<String>, bool, Option<i64>)>(
+        r#"
+        SELECT uuid, board_state, next_piece, advanced, board_format
+        FROM game
+        WHERE uuid IS NOT NULL AND uuid NOT IN (SELECT uuid FROM event)
+        ORDER BY id
+        "#,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let mut backfilled = 0;
+    for (uuid, board_state, next_piece, advanced, board_format) in games {
+        let quarto = match store::from_row(&board_state, &next_piece, advanced, board_format) {
+            Ok(quarto) => quarto,
+            Err(e) => {
+                warn!(uuid = %game_id::logged(&uuid), ?e, "skipping unreadable game");
+                continue;
+            }
+        };
+        let position = Event::Position {
+            position: quarto.to_share_code(),
+        };
+        append(&mut tx, &uuid, &position, 0).await?;
+        backfilled += 1;
+    }
+    tx.commit().await?;
+    if backfilled > 0 {
+        info!(backfilled, "recorded the boards of games without events");
+    }
+    Ok(())
+}
+
+/* Gives moves recorded before event.handed_by the seat which handed their piece,
+   replaying each game's board events. Offers, position events and the like leave
+   seq out of step with the seats, so its parity alone would not do.
+   Run by `init --force`; games whose events cannot be replayed are left as they are.
+*/
+pub async fn backfill_handed_by(db: &Pool<Sqlite>) -> Result<(), SqlxError> {
+    let mut tx = db.begin().await?;
+    let games = sqlx::query_as::<_, (String,)>(
+        r#"
+        SELECT DISTINCT uuid
+        FROM event
+        WHERE kind = 'move' AND handed_by IS NULL
+        ORDER BY uuid
+        "#,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let mut backfilled = 0;
+    for (uuid,) in games {
+        let records = load_on(&mut tx, &uuid, 0).await?;
+        let seats = match hand_seats(&records) {
+            Ok(seats) => seats,
+            Err(e) => {
+                warn!(uuid = %game_id::logged(&uuid), ?e, "skipping unreplayable game");
+                continue;
+            }
+        };
+        for (record, seat) in records.iter().zip(seats) {
+            if let (None, Some(seat)) = (record.handed_by, seat) {
+                sqlx::query("UPDATE event SET handed_by = ?3 WHERE uuid = ?1 AND seq = ?2;")
+                    .bind(&uuid)
+                    .bind(record.seq)
+                    .bind(seat as i64)
+                    .execute(&mut *tx)
+                    .await?;
+                backfilled += 1;
+            }
+        }
+    }
+    tx.commit().await?;
+    if backfilled > 0 {
+        info!(backfilled, "recorded who handed each piece");
+    }
+    Ok(())
+}
+
+/* Appends `event` as the game's next one and returns its number. Pass the
+   transaction that changes the game, so both are written or neither is. The
+   position index follows the board in the same transaction. A move gets its
+   think time from the event before it which changed the board, and the seat
+   handing its piece from the board those events leave.
+*/
+pub async fn append(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    event: &Event,
+    now: i64,
+) -> Result<i64, Box<dyn Error>> {
+    let payload =
+        serde_json::to_string(event).map_err(|e| QuartoError::InvalidEvent(e.to_string()))?;
+    let think_ms = match event {
+        Event::Move { .. } => think_time(conn, uuid, now).await?,
+        _ => None,
+    };
+    let handed_by = match event {
+        Event::Move { ply } if ply.hand.is_some() => {
+            let records = load_on(conn, uuid, 0).await?;
+            match replay(&records) {
+                Ok(quarto) => quarto.map(|quarto| clock::seat_to_move(&quarto) as i64),
+                Err(e) => {
+                    warn!(uuid = %game_id::logged(&uuid), ?e, "cannot tell who handed the piece");
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    let (seq,) = sqlx::query_as::<_, (i64,)>(
+        r#"
+        INSERT INTO event (uuid, seq, kind, payload, created_at, think_ms, handed_by)
+        SELECT ?1, COALESCE(MAX(seq), 0) + 1, ?2, ?3, ?4, ?5, ?6 FROM event WHERE uuid = ?1
+        RETURNING seq
+        "#,
+    )
+    .bind(uuid)
+    .bind(event.kind())
+    .bind(payload)
+    .bind(now)
+    .bind(think_ms)
+    .bind(handed_by)
+    .fetch_one(&mut *conn)
+    .await?;
+    index::follow(conn, uuid, event).await?;
+    Ok(seq)
+}
+
+/* How long the player to move has had at `now`: since the last event which
+   changed the board, or None before the game was created
+*/
+pub async fn think_time(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    now: i64,
+) -> Result<Option<i64>, SqlxError> {
+    let (since,) = sqlx::query_as::<_, (Option<i64>,)>(
+        r#"
+        SELECT MAX(created_at)
+        FROM event
+        WHERE uuid = ?1 AND kind IN ('created', 'move', 'position')
+        "#,
+    )
+    .bind(uuid)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(since.map(|since| (now - since).max(0)))
+}
+
+/* The game's events after number `after`, oldest first; 0 gives them all */
+pub async fn load(db: &Pool<Sqlite>, uuid: &str, after: i64) -> Result<Vec<Record>, SqlxError> {
+    load_on(&mut *db.acquire().await?, uuid, after).await
+}
+
+/* As load, inside a transaction */
+async fn load_on(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    after: i64,
+) -> Result<Vec<Record>, SqlxError> {
+    let rows = sqlx::query_as::<_, (i64, String, i64, Option<i64>, Option<i64>)>(
+        r#"
+        SELECT seq, payload, created_at, think_ms, handed_by
+        FROM event
+        WHERE uuid = ?1 AND seq > ?2
+        ORDER BY seq
+        "#,
+    )
+    .bind(uuid)
+    .bind(after)
+    .fetch_all(&mut *conn)
+    .await?;
+    rows.into_iter()
+        .map(|(seq, payload, created_at, think_ms, handed_by)| {
+            record(seq, &payload, created_at, think_ms, handed_by)
+        })
+        .collect()
+}
+
+/* Games with events, in the order they were created */
+pub async fn games(db: &Pool<Sqlite>) -> Result<Vec<String>, SqlxError> {
+    let rows = sqlx::query_as::<_, (String,)>(
+        r#"
+        SELECT uuid
+        FROM game
+        WHERE uuid IN (SELECT uuid FROM event)
+        ORDER BY id
+        "#,
+    )
+    .fetch_all(db)
+    .await?;
+    Ok(rows.into_iter().map(|(uuid,)| uuid).collect())
+}
+
+/* An event row as read back */
+pub fn record(
+    seq: i64,
+    payload: &str,
+    created_at: i64,
+    think_ms: Option<i64>,
+    handed_by: Option<i64>,
+) -> Result<Record, SqlxError> {
+    let event = serde_json::from_str(payload).map_err(|e| SqlxError::Decode(e.into()))?;
+    Ok(Record {
+        seq,
+        event,
+        created_at,
+        think_ms,
+        handed_by: handed_by.map(|seat| seat as usize),
+    })
+}
+
+/* The board the events leave, or None when none of them sets one */
+pub fn replay(records: &[Record]) -> Result<Option<Quarto>, QuartoError> {
+    let mut quarto: Option<Quarto> = None;
+    for record in records {
+        match &record.event {
+            Event::Created { position } | Event::Position { position } => {
+                quarto = Some(Quarto::from_share_code(position)?);
+            }
+            Event::Move { ply } => {
+                let Some(quarto) = quarto.as_mut() else {
+                    return Err(QuartoError::CorruptRecord(format!(
+                        "event {}: move before the game was created",
+                        record.seq
+                    )));
+                };
+                let placed = match ply.place {
+                    Some(at) => {
+                        let (x, y) = at.xy();
+                        quarto.move_piece(x, y)
+                    }
+                    None => true,
+                };
+                let handed = match ply.hand {
+                    Some(p) => placed && quarto.pick_piece(&p),
+                    None => placed,
+                };
+                if !handed {
+                    return Err(QuartoError::CorruptRecord(format!(
+                        "event {}: illegal move {}",
+                        record.seq, ply
+                    )));
+                }
+            }
+            Event::Forfeited { .. }
+            | Event::Resigned { .. }
+            | Event::DrawOffered { .. }
+            | Event::DrawAgreed { .. }
+            | Event::AbortRequested { .. }
+            | Event::Aborted { .. } => {}
+        }
+    }
+    Ok(quarto)
+}
+
+/* The seat handing over each record's piece, which is the seat to move before it.
+   None for events other than moves and for moves which hand nothing.
+*/
+pub fn hand_seats(records: &[Record]) -> Result<Vec<Option<usize>>, QuartoError> {
+    let mut quarto: Option<Quarto> = None;
+    let mut seats = Vec::with_capacity(records.len());
+    for record in records {
+        let seat = match &record.event {
+            Event::Created { position } | Event::Position { position } => {
+                quarto = Some(Quarto::from_share_code(position)?);
+                None
+            }
+            Event::Move { ply } => {
+                let Some(quarto) = quarto.as_mut() else {
+                    return Err(QuartoError::CorruptRecord(format!(
+                        "event {}: move before the game was created",
+                        record.seq
+                    )));
+                };
+                let seat = ply.hand.map(|_| clock::seat_to_move(quarto));
+                if !quarto.apply_move(ply) {
+                    return Err(QuartoError::CorruptRecord(format!(
+                        "event {}: illegal move {}",
+                        record.seq, ply
+                    )));
+                }
+                seat
+            }
+            _ => None,
+        };
+        seats.push(seat);
+    }
+    Ok(seats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory_db;
+    use crate::quarto::Piece;
+
+    fn piece(code: &str) -> Piece {
+        Piece::try_from(code.to_string()).unwrap()
+    }
+
+    fn ply(text: &str) -> Event {
+        Event::Move {
+            ply: text.parse().unwrap(),
+        }
+    }
+
+    fn records(events: Vec<Event>) -> Vec<Record> {
+        events
+            .into_iter()
+            .zip(1..)
+            .map(|(event, seq)| Record {
+                seq,
+                event,
+                created_at: 0,
+                think_ms: None,
+                handed_by: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_replay_rebuilds_the_board() {
+        let mut opening = Quarto::new();
+        opening.pick_piece(&piece("BSCF"));
+        let created = Event::Created {
+            position: opening.to_share_code(),
+        };
+        let mut expected = opening.clone();
+        expected.move_piece(0, 0);
+        expected.pick_piece(&piece("WSCF"));
+        expected.move_piece(1, 1);
+        expected.pick_piece(&piece("BTCF"));
+
+        let log = records(vec![
+            created.clone(),
+            ply("0 0 WSCF"),
+            Event::Forfeited {
+                seat: 1,
+                reason: "late".to_string(),
+            },
+            ply("1 1 BTCF"),
+        ]);
+        assert_eq!(replay(&log).unwrap(), Some(expected.clone()));
+
+        // A position event replaces everything before it
+        let mut later = expected.clone();
+        later.move_piece(2, 2);
+        later.pick_piece(&piece("WTCH"));
+        let mut log = log;
+        log.extend(records(vec![Event::Position {
+            position: later.to_share_code(),
+        }]));
+        assert_eq!(replay(&log).unwrap(), Some(later));
+
+        assert_eq!(replay(&[]).unwrap(), None);
+        for broken in [
+            vec![ply("0 0 WSCF")],
+            vec![created.clone(), ply("0 0 BSCF")],
+            vec![created, ply("0 0 WSCF"), ply("0 0 BTCF")],
+        ] {
+            assert!(matches!(
+                replay(&records(broken)),
+                Err(QuartoError::CorruptRecord(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_events_are_numbered_per_game_and_resume_after_a_seq() {
+        let db = memory_db().await;
+        init_events(&db).await.unwrap();
+        let mut tx = db.begin().await.unwrap();
+        let created = Event::Created {
+            position: Quarto::new().to_share_code(),
+        };
+        assert_eq!(append(&mut tx, "a", &created, 1).await.unwrap(), 1);
+        assert_eq!(append(&mut tx, "b", &created, 2).await.unwrap(), 1);
+        assert_eq!(append(&mut tx, "a", &ply("0 0 WSCF"), 3).await.unwrap(), 2);
+        assert_eq!(append(&mut tx, "a", &ply("1 1 BTCF"), 4).await.unwrap(), 3);
+        tx.commit().await.unwrap();
+
+        let all = load(&db, "a", 0).await.unwrap();
+        assert_eq!(all.iter().map(|r| r.seq).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(all[1].event, ply("0 0 WSCF"));
+        assert_eq!(all[1].created_at, 3);
+        // Resuming after the last event seen gives only what came later
+        assert_eq!(load(&db, "a", 2).await.unwrap(), all[2..].to_vec());
+        assert!(load(&db, "a", 3).await.unwrap().is_empty());
+
+        // Nothing is kept from a transaction that is rolled back
+        let mut tx = db.begin().await.unwrap();
+        append(&mut tx, "a", &ply("2 2 WTCH"), 5).await.unwrap();
+        tx.rollback().await.unwrap();
+        assert_eq!(load(&db, "a", 0).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_moves_keep_their_think_time() {
+        let db = memory_db().await;
+        sqlx::query("DROP TABLE event;").execute(&db).await.unwrap();
+        sqlx::query(
+            r#"
+            CREATE TABLE event
+            (
+                  uuid VARCHAR NOT NULL,
+                  seq INTEGER NOT NULL,
+                  kind VARCHAR NOT NULL,
+                  payload VARCHAR NOT NULL,
+                  created_at INTEGER NOT NULL,
+                  PRIMARY KEY (uuid, seq)
+            );"#,
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        // An event table from before think times gets the column
+        init_events(&db).await.unwrap();
+        init_events(&db).await.unwrap();
+
+        let mut tx = db.begin().await.unwrap();
+        assert_eq!(think_time(&mut tx, "a", 500).await.unwrap(), None);
+        let created = Event::Created {
+            position: Quarto::new().to_share_code(),
+        };
+        append(&mut tx, "a", &created, 1_000).await.unwrap();
+        append(&mut tx, "a", &ply("0 0 WSCF"), 8_000).await.unwrap();
+        // Offering a draw does not change whose turn it is or since when
+        append(&mut tx, "a", &Event::DrawOffered { seat: 1 }, 9_000)
+            .await
+            .unwrap();
+        append(&mut tx, "a", &ply("1 1 BTCF"), 10_500)
+            .await
+            .unwrap();
+        assert_eq!(think_time(&mut tx, "a", 12_000).await.unwrap(), Some(1_500));
+        tx.commit().await.unwrap();
+
+        let thinks: Vec<_> = load(&db, "a", 0)
+            .await
+            .unwrap()
+            .iter()
+            .map(|record| record.think_ms)
+            .collect();
+        assert_eq!(thinks, vec![None, Some(7_000), None, Some(2_500)]);
+    }
+
+    #[tokio::test]
+    async fn test_games_without_events_are_backfilled_once() {
+        let db = memory_db().await;
+        let mut quarto = Quarto::new();
+        quarto.pick_piece(&piece("BSCF"));
+        quarto.move_piece(3, 3);
+        quarto.pick_piece(&piece("WTCH"));
+        let board_state: String = quarto.board_state.clone().into();
+        sqlx::query("INSERT INTO game (uuid, next_piece, board_state) VALUES (?1, ?2, ?3);")
+            .bind("old")
+            .bind("WTCH")
+            .bind(board_state)
+            .execute(&db)
+            .await
+            .unwrap();
+
+        init_events(&db).await.unwrap();
+        backfill(&db).await.unwrap();
+        let events = load(&db, "old", 0).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(replay(&events).unwrap(), Some(quarto));
+
+        // Running it again adds nothing
+        backfill(&db).await.unwrap();
+        assert_eq!(load(&db, "old", 0).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_handed_by_matches_the_replayed_games() {
+        const FIXTURE: &str = include_str!("../tests/fixtures/three-short-games.sql");
+        const WON: &str = "a0000000-0000-4000-8000-000000000001";
+        const UNFINISHED: &str = "a0000000-0000-4000-8000-000000000003";
+        let db = memory_db().await;
+        // The fixture's tables stand in for the ones init created
+        for table in ["game", "event"] {
+            sqlx::query(&format!("DROP TABLE {};", table))
+                .execute(&db)
+                .await
+                .unwrap();
+        }
+        for statement in FIXTURE.split(";\n").filter(|s| !s.trim().is_empty()) {
+            sqlx::query(statement).execute(&db).await.unwrap();
+        }
+        // Events from before the column have no seat until backfilled
+        init_events(&db).await.unwrap();
+        let won = load(&db, WON, 0).await.unwrap();
+        assert!(won.iter().all(|record| record.handed_by.is_none()));
+        backfill_handed_by(&db).await.unwrap();
+
+        for uuid in games(&db).await.unwrap() {
+            let records = load(&db, &uuid, 0).await.unwrap();
+            let recorded: Vec<_> = records.iter().map(|record| record.handed_by).collect();
+            assert_eq!(recorded, hand_seats(&records).unwrap(), "{}", uuid);
+        }
+        // The 2nd player moves first from a piece in hand; the winning move hands nothing
+        let won = load(&db, WON, 0).await.unwrap();
+        let seats: Vec<_> = won.iter().map(|record| record.handed_by).collect();
+        assert_eq!(seats, vec![None, Some(1), Some(0), Some(1), None]);
+
+        // New moves record it as they are appended
+        let mut tx = db.begin().await.unwrap();
+        append(&mut tx, UNFINISHED, &ply("b2 BTCH"), 1_700_000_300_000)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        let records = load(&db, UNFINISHED, 0).await.unwrap();
+        assert_eq!(records.last().unwrap().handed_by, Some(1));
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.handed_by)
+                .collect::<Vec<_>>(),
+            hand_seats(&records).unwrap()
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use crate::clock::{seat_name, seat_to_move};
+use crate::event::{self, Event, Record};
+use crate::quarto::{self, Move, Piece, Quarto, QuartoError};
+use crate::replay;
+use crate::verify::Outcome;
+use clap::ValueEnum;
+use std::fmt::Write;
+
+/* `export` writes a game for printing. LaTeX is a standalone article which needs
+   nothing beyond TikZ: a header with the game, date, players, rules and the engine
+   version which wrote it, the final board, the moves in two columns and the result
+   line.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ExportFormat {
+    Latex,
+}
+
+/* Everything on a score sheet, gathered from a game's events */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoreSheet {
+    pub game: String,
+    /* Left blank to be filled in by hand when not given */
+    pub players: [Option<String>; 2],
+    /* When the game was created, in milliseconds since the epoch */
+    pub created_at: i64,
+    /* The moves since the board was last set, each with the position it was played in */
+    pub plies: Vec<(Quarto, Move)>,
+    pub last: Quarto,
+    pub result: String,
+    pub engine_version: String,
+}
+
+impl ScoreSheet {
+    pub fn of(
+        game: &str,
+        records: &[Record],
+        players: [Option<String>; 2],
+    ) -> Result<ScoreSheet, QuartoError> {
+        let Some(last) = event::replay(records)? else {
+            return Err(QuartoError::CorruptRecord(
+                "the game was never created".to_string(),
+            ));
+        };
+        Ok(ScoreSheet {
+            game: game.to_string(),
+            players,
+            created_at: records.first().map_or(0, |record| record.created_at),
+            plies: replay::plies(records)?,
+            result: result(records, &last),
+            last,
+            engine_version: quarto::engine_version(),
+        })
+    }
+
+    pub fn to_latex(&self) -> String {
+        let mut tex = String::new();
+        tex.push_str("\\documentclass{article}\n\\usepackage{tikz}\n\\pagestyle{empty}\n");
+        tex.push_str("\\begin{document}\n\\section*{Quarto score sheet}\n\n");
+
+        tex.push_str("\\begin{tabular}{@{}ll@{}}\n");
+        let _ = writeln!(tex, "Game: & \\texttt{{{}}} \\\\", self.game);
+        let _ = writeln!(tex, "Date: & {} \\\\", date(self.created_at));
+        for (seat, player) in self.players.iter().enumerate() {
+            let name = player
+                .as_deref()
+                .map_or("\\rule{5cm}{0.4pt}".to_string(), escape);
+            let _ = writeln!(tex, "{} player: & {} \\\\", seat_name(seat), name);
+        }
+        let _ = writeln!(tex, "Variant: & {} \\\\", self.last.rules.variant);
+        if let Some(piece) = self.plies.first().and_then(|(start, _)| start.next_piece) {
+            let _ = writeln!(tex, "Opening piece: & {} \\\\", String::from(piece));
+        }
+        let _ = writeln!(tex, "Engine: & \\texttt{{{}}} \\\\", self.engine_version);
+        tex.push_str("\\end{tabular}\n\n\\bigskip\n");
+
+        tex.push_str(&board(&self.last));
+        tex.push_str("\n\\bigskip\n");
+        tex.push_str(&transcript(&self.plies));
+        let _ = write!(
+            tex,
+            "\n\\bigskip\n\\noindent\\textbf{{Result:}} {}\n\\end{{document}}\n",
+            escape(&self.result)
+        );
+        tex
+    }
+}
+
+/* The last way the game ended, or what the board says */
+fn result(records: &[Record], last: &Quarto) -> String {
+    let ended = records.iter().rev().find_map(|record| match &record.event {
+        Event::Resigned { seat } => Some(format!(
+            "{} resigned; {} wins",
+            seat_name(*seat),
+            seat_name(1 - seat)
+        )),
+        Event::Forfeited { seat, reason } => Some(format!(
+            "{} forfeited, {}; {} wins",
+            seat_name(*seat),
+            reason,
+            seat_name(1 - seat)
+        )),
+        Event::DrawAgreed { .. } => Some("drawn by agreement".to_string()),
+        Event::Aborted { seat } => Some(format!("aborted by {}, no result", seat_name(*seat))),
+        _ => None,
+    });
+    if let Some(ended) = ended {
+        return ended;
+    }
+    match Outcome::of(last) {
+        Some(Outcome::Won(seat)) => format!("{} wins with a quarto", seat_name(seat)),
+        Some(Outcome::Drawn) => "drawn, the board is full".to_string(),
+        None => format!("unfinished, {} to move", seat_name(seat_to_move(last))),
+    }
+}
+
+/* Squares as on the board text: a to d from the left, 1 to 4 from the bottom.
+   Tall pieces are drawn larger, square ones as squares and hollow ones with a
+   dot.
+*/
+fn board(quarto: &Quarto) -> String {
+    let mut tex = String::from("\\begin{tikzpicture}\n\\draw (0,0) grid (4,4);\n");
+    for (n, file) in ["a", "b", "c", "d"].iter().enumerate() {
+        let _ = writeln!(tex, "\\node at ({}.5,-0.3) {{{}}};", n, file);
+    }
+    for rank in 0..4 {
+        let _ = writeln!(tex, "\\node at (-0.3,{}.5) {{{}}};", rank, rank + 1);
+    }
+    for (x, row) in quarto.board_state.cells().iter().enumerate() {
+        for (y, cell) in row.iter().enumerate() {
+            if let Some(piece) = cell {
+                tex.push_str(&piece_at(piece, &format!("{}.5,{}.5", y, 3 - x)));
+            }
+        }
+    }
+    tex.push_str("\\end{tikzpicture}\n");
+    tex
+}
+
+/* Properties by Piece::to_index bit; Brown, Short, Circle and Flat are 0 */
+fn piece_at(piece: &Piece, center: &str) -> String {
+    let index = piece.to_index();
+    let fill = if index & 0b1000 == 0 {
+        "brown!70"
+    } else {
+        "white"
+    };
+    let size: f64 = if index & 0b0100 == 0 { 0.28 } else { 0.4 };
+    let mut tex = if index & 0b0010 == 0 {
+        format!("\\draw[fill={}] ({}) circle ({});\n", fill, center, size)
+    } else {
+        format!(
+            "\\draw[fill={}] ({}) ++(-{},-{}) rectangle ++({},{});\n",
+            fill,
+            center,
+            size,
+            size,
+            2.0 * size,
+            2.0 * size
+        )
+    };
+    if index & 0b0001 != 0 {
+        let _ = writeln!(tex, "\\fill[black!70] ({}) circle (0.08);", center);
+    }
+    tex
+}
+
+/* The first half of the moves on the left, the rest on the right */
+fn transcript(plies: &[(Quarto, Move)]) -> String {
+    let lines: Vec<String> = plies
+        .iter()
+        .zip(1..)
+        .map(|((quarto, ply), number)| {
+            format!(
+                "{}. & {} & {}",
+                number,
+                seat_name(seat_to_move(quarto)),
+                ply
+            )
+        })
+        .collect();
+    let half = lines.len().div_ceil(2);
+    let mut tex = String::from("\\noindent\\begin{tabular}{rll@{\\hspace{3em}}rll}\n");
+    for (n, left) in lines[..half].iter().enumerate() {
+        match lines.get(half + n) {
+            Some(right) => {
+                let _ = writeln!(tex, "{} & {} \\\\", left, right);
+            }
+            None => {
+                let _ = writeln!(tex, "{} & & & \\\\", left);
+            }
+        }
+    }
+    tex.push_str("\\end{tabular}\n");
+    tex
+}
+
+/* yyyy-mm-dd in UTC */
+pub fn date(millis: i64) -> String {
+    // Howard Hinnant's civil_from_days
+    let days = millis.div_euclid(86_400_000) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/* Names and reasons are written as text, not as LaTeX */
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GOLDEN: &str = include_str!("../tests/fixtures/score-sheet.tex");
+
+    fn records() -> Vec<Record> {
+        let mut opening = Quarto::new();
+        opening.apply_move(&"BSCF".parse().unwrap());
+        let created = Event::Created {
+            position: opening.to_share_code(),
+        };
+        let plies = ["a1 BTSH", "b1 WSCH", "d4 BTCF", "c1 WTSF", "a4 WSSF"];
+        let moves = plies.iter().map(|ply| Event::Move {
+            ply: ply.parse().unwrap(),
+        });
+        std::iter::once(created)
+            .chain(moves)
+            .chain(std::iter::once(Event::Resigned { seat: 1 }))
+            .zip(1..)
+            .map(|(event, seq)| Record {
+                seq,
+                event,
+                created_at: 1_760_572_800_000 + seq * 60_000,
+                think_ms: None,
+                handed_by: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_score_sheet_matches_golden_file() {
+        let players = [Some("Ada & Co".to_string()), None];
+        let mut sheet =
+            ScoreSheet::of("d3b07384-0000-4000-8000-000000000001", &records(), players).unwrap();
+        assert_eq!(sheet.result, "2nd resigned; 1st wins");
+        assert_eq!(sheet.engine_version, quarto::engine_version());
+        // Pinned so that the golden file survives version bumps
+        sheet.engine_version = "0.1.0+rules.1".to_string();
+        assert_eq!(sheet.to_latex(), GOLDEN);
+    }
+
+    #[test]
+    fn test_dates_and_escaping() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(951_782_400_000), "2000-02-29");
+        assert_eq!(date(1_760_572_800_000), "2025-10-16");
+        assert_eq!(escape("50% of #1_a {b}"), "50\\% of \\#1\\_a \\{b\\}");
+        assert_eq!(escape("a\\b~"), "a\\textbackslash{}b\\textasciitilde{}");
+    }
+}
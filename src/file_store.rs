@@ -0,0 +1,319 @@
+use crate::game_id::{self, GameId};
+use crate::play;
+use crate::quarto::{BoardState, GameStatus, Piece, Quarto, QuartoError, Rules, Variant};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/* Games kept as one text file each under a directory, for playing without a database.
+   A file holds a format line and the game as Quarto's Display writes it:
+
+       quarto 2
+       BSCF ---- ---- ----
+       ...
+       hand: WSCF
+       turn: 1
+       variant: classic
+
+   Files in format 1 held the variant and the piece in hand before the board,
+   without the turn, and still read.
+
+   Writes go to a temporary file which is renamed over the game, so an interrupted
+   write leaves the previous position in place.
+*/
+pub const FILE_VERSION: u32 = 2;
+
+const EXTENSION: &str = "qrt";
+
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+/* Held while a game file is being changed; removing the lock file releases it */
+pub struct GameLock {
+    path: PathBuf,
+}
+
+impl Drop for GameLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!(path = %self.path.display(), ?e, "cannot remove lock file");
+        }
+    }
+}
+
+pub fn encode(quarto: &Quarto) -> String {
+    format!("quarto {}\n{}\n", FILE_VERSION, quarto)
+}
+
+pub fn decode(text: &str) -> Result<Quarto, QuartoError> {
+    let corrupt = |reason: String| QuartoError::CorruptRecord(reason);
+    let mut lines = text.lines();
+    let mut field = |name: &str| match lines.next().and_then(|line| line.split_once(' ')) {
+        Some((key, value)) if key == name => Ok(value.trim().to_string()),
+        _ => Err(corrupt(format!("expected a {} line", name))),
+    };
+    let version = field("quarto")?;
+    if version == FILE_VERSION.to_string() {
+        let game: Vec<&str> = lines.collect();
+        return Quarto::try_from(&game.join("\n")).map_err(|e| corrupt(format!("{:?}", e)));
+    }
+    if version != "1" {
+        return Err(corrupt(format!("unsupported format version {}", version)));
+    }
+    let variant = match field("variant")?.as_str() {
+        "classic" => Variant::Classic,
+        "advanced" => Variant::Advanced,
+        other => return Err(corrupt(format!("unknown variant {}", other))),
+    };
+    let hand = match field("hand")?.as_str() {
+        "none" => None,
+        code => Some(
+            Piece::try_from(code.to_string())
+                .map_err(|_| corrupt(format!("hand: invalid piece {}", code)))?,
+        ),
+    };
+    let board: Vec<&str> = lines.collect();
+    let board =
+        BoardState::try_from(&board.join("\n")).map_err(|e| corrupt(format!("board: {:?}", e)))?;
+    Quarto::from_parts(
+        board,
+        hand,
+        Rules {
+            variant,
+            ..Rules::default()
+        },
+    )
+}
+
+impl FileStore {
+    pub fn open(dir: &Path) -> Result<FileStore, QuartoError> {
+        fs::create_dir_all(dir).map_err(QuartoError::Io)?;
+        Ok(FileStore {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn path(&self, uuid: &GameId) -> PathBuf {
+        self.dir.join(format!("{}.{}", uuid, EXTENSION))
+    }
+
+    fn lock_path(&self, uuid: &GameId) -> PathBuf {
+        self.dir.join(format!("{}.{}.lock", uuid, EXTENSION))
+    }
+
+    /* Fails at once with GameLocked while another process holds the game */
+    pub fn lock(&self, uuid: &GameId) -> Result<GameLock, QuartoError> {
+        let path = self.lock_path(uuid);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                writeln!(file, "{}", std::process::id()).map_err(QuartoError::Io)?;
+                Ok(GameLock { path })
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                Err(QuartoError::GameLocked(path.display().to_string()))
+            }
+            Err(e) => Err(QuartoError::Io(e)),
+        }
+    }
+
+    pub fn load(&self, uuid: &GameId) -> Result<Option<Quarto>, QuartoError> {
+        match fs::read_to_string(self.path(uuid)) {
+            Ok(text) => decode(&text).map(Some),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(QuartoError::Io(e)),
+        }
+    }
+
+    /* Replaces the game file in one rename; the caller holds the game's lock */
+    pub fn save(&self, uuid: &GameId, quarto: &Quarto) -> Result<(), QuartoError> {
+        let path = self.path(uuid);
+        let tmp = path.with_extension(format!("{}.tmp", EXTENSION));
+        let mut file = File::create(&tmp).map_err(QuartoError::Io)?;
+        file.write_all(encode(quarto).as_bytes())
+            .map_err(QuartoError::Io)?;
+        file.sync_all().map_err(QuartoError::Io)?;
+        fs::rename(&tmp, &path).map_err(QuartoError::Io)?;
+        info!(uuid = %game_id::logged(&uuid), "saved game");
+        Ok(())
+    }
+
+    pub fn insert(&self, uuid: &GameId, quarto: &Quarto) -> Result<(), QuartoError> {
+        let _lock = self.lock(uuid)?;
+        if self.path(uuid).exists() {
+            return Err(QuartoError::DuplicateGame(uuid.to_string()));
+        }
+        self.save(uuid, quarto)
+    }
+
+    /* Every readable game, ordered by uuid; files not named after a uuid are left alone */
+    pub fn list(&self) -> Result<Vec<(GameId, Quarto)>, QuartoError> {
+        let mut games = Vec::new();
+        for entry in fs::read_dir(&self.dir).map_err(QuartoError::Io)? {
+            let path = entry.map_err(QuartoError::Io)?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            let Some(Ok(uuid)) = path.file_stem().and_then(|s| s.to_str()).map(GameId::parse)
+            else {
+                continue;
+            };
+            match self.load(&uuid) {
+                Ok(Some(quarto)) => games.push((uuid, quarto)),
+                Ok(None) => {}
+                Err(e) => warn!(uuid = %game_id::logged(&uuid), ?e, "skipping unreadable game"),
+            }
+        }
+        games.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(games)
+    }
+}
+
+impl play::Store for FileStore {
+    fn games(&mut self) -> Result<Vec<(GameId, Quarto)>, Box<dyn Error>> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .filter(|(_, quarto)| {
+                quarto.status() == GameStatus::InProgress && quarto.next_piece.is_some()
+            })
+            .collect())
+    }
+
+    fn load(&mut self, game: &GameId) -> Result<Option<Quarto>, Box<dyn Error>> {
+        Ok(FileStore::load(self, game)?)
+    }
+
+    fn create(&mut self, advanced: bool) -> Result<(GameId, Quarto), Box<dyn Error>> {
+        let mut quarto = Quarto::new();
+        if advanced {
+            quarto.rules.variant = Variant::Advanced;
+        }
+        quarto.pick_piece(&Piece::try_from("BSCF".to_string())?);
+        let uuid = GameId::random();
+        self.insert(&uuid, &quarto)?;
+        Ok((uuid, quarto))
+    }
+
+    fn commit(&mut self, game: &GameId, quarto: &Quarto) -> Result<(), Box<dyn Error>> {
+        let _lock = self.lock(game)?;
+        Ok(self.save(game, quarto)?)
+    }
+
+    fn create_from(&mut self, quarto: &Quarto) -> Result<GameId, Box<dyn Error>> {
+        let uuid = GameId::random();
+        self.insert(&uuid, quarto)?;
+        Ok(uuid)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use uuid::Uuid;
+
+    /* A fresh directory under the system temp dir, removed when dropped */
+    pub(crate) struct TempDir(pub PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new() -> TempDir {
+            let dir = std::env::temp_dir().join(format!("quarto-test-{}", Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn piece(code: &str) -> Piece {
+        Piece::try_from(code.to_string()).unwrap()
+    }
+
+    fn opening() -> Quarto {
+        let mut quarto = Quarto::with_rules(Rules {
+            variant: Variant::Advanced,
+            ..Rules::default()
+        });
+        quarto.pick_piece(&piece("BSCF"));
+        quarto
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let mut quarto = opening();
+        assert_eq!(decode(&encode(&quarto)).unwrap(), quarto);
+        quarto.move_piece(1, 2);
+        quarto.pick_piece(&piece("WTCH"));
+        let text = encode(&quarto);
+        assert!(text.starts_with("quarto 2\n"));
+        assert!(text.ends_with("\nhand: WTCH\nturn: 1\nvariant: advanced\n"));
+        assert_eq!(decode(&text).unwrap(), quarto);
+
+        // Format 1, from before the turn was written
+        let board = quarto.board_state.to_display_string();
+        let old = format!("quarto 1\nvariant advanced\nhand WTCH\n{}\n", board);
+        assert_eq!(decode(&old).unwrap(), quarto);
+
+        for corrupt in [
+            "",
+            "quarto 3\nvariant classic\nhand none\n",
+            text.replace("hand: WTCH", "hand: BSCF").as_str(),
+            text.replace("variant: advanced", "variant: huge").as_str(),
+            old.replace("hand WTCH", "hand BSCF").as_str(),
+            old.replace("variant advanced", "variant huge").as_str(),
+        ] {
+            assert!(
+                matches!(decode(corrupt), Err(QuartoError::CorruptRecord(_))),
+                "{}",
+                corrupt
+            );
+        }
+    }
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let dir = TempDir::new();
+        let store = FileStore::open(&dir.0).unwrap();
+        let (game, other) = (GameId::random(), GameId::random());
+        let lock = store.lock(&game).unwrap();
+        assert!(matches!(store.lock(&game), Err(QuartoError::GameLocked(_))));
+        assert!(store.lock(&other).is_ok());
+        drop(lock);
+        assert!(store.lock(&game).is_ok());
+    }
+
+    #[test]
+    fn test_interrupted_write_keeps_previous_position() {
+        let dir = TempDir::new();
+        let store = FileStore::open(&dir.0).unwrap();
+        let quarto = opening();
+        let game = GameId::random();
+        store.insert(&game, &quarto).unwrap();
+        assert!(matches!(
+            store.insert(&game, &quarto),
+            Err(QuartoError::DuplicateGame(_))
+        ));
+
+        // A crash after writing part of the temporary file but before the rename
+        let tmp = dir.0.join(format!("{}.qrt.tmp", game));
+        fs::write(&tmp, "quarto 1\nvariant adv").unwrap();
+        fs::write(dir.0.join("notes.qrt"), encode(&quarto)).unwrap();
+        assert_eq!(store.load(&game).unwrap(), Some(quarto.clone()));
+        assert_eq!(store.list().unwrap().len(), 1);
+
+        let mut next = quarto.clone();
+        next.move_piece(0, 0);
+        next.pick_piece(&piece("WSCF"));
+        store.save(&game, &next).unwrap();
+        assert_eq!(store.load(&game).unwrap(), Some(next));
+        assert!(!tmp.exists());
+        assert_eq!(store.load(&GameId::random()).unwrap(), None);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::quarto::QuartoError;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+/* The uuid a game is stored under, checked once when it enters the program.
+   Any form uuid accepts is taken and kept in the hyphenated lower case form,
+   which is what the database and the game files are keyed by.
+*/
+#[derive(
+    Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Deserialize, Serialize, sqlx::Type,
+)]
+#[serde(try_from = "String", into = "String")]
+#[sqlx(transparent)]
+pub struct GameId(String);
+
+impl GameId {
+    pub fn random() -> GameId {
+        GameId(Uuid::new_v4().to_string())
+    }
+
+    pub fn parse(id: &str) -> Result<GameId, QuartoError> {
+        Uuid::parse_str(id)
+            .map(|uuid| GameId(uuid.to_string()))
+            .map_err(|_| QuartoError::InvalidGameId(id.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/* Ids are printed for people by their first SHORT_LEN characters, lengthened
+   where another known game starts the same way, so that what is printed names one
+   game. --full-ids prints them whole, and JSON always carries the whole id.
+*/
+pub const SHORT_LEN: usize = 8;
+
+static FULL: OnceLock<bool> = OnceLock::new();
+static LOGGED_FULL: OnceLock<bool> = OnceLock::new();
+
+/* For --full-ids; called once, before anything is printed */
+pub fn show_full() {
+    let _ = FULL.set(true);
+}
+
+/* For --full-ids and JSON log lines, which are read by machines */
+pub fn log_full() {
+    let _ = LOGGED_FULL.set(true);
+}
+
+/* The shortest prefix of `id` from SHORT_LEN characters on which no other of
+   `known` starts with
+*/
+fn unambiguous<'a, S: AsRef<str>>(id: &'a str, known: &[S]) -> &'a str {
+    let len = known
+        .iter()
+        .map(AsRef::as_ref)
+        .filter(|other| *other != id)
+        .map(|other| {
+            let shared = id.bytes().zip(other.bytes()).take_while(|(a, b)| a == b);
+            shared.count() + 1
+        })
+        .fold(SHORT_LEN, usize::max);
+    prefix(id, len)
+}
+
+/* The first `len` bytes of `id`, or a little more to end on a character */
+fn prefix(id: &str, len: usize) -> &str {
+    let end = (len.min(id.len())..=id.len())
+        .find(|end| id.is_char_boundary(*end))
+        .unwrap_or(id.len());
+    &id[..end]
+}
+
+/* `id` as printed for people, among the `known` ids it must not be confused with */
+pub fn short<'a, S: AsRef<str>>(id: &'a str, known: &[S]) -> &'a str {
+    match FULL.get() {
+        Some(true) => id,
+        _ => unambiguous(id, known),
+    }
+}
+
+/* `id` as a field of a log line; nothing is looked up to lengthen it */
+pub fn logged<S: AsRef<str> + ?Sized>(id: &S) -> &str {
+    let id = id.as_ref();
+    match LOGGED_FULL.get() {
+        Some(true) => id,
+        _ => prefix(id, SHORT_LEN),
+    }
+}
+
+impl GameId {
+    pub fn short<'a>(&'a self, known: &[GameId]) -> &'a str {
+        short(&self.0, known)
+    }
+}
+
+impl AsRef<str> for GameId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for GameId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for GameId {
+    type Err = QuartoError;
+
+    fn from_str(id: &str) -> Result<GameId, QuartoError> {
+        GameId::parse(id)
+    }
+}
+
+impl TryFrom<String> for GameId {
+    type Error = QuartoError;
+
+    fn try_from(id: String) -> Result<GameId, QuartoError> {
+        GameId::parse(&id)
+    }
+}
+
+impl From<GameId> for String {
+    fn from(id: GameId) -> String {
+        id.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_game_id() {
+        let id = GameId::parse("1A2B3C4D-0000-4000-8000-000000000001").unwrap();
+        assert_eq!(id.to_string(), "1a2b3c4d-0000-4000-8000-000000000001");
+        assert_eq!(
+            "1a2b3c4d000040008000000000000001"
+                .parse::<GameId>()
+                .unwrap(),
+            id
+        );
+        for invalid in [
+            "",
+            "nope",
+            "1a2b3c4d",
+            "../1a2b3c4d-0000-4000-8000-000000000001",
+        ] {
+            assert!(
+                matches!(GameId::parse(invalid), Err(QuartoError::InvalidGameId(_))),
+                "{}",
+                invalid
+            );
+        }
+        assert_ne!(GameId::random(), GameId::random());
+    }
+
+    #[test]
+    fn test_short_id_lengthens_when_ambiguous() {
+        let first = GameId::parse("1a2b3c4d-0000-4000-8000-000000000001").unwrap();
+        let second = GameId::parse("1a2b3c4d-9000-4000-8000-000000000002").unwrap();
+        let other = GameId::parse("5e6f7a8b-0000-4000-8000-000000000003").unwrap();
+        assert_eq!(first.short(&[first.clone(), other.clone()]), "1a2b3c4d");
+        assert_eq!(first.short(&[]), "1a2b3c4d");
+        let known = [first.clone(), second.clone(), other.clone()];
+        assert_eq!(first.short(&known), "1a2b3c4d-0");
+        assert_eq!(second.short(&known), "1a2b3c4d-9");
+        assert_eq!(other.short(&known), "5e6f7a8b");
+        // Each printed form picks out its own game alone
+        for game in &known {
+            let shown = game.short(&known);
+            let named: Vec<_> = known
+                .iter()
+                .filter(|g| g.as_str().starts_with(shown))
+                .collect();
+            assert_eq!(named, vec![game]);
+        }
+        assert_eq!(short("game-0007", &["game-0001", "game-0007"]), "game-0007");
+        assert_eq!(short("abc", &["abd"]), "abc");
+        assert_eq!(logged(first.as_str()), "1a2b3c4d");
+    }
+
+    #[test]
+    fn test_game_id_serde_as_string() {
+        let id = GameId::parse("1a2b3c4d-0000-4000-8000-000000000001").unwrap();
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(json, "\"1a2b3c4d-0000-4000-8000-000000000001\"");
+        assert_eq!(serde_json::from_str::<GameId>(&json).unwrap(), id);
+        assert!(serde_json::from_str::<GameId>("\"nope\"").is_err());
+    }
+}
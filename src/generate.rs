@@ -0,0 +1,552 @@
+use crate::intersperse::intersperse;
+use crate::progress::Progress;
+use crate::quarto::{
+    Adjudication, GameRng, GameStatus, Move, PlayoutRng, Quarto, QuartoError, Rules, Variant,
+};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/* Self-play games in bulk, for the statistics and the opening book. The games are
+   split into shards of SHARD_GAMES, each kept in one file of the output directory
+   with a game per line, as `quarto book build --from-games` reads them:
+
+       manifest
+       shard-00000.games
+       shard-00001.games
+
+   A shard is written in one go when it is done or the run is stopped, and the
+   manifest then records how many of its games are in the file. Game i is played
+   from a seed made of the run's seed and i alone, so a rerun with the same
+   settings skips what is written and finishes a shard with the games it would
+   have had.
+*/
+pub const SHARD_GAMES: usize = 500;
+
+const MANIFEST: &str = "manifest";
+/* 2 since the engines draw from each game's GameRng, which plays other games */
+const MANIFEST_VERSION: u32 = 2;
+
+/* How a side picks its moves: random, or playout:N for the move scoring best over
+   N playouts after each legal move
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Engine {
+    Random,
+    Playout(u32),
+}
+
+impl Engine {
+    fn choose(&self, quarto: &Quarto, rng: &mut PlayoutRng) -> Option<Move> {
+        match self {
+            Engine::Random => {
+                let moves = quarto.legal_moves();
+                (!moves.is_empty()).then(|| moves[rng.below(moves.len())])
+            }
+            Engine::Playout(playouts) => quarto
+                .best_move(*playouts, rng.next_u64())
+                .map(|(mv, _)| mv),
+        }
+    }
+}
+
+impl FromStr for Engine {
+    type Err = QuartoError;
+    fn from_str(s: &str) -> Result<Engine, QuartoError> {
+        match s.split_once(':') {
+            None if s == "random" => Ok(Engine::Random),
+            Some(("playout", n)) => n
+                .parse()
+                .ok()
+                .filter(|n| *n > 0)
+                .map(Engine::Playout)
+                .ok_or_else(|| QuartoError::InvalidEngine(s.to_string())),
+            _ => Err(QuartoError::InvalidEngine(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Engine::Random => f.write_str("random"),
+            Engine::Playout(playouts) => write!(f, "playout:{}", playouts),
+        }
+    }
+}
+
+/* Everything the games depend on; a rerun must give the same */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    pub games: usize,
+    /* The 1st player's engine, then the 2nd's */
+    pub engines: [Engine; 2],
+    pub seed: u64,
+    pub variant: Variant,
+    /* Games end at a dead position, scored like this, instead of being played out */
+    pub adjudication: Option<Adjudication>,
+}
+
+/* Without adjudication as before it existed, so that older manifests still match */
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "games {} engine1 {} engine2 {} seed {} variant {}",
+            self.games, self.engines[0], self.engines[1], self.seed, self.variant
+        )?;
+        if let Some(adjudication) = self.adjudication {
+            write!(f, " adjudicate {}", adjudication)?;
+        }
+        Ok(())
+    }
+}
+
+impl Settings {
+    fn shards(&self) -> usize {
+        self.games.div_ceil(SHARD_GAMES)
+    }
+
+    fn rng(&self, index: usize) -> GameRng {
+        GameRng(self.seed ^ (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+    }
+
+    /* The last shard may be short */
+    fn shard_games(&self, shard: usize) -> usize {
+        SHARD_GAMES.min(self.games - shard * SHARD_GAMES)
+    }
+}
+
+/* Game `index` of the run as the moves played, from the opening hand to the end.
+   Each game has its own GameRng, so any move can be repeated from the run's seed,
+   the game's index and the moves before it.
+*/
+pub fn play_game(settings: &Settings, index: usize) -> Vec<Move> {
+    let rng = settings.rng(index);
+    let mut quarto = Quarto::with_rules(Rules {
+        variant: settings.variant,
+        dead_position_adjudication: settings.adjudication,
+    });
+    let mut moves = Vec::with_capacity(33);
+    while quarto.status() == GameStatus::InProgress {
+        let engine = settings.engines[moves.len() % 2];
+        let Some(mv) = engine.choose(&quarto, &mut rng.rng_for(&quarto)) else {
+            break;
+        };
+        quarto.play_legal(&mv);
+        moves.push(mv);
+    }
+    moves
+}
+
+/* Sized for every move to have a square and a piece, so one allocation */
+fn transcript(moves: &[Move]) -> String {
+    let mut text = String::with_capacity(moves.len() * "a1 BSCF; ".len());
+    let _ = write!(text, "{}", intersperse(moves, "; "));
+    text
+}
+
+/* Games written per shard. Settings other than the ones the directory was
+   started with are refused, since the games would not match.
+*/
+fn load_manifest(out: &Path, settings: &Settings) -> Result<BTreeMap<usize, usize>, QuartoError> {
+    let text = match fs::read_to_string(out.join(MANIFEST)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(QuartoError::Io(e)),
+    };
+    let invalid = |reason: String| QuartoError::InvalidManifest(reason);
+    let mut lines = text.lines();
+    if lines.next() != Some(format!("quarto-generate {}", MANIFEST_VERSION).as_str()) {
+        return Err(invalid("unknown format".to_string()));
+    }
+    let expected = settings.to_string();
+    match lines.next().and_then(|line| line.strip_prefix("settings ")) {
+        Some(found) if found == expected => {}
+        Some(found) => {
+            return Err(invalid(format!(
+                "the output was generated with {}, not {}",
+                found, expected
+            )))
+        }
+        None => return Err(invalid("no settings line".to_string())),
+    }
+    let mut written = BTreeMap::new();
+    for line in lines {
+        let shard = match line.split(' ').collect::<Vec<_>>().as_slice() {
+            ["shard", shard, games] => shard.parse().ok().zip(games.parse().ok()),
+            _ => None,
+        };
+        let Some((shard, games)) = shard.filter(|(shard, games)| {
+            *shard < settings.shards() && *games <= settings.shard_games(*shard)
+        }) else {
+            return Err(invalid(format!("bad shard line: {}", line)));
+        };
+        written.insert(shard, games);
+    }
+    Ok(written)
+}
+
+/* Replaced in one rename, like the game files */
+fn replace(path: &Path, text: &str) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(text.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+fn save_manifest(
+    out: &Path,
+    settings: &Settings,
+    written: &BTreeMap<usize, usize>,
+) -> io::Result<()> {
+    let mut text = format!(
+        "quarto-generate {}\nsettings {}\n",
+        MANIFEST_VERSION, settings
+    );
+    for (shard, games) in written {
+        text.push_str(&format!("shard {} {}\n", shard, games));
+    }
+    replace(&out.join(MANIFEST), &text)
+}
+
+fn shard_path(out: &Path, shard: usize) -> std::path::PathBuf {
+    out.join(format!("shard-{:05}.games", shard))
+}
+
+/* Plays the games of `shard` the manifest does not have yet, until it is full or
+   `stop` is set, and writes them after the ones already there
+*/
+fn fill_shard(
+    settings: &Settings,
+    out: &Path,
+    shard: usize,
+    written: &Mutex<BTreeMap<usize, usize>>,
+    played: &AtomicUsize,
+    stop: &AtomicBool,
+    progress: &dyn Progress,
+) -> Result<(), QuartoError> {
+    let done = written.lock().unwrap().get(&shard).copied().unwrap_or(0);
+    let path = shard_path(out, shard);
+    let mut text = String::new();
+    if done > 0 {
+        // Lines past `done` are from a run stopped before the manifest was saved
+        let kept = fs::read_to_string(&path).map_err(QuartoError::Io)?;
+        let kept: Vec<&str> = kept.lines().take(done).collect();
+        if kept.len() < done {
+            return Err(QuartoError::InvalidManifest(format!(
+                "{} has fewer than {} games",
+                path.display(),
+                done
+            )));
+        }
+        for line in kept {
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+    let first = shard * SHARD_GAMES;
+    let mut games = done;
+    while games < settings.shard_games(shard) && !stop.load(Ordering::Relaxed) {
+        text.push_str(&transcript(&play_game(settings, first + games)));
+        text.push('\n');
+        games += 1;
+        played.fetch_add(1, Ordering::Relaxed);
+        progress.advance(1);
+    }
+    if games == done {
+        return Ok(());
+    }
+    replace(&path, &text).map_err(QuartoError::Io)?;
+    let mut written = written.lock().unwrap();
+    written.insert(shard, games);
+    save_manifest(out, settings, &written).map_err(QuartoError::Io)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Summary {
+    pub played: usize,
+    /* Written by earlier runs */
+    pub skipped: usize,
+    pub stopped: bool,
+    pub elapsed: Duration,
+}
+
+fn rate(games: usize, elapsed: Duration) -> f64 {
+    games as f64 / elapsed.as_secs_f64().max(1e-3)
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "played {} games in {:.1}s ({:.0} games/s)",
+            self.played,
+            self.elapsed.as_secs_f64(),
+            rate(self.played, self.elapsed)
+        )?;
+        if self.skipped > 0 {
+            write!(f, "; {} were already written", self.skipped)?;
+        }
+        if self.stopped {
+            write!(f, "; stopped, run again to finish")?;
+        }
+        Ok(())
+    }
+}
+
+/* Fills the output directory with `jobs` threads, each taking a whole shard at a
+   time and reporting every game into `progress`, with the games written by
+   earlier runs counted as done from the start. Setting `stop` makes every thread
+   write the games it has and return.
+*/
+pub fn run(
+    settings: &Settings,
+    out: &Path,
+    jobs: usize,
+    stop: &AtomicBool,
+    progress: &dyn Progress,
+) -> Result<Summary, QuartoError> {
+    fs::create_dir_all(out).map_err(QuartoError::Io)?;
+    let written = load_manifest(out, settings)?;
+    let skipped: usize = written.values().sum();
+    let pending: Vec<usize> = (0..settings.shards())
+        .filter(|shard| written.get(shard).copied().unwrap_or(0) < settings.shard_games(*shard))
+        .collect();
+    info!(%settings, shards = pending.len(), skipped, "generating");
+    let written = Mutex::new(written);
+    let (next, played) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    let start = Instant::now();
+    progress.phase("games", Some(settings.games as u64));
+    progress.advance(skipped as u64);
+    let result = thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.max(1))
+            .map(|_| {
+                scope.spawn(|| loop {
+                    if stop.load(Ordering::Relaxed) {
+                        return Ok(());
+                    }
+                    let Some(&shard) = pending.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        return Ok(());
+                    };
+                    fill_shard(settings, out, shard, &written, &played, stop, progress)?;
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .try_for_each(|worker| worker.join().expect("generator thread panicked"))
+    });
+    progress.finish();
+    result?;
+    Ok(Summary {
+        played: played.into_inner(),
+        skipped,
+        stopped: stop.load(Ordering::Relaxed),
+        elapsed: start.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::book;
+    use crate::file_store::test::TempDir;
+    use crate::progress::test::Recording;
+    use crate::progress::Silent;
+
+    fn settings(games: usize, engine2: Engine) -> Settings {
+        Settings {
+            games,
+            engines: [Engine::Random, engine2],
+            seed: 7,
+            variant: Variant::Classic,
+            adjudication: None,
+        }
+    }
+
+    fn read_games(out: &Path) -> Vec<String> {
+        let mut files: Vec<_> = fs::read_dir(out)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "games"))
+            .collect();
+        files.sort();
+        files
+            .iter()
+            .flat_map(|path| {
+                let text = fs::read_to_string(path).unwrap();
+                text.lines().map(str::to_string).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_adjudicated_games_stop_at_dead_positions() {
+        let classic = settings(200, Engine::Random);
+        assert_eq!(
+            classic.to_string(),
+            "games 200 engine1 random engine2 random seed 7 variant classic"
+        );
+        let adjudicated = Settings {
+            adjudication: Some(Adjudication::Loss),
+            ..classic
+        };
+        assert_eq!(
+            adjudicated.to_string(),
+            "games 200 engine1 random engine2 random seed 7 variant classic adjudicate loss"
+        );
+        let rules = Rules {
+            dead_position_adjudication: Some(Adjudication::Loss),
+            ..Rules::default()
+        };
+        let mut ended = 0;
+        for index in 0..classic.games {
+            // Without adjudication every game is played to a quarto or a full board
+            let mut quarto = Quarto::new();
+            for mv in play_game(&classic, index) {
+                assert!(quarto.apply_move(&mv));
+            }
+            assert_ne!(quarto.status(), GameStatus::InProgress);
+
+            let mut quarto = Quarto::with_rules(rules);
+            for mv in play_game(&adjudicated, index) {
+                assert_eq!(quarto.status(), GameStatus::InProgress);
+                assert!(quarto.apply_move(&mv));
+            }
+            match quarto.status() {
+                GameStatus::Adjudicated(Adjudication::Loss) => ended += 1,
+                status => assert!(matches!(status, GameStatus::Won | GameStatus::Drawn)),
+            }
+        }
+        assert!(ended > 0);
+    }
+
+    #[test]
+    fn test_engine_names() {
+        assert_eq!("random".parse::<Engine>().unwrap(), Engine::Random);
+        assert_eq!("playout:50".parse::<Engine>().unwrap(), Engine::Playout(50));
+        for engine in [Engine::Random, Engine::Playout(50)] {
+            assert_eq!(engine.to_string().parse::<Engine>().unwrap(), engine);
+        }
+        for invalid in [
+            "",
+            "minimax",
+            "playout",
+            "playout:0",
+            "playout:x",
+            "random:1",
+        ] {
+            assert!(
+                matches!(
+                    invalid.parse::<Engine>(),
+                    Err(QuartoError::InvalidEngine(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_transcript_joins_the_moves() {
+        let moves = play_game(&settings(1, Engine::Random), 0);
+        let text = transcript(&moves);
+        let joined: Vec<String> = moves.iter().map(Move::to_string).collect();
+        assert_eq!(text, joined.join("; "));
+    }
+
+    #[test]
+    fn test_games_are_complete_and_reproducible() {
+        let settings = settings(4, Engine::Playout(2));
+        for index in 0..4 {
+            let moves = play_game(&settings, index);
+            assert_eq!(moves, play_game(&settings, index));
+            let mut quarto = Quarto::new();
+            for mv in &moves {
+                assert!(quarto.apply_move(mv), "{}", transcript(&moves));
+            }
+            assert_ne!(quarto.status(), GameStatus::InProgress);
+        }
+        assert_ne!(play_game(&settings, 0), play_game(&settings, 1));
+
+        // Each move is the engine's choice from the game's seed and the moves before
+        let moves = play_game(&settings, 3);
+        let mut quarto = Quarto::new();
+        for (ply, mv) in moves.iter().enumerate() {
+            let mut rng = settings.rng(3).rng_for(&quarto);
+            assert_eq!(
+                settings.engines[ply % 2].choose(&quarto, &mut rng),
+                Some(*mv)
+            );
+            quarto.play_legal(mv);
+        }
+    }
+
+    #[test]
+    fn test_stopped_run_resumes_where_it_left_off() {
+        let dir = TempDir::new();
+        let out = dir.0.join("games");
+        let settings = settings(SHARD_GAMES + 3, Engine::Random);
+        let progress = Recording::default();
+
+        // Stopped before it starts: nothing is written, and a rerun does it all
+        let stopped = run(&settings, &out, 2, &AtomicBool::new(true), &progress).unwrap();
+        assert!(stopped.stopped);
+        assert_eq!(stopped.played, 0);
+        let summary = run(&settings, &out, 2, &AtomicBool::new(false), &progress).unwrap();
+        assert_eq!((summary.played, summary.skipped), (SHARD_GAMES + 3, 0));
+        let games = read_games(&out);
+        assert_eq!(games.len(), SHARD_GAMES + 3);
+        assert_eq!(
+            games[SHARD_GAMES + 2],
+            transcript(&play_game(&settings, SHARD_GAMES + 2))
+        );
+        let parsed = book::parse_games(Rules::default(), &games.join("\n")).unwrap();
+        assert_eq!(parsed.len(), SHARD_GAMES + 3);
+        let total = Some(SHARD_GAMES as u64 + 3);
+        assert_eq!(
+            progress.phases(),
+            vec![
+                ("games".to_string(), total, 0),
+                ("games".to_string(), total, SHARD_GAMES as u64 + 3)
+            ]
+        );
+        assert!(progress.finished());
+
+        // A shard cut short, with a line its manifest entry does not cover
+        let manifest = fs::read_to_string(out.join(MANIFEST)).unwrap();
+        let short = manifest.replace("shard 1 3", "shard 1 1");
+        fs::write(out.join(MANIFEST), &short).unwrap();
+        let shard = shard_path(&out, 1);
+        let lines: Vec<String> = games[SHARD_GAMES..].to_vec();
+        fs::write(&shard, format!("{}\n{}\n", lines[0], lines[1])).unwrap();
+        let summary = run(&settings, &out, 1, &AtomicBool::new(false), &Silent).unwrap();
+        assert_eq!((summary.played, summary.skipped), (2, SHARD_GAMES + 1));
+        assert_eq!(read_games(&out), games);
+        assert_eq!(fs::read_to_string(out.join(MANIFEST)).unwrap(), manifest);
+
+        // Done already
+        let summary = run(&settings, &out, 1, &AtomicBool::new(false), &Silent).unwrap();
+        assert_eq!(summary.played, 0);
+
+        // Other settings would mix games that do not belong together
+        let other = Settings {
+            seed: 8,
+            ..settings
+        };
+        assert!(matches!(
+            run(&other, &out, 1, &AtomicBool::new(false), &Silent),
+            Err(QuartoError::InvalidManifest(_))
+        ));
+    }
+}
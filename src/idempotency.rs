@@ -0,0 +1,110 @@
+use sqlx::sqlite::SqliteQueryResult;
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite, SqliteConnection};
+
+/* Keys clients send with `move --key` so that a retry after a lost reply gets the
+   original reply instead of failing on the turn order. A key belongs to one game
+   and names the event its move appended; it is written in the transaction of the
+   move, so a key is known exactly when its move was made. Once a move ends the
+   game the keys of earlier moves expire, and only the last one is still answered.
+*/
+pub async fn init_keys(db: &Pool<Sqlite>) -> Result<SqliteQueryResult, SqlxError> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS move_key
+        (
+              uuid VARCHAR NOT NULL,
+              key VARCHAR NOT NULL,
+              seq INTEGER NOT NULL,
+              reply VARCHAR NOT NULL,
+              PRIMARY KEY (uuid, key)
+        );"#,
+    )
+    .execute(db)
+    .await
+}
+
+/* The reply to the move made with `key` in the game, if there was one */
+pub async fn lookup(db: &Pool<Sqlite>, uuid: &str, key: &str) -> Result<Option<String>, SqlxError> {
+    Ok(
+        sqlx::query_as::<_, (String,)>("SELECT reply FROM move_key WHERE uuid = ?1 AND key = ?2")
+            .bind(uuid)
+            .bind(key)
+            .fetch_optional(db)
+            .await?
+            .map(|(reply,)| reply),
+    )
+}
+
+/* Records the key of the move which appended event `seq`. Pass the transaction of
+   the move. A key already used in the game fails the insert, and so the move.
+*/
+pub async fn record(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    key: &str,
+    seq: i64,
+    reply: &str,
+    game_over: bool,
+) -> Result<(), SqlxError> {
+    if game_over {
+        sqlx::query("DELETE FROM move_key WHERE uuid = ?1;")
+            .bind(uuid)
+            .execute(&mut *conn)
+            .await?;
+    }
+    sqlx::query("INSERT INTO move_key (uuid, key, seq, reply) VALUES (?1, ?2, ?3, ?4);")
+        .bind(uuid)
+        .bind(key)
+        .bind(seq)
+        .bind(reply)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    #[tokio::test]
+    async fn test_keys_are_per_game_and_expire_when_it_ends() {
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        init_keys(&db).await.unwrap();
+        let mut tx = db.begin().await.unwrap();
+        record(&mut tx, "a", "k1", 2, "move 2: a4 WSCF", false)
+            .await
+            .unwrap();
+        record(&mut tx, "b", "k1", 2, "move 2: b4 WSCF", false)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        let mut tx = db.begin().await.unwrap();
+        assert!(record(&mut tx, "a", "k1", 3, "move 3: c4 BSCH", false)
+            .await
+            .is_err());
+        tx.rollback().await.unwrap();
+        assert_eq!(
+            lookup(&db, "a", "k1").await.unwrap().as_deref(),
+            Some("move 2: a4 WSCF")
+        );
+        assert_eq!(lookup(&db, "a", "k2").await.unwrap(), None);
+
+        let mut tx = db.begin().await.unwrap();
+        record(&mut tx, "a", "k9", 9, "move 9: d1", true)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!(lookup(&db, "a", "k1").await.unwrap(), None);
+        assert_eq!(
+            lookup(&db, "a", "k9").await.unwrap().as_deref(),
+            Some("move 9: d1")
+        );
+        assert!(lookup(&db, "b", "k1").await.unwrap().is_some());
+    }
+}
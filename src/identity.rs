@@ -0,0 +1,294 @@
+use crate::clock;
+use crate::game_id;
+use crate::quarto::QuartoError;
+use crate::resume;
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite};
+use std::env;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/* Who plays at this terminal. `login` adds a name, with a token generated for it,
+   and makes it the one in use; `--as` picks another for one command. Moves,
+   resignations, draw offers and aborts made under a name record it in the seat
+   they are made from, and are refused from a seat recorded for someone else.
+   The file holds a format line, the name in use and every name logged in here:
+
+       quarto-identities 1
+       current alice
+       identity alice 6f1c0a93...
+       identity bob 0a93e2d4...
+
+   The file is QUARTO_IDENTITIES, or quarto/identities under XDG_STATE_HOME or
+   ~/.local/state, and only its owner may read it.
+*/
+pub const IDENTITIES_ENV: &str = "QUARTO_IDENTITIES";
+
+pub const IDENTITIES_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub token: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Identities {
+    pub current: Option<String>,
+    pub known: Vec<Identity>,
+}
+
+pub fn path() -> Option<PathBuf> {
+    if let Ok(path) = env::var(IDENTITIES_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    resume::state_file("identities")
+}
+
+/* Names are written in one word, as the file and `--as` read them */
+fn check_name(name: &str) -> Result<(), QuartoError> {
+    if name.is_empty() || name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(QuartoError::InvalidIdentity(name.to_string()));
+    }
+    Ok(())
+}
+
+impl Identities {
+    pub fn get(&self, name: &str) -> Option<&Identity> {
+        self.known.iter().find(|identity| identity.name == name)
+    }
+
+    /* Adds the name the first time and makes it the one in use */
+    pub fn login(&mut self, name: &str) -> Result<&Identity, QuartoError> {
+        check_name(name)?;
+        if self.get(name).is_none() {
+            self.known.push(Identity {
+                name: name.to_string(),
+                token: Uuid::new_v4().simple().to_string(),
+            });
+        }
+        self.current = Some(name.to_string());
+        Ok(self.get(name).unwrap())
+    }
+
+    /* The one named by --as, which must have logged in here, or else the one in use */
+    pub fn select(&self, chosen: Option<&str>) -> Result<Option<&Identity>, QuartoError> {
+        match chosen {
+            Some(name) => self
+                .get(name)
+                .map(Some)
+                .ok_or_else(|| QuartoError::UnknownIdentity(name.to_string())),
+            None => Ok(self.current.as_deref().and_then(|name| self.get(name))),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut text = format!("quarto-identities {}\n", IDENTITIES_VERSION);
+        if let Some(current) = &self.current {
+            text.push_str(&format!("current {}\n", current));
+        }
+        for identity in &self.known {
+            text.push_str(&format!("identity {} {}\n", identity.name, identity.token));
+        }
+        text
+    }
+
+    pub fn decode(text: &str) -> Result<Identities, QuartoError> {
+        let corrupt = |reason: String| QuartoError::CorruptRecord(reason);
+        let mut lines = text.lines();
+        match lines.next().and_then(|line| line.split_once(' ')) {
+            Some(("quarto-identities", version)) if version == IDENTITIES_VERSION.to_string() => {}
+            Some(("quarto-identities", version)) => {
+                return Err(corrupt(format!("unsupported format version {}", version)))
+            }
+            _ => return Err(corrupt("expected a quarto-identities line".to_string())),
+        }
+        let mut identities = Identities::default();
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                ["current", name] => identities.current = Some(name.to_string()),
+                ["identity", name, token] if identities.get(name).is_none() => {
+                    identities.known.push(Identity {
+                        name: name.to_string(),
+                        token: token.to_string(),
+                    })
+                }
+                _ => return Err(corrupt(format!("unreadable line: {}", line))),
+            }
+        }
+        if let Some(current) = &identities.current {
+            if identities.get(current).is_none() {
+                return Err(corrupt(format!("current {} has no identity line", current)));
+            }
+        }
+        Ok(identities)
+    }
+}
+
+/* Replaced in one rename, like the session file. The file is created readable by
+   its owner alone, since it holds the tokens.
+*/
+pub fn save(path: &Path, identities: &Identities) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp)?;
+    file.write_all(identities.encode().as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/* A missing file means nobody has logged in. One that cannot be read is reported
+   and moved aside with a .corrupt extension, so that logging in again starts
+   afresh without losing it.
+*/
+pub fn load(path: &Path) -> Identities {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Identities::default(),
+        Err(e) => {
+            warn!(path = %path.display(), ?e, "ignoring unreadable identity file");
+            return Identities::default();
+        }
+    };
+    match Identities::decode(&text) {
+        Ok(identities) => identities,
+        Err(e) => {
+            let aside = path.with_extension("corrupt");
+            warn!(path = %path.display(), aside = %aside.display(), ?e,
+                  "moving unreadable identity file aside");
+            if let Err(e) = fs::rename(path, &aside) {
+                warn!(?e, "cannot move the identity file aside");
+            }
+            Identities::default()
+        }
+    }
+}
+
+const SEATS: [&str; 2] = ["player_1st", "player_2nd"];
+
+/* The names recorded in the game's seats */
+pub async fn players(db: &Pool<Sqlite>, uuid: &str) -> Result<[Option<String>; 2], SqlxError> {
+    let row = sqlx::query_as::<_, (Option<String>, Option<String>)>(
+        "SELECT player_1st, player_2nd FROM game WHERE uuid = ?1;",
+    )
+    .bind(uuid)
+    .fetch_optional(db)
+    .await?;
+    Ok(row.map_or([None, None], |(first, second)| [first, second]))
+}
+
+/* The seat recorded for `name`, the 1st when both are */
+pub async fn seat_of(
+    db: &Pool<Sqlite>,
+    uuid: &str,
+    name: &str,
+) -> Result<Option<usize>, SqlxError> {
+    let players = players(db, uuid).await?;
+    Ok(players
+        .iter()
+        .position(|player| player.as_deref() == Some(name)))
+}
+
+/* Records `name` in a free seat; a seat recorded for someone else is refused */
+pub async fn claim(
+    db: &Pool<Sqlite>,
+    uuid: &str,
+    seat: usize,
+    name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let update = format!(
+        "UPDATE game SET {0} = ?2 WHERE uuid = ?1 AND {0} IS NULL;",
+        SEATS[seat]
+    );
+    let result = sqlx::query(&update)
+        .bind(uuid)
+        .bind(name)
+        .execute(db)
+        .await?;
+    if result.rows_affected() == 1 {
+        info!(uuid = %game_id::logged(uuid), seat = clock::seat_name(seat), name, "took seat");
+    }
+    match &players(db, uuid).await?[seat] {
+        Some(player) if player != name => {
+            error!(seat = clock::seat_name(seat), player, "seat is taken");
+            Err(QuartoError::SeatTaken(format!(
+                "{} seat is {}'s",
+                clock::seat_name(seat),
+                player
+            )))?
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file_store::test::TempDir;
+
+    #[test]
+    fn test_login_switches_and_keeps_identities() {
+        let dir = TempDir::new();
+        let path = dir.0.join("state").join("identities");
+        let mut identities = load(&path);
+        assert_eq!(identities.select(None).unwrap(), None);
+
+        let alice = identities.login("alice").unwrap().clone();
+        identities.login("bob").unwrap();
+        save(&path, &identities).unwrap();
+        let mut identities = load(&path);
+        assert_eq!(identities.select(None).unwrap().unwrap().name, "bob");
+        assert_eq!(identities.select(Some("alice")).unwrap(), Some(&alice));
+        assert!(matches!(
+            identities.select(Some("carol")),
+            Err(QuartoError::UnknownIdentity(name)) if name == "carol"
+        ));
+
+        // Logging in again keeps the token
+        assert_eq!(identities.login("alice").unwrap(), &alice);
+        assert_eq!(identities.known.len(), 2);
+        assert!(identities.login("alice smith").is_err());
+        assert!(identities.login("").is_err());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_corrupt_identity_file_is_moved_aside() {
+        let dir = TempDir::new();
+        let path = dir.0.join("identities");
+        for text in [
+            "quarto-identities 9\n",
+            "quarto-identities 1\ncurrent alice\n",
+            "quarto-identities 1\nidentity alice\n",
+            "\u{0}\u{1}binary",
+        ] {
+            fs::write(&path, text).unwrap();
+            assert_eq!(load(&path), Identities::default(), "{:?}", text);
+            assert!(!path.exists());
+            assert_eq!(
+                fs::read_to_string(path.with_extension("corrupt")).unwrap(),
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use crate::quarto::{Coord, Move, Piece, Quarto, QuartoError, Rules, Variant};
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+
+/* Games from other sites. Each ForeignFormat turns an export into games in this
+   crate's terms; `import` then plays every game through the rules and stores the
+   ones which hold up. A game which cannot be translated or played is reported
+   and skipped, and the rest of the file is still imported.
+*/
+/* A game by the site's id, translated or with why it could not be */
+pub type Translated = (String, Result<Imported, String>);
+
+pub trait ForeignFormat {
+    fn name(&self) -> &'static str;
+    /* Every game in the export; only a file which cannot be read at all fails
+       as a whole
+    */
+    fn read(&self, text: &str) -> Result<Vec<Translated>, QuartoError>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ImportFormat {
+    Bga,
+}
+
+impl ImportFormat {
+    pub fn adapter(&self) -> &'static dyn ForeignFormat {
+        match self {
+            ImportFormat::Bga => &Bga,
+        }
+    }
+}
+
+/* A translated game: the piece handed over to open it, then the moves as
+   `quarto move` plays them
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Imported {
+    pub rules: Rules,
+    pub opening: Piece,
+    pub moves: Vec<Move>,
+}
+
+impl Imported {
+    /* Plays the game from an empty board; plies are numbered like `replay` */
+    pub fn play(&self) -> Result<Quarto, String> {
+        let mut quarto = Quarto::with_rules(self.rules);
+        let opening = Move {
+            place: None,
+            hand: Some(self.opening),
+        };
+        quarto.apply_move(&opening);
+        for (ply, number) in self.moves.iter().zip(1..) {
+            if !quarto.apply_move(ply) {
+                return Err(format!("illegal move at ply {}: {}", number, ply));
+            }
+        }
+        Ok(quarto)
+    }
+}
+
+/* What became of one game of the file, e.g. 1234: imported as 6b1f..., 7 moves */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub source: String,
+    pub outcome: Result<(String, usize), String>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.outcome {
+            Ok((uuid, moves)) => {
+                write!(f, "{}: imported as {}, {} moves", self.source, uuid, moves)
+            }
+            Err(reason) => write!(f, "{}: skipped, {}", self.source, reason),
+        }
+    }
+}
+
+/* BoardGameArena-style exports: a games array whose entries hold the table id,
+   an optional variant and the actions in order, each handing over or placing a
+   piece:
+
+       {"games": [{"table_id": 1234, "variant": "classic", "moves": [
+           {"action": "give", "piece": 1},
+           {"action": "place", "x": 1, "y": 4},
+           ...]}]}
+
+   Pieces are numbered 1 to 16; one less than the number has a bit each for
+   light, tall, square and hollow from the least significant, so 1 is BSCF and
+   16 WTSH. x counts columns from the left and y rows from the top, both from 1,
+   so x 1, y 4 is a1. Numbers may also be given as strings and actions in any
+   case, with select and put read as give and place.
+*/
+pub struct Bga;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Scalar {
+    Number(i64),
+    Text(String),
+}
+
+impl Scalar {
+    fn number(&self) -> Option<i64> {
+        match self {
+            Scalar::Number(n) => Some(*n),
+            Scalar::Text(text) => text.trim().parse().ok(),
+        }
+    }
+}
+
+impl fmt::Display for Scalar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Scalar::Number(n) => write!(f, "{}", n),
+            Scalar::Text(text) => f.write_str(text),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BgaExport {
+    games: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+struct BgaGame {
+    #[serde(default)]
+    variant: Option<String>,
+    moves: Vec<BgaAction>,
+}
+
+#[derive(Deserialize)]
+struct BgaAction {
+    action: String,
+    #[serde(default)]
+    piece: Option<Scalar>,
+    #[serde(default)]
+    x: Option<Scalar>,
+    #[serde(default)]
+    y: Option<Scalar>,
+}
+
+/* BGA's bits are those of Piece::to_index in reverse order */
+fn bga_piece(id: &Scalar) -> Result<Piece, String> {
+    let bits = id
+        .number()
+        .filter(|n| (1..=16).contains(n))
+        .ok_or_else(|| format!("unknown piece code {}", id))?
+        - 1;
+    let index = (0..4).fold(0, |index, bit| index | (bits >> bit & 1) << (3 - bit));
+    Ok(Piece::from_index(index as u8).unwrap())
+}
+
+fn bga_square(x: &Option<Scalar>, y: &Option<Scalar>) -> Result<Coord, String> {
+    let (Some(x), Some(y)) = (x, y) else {
+        return Err("a placement without x and y".to_string());
+    };
+    let column = x.number().filter(|n| (1..=4).contains(n));
+    let row = y.number().filter(|n| (1..=4).contains(n));
+    match (column, row) {
+        (Some(column), Some(row)) => {
+            Ok(Coord::from_xy((row - 1) as usize, (column - 1) as usize).unwrap())
+        }
+        _ => Err(format!("no square at x {}, y {}", x, y)),
+    }
+}
+
+impl Bga {
+    fn translate(game: Value) -> Result<Imported, String> {
+        let game: BgaGame =
+            serde_json::from_value(game).map_err(|e| format!("unreadable game: {}", e))?;
+        let variant = match game.variant.as_deref() {
+            None => Variant::Classic,
+            Some(variant) => variant
+                .to_lowercase()
+                .parse()
+                .map_err(|_| format!("unknown variant {}", variant))?,
+        };
+        let mut opening = None;
+        let mut moves = Vec::new();
+        let mut placed = None;
+        for (action, number) in game.moves.iter().zip(1..) {
+            let at = |reason: String| format!("action {}: {}", number, reason);
+            match action.action.to_lowercase().as_str() {
+                "give" | "select" => {
+                    let Some(piece) = &action.piece else {
+                        return Err(at("a piece handed over without its code".to_string()));
+                    };
+                    let piece = bga_piece(piece).map_err(at)?;
+                    match (placed.take(), opening) {
+                        (Some(square), _) => moves.push(Move {
+                            place: Some(square),
+                            hand: Some(piece),
+                        }),
+                        (None, None) => opening = Some(piece),
+                        (None, Some(_)) => {
+                            return Err(at("two pieces handed over in a row".to_string()))
+                        }
+                    }
+                }
+                "place" | "put" => {
+                    if placed.is_some() {
+                        return Err(at("two placements in a row".to_string()));
+                    }
+                    placed = Some(bga_square(&action.x, &action.y).map_err(at)?);
+                }
+                other => return Err(at(format!("unknown action {:?}", other))),
+            }
+        }
+        // The winning placement hands nothing over
+        if let Some(square) = placed {
+            moves.push(Move {
+                place: Some(square),
+                hand: None,
+            });
+        }
+        Ok(Imported {
+            rules: Rules {
+                variant,
+                ..Rules::default()
+            },
+            opening: opening.ok_or("no opening piece")?,
+            moves,
+        })
+    }
+}
+
+impl ForeignFormat for Bga {
+    fn name(&self) -> &'static str {
+        "bga"
+    }
+
+    fn read(&self, text: &str) -> Result<Vec<Translated>, QuartoError> {
+        let export: BgaExport = serde_json::from_str(text).map_err(|e| {
+            QuartoError::CorruptRecord(format!("not a {} export: {}", self.name(), e))
+        })?;
+        Ok(export
+            .games
+            .into_iter()
+            .zip(1..)
+            .map(|(game, number)| {
+                let id = game.get("table_id").or_else(|| game.get("id"));
+                let source = match id {
+                    Some(id) => id.as_str().map_or_else(|| id.to_string(), str::to_string),
+                    None => format!("game {}", number),
+                };
+                (source, Bga::translate(game))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::quarto::GameStatus;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/bga-games.json");
+
+    #[test]
+    fn test_bga_pieces_and_squares() {
+        let piece = |n: i64| String::from(bga_piece(&Scalar::Number(n)).unwrap());
+        assert_eq!(piece(1), "BSCF");
+        assert_eq!(piece(2), "WSCF");
+        assert_eq!(piece(3), "BTCF");
+        assert_eq!(piece(9), "BSCH");
+        assert_eq!(piece(16), "WTSH");
+        assert_eq!(
+            String::from(bga_piece(&Scalar::Text(" 15 ".to_string())).unwrap()),
+            "BTSH"
+        );
+        for code in [
+            Scalar::Number(0),
+            Scalar::Number(17),
+            Scalar::Text("Q".to_string()),
+        ] {
+            assert_eq!(
+                bga_piece(&code).unwrap_err(),
+                format!("unknown piece code {}", code)
+            );
+        }
+        let square = |x: i64, y: i64| {
+            bga_square(&Some(Scalar::Number(x)), &Some(Scalar::Number(y))).map(|at| at.to_string())
+        };
+        assert_eq!(square(1, 4).unwrap(), "a1");
+        assert_eq!(square(4, 1).unwrap(), "d4");
+        assert_eq!(square(2, 3).unwrap(), "b2");
+        assert_eq!(square(5, 1).unwrap_err(), "no square at x 5, y 1");
+    }
+
+    #[test]
+    fn test_bga_fixture_imports_each_game_on_its_own() {
+        let games = Bga.read(FIXTURE).unwrap();
+        let sources: Vec<&str> = games.iter().map(|(source, _)| source.as_str()).collect();
+        assert_eq!(sources, ["1001", "1002", "1003"]);
+
+        let valid = games[0].1.as_ref().unwrap();
+        assert_eq!(valid.opening, "BSCF".parse().unwrap());
+        assert_eq!(valid.moves.len(), 7);
+        assert_eq!(valid.moves[0], "a1 BTSH".parse().unwrap());
+        let played = valid.play().unwrap();
+        assert_eq!(played.status(), GameStatus::Won);
+
+        assert_eq!(
+            games[1].1.as_ref().unwrap_err(),
+            "action 5: unknown piece code 17"
+        );
+
+        let illegal = games[2].1.as_ref().unwrap();
+        assert_eq!(
+            illegal.play().unwrap_err(),
+            "illegal move at ply 2: a1 WSCH"
+        );
+        assert!(matches!(
+            Bga.read("{\"tables\": []}"),
+            Err(QuartoError::CorruptRecord(_))
+        ));
+    }
+}
@@ -0,0 +1,514 @@
+use crate::book;
+use crate::db_policy::DbPolicy;
+use crate::event::{self, Event};
+use crate::game_id;
+use crate::pattern::{self, Match, Pattern};
+use crate::progress::Progress;
+use crate::quarto::{Move, Quarto, Rules};
+use futures::TryStreamExt;
+use sqlx::sqlite::SqliteQueryResult;
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite, SqliteConnection};
+use std::error::Error;
+use tracing::{info, warn};
+
+/* The position index: one row per position a game's board went through, numbered
+   like `search` numbers them, with the position as a share code and a hash of its
+   canonical book key so that every orientation of it is found together.
+
+   The table is optional. Where it exists, event::append keeps it up to date in
+   the transaction writing the event; `index rebuild` creates it and fills it in
+   from the events of every game.
+*/
+pub async fn init_index(db: &Pool<Sqlite>) -> Result<SqliteQueryResult, SqlxError> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS position
+        (
+              uuid VARCHAR NOT NULL,
+              number INTEGER NOT NULL,
+              hash INTEGER NOT NULL,
+              code VARCHAR NOT NULL,
+              PRIMARY KEY (uuid, number)
+        );"#,
+    )
+    .execute(db)
+    .await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS position_hash ON position (hash);")
+        .execute(db)
+        .await
+}
+
+pub async fn exists(conn: &mut SqliteConnection) -> Result<bool, SqlxError> {
+    let (count,) = sqlx::query_as::<_, (i64,)>(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'position'",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(count > 0)
+}
+
+/* FNV-1a of the canonical key, as the INTEGER with the same bits */
+pub fn position_hash(quarto: &Quarto) -> i64 {
+    let (key, _) = book::canonical(quarto);
+    key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    }) as i64
+}
+
+async fn insert(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    number: i64,
+    quarto: &Quarto,
+) -> Result<SqliteQueryResult, SqlxError> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO position (uuid, number, hash, code) VALUES (?1, ?2, ?3, ?4);",
+    )
+    .bind(uuid)
+    .bind(number)
+    .bind(position_hash(quarto))
+    .bind(quarto.to_share_code())
+    .execute(&mut *conn)
+    .await
+}
+
+/* Indexes the board `event` leaves. A game whose earlier positions are missing is
+   left for `index rebuild`, which `doctor` points out.
+*/
+pub async fn follow(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    event: &Event,
+) -> Result<(), SqlxError> {
+    let ply = match event {
+        Event::Created { position } | Event::Position { position } => {
+            if !exists(conn).await? {
+                return Ok(());
+            }
+            let Ok(quarto) = Quarto::from_share_code(position) else {
+                warn!(uuid = %game_id::logged(&uuid), %position, "not indexing an unreadable position");
+                return Ok(());
+            };
+            sqlx::query("DELETE FROM position WHERE uuid = ?1;")
+                .bind(uuid)
+                .execute(&mut *conn)
+                .await?;
+            insert(conn, uuid, 0, &quarto).await?;
+            return Ok(());
+        }
+        Event::Move { ply } => ply,
+        Event::Forfeited { .. }
+        | Event::Resigned { .. }
+        | Event::DrawOffered { .. }
+        | Event::DrawAgreed { .. }
+        | Event::AbortRequested { .. }
+        | Event::Aborted { .. } => return Ok(()),
+    };
+    if !exists(conn).await? {
+        return Ok(());
+    }
+    let last = sqlx::query_as::<_, (i64, String)>(
+        "SELECT number, code FROM position WHERE uuid = ?1 ORDER BY number DESC LIMIT 1",
+    )
+    .bind(uuid)
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some((number, code)) = last else {
+        return Ok(());
+    };
+    let next = Quarto::from_share_code(&code)
+        .ok()
+        .and_then(|mut quarto| quarto.apply_move(ply).then_some(quarto));
+    if let Some(quarto) = next {
+        insert(conn, uuid, number + 1, &quarto).await?;
+    } else {
+        warn!(uuid = %game_id::logged(&uuid), number, %ply, "index does not follow the move");
+    }
+    Ok(())
+}
+
+/* Replaces the index with the positions replayed from every game's events.
+   Games whose events do not replay are left out. Reports replaying the games,
+   then writing their positions.
+*/
+pub async fn rebuild(
+    db: &Pool<Sqlite>,
+    policy: &DbPolicy,
+    progress: &dyn Progress,
+) -> Result<usize, Box<dyn Error>> {
+    let uuids = policy.run("list games", || event::games(db)).await?;
+    progress.phase("replaying games", Some(uuids.len() as u64));
+    let mut games = Vec::new();
+    for uuid in uuids {
+        let events = policy
+            .run("load events", || event::load(db, &uuid, 0))
+            .await?;
+        match pattern::positions(&events) {
+            Ok(positions) => games.push((uuid, positions)),
+            Err(e) => warn!(uuid = %game_id::logged(&uuid), ?e, "skipping unreadable game"),
+        }
+        progress.advance(1);
+    }
+    let positions = games.iter().map(|(_, positions)| positions.len() as u64);
+    progress.phase("indexing positions", Some(positions.sum()));
+    policy.run("create index", || init_index(db)).await?;
+    let indexed = policy
+        .once("write index", write(db, &games, progress))
+        .await?;
+    progress.finish();
+    info!(games = games.len(), indexed, "rebuilt the position index");
+    Ok(indexed)
+}
+
+/* Replaces every row of the index with the positions of `games` in one
+   transaction
+*/
+async fn write(
+    db: &Pool<Sqlite>,
+    games: &[(String, Vec<Quarto>)],
+    progress: &dyn Progress,
+) -> Result<usize, SqlxError> {
+    let mut tx = db.begin().await?;
+    sqlx::query("DELETE FROM position;")
+        .execute(&mut *tx)
+        .await?;
+    let mut indexed = 0;
+    for (uuid, positions) in games {
+        for (number, quarto) in (0..).zip(positions) {
+            insert(&mut tx, uuid, number, quarto).await?;
+            indexed += 1;
+            progress.advance(1);
+        }
+    }
+    tx.commit().await?;
+    Ok(indexed)
+}
+
+/* A game whose rows in the index differ from what its events replay to */
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Gap {
+    pub uuid: String,
+    /* Rows agreeing with the events, and rows which do not */
+    pub indexed: usize,
+    pub stale: usize,
+    pub expected: usize,
+}
+
+impl Gap {
+    /* e.g. 6b1f2a3c: 3 of 5 positions indexed, 1 stale, with the uuid shortened
+       among `known`
+    */
+    pub fn line(&self, known: &[String]) -> String {
+        let mut line = format!(
+            "{}: {} of {} positions indexed",
+            game_id::short(&self.uuid, known),
+            self.indexed,
+            self.expected
+        );
+        if self.stale > 0 {
+            line += &format!(", {} stale", self.stale);
+        }
+        line
+    }
+}
+
+/* Checks the index against the events of every game, for `doctor` */
+pub async fn check(db: &Pool<Sqlite>, policy: &DbPolicy) -> Result<Vec<Gap>, Box<dyn Error>> {
+    let mut gaps = Vec::new();
+    for uuid in policy.run("list games", || event::games(db)).await? {
+        let events = policy
+            .run("load events", || event::load(db, &uuid, 0))
+            .await?;
+        let Ok(positions) = pattern::positions(&events) else {
+            continue;
+        };
+        let rows = policy.run("load index rows", || rows_of(db, &uuid)).await?;
+        let indexed = rows
+            .iter()
+            .filter(|(number, code)| {
+                usize::try_from(*number)
+                    .ok()
+                    .and_then(|n| positions.get(n))
+                    .is_some_and(|quarto| quarto.to_share_code() == *code)
+            })
+            .count();
+        if indexed != positions.len() || indexed != rows.len() {
+            gaps.push(Gap {
+                uuid,
+                indexed,
+                stale: rows.len() - indexed,
+                expected: positions.len(),
+            });
+        }
+    }
+    Ok(gaps)
+}
+
+/* The index rows of one game, in move order */
+async fn rows_of(db: &Pool<Sqlite>, uuid: &str) -> Result<Vec<(i64, String)>, SqlxError> {
+    sqlx::query_as::<_, (i64, String)>(
+        "SELECT number, code FROM position WHERE uuid = ?1 ORDER BY number",
+    )
+    .bind(uuid)
+    .fetch_all(db)
+    .await
+}
+
+/* Every indexed position matching the pattern, games in the order they were
+   created. Rows are streamed, so no more than one is held at a time.
+*/
+pub async fn search(db: &Pool<Sqlite>, pattern: &Pattern) -> Result<Vec<Match>, SqlxError> {
+    let mut rows = sqlx::query_as::<_, (String, i64, String)>(
+        r#"
+        SELECT position.uuid, position.number, position.code
+        FROM position JOIN game ON game.uuid = position.uuid
+        ORDER BY game.id, position.number
+        "#,
+    )
+    .fetch(db);
+    let mut matches = Vec::new();
+    while let Some((uuid, number, code)) = rows.try_next().await? {
+        if Quarto::from_share_code(&code).is_ok_and(|quarto| pattern.matches(&quarto)) {
+            matches.push(Match {
+                uuid,
+                number: number as usize,
+            });
+        }
+    }
+    Ok(matches)
+}
+
+/* Where the position, in any orientation, occurred */
+pub async fn occurrences(db: &Pool<Sqlite>, quarto: &Quarto) -> Result<Vec<Match>, SqlxError> {
+    let (key, _) = book::canonical(quarto);
+    let rows = sqlx::query_as::<_, (String, i64, String)>(
+        r#"
+        SELECT position.uuid, position.number, position.code
+        FROM position JOIN game ON game.uuid = position.uuid
+        WHERE position.hash = ?1
+        ORDER BY game.id, position.number
+        "#,
+    )
+    .bind(position_hash(quarto))
+    .fetch_all(db)
+    .await?;
+    // Different positions can share a hash
+    Ok(rows
+        .into_iter()
+        .filter(|(_, _, code)| {
+            Quarto::from_share_code(code).is_ok_and(|found| book::canonical(&found).0 == key)
+        })
+        .map(|(uuid, number, _)| Match {
+            uuid,
+            number: number as usize,
+        })
+        .collect())
+}
+
+/* The moves of every indexed game played from the empty board under `rules`, for
+   Book::from_games. Each move is the legal one leading to the next position;
+   games set to a position part way are left out.
+*/
+pub async fn games(db: &Pool<Sqlite>, rules: Rules) -> Result<Vec<Vec<Move>>, SqlxError> {
+    let rows = sqlx::query_as::<_, (String, String)>(
+        "SELECT uuid, code FROM position ORDER BY uuid, number",
+    )
+    .fetch_all(db)
+    .await?;
+    let mut games = Vec::new();
+    for chunk in rows.chunk_by(|a, b| a.0 == b.0) {
+        let Ok(positions) = chunk
+            .iter()
+            .map(|(_, code)| Quarto::from_share_code(code))
+            .collect::<Result<Vec<_>, _>>()
+        else {
+            continue;
+        };
+        if let Some(moves) = moves_between(&positions, rules) {
+            games.push(moves);
+        }
+    }
+    Ok(games)
+}
+
+fn moves_between(positions: &[Quarto], rules: Rules) -> Option<Vec<Move>> {
+    let first = positions.first()?;
+    if first.rules != rules || first.placed_pieces() > 0 {
+        return None;
+    }
+    // New games are stored with the opening piece already handed over
+    let mut moves: Vec<Move> = first
+        .next_piece
+        .map(|hand| Move {
+            place: None,
+            hand: Some(hand),
+        })
+        .into_iter()
+        .collect();
+    for pair in positions.windows(2) {
+        let target = pair[1].to_share_code();
+        let mv = pair[0].legal_moves().into_iter().find(|mv| {
+            let mut next = pair[0].clone();
+            next.play_legal(mv);
+            next.to_share_code() == target
+        })?;
+        moves.push(mv);
+    }
+    Some(moves)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory_db;
+    use crate::progress::test::Recording;
+    use crate::quarto::Symmetry;
+    use indoc::indoc;
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn test_position_hash_ignores_orientation() {
+        let mut quarto = Quarto::new();
+        for ply in ["BSCF", "a1 WTSH", "b3 BTCH"] {
+            assert!(quarto.apply_move(&ply.parse().unwrap()));
+        }
+        for symmetry in Symmetry::iter() {
+            let turned = quarto.transformed(symmetry);
+            assert_eq!(position_hash(&turned), position_hash(&quarto));
+        }
+        let mut other = quarto.clone();
+        assert!(other.apply_move(&"c2 WSSF".parse().unwrap()));
+        assert_ne!(position_hash(&other), position_hash(&quarto));
+    }
+
+    #[test]
+    fn test_moves_between_positions() {
+        let mut quarto = Quarto::new();
+        let plies: Vec<Move> = ["BSCF", "a1 WTSH", "b3 BTCH", "c2 WSSF"]
+            .iter()
+            .map(|ply| ply.parse().unwrap())
+            .collect();
+        let mut positions = Vec::new();
+        for ply in &plies {
+            assert!(quarto.apply_move(ply));
+            positions.push(quarto.clone());
+        }
+        assert_eq!(moves_between(&positions, Rules::default()), Some(plies));
+        assert_eq!(moves_between(&positions[1..], Rules::default()), None);
+    }
+
+    async fn play(db: &Pool<Sqlite>, uuid: &str, events: &[Event]) {
+        let mut tx = db.begin().await.unwrap();
+        for event in events {
+            event::append(&mut tx, uuid, event, 0).await.unwrap();
+        }
+        tx.commit().await.unwrap();
+    }
+
+    fn ply(text: &str) -> Event {
+        Event::Move {
+            ply: text.parse().unwrap(),
+        }
+    }
+
+    async fn rows(db: &Pool<Sqlite>) -> Vec<(String, i64, i64, String)> {
+        sqlx::query_as("SELECT uuid, number, hash, code FROM position ORDER BY uuid, number")
+            .fetch_all(db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_matches_incremental_index() {
+        let db = memory_db().await;
+        let mut opening = Quarto::new();
+        opening.apply_move(&"BSCF".parse().unwrap());
+        let created = Event::Created {
+            position: opening.to_share_code(),
+        };
+        let mut set = opening.clone();
+        set.apply_move(&"d4 WTSH".parse().unwrap());
+        for uuid in ["a", "b", "c"] {
+            sqlx::query("INSERT INTO game (uuid) VALUES (?1);")
+                .bind(uuid)
+                .execute(&db)
+                .await
+                .unwrap();
+        }
+        play(&db, "a", &[created.clone(), ply("a1 WTSH"), ply("b3 BTCH")]).await;
+        play(&db, "b", &[created.clone(), ply("c3 WTSH")]).await;
+        // Setting the board starts the numbering again
+        let position = Event::Position {
+            position: set.to_share_code(),
+        };
+        play(&db, "b", &[position, ply("a2 BTCH")]).await;
+        play(&db, "c", &[created.clone(), Event::Resigned { seat: 0 }]).await;
+        play(&db, "a", &[ply("c2 WSSF")]).await;
+
+        let incremental = rows(&db).await;
+        let counts: Vec<(&str, usize)> = ["a", "b", "c"]
+            .into_iter()
+            .map(|uuid| (uuid, incremental.iter().filter(|r| r.0 == uuid).count()))
+            .collect();
+        assert_eq!(counts, vec![("a", 4), ("b", 2), ("c", 1)]);
+        assert!(check(&db, &DbPolicy::default()).await.unwrap().is_empty());
+
+        let progress = Recording::default();
+        assert_eq!(
+            rebuild(&db, &DbPolicy::default(), &progress).await.unwrap(),
+            7
+        );
+        assert_eq!(rows(&db).await, incremental);
+        assert_eq!(
+            progress.phases(),
+            vec![
+                ("replaying games".to_string(), Some(3), 3),
+                ("indexing positions".to_string(), Some(7), 7)
+            ]
+        );
+        assert!(progress.finished());
+
+        // Where "a" went after its second move, in another orientation
+        let mut turned = opening.clone();
+        turned.apply_move(&"a1 WTSH".parse().unwrap());
+        turned.apply_move(&"b3 BTCH".parse().unwrap());
+        let found = occurrences(&db, &turned.transformed(Symmetry::Rotate90))
+            .await
+            .unwrap();
+        assert_eq!(
+            found
+                .iter()
+                .map(|found| found.line(&[]))
+                .collect::<Vec<_>>(),
+            vec!["a  move 2"]
+        );
+        let pattern: Pattern = indoc! {
+        r#".... .... .... ....
+           .... .... .... ....
+           .... .... .... ....
+           **** .... .... ...."#}
+        .parse()
+        .unwrap();
+        let found: Vec<String> = search(&db, &pattern)
+            .await
+            .unwrap()
+            .iter()
+            .map(|found| found.line(&[]))
+            .collect();
+        assert_eq!(found, vec!["a  move 1", "a  move 2", "a  move 3"]);
+        // "b" was set to a position part way
+
+        assert_eq!(games(&db, Rules::default()).await.unwrap().len(), 2);
+
+        sqlx::query("DELETE FROM position WHERE uuid = 'a' AND number = 3;")
+            .execute(&db)
+            .await
+            .unwrap();
+        let gaps = check(&db, &DbPolicy::default()).await.unwrap();
+        assert_eq!(
+            gaps.iter().map(|gap| gap.line(&[])).collect::<Vec<_>>(),
+            vec!["a: 3 of 4 positions indexed"]
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+
+/* Items with a separator between them, written straight into a formatter: what
+   Iterator::intersperse would give on nightly, without the Vec of strings which
+   join needs first. Each formatting walks a clone of the iterator, so it should
+   be a cheap one over borrowed data. Nests, since it is Display itself:
+
+       intersperse(rows.iter().map(|row| intersperse(row, " ")), "\n")
+*/
+#[derive(Clone)]
+pub struct Intersperse<I, S> {
+    items: I,
+    separator: S,
+}
+
+pub fn intersperse<I, S>(items: I, separator: S) -> Intersperse<I::IntoIter, S>
+where
+    I: IntoIterator,
+    I::IntoIter: Clone,
+{
+    Intersperse {
+        items: items.into_iter(),
+        separator,
+    }
+}
+
+impl<I, S> fmt::Display for Intersperse<I, S>
+where
+    I: Iterator + Clone,
+    I::Item: fmt::Display,
+    S: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut items = self.items.clone();
+        if let Some(first) = items.next() {
+            first.fmt(f)?;
+            for item in items {
+                self.separator.fmt(f)?;
+                item.fmt(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_intersperse_writes_like_join() {
+        let words = ["a", "b", "c"];
+        assert_eq!(intersperse(words, ", ").to_string(), words.join(", "));
+        assert_eq!(intersperse(["a"], ", ").to_string(), "a");
+        assert_eq!(intersperse(Vec::<u8>::new(), ", ").to_string(), "");
+        let rows = [[1, 2], [3, 4]];
+        let grid = intersperse(rows.iter().map(|row| intersperse(row, " ")), '\n');
+        assert_eq!(grid.to_string(), "1 2\n3 4");
+        // Formatting again starts from the first item
+        assert_eq!(grid.to_string(), "1 2\n3 4");
+    }
+}
@@ -1,50 +1,757 @@
-use crate::quarto::BoardState;
-use crate::quarto::{Piece, Quarto, QuartoError};
-use sqlx::sqlite::SqliteQueryResult;
+use crate::book::Merge;
+use crate::cache::CachedAnalysis;
+use crate::clock::{Clock, Seat, TimeControl};
+use crate::concede::Abort;
+use crate::db_policy::{DbPolicy, DbTimeout};
+use crate::deadline::{Deadline, MoveDeadline};
+use crate::event::Event;
+use crate::export::{ExportFormat, ScoreSheet};
+use crate::file_store::FileStore;
+use crate::game_id::GameId;
+use crate::generate::Engine;
+use crate::identity::Identities;
+use crate::import::{ImportFormat, Report};
+use crate::pattern::Pattern;
+use crate::progress::Progress;
+use crate::quarto::{
+    Adjudication, Coord, GameRng, GameStatus, Move, MovePreview, ParseOptions, Piece, Place,
+    Quarto, QuartoError, Rules, Symmetry, Variant,
+};
+use crate::shutdown::Shutdown;
+use crate::spectate::{DelayedFeed, SpectatorDelay};
+use crate::template::{Settings, Template};
+use crate::time::SystemClock;
+use crate::timeline::TimeStyle;
+use crate::verify::Outcome;
+use sqlx::sqlite::{SqlitePoolOptions, SqliteQueryResult};
 
 use sqlx::migrate::MigrateDatabase;
+use sqlx::Error as SqlxError;
 use sqlx::{Pool, Sqlite, SqlitePool};
 use std::convert::TryFrom;
 use std::env;
 use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Handle;
 
-use log::{error, info};
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
 
-use clap::{Parser, Subcommand};
-use uuid::Uuid;
+use clap::{Parser, Subcommand, ValueEnum};
+mod analysis;
+mod book;
+mod cache;
+mod census;
+mod clock;
+mod compat;
+mod concede;
+mod db_policy;
+mod deadline;
+#[cfg(feature = "setup")]
+mod edit;
+mod event;
+mod export;
+mod file_store;
+mod game_id;
+mod generate;
+mod idempotency;
+mod identity;
+mod import;
+mod index;
+mod intersperse;
+mod notation;
+mod page;
+mod pattern;
+mod play;
+mod progress;
 mod quarto;
+mod replay;
+mod resume;
+mod rpc;
+mod seed;
+mod shutdown;
+mod spectate;
+mod sql_log;
+mod stats;
+mod store;
+mod summary;
+mod template;
+mod time;
+mod timeline;
+mod todo;
+mod verify;
 
 #[derive(Clone, Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// Format of the log lines written to stderr
+    #[arg(long, value_enum, global = true, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Keep games as files under a directory instead of in DATABASE_URL, e.g. file:games
+    #[arg(long, global = true)]
+    store: Option<StoreLocation>,
+    /// Give up on a database operation after this long, e.g. 10s; defaults to the config
+    /// file's [database] timeout, or 5s
+    #[arg(long, global = true)]
+    db_timeout: Option<DbTimeout>,
+    /// Draw no progress bars; they are also left out when stdout is not a terminal
+    #[arg(long, global = true)]
+    quiet: bool,
+    /// Print game ids whole instead of by their first 8 characters
+    #[arg(long, global = true)]
+    full_ids: bool,
+    /// Log every SQL statement with its row counts and time, and the binds of game
+    /// writes, at debug level; also set by debug_sql = "true" under [database]
+    #[arg(long, global = true)]
+    debug_sql: bool,
+    /// Only read the database: commands that would write to it fail before they
+    /// start. Also on when DATABASE_URL has mode=ro or its file cannot be written
+    #[arg(long, global = true)]
+    read_only: bool,
+    /// Play under this identity, logged in here before, instead of the one in use
+    #[arg(long = "as", global = true, value_name = "NAME")]
+    identity: Option<String>,
     #[clap(subcommand)]
     command: Command,
 }
 
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Debug)]
+enum StoreLocation {
+    File(PathBuf),
+}
+
+impl FromStr for StoreLocation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<StoreLocation, String> {
+        match s.strip_prefix("file:") {
+            Some(dir) if !dir.is_empty() => Ok(StoreLocation::File(PathBuf::from(dir))),
+            _ => Err(format!("expected file:<dir>, found {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
 #[derive(Clone, Debug, Subcommand)]
 enum Command {
     Init {
         #[arg(long)]
         force: bool,
     },
-    NewGame,
+    NewGame {
+        /// Named settings from the config file; the flags below override them
+        #[arg(long)]
+        template: Option<String>,
+        /// classic or advanced
+        #[arg(long, conflicts_with_all = ["from_code", "from_file"])]
+        variant: Option<Variant>,
+        /// Per-player budget and increment, e.g. 10m+5s
+        #[arg(long)]
+        clock: Option<TimeControl>,
+        /// Correspondence limit for every move, e.g. 72h
+        #[arg(long)]
+        deadline: Option<Deadline>,
+        /// Show the game to `watch` this long after it is played, e.g. 30s
+        #[arg(long)]
+        spectator_delay: Option<SpectatorDelay>,
+        /// Start from a shared position instead of an empty board
+        #[arg(long)]
+        from_code: Option<String>,
+        /// Start from game text as `show` writes it, reporting what had to be put right
+        #[arg(long, conflicts_with = "from_code")]
+        from_file: Option<PathBuf>,
+        /// Seed for the game's randomness, shown by `show`; drawn from the OS by default
+        #[arg(long)]
+        seed: Option<u64>,
+    },
     Move {
-        uuid: String,
-        x: usize,
-        y: usize,
-        piece: String,
+        uuid: GameId,
+        /// The square to place on, e.g. b3; x y is still read but deprecated
+        #[arg(num_args = 1..=2, required = true, value_name = "SQUARE")]
+        at: Vec<Place>,
+        /// Written in the notation of the config file, BSCF by default, or in words:
+        /// "white tall circle hole", or "w t c h" for short
+        #[arg(value_parser = notation::piece_argument)]
+        piece: Piece,
+        /// Report what the move would lead to without playing it
+        #[arg(long)]
+        check: bool,
+        /// Makes retrying safe: the move is played once, and every try with this key
+        /// prints the reply of the first
+        #[arg(long)]
+        key: Option<String>,
+        /// Print the preview and any broken rules as JSON
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
     },
     Quarto {
-        uuid: String,
-        x: usize,
-        y: usize,
+        uuid: GameId,
+        #[arg(num_args = 1..=2, required = true, value_name = "SQUARE")]
+        at: Vec<Place>,
+    },
+    Show {
+        #[arg(required_unless_present = "code")]
+        uuid: Option<GameId>,
+        /// Show a shared position instead of a stored game
+        #[arg(long, conflicts_with = "uuid")]
+        code: Option<String>,
+        /// Describe the position in prose instead of drawing the board
+        #[arg(long)]
+        describe: bool,
+        /// Clockwise rotation in degrees: 0, 90, 180 or 270
+        #[arg(long, default_value_t = 0)]
+        orientation: u16,
+        /// The position after this many moves; negative counts back, -1 is one move ago
+        #[arg(long, allow_hyphen_values = true, conflicts_with = "code")]
+        at: Option<i64>,
+        /// Print the position alone, as JSON
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+        /// Add how dangerous each empty cell is to fill, from 0 to 255
+        #[arg(long)]
+        analysis: bool,
+    },
+    /// List every legal move for the player to move, one per line
+    Legal {
+        uuid: GameId,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Print a short code for sharing the current position
+    Share {
+        uuid: GameId,
+    },
+    /// Declare the player to move lost on time once their clock has run out
+    Flag {
+        uuid: GameId,
+    },
+    /// Concede the game; the other player wins
+    Resign {
+        uuid: GameId,
+        /// The player resigning, 1st or 2nd; defaults to the seat of the identity in use
+        #[arg(long)]
+        seat: Option<Seat>,
+    },
+    /// Offer a draw, open until the next placement
+    DrawOffer {
+        uuid: GameId,
+        /// The player offering, 1st or 2nd; defaults to the seat of the identity in use
+        #[arg(long)]
+        seat: Option<Seat>,
+    },
+    /// Accept the other player's open draw offer
+    DrawAccept {
+        uuid: GameId,
+        /// The player accepting, 1st or 2nd; defaults to the seat of the identity in use
+        #[arg(long)]
+        seat: Option<Seat>,
+    },
+    /// Call the game off with no result: alone within the first plies, set by
+    /// abort_before under [games] in the config file, else once both players abort
+    Abort {
+        uuid: GameId,
+        /// The player aborting, 1st or 2nd; defaults to the seat of the identity in use
+        #[arg(long)]
+        seat: Option<Seat>,
+    },
+    /// Cell usage, winning pieces and game lengths over finished games
+    Stats {
+        /// Include the cell usage grid
+        #[arg(long)]
+        heatmap: bool,
+        /// Count aborted games too, as ended by abort
+        #[arg(long)]
+        include_aborted: bool,
+        /// Include the per-game summaries written by `analyze --all`
+        #[arg(long)]
+        analysis: bool,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// List stored games, oldest first, a page at a time
+    List {
+        #[arg(long, default_value_t = page::DEFAULT_LIMIT,
+              value_parser = clap::value_parser!(u32).range(1..))]
+        limit: u32,
+        /// Start after this game, as printed at the end of the previous page
+        #[arg(long, default_value_t = 0)]
+        cursor: i64,
+        /// Only games with a seat taken by the identity in use
+        #[arg(long)]
+        mine: bool,
+    },
+    /// List the games waiting on a move from the identity in use, least time left first
+    Todo {
+        /// Print only how many games there are, e.g. for a shell prompt
+        #[arg(long, conflicts_with = "format")]
+        count: bool,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// List a game's events, oldest first, a page at a time
+    History {
+        uuid: GameId,
+        #[arg(long, default_value_t = page::DEFAULT_LIMIT,
+              value_parser = clap::value_parser!(u32).range(1..))]
+        limit: u32,
+        /// Start after this event, as printed at the end of the previous page
+        #[arg(long, default_value_t = 0)]
+        cursor: i64,
+        /// Also show how long each move took
+        #[arg(long)]
+        long: bool,
+    },
+    /// Print a game's timeline: every move, offer, resignation and ending, with when
+    Log {
+        uuid: GameId,
+        #[arg(long, value_enum, default_value_t = TimeStyle::Absolute)]
+        time: TimeStyle,
+    },
+    /// Write a game as a printable score sheet on stdout
+    Export {
+        uuid: GameId,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Latex)]
+        format: ExportFormat,
+        /// The 1st player's name; left as a line to fill in when not given
+        #[arg(long)]
+        first: Option<String>,
+        /// The 2nd player's name
+        #[arg(long)]
+        second: Option<String>,
+    },
+    /// Store the games of another site's export, reporting each one
+    Import {
+        file: PathBuf,
+        #[arg(long, value_enum)]
+        format: ImportFormat,
+    },
+    /// List the moves of a game, optionally with what each one gave away
+    Replay {
+        uuid: GameId,
+        /// Search each position and flag the moves which lose ground
+        #[arg(long)]
+        evaluate: bool,
+        /// Moves to search ahead when evaluating
+        #[arg(long, default_value_t = 4)]
+        depth: u32,
+    },
+    /// Find the moves after which a game's board matched a pattern or a position
+    Search {
+        /// Board text with wildcards: ****, ?T?? and so on, ---- and ....
+        #[arg(long, required_unless_present = "code", conflicts_with = "code")]
+        pattern: Option<PathBuf>,
+        /// A share code; the position counts in any orientation
+        #[arg(long)]
+        code: Option<String>,
+    },
+    Index {
+        #[clap(subcommand)]
+        command: IndexCommand,
+    },
+    /// Forfeit in-progress games whose player to move is past the deadline
+    Sweep,
+    Analyze {
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        uuid: Option<GameId>,
+        /// Search every move of every matching game and store a summary per game
+        #[arg(long)]
+        all: bool,
+        /// Only games matching this, e.g. status=finished or variant=advanced
+        #[arg(long, requires = "all")]
+        filter: Vec<summary::Filter>,
+        /// Moves to search ahead from each position with --all
+        #[arg(long, default_value_t = 5, requires = "all")]
+        depth: u32,
+        /// Games searched at a time with --all; defaults to one per CPU
+        #[arg(long, requires = "all")]
+        jobs: Option<usize>,
+        #[arg(long, default_value_t = 1000)]
+        playouts: u32,
+        /// Defaults to the game's seed at this move
+        #[arg(long)]
+        seed: Option<u64>,
+        #[arg(long)]
+        no_cache: bool,
+        /// Show where every line stands on each property
+        #[arg(long)]
+        explain: bool,
+    },
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommand,
+    },
+    /// Suggest a move, from the opening book when it covers the position
+    Hint {
+        uuid: GameId,
+        /// Playouts per legal move when searching
+        #[arg(long, default_value_t = 200)]
+        playouts: u32,
+        /// Defaults to the game's seed at this move
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Search even when the book has the position
+        #[arg(long)]
+        no_book: bool,
+        /// Explain the double threat the position offers, if any
+        #[arg(long)]
+        teach: bool,
+    },
+    /// Check for a win the player to move can force, whatever the other player
+    /// places and hands back
+    Prove {
+        /// A game's uuid or a share code
+        target: String,
+        /// Moves, counting both players', within which the win must come
+        #[arg(long, default_value_t = 3)]
+        plies: u8,
     },
+    /// Count the distinct positions reachable from the empty board, ply by ply
+    CountPositions {
+        /// Up to 17: a piece handed over, then one for each cell
+        #[arg(long, value_parser = clap::value_parser!(u8).range(0..=17))]
+        plies: u8,
+        /// Count positions the same under a rotation or reflection of the board once
+        #[arg(long)]
+        canonical: bool,
+        /// Remember positions in a Bloom filter of this many MiB instead of exactly;
+        /// the counts become lower bounds
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        bloom: Option<u32>,
+    },
+    Book {
+        #[clap(subcommand)]
+        command: BookCommand,
+    },
+    /// Play engine games against each other into shard files, picking up where an
+    /// earlier run into the same directory stopped
+    Generate {
+        #[arg(long)]
+        games: usize,
+        /// The 1st player's engine: random, or playout:N
+        #[arg(long, default_value = "random")]
+        engine1: Engine,
+        /// The 2nd player's engine
+        #[arg(long, default_value = "random")]
+        engine2: Engine,
+        #[arg(long)]
+        out: PathBuf,
+        /// Threads playing games; defaults to one per CPU
+        #[arg(long)]
+        jobs: Option<usize>,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        #[arg(long, default_value_t = Variant::Classic)]
+        variant: Variant,
+        /// End games at a dead position, scored as a loss or a draw for the player
+        /// left to hand over; by default they are played out
+        #[arg(long)]
+        adjudicate: Option<Adjudication>,
+    },
+    /// Play interactively with undo and redo, writing to the database on commit
+    Play {
+        /// Game to start with; others can be opened from the prompt. Without one the
+        /// last session is offered back, or a new game is started
+        uuid: Option<GameId>,
+        /// Commit uncommitted moves at quit instead of discarding them
+        #[arg(long)]
+        autocommit: bool,
+        /// Start a new game instead of offering the last session back
+        #[arg(long, conflicts_with = "uuid")]
+        no_resume: bool,
+    },
+    /// Newline-delimited JSON-RPC on stdin/stdout
+    Rpc,
+    /// Compose a position interactively and save it as a board file or a new game
+    #[cfg(feature = "setup")]
+    Edit {
+        /// Start from a stored game
+        #[arg(long, conflicts_with = "from_board")]
+        from: Option<GameId>,
+        /// Start from a board file written by `save --as-board`
+        #[arg(long)]
+        from_board: Option<PathBuf>,
+    },
+    /// Check a finished game against its events and the result hash stored when it ended
+    Verify {
+        uuid: GameId,
+    },
+    /// List the templates in the config file with the settings they expand to
+    Templates,
+    /// Report games stored in an older board format, or unreadable, and gaps in the
+    /// position index
+    Doctor,
+    /// Rewrite games stored in an older board format in the current one
+    MigrateBoardFormat,
+    /// Follow a game read-only, behind by its spectator delay, until it is over
+    Watch {
+        uuid: GameId,
+    },
+    /// Print the identity in use
+    Whoami,
+    /// Use this identity from now on, adding it the first time
+    Login { name: String },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum CacheCommand {
+    Stats,
+    Clear,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum IndexCommand {
+    /// Index every position of every game again from the events
+    Rebuild,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum BookCommand {
+    /// Add the best moves of the first plies to the opening book
+    Build {
+        /// Game transcripts, one game per line as `;`-separated moves
+        #[arg(
+            long,
+            required_unless_present_any = ["from_solver", "from_index"],
+            conflicts_with_all = ["from_solver", "from_index"]
+        )]
+        from_games: Option<PathBuf>,
+        /// Search the positions with the playout engine instead
+        #[arg(long, conflicts_with = "from_index")]
+        from_solver: bool,
+        /// Use the games in the position index instead
+        #[arg(long)]
+        from_index: bool,
+        /// Plies from the start covered by the book
+        #[arg(long, default_value_t = 2)]
+        depth: usize,
+        #[arg(long, default_value_t = Variant::Classic)]
+        variant: Variant,
+        /// As given to `generate`, for games which end at a dead position
+        #[arg(long)]
+        adjudicate: Option<Adjudication>,
+        /// Playouts per legal move for --from-solver
+        #[arg(long, default_value_t = 100)]
+        playouts: u32,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Write the opening book as text, one position per line
+    Export {
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Add the positions of a book written by `book export`
+    Import {
+        file: PathBuf,
+        /// For positions both books have, add up the weights of the same move or
+        /// keep the one with more weight
+        #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "sum",
+              default_value_t = Merge::Sum)]
+        merge: Merge,
+        /// Empty the book first
+        #[arg(long, conflicts_with = "merge")]
+        replace: bool,
+    },
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Init { .. } => "init",
+            Command::NewGame { .. } => "new-game",
+            Command::Move { .. } => "move",
+            Command::Quarto { .. } => "quarto",
+            Command::Show { .. } => "show",
+            Command::Legal { .. } => "legal",
+            Command::Share { .. } => "share",
+            Command::Flag { .. } => "flag",
+            Command::Resign { .. } => "resign",
+            Command::DrawOffer { .. } => "draw-offer",
+            Command::DrawAccept { .. } => "draw-accept",
+            Command::Abort { .. } => "abort",
+            Command::Sweep => "sweep",
+            Command::Stats { .. } => "stats",
+            Command::List { .. } => "list",
+            Command::Todo { .. } => "todo",
+            Command::History { .. } => "history",
+            Command::Log { .. } => "log",
+            Command::Export { .. } => "export",
+            Command::Import { .. } => "import",
+            Command::Replay { .. } => "replay",
+            Command::Search { .. } => "search",
+            Command::Index { .. } => "index",
+            Command::Analyze { .. } => "analyze",
+            Command::Cache { .. } => "cache",
+            Command::Hint { .. } => "hint",
+            Command::Prove { .. } => "prove",
+            Command::CountPositions { .. } => "count-positions",
+            Command::Book { .. } => "book",
+            Command::Generate { .. } => "generate",
+            Command::Play { .. } => "play",
+            Command::Rpc => "rpc",
+            #[cfg(feature = "setup")]
+            Command::Edit { .. } => "edit",
+            Command::Verify { .. } => "verify",
+            Command::Templates => "templates",
+            Command::Doctor => "doctor",
+            Command::MigrateBoardFormat => "migrate-board-format",
+            Command::Watch { .. } => "watch",
+            Command::Whoami => "whoami",
+            Command::Login { .. } => "login",
+        }
+    }
+
+    /* Whether the command changes the database, and so fails at once on a read-only
+       one. `generate` writes shard files only, and `rpc` keeps its games in memory.
+    */
+    fn writes(&self) -> bool {
+        match self {
+            Command::Move { check, .. } => !check,
+            Command::Analyze { all, .. } => *all,
+            Command::Cache { command } => matches!(command, CacheCommand::Clear),
+            Command::Book { command } => !matches!(command, BookCommand::Export { .. }),
+            Command::Init { .. }
+            | Command::NewGame { .. }
+            | Command::Flag { .. }
+            | Command::Resign { .. }
+            | Command::DrawOffer { .. }
+            | Command::DrawAccept { .. }
+            | Command::Abort { .. }
+            | Command::Sweep
+            | Command::Import { .. }
+            | Command::Index { .. }
+            | Command::Play { .. }
+            | Command::MigrateBoardFormat => true,
+            #[cfg(feature = "setup")]
+            Command::Edit { .. } => true,
+            _ => false,
+        }
+    }
+
+    /* Whether the command works on the database. `init` opens it once the file is
+       created, and `prove` only for a game given by its uuid.
+    */
+    fn opens_store(&self) -> bool {
+        match self {
+            Command::Prove { target, .. } => GameId::parse(target).is_ok(),
+            Command::Init { .. }
+            | Command::CountPositions { .. }
+            | Command::Rpc
+            | Command::Generate { .. }
+            | Command::Templates
+            | Command::Whoami
+            | Command::Login { .. } => false,
+            _ => true,
+        }
+    }
+
+    /* Every event logged while running a command carries the game it works on.
+       `moves` is recorded once the game has been loaded.
+    */
+    fn span(&self) -> Span {
+        match self {
+            Command::Move { uuid, .. }
+            | Command::Quarto { uuid, .. }
+            | Command::Show {
+                uuid: Some(uuid), ..
+            }
+            | Command::Legal { uuid, .. }
+            | Command::Share { uuid }
+            | Command::Flag { uuid }
+            | Command::Resign { uuid, .. }
+            | Command::DrawOffer { uuid, .. }
+            | Command::DrawAccept { uuid, .. }
+            | Command::Abort { uuid, .. }
+            | Command::Play {
+                uuid: Some(uuid), ..
+            }
+            | Command::Watch { uuid }
+            | Command::Verify { uuid }
+            | Command::Hint { uuid, .. }
+            | Command::History { uuid, .. }
+            | Command::Log { uuid, .. }
+            | Command::Export { uuid, .. }
+            | Command::Replay { uuid, .. }
+            | Command::Analyze {
+                uuid: Some(uuid), ..
+            } => {
+                info_span!(
+                    "command",
+                    name = self.name(),
+                    uuid = %game_id::logged(&uuid),
+                    moves = field::Empty
+                )
+            }
+            _ => info_span!(
+                "command",
+                name = self.name(),
+                uuid = field::Empty,
+                moves = field::Empty
+            ),
+        }
+    }
+}
+
+/* RUST_LOG selects levels as before; closing spans report their time.busy */
+fn init_tracing(format: LogFormat, debug_sql: bool) {
+    let mut filter = EnvFilter::from_default_env();
+    if debug_sql {
+        for directive in sql_log::DIRECTIVES {
+            filter = filter.add_directive(directive.parse().unwrap());
+        }
+    }
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(io::stderr);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
 }
 
-async fn init_sqlite(db_url: &str) -> Result<SqliteQueryResult, SqlxError> {
+async fn init_sqlite(db_url: &str, policy: &DbPolicy) -> Result<(), Box<dyn Error>> {
     Sqlite::create_database(db_url).await?;
 
-    let db: Pool<Sqlite> = SqlitePool::connect(db_url).await.unwrap();
+    let db = connect(db_url, policy).await?;
+    policy.run("create tables", || init_schema(&db)).await?;
+    Ok(())
+}
+
+/* Opens the database under the policy, so that a busy one is tried again and one
+   which never answers times out
+*/
+async fn connect(db_url: &str, policy: &DbPolicy) -> Result<Pool<Sqlite>, Box<dyn Error>> {
+    policy
+        .run("connect", || SqlitePool::connect(db_url))
+        .await
+        .map_err(|e| match e.downcast::<SqlxError>() {
+            Ok(e) => {
+                error!(%e, "cannot open the database");
+                QuartoError::StoreUnavailable(e.to_string()).into()
+            }
+            Err(e) => e,
+        })
+}
+
+/* The tables `init` creates, and `init --force` brings up to date */
+async fn init_schema(db: &Pool<Sqlite>) -> Result<SqliteQueryResult, Box<dyn Error>> {
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS game
@@ -54,126 +761,441 @@ async fn init_sqlite(db_url: &str) -> Result<SqliteQueryResult, SqlxError> {
               assigned_1st BOOLEAN NOT NULL default false,
               assigned_2nd BOOLEAN NOT NULL default false,
               next_piece VARCHAR,
-              board_state VARCHAR
+              board_state VARCHAR,
+              advanced BOOLEAN NOT NULL default false,
+              setup BOOLEAN NOT NULL default false,
+              time_control VARCHAR,
+              clock_1st INTEGER,
+              clock_2nd INTEGER,
+              last_move_at INTEGER,
+              flagged INTEGER,
+              deadline_secs INTEGER,
+              due_at INTEGER,
+              forfeited INTEGER,
+              forfeit_reason VARCHAR,
+              spectator_delay_secs INTEGER,
+              result_hash VARCHAR,
+              resigned INTEGER,
+              draw_offered_by INTEGER,
+              draw_offered_at INTEGER,
+              draw_agreed BOOLEAN NOT NULL default false,
+              board_format INTEGER,
+              seed INTEGER,
+              aborted INTEGER,
+              abort_requested_by INTEGER,
+              engine_version VARCHAR,
+              player_1st VARCHAR,
+              player_2nd VARCHAR
         );"#,
     )
-    .execute(&db)
-    .await
+    .execute(db)
+    .await?;
+    compat::upgrade_schema(db).await?;
+    sqlx::query(UUID_INDEX).execute(db).await?;
+    book::init_book(db).await?;
+    event::init_events(db).await?;
+    index::init_index(db).await?;
+    event::backfill(db).await?;
+    event::backfill_handed_by(db).await?;
+    idempotency::init_keys(db).await?;
+    summary::init_summary(db).await?;
+    Ok(cache::init_cache(db).await?)
 }
 
-use sqlx::Error as SqlxError;
+/* An in-memory database with the schema `init` creates, for tests. The single
+   connection keeps it alive.
+*/
+#[cfg(test)]
+pub async fn memory_db() -> Pool<Sqlite> {
+    let db = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    init_schema(&db).await.unwrap();
+    db
+}
 
-impl Quarto {
-    pub async fn insert_new_game(&mut self, db: &Pool<Sqlite>, uuid: &String, piece: &Piece) -> () {
-        #[cfg(not(feature = "init"))]
-        {
-            if !self.pick_piece(piece) {
-                return ();
-            }
-            let piece: String = Piece::from(self.next_piece.as_ref().unwrap().clone()).into();
-            let board_state: String = (BoardState::from(self.board_state.clone())).into();
-            let result = sqlx::query!(
-                r#"
-                INSERT INTO game (uuid, next_piece, board_state)
-                VALUES (?1, ?2, ?3);
-                "#,
-                uuid,
-                piece,
-                board_state
-            )
-            //Quarto::format_board_state(self.board_state))
-            .execute(db)
-            .await
-            .unwrap();
-            info!("Insert record: {:?}", result);
+/* Also run by `init --force` on databases created before the index existed */
+const UUID_INDEX: &str = "CREATE UNIQUE INDEX IF NOT EXISTS game_uuid ON game (uuid);";
+
+/* The pool of commands which never open the store, which is never connected */
+const UNUSED_URL: &str = "sqlite::memory:";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    // Pieces on the command line are read in the configured notation
+    let config = template::load()?;
+    notation::install(config.notation.unwrap_or_default());
+    let args = Cli::parse();
+    if args.full_ids {
+        game_id::show_full();
+    }
+    // JSON log lines are read by machines, which want the whole id
+    if args.full_ids || matches!(args.log_format, LogFormat::Json) {
+        game_id::log_full();
+    }
+    init_tracing(args.log_format, args.debug_sql || config.debug_sql);
+    info!(?args, "parsed arguments");
+
+    let span = args.command.span();
+    let identities_path = identity::path();
+    let identities = identities_path
+        .as_deref()
+        .map(identity::load)
+        .unwrap_or_default();
+    if matches!(args.command, Command::Whoami | Command::Login { .. }) {
+        let chosen = args.identity.as_deref();
+        return span.in_scope(|| {
+            run_identity(args.command, identities, identities_path.as_deref(), chosen)
+        });
+    }
+    let player = identities
+        .select(args.identity.as_deref())?
+        .map(|identity| identity.name.clone());
+    if let Some(StoreLocation::File(dir)) = args.store {
+        return span.in_scope(|| run_offline(args.command, &dir));
+    }
+    // Commands which never open the store run without DATABASE_URL
+    let opens_store = args.command.opens_store() || matches!(args.command, Command::Init { .. });
+    let mut db_url = match env::var("DATABASE_URL") {
+        _ if !opens_store => UNUSED_URL.to_string(),
+        Ok(db_url) => db_url,
+        Err(e) => {
+            let message = format!("DATABASE_URL: {}", e);
+            error!("{}", message);
+            return Err(QuartoError::StoreUnavailable(message))?;
         }
+    };
+    let writable = db_policy::is_writable(&db_url);
+    if !writable && !args.read_only {
+        warn!("database file is not writable, only reading it");
+    }
+    let read_only = args.read_only || !writable || db_policy::opens_read_only(&db_url);
+    if read_only {
+        db_url = db_policy::read_only_url(&db_url);
+    }
+    let timeout = match args.db_timeout {
+        Some(timeout) => timeout,
+        None => config.db_timeout.unwrap_or_default(),
+    };
+    let policy = DbPolicy {
+        timeout,
+        read_only,
+        ..DbPolicy::default()
+    };
+    let progress = progress::for_cli(args.quiet);
+    run(
+        args.command,
+        db_url,
+        policy,
+        progress,
+        Arc::new(SystemClock),
+        player.as_deref(),
+    )
+    .instrument(span)
+    .await
+}
 
-        ()
+/* `whoami` and `login`, which need nothing but the identity file */
+fn run_identity(
+    command: Command,
+    mut identities: Identities,
+    path: Option<&Path>,
+    chosen: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Login { name } => {
+            let Some(path) = path else {
+                error!(
+                    "no state directory; set {} or HOME",
+                    identity::IDENTITIES_ENV
+                );
+                return Err(QuartoError::AnyOther)?;
+            };
+            identities.login(&name)?;
+            identity::save(path, &identities)?;
+            println!("logged in as {}", name);
+        }
+        _ => {
+            let Some(identity) = identities.select(chosen)? else {
+                error!("not logged in");
+                return Err(QuartoError::NotLoggedIn)?;
+            };
+            println!("{}", identity.name);
+        }
     }
-    async fn search_game_by_uuid(db: &Pool<Sqlite>, uuid: &str) -> Option<Quarto> {
-        #[cfg(not(feature = "init"))]
+    Ok(())
+}
+
+/* The game with `uuid` as stored now */
+async fn load_game(
+    db: &Pool<Sqlite>,
+    policy: &DbPolicy,
+    uuid: &GameId,
+) -> Result<Option<Quarto>, Box<dyn Error>> {
+    policy.run("load game", || store::find_game(db, uuid)).await
+}
+
+/* Store calls which take a connection, on one of their own, so that the policy
+   can start every try afresh
+*/
+async fn think_time(db: &Pool<Sqlite>, uuid: &str, now: i64) -> Result<Option<i64>, SqlxError> {
+    event::think_time(&mut *db.acquire().await?, uuid, now).await
+}
+
+async fn save_clock(
+    db: &Pool<Sqlite>,
+    uuid: &str,
+    game_clock: &Clock,
+) -> Result<SqliteQueryResult, SqlxError> {
+    clock::save(&mut *db.acquire().await?, uuid, game_clock).await
+}
+
+async fn index_exists(db: &Pool<Sqlite>) -> Result<bool, SqlxError> {
+    index::exists(&mut *db.acquire().await?).await
+}
+
+/* The seat a resignation, draw offer or abort is made from: the one given, taken
+   for the identity in use when there is one, or else the identity's own
+*/
+async fn acting_seat(
+    db: &Pool<Sqlite>,
+    policy: &DbPolicy,
+    uuid: &GameId,
+    given: Option<Seat>,
+    identity: Option<&str>,
+) -> Result<usize, Box<dyn Error>> {
+    match (given, identity) {
+        (Some(Seat(seat)), Some(name)) => {
+            policy
+                .run("claim seat", || {
+                    identity::claim(db, uuid.as_str(), seat, name)
+                })
+                .await?;
+            Ok(seat)
+        }
+        (Some(Seat(seat)), None) => Ok(seat),
+        (None, Some(name)) => match policy
+            .run("find seat", || identity::seat_of(db, uuid.as_str(), name))
+            .await?
         {
-            let result = sqlx::query!(
-                r#"
-                 SELECT uuid, next_piece, board_state, assigned_1st, assigned_2nd
-                 FROM game
-                 WHERE uuid = ?1
-                 "#,
-                uuid
-            )
-            .fetch_one(db)
-            .await
-            .ok()?;
-            if let (Some(bs), Some(np)) = (&result.board_state, &result.next_piece) {
-                let np = Piece::try_from(np.to_string()).ok()?;
-                let mut q = Quarto::try_from(bs).ok()?;
-                if !q.pick_piece(&np) {
-                    return None;
-                }
-                return Some(q);
+            Some(seat) => Ok(seat),
+            None => {
+                error!(name, "no seat in this game; give --seat");
+                Err(QuartoError::InvalidSeat(name.to_string()))?
             }
-            None
+        },
+        (None, None) => {
+            error!("give --seat, or log in to play from your own");
+            Err(QuartoError::NotLoggedIn)?
         }
-        #[cfg(feature = "init")]
-        None
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
-    let args = Cli::parse();
-    let db_url = env::var("DATABASE_URL").expect("DATABASE_URL should be set");
-    info!("{:?}", &args);
+/* How far `analyze` looks for a forced win to announce: a move, any reply, and
+   the winning move
+*/
+const MATE_PLIES: u8 = 3;
 
-    let result: Result<(), Box<dyn Error>> = match args.command {
+async fn run(
+    command: Command,
+    db_url: String,
+    policy: DbPolicy,
+    progress: Box<dyn Progress>,
+    wall_clock: Arc<dyn time::Clock>,
+    identity: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    if policy.read_only && command.writes() {
+        error!("database is read-only");
+        return Err(QuartoError::ReadOnly(command.name().to_string()))?;
+    }
+    // Commands which never touch the store get a pool that would only connect on use
+    let db = if command.opens_store() {
+        connect(&db_url, &policy).await?
+    } else {
+        SqlitePoolOptions::new().connect_lazy(&db_url)?
+    };
+    let result: Result<(), Box<dyn Error>> = match command {
         Command::Init { force } => {
             if !Sqlite::database_exists(&db_url).await.unwrap_or(false) || force {
-                let _result = init_sqlite(&db_url).await?;
+                init_sqlite(&db_url, &policy).await?;
             }
             Ok(())
         }
-        Command::NewGame => {
-            let db: Pool<Sqlite> = SqlitePool::connect(&db_url).await.unwrap();
-            let uuid = Uuid::new_v4().to_string();
-            let mut new_game = Quarto::new();
-            // We are sure BSCF is valid Piece.
-            let first_piece: Piece = Piece::try_from("BSCF".to_string()).unwrap();
-            let _result = new_game.insert_new_game(&db, &uuid, &first_piece).await;
+        Command::NewGame {
+            template,
+            variant,
+            clock,
+            deadline,
+            spectator_delay,
+            from_code,
+            from_file,
+            seed,
+        } => {
+            let flags = Template {
+                variant,
+                clock,
+                deadline,
+                spectator_delay,
+            };
+            let settings = new_game_settings(&template, &flags)?;
+            let (mut new_game, first_piece) = starting_position(&from_code, &from_file)?;
+            if from_code.is_none() && from_file.is_none() {
+                new_game.rules.variant = settings.variant;
+            }
+            let uuid = GameId::random();
+            let rng = seed.map_or_else(GameRng::from_entropy, GameRng);
+            policy
+                .once(
+                    "insert game",
+                    store::create_game(
+                        &db,
+                        &mut new_game,
+                        &uuid,
+                        &first_piece,
+                        rng,
+                        &settings,
+                        wall_clock.now(),
+                    ),
+                )
+                .await?;
+            if from_code.is_some() || from_file.is_some() {
+                policy
+                    .run("mark setup", || stats::mark_setup(&db, uuid.as_str()))
+                    .await?;
+            }
+            // The 1st player hands over the opening piece
+            if let Some(name) = identity {
+                policy
+                    .run("claim seat", || {
+                        identity::claim(&db, uuid.as_str(), 0, name)
+                    })
+                    .await?;
+            }
             println!("{}", uuid);
             Ok(())
         }
-        Command::Move { uuid, x, y, piece } => {
-            let coord = parse_coord(&x, &y);
-            if let None = coord {
-                error!("invalid coordinate: ({}, {})", &x, &y);
-                return Err(QuartoError::OutOfRange)?;
-            }
-            if let None = piece.clone().into() {
-                error!("invalid piece: {}", &piece);
-                return Err(QuartoError::InvalidPieceError)?;
-            }
-            let db: Pool<Sqlite> = SqlitePool::connect(&db_url).await.unwrap();
-            let np = Piece::try_from(piece.clone())?;
-            if let Some(mut quarto) = Quarto::search_game_by_uuid(&db, &uuid).await {
-                info!("{:?}", quarto);
-                quarto.move_piece(x, y);
-                quarto.pick_piece(&np);
+        Command::Move {
+            uuid,
+            at,
+            piece,
+            check,
+            key,
+            format,
+        } => {
+            let at = cell_argument(&at)?;
+            if let Some(mut quarto) = load_game(&db, &policy, &uuid).await? {
+                record_loaded(&quarto);
+                let ply = requested_move(&quarto, at, piece);
+                if check {
+                    let preview = checked_move(&quarto, &ply, format)?;
+                    match format {
+                        OutputFormat::Text => println!("{}", preview),
+                        OutputFormat::Json => {
+                            println!("{}", serde_json::to_string_pretty(&preview)?)
+                        }
+                    }
+                    return Ok(());
+                }
+                // A retried move was legal when first played, and gets the same reply
+                if let Some(key) = &key {
+                    if let Some(reply) = policy
+                        .run("look up key", || {
+                            idempotency::lookup(&db, uuid.as_str(), key)
+                        })
+                        .await?
+                    {
+                        info!(key, "move already played");
+                        println!("{}", reply);
+                        return Ok(());
+                    }
+                }
+                checked_move(&quarto, &ply, format)?;
+                if let Some(MoveDeadline {
+                    forfeited: Some(seat),
+                    ..
+                }) = policy
+                    .run("load deadline", || deadline::load(&db, uuid.as_str()))
+                    .await?
+                {
+                    error!(seat = clock::seat_name(seat), "game was forfeited");
+                    return Err(QuartoError::Forfeited)?;
+                }
+                if policy
+                    .run("load concessions", || concede::load(&db, uuid.as_str()))
+                    .await?
+                    .is_over()
+                {
+                    error!("game is over");
+                    return Err(QuartoError::GameOver)?;
+                }
+                if let Some(name) = identity {
+                    policy
+                        .run("claim seat", || {
+                            identity::claim(&db, uuid.as_str(), clock::seat_to_move(&quarto), name)
+                        })
+                        .await?;
+                }
+                let now = wall_clock.now();
+                let mut game_clock = policy
+                    .run("load clock", || clock::load(&db, uuid.as_str()))
+                    .await?;
+                if let Some(game_clock) = game_clock.as_mut() {
+                    let seat = clock::seat_to_move(&quarto);
+                    let think_ms = policy
+                        .run("load think time", || think_time(&db, uuid.as_str(), now))
+                        .await?;
+                    if let Some(think_ms) = think_ms {
+                        if !game_clock.agrees_with(think_ms, now) {
+                            let charged_ms = game_clock.charge_at(now);
+                            warn!(think_ms, charged_ms, "think time disagrees with the clock");
+                        }
+                    }
+                    if let Err(e) = game_clock.complete_move(seat, now) {
+                        let game_clock = &*game_clock;
+                        policy
+                            .run("save clock", || save_clock(&db, uuid.as_str(), game_clock))
+                            .await?;
+                        error!(seat = clock::seat_name(seat), "lost on time");
+                        return Err(e)?;
+                    }
+                }
+                quarto.play_legal(&ply);
+                if quarto.status() != GameStatus::InProgress {
+                    let (key, game_clock) = (key.as_deref(), game_clock.as_ref());
+                    let finalized = policy
+                        .run("finalize game", || {
+                            store::finalize_game(&db, &quarto, &uuid, &ply, key, game_clock, now)
+                        })
+                        .await?;
+                    if key.is_some() {
+                        println!("{}", finalized.reply);
+                    }
+                    println!("{}", finalized);
+                    return Ok(());
+                }
+                let (key, game_clock) = (key.as_deref(), game_clock.as_ref());
+                let reply = policy
+                    .run("update game", || {
+                        policy.run("update game", || {
+                            store::save_move(&db, &quarto, &uuid, &ply, key, game_clock, now)
+                        })
+                    })
+                    .await?;
+                if key.is_some() {
+                    println!("{}", reply);
+                }
                 return Ok(());
             } else {
-                error!("unknown uuid: {}", &uuid);
+                error!("unknown uuid");
                 return Err(QuartoError::AnyOther)?;
             }
         }
-        Command::Quarto { uuid, x, y } => {
-            let coord = parse_coord(&x, &y);
-            if let None = coord {
-                error!("invalid coordinate: ({}, {})", &x, &y);
-                return Err(QuartoError::OutOfRange)?;
-            }
-            let db: Pool<Sqlite> = SqlitePool::connect(&db_url).await.unwrap();
-            if let Some(mut quarto) = Quarto::search_game_by_uuid(&db, &uuid).await {
-                info!("{:?}", quarto);
+        Command::Quarto { uuid, at } => {
+            let (x, y) = cell_argument(&at)?.xy();
+            if let Some(mut quarto) = load_game(&db, &policy, &uuid).await? {
+                record_loaded(&quarto);
                 quarto.move_piece(x, y);
                 if quarto.is_quarto() {
                     return Ok(());
@@ -181,18 +1203,2433 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     return Err(QuartoError::InvalidQuarto)?;
                 }
             } else {
-                error!("unknown uuid: {}", &uuid);
+                error!("unknown uuid");
                 return Err(QuartoError::AnyOther)?;
             }
         }
-    };
-    result
-}
-
-fn parse_coord<'a>(x: &'a usize, y: &'a usize) -> Option<(&'a usize, &'a usize)> {
-    if (0..4).contains(x) && (0..4).contains(y) {
-        return Some((x, y));
-    } else {
-        None
+        Command::Show {
+            uuid,
+            code,
+            describe,
+            orientation,
+            at,
+            format,
+            analysis,
+        } => {
+            let symmetry = orientation_symmetry(orientation)?;
+            let Some(uuid) = uuid else {
+                // clap requires one of them
+                let quarto = Quarto::from_share_code(&code.unwrap_or_default())?;
+                show_position(&quarto.transformed(symmetry), describe, format, analysis)?;
+                return Ok(());
+            };
+            if let Some(quarto) = load_game(&db, &policy, &uuid).await? {
+                record_loaded(&quarto);
+                if let Some(at) = at {
+                    // Clocks and deadlines belong to the latest position only
+                    let records = policy
+                        .run("load events", || event::load(&db, uuid.as_str(), 0))
+                        .await?;
+                    let (number, past) = replay::position_at(&records, at)?;
+                    show_position(&past.transformed(symmetry), describe, format, analysis)?;
+                    if format == OutputFormat::Json {
+                        return Ok(());
+                    }
+                    println!(
+                        "as of move {}: {} to move",
+                        number,
+                        clock::seat_name(clock::seat_to_move(&past))
+                    );
+                    return Ok(());
+                }
+                let quarto = quarto.transformed(symmetry);
+                show_position(&quarto, describe, format, analysis)?;
+                if format == OutputFormat::Json {
+                    return Ok(());
+                }
+                if let Some(game_clock) = policy
+                    .run("load clock", || clock::load(&db, uuid.as_str()))
+                    .await?
+                {
+                    let seat = clock::seat_to_move(&quarto);
+                    let now = wall_clock.now();
+                    let state = match game_clock.flagged {
+                        Some(flagged) => format!("{} lost on time", clock::seat_name(flagged)),
+                        None => format!("{} to move", clock::seat_name(seat)),
+                    };
+                    println!(
+                        "clock {}: 1st {}, 2nd {} ({})",
+                        game_clock.control,
+                        clock::format_clock(game_clock.remaining_at(0, seat, now)),
+                        clock::format_clock(game_clock.remaining_at(1, seat, now)),
+                        state
+                    );
+                }
+                if let Some(due) = policy
+                    .run("load deadline", || deadline::load(&db, uuid.as_str()))
+                    .await?
+                {
+                    let seat = clock::seat_to_move(&quarto);
+                    match (due.forfeited, due.reason) {
+                        (Some(_), Some(reason)) => println!("forfeited: {}", reason),
+                        (Some(forfeited), None) => {
+                            println!("forfeited by {}", clock::seat_name(forfeited))
+                        }
+                        _ if due.due_at <= wall_clock.now() => println!(
+                            "deadline {}: {} is overdue",
+                            due.deadline,
+                            clock::seat_name(seat)
+                        ),
+                        _ => println!(
+                            "deadline {}: {} to move within {}",
+                            due.deadline,
+                            clock::seat_name(seat),
+                            clock::format_clock(due.due_at - wall_clock.now())
+                        ),
+                    }
+                }
+                if let Some(state) = policy
+                    .run("load concessions", || concede::load(&db, uuid.as_str()))
+                    .await?
+                    .describe(&quarto)
+                {
+                    println!("{}", state);
+                }
+                if let Some(rng) = policy
+                    .run("load seed", || seed::load(&db, uuid.as_str()))
+                    .await?
+                {
+                    println!("seed {}", rng.0);
+                }
+                Ok(())
+            } else {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            }
+        }
+        Command::Legal { uuid, format } => {
+            if let Some(quarto) = load_game(&db, &policy, &uuid).await? {
+                record_loaded(&quarto);
+                let moves = quarto.legal_moves();
+                if format == OutputFormat::Json {
+                    println!("{}", serde_json::to_string_pretty(&moves)?);
+                    return Ok(());
+                }
+                for mv in moves {
+                    println!("{}", notation::current().mv(&mv));
+                }
+                Ok(())
+            } else {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            }
+        }
+        Command::Share { uuid } => {
+            if let Some(quarto) = load_game(&db, &policy, &uuid).await? {
+                record_loaded(&quarto);
+                println!("{}", quarto.to_stamped_share_code());
+                Ok(())
+            } else {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            }
+        }
+        Command::Flag { uuid } => {
+            if let Some(quarto) = load_game(&db, &policy, &uuid).await? {
+                record_loaded(&quarto);
+                let Some(mut game_clock) = policy
+                    .run("load clock", || clock::load(&db, uuid.as_str()))
+                    .await?
+                else {
+                    error!("game has no clock");
+                    return Err(QuartoError::AnyOther)?;
+                };
+                let seat = clock::seat_to_move(&quarto);
+                let now = wall_clock.now();
+                if game_clock.check_flag(seat, now) {
+                    policy
+                        .run("save clock", || save_clock(&db, uuid.as_str(), &game_clock))
+                        .await?;
+                    let flagged = game_clock.flagged.unwrap_or(seat);
+                    println!("{} lost on time", clock::seat_name(flagged));
+                } else {
+                    println!(
+                        "{} to move, {} left",
+                        clock::seat_name(seat),
+                        clock::format_clock(game_clock.remaining_at(seat, seat, now))
+                    );
+                }
+                Ok(())
+            } else {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            }
+        }
+        Command::Resign { uuid, seat } => {
+            let Some(quarto) = load_game(&db, &policy, &uuid).await? else {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            };
+            record_loaded(&quarto);
+            let seat = acting_seat(&db, &policy, &uuid, seat, identity).await?;
+            policy
+                .run("load concessions", || concede::load(&db, uuid.as_str()))
+                .await?
+                .check_open(&quarto)?;
+            if !policy
+                .run("resign", || {
+                    concede::resign(&db, uuid.as_str(), seat, wall_clock.now())
+                })
+                .await?
+            {
+                error!("game ended meanwhile");
+                return Err(QuartoError::GameOver)?;
+            }
+            println!(
+                "{} resigned; {} wins",
+                clock::seat_name(seat),
+                clock::seat_name(1 - seat)
+            );
+            Ok(())
+        }
+        Command::DrawOffer { uuid, seat } => {
+            let Some(quarto) = load_game(&db, &policy, &uuid).await? else {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            };
+            record_loaded(&quarto);
+            let seat = acting_seat(&db, &policy, &uuid, seat, identity).await?;
+            policy
+                .run("load concessions", || concede::load(&db, uuid.as_str()))
+                .await?
+                .check_open(&quarto)?;
+            let placed = quarto.placed_pieces();
+            let now = wall_clock.now();
+            if !policy
+                .run("offer draw", || {
+                    concede::offer_draw(&db, uuid.as_str(), seat, placed, now)
+                })
+                .await?
+            {
+                error!("game ended meanwhile");
+                return Err(QuartoError::GameOver)?;
+            }
+            println!("{} offers a draw", clock::seat_name(seat));
+            Ok(())
+        }
+        Command::DrawAccept { uuid, seat } => {
+            let Some(quarto) = load_game(&db, &policy, &uuid).await? else {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            };
+            record_loaded(&quarto);
+            let seat = acting_seat(&db, &policy, &uuid, seat, identity).await?;
+            if let Err(e) = policy
+                .run("load concessions", || concede::load(&db, uuid.as_str()))
+                .await?
+                .check_accept(&quarto, seat)
+            {
+                error!(seat = clock::seat_name(seat), "no draw offer to accept");
+                return Err(e)?;
+            }
+            let placed = quarto.placed_pieces();
+            let now = wall_clock.now();
+            if !policy
+                .run("accept draw", || {
+                    concede::accept_draw(&db, uuid.as_str(), seat, placed, now)
+                })
+                .await?
+            {
+                error!("the offer was withdrawn meanwhile");
+                return Err(QuartoError::NoDrawOffer)?;
+            }
+            println!("drawn by agreement");
+            Ok(())
+        }
+        Command::Abort { uuid, seat } => {
+            let before = template::load()?
+                .abort_before
+                .unwrap_or(concede::ABORT_BEFORE);
+            let Some(quarto) = load_game(&db, &policy, &uuid).await? else {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            };
+            record_loaded(&quarto);
+            let seat = acting_seat(&db, &policy, &uuid, seat, identity).await?;
+            let abort = policy
+                .run("load concessions", || concede::load(&db, uuid.as_str()))
+                .await?
+                .check_abort(&quarto, seat, before)?;
+            let now = wall_clock.now();
+            let done = match abort {
+                Abort::Alone => {
+                    policy
+                        .run("abort game", || {
+                            concede::abort(&db, uuid.as_str(), seat, false, now)
+                        })
+                        .await?
+                }
+                Abort::Agreed => {
+                    policy
+                        .run("abort game", || {
+                            concede::abort(&db, uuid.as_str(), seat, true, now)
+                        })
+                        .await?
+                }
+                Abort::Request => {
+                    policy
+                        .run("request abort", || {
+                            concede::request_abort(&db, uuid.as_str(), seat, now)
+                        })
+                        .await?
+                }
+            };
+            if !done {
+                error!("game ended meanwhile");
+                return Err(QuartoError::GameOver)?;
+            }
+            match abort {
+                Abort::Request => println!(
+                    "{} asks to abort; the game is aborted once {} aborts too",
+                    clock::seat_name(seat),
+                    clock::seat_name(1 - seat)
+                ),
+                Abort::Alone | Abort::Agreed => {
+                    println!("aborted by {}", clock::seat_name(seat))
+                }
+            }
+            Ok(())
+        }
+        Command::Stats {
+            heatmap,
+            include_aborted,
+            analysis,
+            format,
+        } => {
+            let mut stats = policy
+                .run("collect stats", || stats::collect(&db, include_aborted))
+                .await?;
+            if analysis {
+                stats.analysis = Some(policy.run("load summaries", || summary::load(&db)).await?);
+            }
+            if format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+                return Ok(());
+            }
+            print!("{}", stats);
+            if heatmap {
+                print!("{}", stats.heatmap());
+            }
+            if let Some(summaries) = &stats.analysis {
+                let known = policy.run("list uuids", || page::uuids(&db)).await?;
+                println!("analysis:");
+                for summary in summaries {
+                    println!("  {}", summary.line(&known));
+                }
+            }
+            Ok(())
+        }
+        Command::List {
+            limit,
+            cursor,
+            mine,
+        } => {
+            let player = match (mine, identity) {
+                (false, _) => None,
+                (true, Some(name)) => Some(name),
+                (true, None) => {
+                    error!("--mine needs an identity; log in first");
+                    return Err(QuartoError::NotLoggedIn)?;
+                }
+            };
+            let page = policy
+                .run("list games", || page::games(&db, cursor, limit, player))
+                .await?;
+            let known = policy.run("list uuids", || page::uuids(&db)).await?;
+            for entry in &page.items {
+                println!("{}", entry.line(&known));
+            }
+            if let Some(next) = page.next {
+                println!("next cursor {}", next);
+            }
+            Ok(())
+        }
+        Command::Todo { count, format } => {
+            let Some(name) = identity else {
+                error!("todo needs an identity; log in first");
+                return Err(QuartoError::NotLoggedIn)?;
+            };
+            let games = policy
+                .run("list pending games", || {
+                    todo::pending(&db, name, wall_clock.now())
+                })
+                .await?;
+            if count {
+                println!("{}", games.len());
+            } else if format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&games)?);
+            } else {
+                let known = policy.run("list uuids", || page::uuids(&db)).await?;
+                match games.len() {
+                    1 => println!("your move in 1 game"),
+                    n => println!("your move in {} games", n),
+                }
+                for game in &games {
+                    println!("{}", game.line(&known));
+                }
+            }
+            Ok(())
+        }
+        Command::History {
+            uuid,
+            limit,
+            cursor,
+            long,
+        } => {
+            let page = policy
+                .run("page history", || {
+                    page::history(&db, uuid.as_str(), cursor, limit)
+                })
+                .await?;
+            let players = policy
+                .run("load players", || identity::players(&db, uuid.as_str()))
+                .await?;
+            for record in &page.items {
+                let think = match record.think_ms {
+                    Some(ms) if long => format!("  think {}", clock::format_think(ms)),
+                    _ => String::new(),
+                };
+                let handed = match (&record.event, record.handed_by) {
+                    (Event::Move { ply }, Some(seat)) => ply.hand.map_or(String::new(), |p| {
+                        let who = players[seat]
+                            .clone()
+                            .unwrap_or_else(|| format!("{} player", clock::seat_name(seat)));
+                        format!(", {} hands {}", who, p)
+                    }),
+                    _ => String::new(),
+                };
+                println!(
+                    "{} {} {}{}{}",
+                    record.seq,
+                    record.created_at,
+                    serde_json::to_string(&record.event)?,
+                    handed,
+                    think
+                );
+            }
+            if let Some(next) = page.next {
+                println!("next cursor {}", next);
+            }
+            Ok(())
+        }
+        Command::Log { uuid, time } => {
+            let events = policy
+                .run("load events", || event::load(&db, uuid.as_str(), 0))
+                .await?;
+            if events.is_empty() {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            }
+            let players = policy
+                .run("load players", || identity::players(&db, uuid.as_str()))
+                .await?;
+            for line in timeline::format_events(&events, time, &players)? {
+                println!("{}", line);
+            }
+            Ok(())
+        }
+        Command::Export {
+            uuid,
+            format,
+            first,
+            second,
+        } => {
+            let events = policy
+                .run("load events", || event::load(&db, uuid.as_str(), 0))
+                .await?;
+            if events.is_empty() {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            }
+            let sheet = ScoreSheet::of(uuid.as_str(), &events, [first, second])?;
+            match format {
+                ExportFormat::Latex => print!("{}", sheet.to_latex()),
+            }
+            Ok(())
+        }
+        Command::Import { file, format } => {
+            let text = std::fs::read_to_string(&file).map_err(QuartoError::Io)?;
+            let games = format.adapter().read(&text)?;
+            let mut imported = 0;
+            for (source, game) in &games {
+                let outcome = match game.clone().and_then(|g| g.play().map(|_| g)) {
+                    Ok(game) => {
+                        let uuid = GameId::random();
+                        let mut quarto = Quarto::with_rules(game.rules);
+                        let now = wall_clock.now();
+                        let rng = GameRng::from_entropy();
+                        let settings = Settings::default();
+                        policy
+                            .once(
+                                "insert game",
+                                store::create_game(
+                                    &db,
+                                    &mut quarto,
+                                    &uuid,
+                                    &game.opening,
+                                    rng,
+                                    &settings,
+                                    now,
+                                ),
+                            )
+                            .await?;
+                        for ply in &game.moves {
+                            quarto.play_legal(ply);
+                            policy
+                                .run("update game", || {
+                                    store::save_move(&db, &quarto, &uuid, ply, None, None, now)
+                                })
+                                .await?;
+                        }
+                        imported += 1;
+                        Ok((uuid.to_string(), game.moves.len()))
+                    }
+                    Err(reason) => Err(reason),
+                };
+                let report = Report {
+                    source: source.clone(),
+                    outcome,
+                };
+                println!("{}", report);
+            }
+            println!("imported {} of {} games", imported, games.len());
+            Ok(())
+        }
+        Command::Replay {
+            uuid,
+            evaluate,
+            depth,
+        } => {
+            let events = policy
+                .run("load events", || event::load(&db, uuid.as_str(), 0))
+                .await?;
+            if events.is_empty() {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            }
+            let plies = replay::plies(&events)?;
+            let think = replay::think_times(&events)
+                .into_iter()
+                .map(|ms| {
+                    ms.map_or_else(String::new, |ms| format!("  ({})", clock::format_think(ms)))
+                })
+                .collect::<Vec<_>>();
+            if !evaluate {
+                for (((quarto, ply), think), number) in plies.iter().zip(&think).zip(1..) {
+                    let seat = clock::seat_name(clock::seat_to_move(quarto));
+                    println!("{:>3}. {} {}{}", number, seat, ply, think);
+                }
+                return Ok(());
+            }
+            let annotated = replay::evaluate_game(&db, &policy, &plies, depth).await?;
+            for (a, think) in annotated.iter().zip(&think) {
+                println!("{}{}", a, think);
+            }
+            let [first, second] = replay::blunders(&annotated);
+            println!("blunders: 1st {}, 2nd {}", first, second);
+            Ok(())
+        }
+        Command::Search { pattern, code } => {
+            let indexed = policy.run("check index", || index_exists(&db)).await?;
+            if let Some(code) = code {
+                if !indexed {
+                    error!("no position index, run `quarto index rebuild`");
+                    return Err(QuartoError::AnyOther)?;
+                }
+                let quarto = Quarto::from_share_code(&code)?;
+                let found = policy
+                    .run("find position", || index::occurrences(&db, &quarto))
+                    .await?;
+                let known = policy.run("list uuids", || page::uuids(&db)).await?;
+                for found in &found {
+                    println!("{}", found.line(&known));
+                }
+                println!("occurred {} times", found.len());
+                return Ok(());
+            }
+            let pattern = pattern.expect("clap requires --pattern without --code");
+            let pattern: Pattern = std::fs::read_to_string(&pattern)?.parse()?;
+            let found = if indexed {
+                policy
+                    .run("search index", || index::search(&db, &pattern))
+                    .await?
+            } else {
+                pattern::search(&db, &policy, &pattern).await?
+            };
+            let known = policy.run("list uuids", || page::uuids(&db)).await?;
+            for found in found {
+                println!("{}", found.line(&known));
+            }
+            Ok(())
+        }
+        Command::Index {
+            command: IndexCommand::Rebuild,
+        } => {
+            let indexed = index::rebuild(&db, &policy, progress.as_ref()).await?;
+            println!("indexed {} positions", indexed);
+            Ok(())
+        }
+        Command::Sweep => {
+            let forfeited = policy
+                .run("sweep deadlines", || deadline::sweep(&db, wall_clock.now()))
+                .await?;
+            let known = policy.run("list uuids", || page::uuids(&db)).await?;
+            for (uuid, seat) in forfeited {
+                println!(
+                    "{}: forfeited by {}",
+                    game_id::short(&uuid, &known),
+                    clock::seat_name(seat)
+                );
+            }
+            Ok(())
+        }
+        Command::Analyze {
+            uuid,
+            all,
+            filter,
+            depth,
+            jobs,
+            playouts,
+            seed,
+            no_cache,
+            explain,
+        } => {
+            if all {
+                let jobs =
+                    jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, Into::into));
+                let batch =
+                    summary::run(&db, &policy, &filter, depth, jobs, progress.as_ref()).await?;
+                let known = policy.run("list uuids", || page::uuids(&db)).await?;
+                for summary in &batch.summarized {
+                    println!("{}", summary.line(&known));
+                }
+                println!("{}", batch);
+                return Ok(());
+            }
+            let uuid = uuid.expect("clap requires a uuid without --all");
+            if let Some(quarto) = load_game(&db, &policy, &uuid).await? {
+                record_loaded(&quarto);
+                if quarto.every_piece_loses() {
+                    println!("zugzwang: every piece loses");
+                }
+                if let Some(mv) = quarto.has_forced_win(MATE_PLIES) {
+                    println!("forced win: {}", notation::current().mv(&mv));
+                }
+                if explain {
+                    println!("{}", quarto.explain());
+                }
+                let seed = policy
+                    .run("load seed", || {
+                        seed::for_move(&db, uuid.as_str(), &quarto, seed)
+                    })
+                    .await?;
+                let position = cache::position_key(&quarto);
+                let engine = cache::playout_engine(seed);
+                if !no_cache {
+                    if let Some(cached) = policy
+                        .run("look up analysis", || {
+                            cache::lookup(&db, &position, &engine, playouts.into())
+                        })
+                        .await?
+                    {
+                        println!(
+                            "score {:.3} ({} playouts, cached)",
+                            cached.score, cached.depth
+                        );
+                        return Ok(());
+                    }
+                }
+                let estimate = quarto.estimate(playouts, seed);
+                let score = estimate.win + estimate.draw / 2.0;
+                println!(
+                    "win {:.3} draw {:.3} loss {:.3} score {:.3} (+/- {:.3}, {} playouts)",
+                    estimate.win,
+                    estimate.draw,
+                    estimate.loss,
+                    score,
+                    estimate.std_error,
+                    estimate.playouts
+                );
+                let analysis = CachedAnalysis {
+                    engine,
+                    depth: playouts.into(),
+                    score,
+                    best_move: None,
+                };
+                policy
+                    .run("cache analysis", || cache::store(&db, &position, &analysis))
+                    .await?;
+                Ok(())
+            } else {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            }
+        }
+        Command::Cache { command } => {
+            match command {
+                CacheCommand::Stats => {
+                    for (engine, entries, max_depth) in
+                        policy.run("cache stats", || cache::stats(&db)).await?
+                    {
+                        println!("{}: {} entries, max depth {}", engine, entries, max_depth);
+                    }
+                }
+                CacheCommand::Clear => {
+                    let removed = policy.run("clear cache", || cache::clear(&db)).await?;
+                    println!("removed {} entries", removed);
+                }
+            }
+            Ok(())
+        }
+        Command::Hint {
+            uuid,
+            playouts,
+            seed,
+            no_book,
+            teach,
+        } => {
+            if let Some(quarto) = load_game(&db, &policy, &uuid).await? {
+                record_loaded(&quarto);
+                let book = if no_book {
+                    None
+                } else {
+                    Some(policy.run("load book", || book::load(&db)).await?)
+                };
+                let seed = policy
+                    .run("load seed", || {
+                        seed::for_move(&db, uuid.as_str(), &quarto, seed)
+                    })
+                    .await?;
+                match book::hint(&quarto, book.as_ref(), playouts, seed) {
+                    Some(hint) => {
+                        println!("{}", hint);
+                        if let Some(text) =
+                            teach.then(|| book::teach(&quarto, &hint.mv())).flatten()
+                        {
+                            println!("teach: {}", text);
+                        }
+                    }
+                    None => println!("game is over"),
+                }
+                Ok(())
+            } else {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            }
+        }
+        Command::Prove { target, plies } => {
+            let quarto = match GameId::parse(&target) {
+                Ok(uuid) => {
+                    let Some(quarto) = load_game(&db, &policy, &uuid).await? else {
+                        error!("unknown uuid");
+                        return Err(QuartoError::AnyOther)?;
+                    };
+                    record_loaded(&quarto);
+                    quarto
+                }
+                Err(_) => Quarto::from_share_code(&target)?,
+            };
+            match quarto.has_forced_win(plies) {
+                Some(mv) => println!("forced win in {}: {}", plies, notation::current().mv(&mv)),
+                None => println!("no forced win in {}", plies),
+            }
+            Ok(())
+        }
+        Command::CountPositions {
+            plies,
+            canonical,
+            bloom,
+        } => {
+            let bloom_bytes = bloom.map(|mib| mib as usize * 1024 * 1024);
+            let census = tokio::task::spawn_blocking(move || {
+                census::count_positions(plies, canonical, bloom_bytes)
+            })
+            .await?;
+            println!("{}", census);
+            Ok(())
+        }
+        Command::Book {
+            command:
+                BookCommand::Build {
+                    from_games,
+                    from_solver: _,
+                    from_index,
+                    depth,
+                    variant,
+                    adjudicate,
+                    playouts,
+                    seed,
+                },
+        } => {
+            let rules = Rules {
+                variant,
+                dead_position_adjudication: adjudicate,
+            };
+            let book = match from_games {
+                Some(path) => {
+                    let games = book::parse_games(rules, &std::fs::read_to_string(path)?)?;
+                    book::Book::from_games(rules, &games, depth)
+                }
+                None if from_index => {
+                    let games = policy
+                        .run("load indexed games", || index::games(&db, rules))
+                        .await?;
+                    book::Book::from_games(rules, &games, depth)
+                }
+                None => {
+                    tokio::task::spawn_blocking(move || {
+                        book::Book::from_solver(rules, depth, playouts, seed, progress.as_ref())
+                    })
+                    .await?
+                }
+            };
+            policy.run("save book", || book::save(&db, &book)).await?;
+            println!("{} positions in the book", book.len());
+            Ok(())
+        }
+        Command::Book {
+            command: BookCommand::Export { out },
+        } => {
+            let book = policy.run("load book", || book::load(&db)).await?;
+            std::fs::write(&out, book.to_text()).map_err(QuartoError::Io)?;
+            println!("{} positions written to {}", book.len(), out.display());
+            Ok(())
+        }
+        Command::Book {
+            command:
+                BookCommand::Import {
+                    file,
+                    merge,
+                    replace,
+                },
+        } => {
+            let text = std::fs::read_to_string(&file).map_err(QuartoError::Io)?;
+            let imported = book::Book::from_text(&text)?;
+            if replace {
+                policy.run("clear book", || book::clear(&db)).await?;
+            }
+            let mut book = policy.run("load book", || book::load(&db)).await?;
+            let positions = imported.len();
+            book.merge(imported, merge);
+            policy.run("save book", || book::save(&db, &book)).await?;
+            println!(
+                "{} positions imported, {} in the book",
+                positions,
+                book.len()
+            );
+            Ok(())
+        }
+        Command::Play {
+            uuid,
+            autocommit,
+            no_resume,
+        } => {
+            let mut store = GameStore {
+                handle: Handle::current(),
+                db,
+                policy,
+                wall_clock: wall_clock.clone(),
+            };
+            // Box<dyn Error> is not Send, so the error comes back as its message
+            tokio::task::spawn_blocking(move || {
+                play_session(uuid, autocommit, no_resume, &mut store).map_err(|e| e.to_string())
+            })
+            .await??;
+            Ok(())
+        }
+        Command::Rpc => {
+            rpc::serve(io::stdin().lock(), io::stdout().lock())?;
+            Ok(())
+        }
+        Command::Generate {
+            games,
+            engine1,
+            engine2,
+            out,
+            jobs,
+            seed,
+            variant,
+            adjudicate,
+        } => {
+            let settings = generate::Settings {
+                games,
+                engines: [engine1, engine2],
+                seed,
+                variant,
+                adjudication: adjudicate,
+            };
+            let jobs =
+                jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, Into::into));
+            // Ctrl-C lets every thread write the games it has played
+            let stop = Shutdown::on_ctrl_c("writing the games played so far");
+            let summary = tokio::task::spawn_blocking(move || {
+                generate::run(&settings, &out, jobs, stop.flag(), progress.as_ref())
+            })
+            .await??;
+            println!("{}", summary);
+            Ok(())
+        }
+        Command::Templates => print_templates(),
+        // Answered from the identity file before any store is opened
+        Command::Whoami | Command::Login { .. } => Ok(()),
+        Command::Doctor => {
+            let findings = policy.run("survey boards", || compat::survey(&db)).await?;
+            let known = policy.run("list uuids", || page::uuids(&db)).await?;
+            for finding in &findings {
+                println!("{}", finding.line(&known));
+            }
+            if findings.is_empty() {
+                println!(
+                    "every game is stored in board format {}",
+                    compat::BOARD_FORMAT
+                );
+            }
+            let stray = findings.iter().filter(|f| f.stray_whitespace).count();
+            if stray > 0 {
+                println!(
+                    "{} games needed their board text normalized: \\r\\n line endings, \
+                     non-breaking or trailing spaces; each is rewritten on its next move, or \
+                     by `quarto migrate-board-format`",
+                    stray
+                );
+            }
+            if !policy.run("check index", || index_exists(&db)).await? {
+                println!("no position index, run `quarto index rebuild`");
+                return Ok(());
+            }
+            let gaps = index::check(&db, &policy).await?;
+            for gap in &gaps {
+                println!("{}", gap.line(&known));
+            }
+            if gaps.is_empty() {
+                println!("the position index covers every game");
+            }
+            Ok(())
+        }
+        Command::MigrateBoardFormat => {
+            let migrated = policy
+                .run("migrate boards", || compat::migrate(&db))
+                .await?;
+            println!(
+                "rewrote {} games in board format {}",
+                migrated,
+                compat::BOARD_FORMAT
+            );
+            let known = policy.run("list uuids", || page::uuids(&db)).await?;
+            for finding in policy.run("survey boards", || compat::survey(&db)).await? {
+                warn!(finding = %finding.line(&known), "left as it is");
+            }
+            Ok(())
+        }
+        #[cfg(feature = "setup")]
+        Command::Edit { from, from_board } => {
+            let quarto = match from {
+                Some(uuid) => {
+                    let Some(quarto) = load_game(&db, &policy, &uuid).await? else {
+                        error!(uuid = %game_id::logged(&uuid), "unknown uuid");
+                        return Err(QuartoError::AnyOther)?;
+                    };
+                    quarto
+                }
+                None => read_board(&from_board)?,
+            };
+            let mut editor = edit::Editor::new(quarto);
+            let mut store = GameStore {
+                handle: Handle::current(),
+                db,
+                policy,
+                wall_clock: wall_clock.clone(),
+            };
+            tokio::task::spawn_blocking(move || {
+                edit::run(&mut editor, &mut store, io::stdin().lock(), io::stdout())
+            })
+            .await??;
+            Ok(())
+        }
+        Command::Verify { uuid } => {
+            if let Some(quarto) = load_game(&db, &policy, &uuid).await? {
+                record_loaded(&quarto);
+                if let Some(mismatch) = policy
+                    .run("check revision", || {
+                        compat::revision_mismatch(&db, uuid.as_str())
+                    })
+                    .await?
+                {
+                    warn!("game {}", mismatch);
+                }
+                let events = policy
+                    .run("load events", || event::load(&db, uuid.as_str(), 0))
+                    .await?;
+                let replayed = event::replay(&events)?;
+                if replayed
+                    .as_ref()
+                    .is_some_and(|replayed| replayed != &quarto)
+                {
+                    error!(events = events.len(), "game does not match its events");
+                    return Err(QuartoError::TamperedResult(uuid.to_string()))?;
+                }
+                let Some(sealed) = policy
+                    .run("load result hash", || verify::load(&db, uuid.as_str()))
+                    .await?
+                else {
+                    error!("game has no result hash");
+                    return Err(QuartoError::AnyOther)?;
+                };
+                if !sealed.starts_with(&format!("{}:", verify::HASH_VERSION)) {
+                    error!(%sealed, "unsupported result hash version");
+                    return Err(QuartoError::CorruptRecord(sealed))?;
+                }
+                let hash =
+                    Outcome::of(&quarto).map(|outcome| verify::result_hash(&quarto, outcome));
+                if hash.as_ref() != Some(&sealed) {
+                    error!(%sealed, ?hash, "game does not match its result hash");
+                    return Err(QuartoError::TamperedResult(uuid.to_string()))?;
+                }
+                println!("{} verified", sealed);
+                Ok(())
+            } else {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            }
+        }
+        Command::Watch { uuid } => {
+            let mut feed = DelayedFeed::new(
+                policy
+                    .run("load spectator delay", || {
+                        spectate::load(&db, uuid.as_str())
+                    })
+                    .await?,
+            );
+            let mut seen: Option<Quarto> = None;
+            let shutdown = Shutdown::on_ctrl_c("stopping after this poll");
+            loop {
+                if shutdown.requested() {
+                    db.close().await;
+                    return Ok(());
+                }
+                let quarto = match load_game(&db, &policy, &uuid).await {
+                    Ok(Some(quarto)) => quarto,
+                    Ok(None) => {
+                        error!("unknown uuid");
+                        return Err(QuartoError::AnyOther)?;
+                    }
+                    // A slow poll is skipped; the next one may get through
+                    Err(e) if matches!(e.downcast_ref(), Some(QuartoError::Timeout(_))) => {
+                        wall_clock.sleep(WATCH_INTERVAL).await;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+                let now = wall_clock.now();
+                if seen.as_ref() != Some(&quarto) {
+                    record_loaded(&quarto);
+                    if quarto.status() != GameStatus::InProgress {
+                        feed.finish();
+                    }
+                    feed.push(now, quarto.clone());
+                    seen = Some(quarto);
+                }
+                for position in feed.release(now) {
+                    print_position(&position, false, false);
+                    println!();
+                }
+                if feed.is_finished() {
+                    return Ok(());
+                }
+                wall_clock.sleep(WATCH_INTERVAL).await;
+            }
+        }
+    };
+    result
+}
+
+/* Games for `play`, whose loop runs on a blocking thread. One pool serves every
+   game opened in the session.
+*/
+struct GameStore {
+    handle: Handle,
+    db: Pool<Sqlite>,
+    policy: DbPolicy,
+    wall_clock: Arc<dyn time::Clock>,
+}
+
+impl play::Store for GameStore {
+    fn games(&mut self) -> Result<Vec<(GameId, Quarto)>, Box<dyn Error>> {
+        self.handle.block_on(
+            self.policy
+                .run("list games", || store::games_in_progress(&self.db)),
+        )
+    }
+
+    fn load(&mut self, game: &GameId) -> Result<Option<Quarto>, Box<dyn Error>> {
+        self.handle
+            .block_on(load_game(&self.db, &self.policy, game))
+    }
+
+    fn create(&mut self, advanced: bool) -> Result<(GameId, Quarto), Box<dyn Error>> {
+        let mut quarto = Quarto::new();
+        if advanced {
+            quarto.rules.variant = Variant::Advanced;
+        }
+        let uuid = GameId::random();
+        let first_piece = Piece::try_from("BSCF".to_string())?;
+        self.handle.block_on(self.policy.once(
+            "insert game",
+            store::create_game(
+                &self.db,
+                &mut quarto,
+                &uuid,
+                &first_piece,
+                GameRng::from_entropy(),
+                &Settings::default(),
+                self.wall_clock.now(),
+            ),
+        ))?;
+        Ok((uuid, quarto))
+    }
+
+    /* A session commits where it got to rather than each ply, so the event is the position */
+    fn commit(&mut self, game: &GameId, quarto: &Quarto) -> Result<(), Box<dyn Error>> {
+        let position = Event::Position {
+            position: quarto.to_share_code(),
+        };
+        self.handle.block_on(self.policy.run("update game", || {
+            store::save_game(&self.db, quarto, game, &position, self.wall_clock.now())
+        }))
+    }
+
+    /* Composed positions are setups and stay out of the statistics */
+    fn create_from(&mut self, quarto: &Quarto) -> Result<GameId, Box<dyn Error>> {
+        let Some(hand) = quarto.next_piece else {
+            return Err("a game needs a piece in hand".into());
+        };
+        let uuid = GameId::random();
+        self.handle.block_on(self.policy.once(
+            "insert game",
+            store::create_game(
+                &self.db,
+                &mut quarto.clone(),
+                &uuid,
+                &hand,
+                GameRng::from_entropy(),
+                &Settings::default(),
+                self.wall_clock.now(),
+            ),
+        ))?;
+        self.handle.block_on(
+            self.policy
+                .run("mark setup", || stats::mark_setup(&self.db, uuid.as_str())),
+        )?;
+        Ok(uuid)
+    }
+
+    fn seed(&mut self, game: &GameId) -> Result<Option<GameRng>, Box<dyn Error>> {
+        self.handle.block_on(
+            self.policy
+                .run("load seed", || seed::load(&self.db, game.as_str())),
+        )
+    }
+}
+
+/* The commands that work on game files alone; clocks, deadlines and analysis
+   need the database.
+*/
+fn run_offline(command: Command, dir: &Path) -> Result<(), Box<dyn Error>> {
+    let mut store = FileStore::open(dir)?;
+    match command {
+        Command::NewGame {
+            template,
+            variant,
+            clock,
+            deadline,
+            spectator_delay,
+            from_code,
+            from_file,
+            // Game files keep no seed; hint and analyze need the database anyway
+            seed: _,
+        } => {
+            let flags = Template {
+                variant,
+                clock,
+                deadline,
+                spectator_delay,
+            };
+            let settings = new_game_settings(&template, &flags)?;
+            if settings.clock.is_some()
+                || settings.deadline.is_some()
+                || settings.spectator_delay.is_some()
+            {
+                error!(%settings, "clocks, deadlines and spectator delays need the database");
+                return Err(QuartoError::AnyOther.into());
+            }
+            let (mut new_game, first_piece) = starting_position(&from_code, &from_file)?;
+            if from_code.is_none() && from_file.is_none() {
+                new_game.rules.variant = settings.variant;
+            }
+            if new_game.next_piece.is_none() {
+                new_game.pick_piece(&first_piece);
+            }
+            let uuid = GameId::random();
+            store.insert(&uuid, &new_game)?;
+            println!("{}", uuid);
+            Ok(())
+        }
+        Command::Move {
+            uuid,
+            at,
+            piece,
+            check,
+            // A local store has no lost replies to retry
+            key: _,
+            format,
+        } => {
+            let at = cell_argument(&at)?;
+            let _lock = store.lock(&uuid)?;
+            let Some(mut quarto) = store.load(&uuid)? else {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            };
+            record_loaded(&quarto);
+            let ply = requested_move(&quarto, at, piece);
+            let preview = checked_move(&quarto, &ply, format)?;
+            if check {
+                match format {
+                    OutputFormat::Text => println!("{}", preview),
+                    OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&preview)?),
+                }
+                return Ok(());
+            }
+            quarto.play_legal(&ply);
+            store.save(&uuid, &quarto)?;
+            Ok(())
+        }
+        Command::Show {
+            uuid,
+            code,
+            describe,
+            orientation,
+            at,
+            format,
+            analysis,
+        } => {
+            if at.is_some() {
+                error!("game files keep no history; showing past moves needs the database");
+                return Err(QuartoError::AnyOther.into());
+            }
+            let symmetry = orientation_symmetry(orientation)?;
+            let quarto = match uuid {
+                Some(uuid) => {
+                    let Some(quarto) = store.load(&uuid)? else {
+                        error!("unknown uuid");
+                        return Err(QuartoError::AnyOther)?;
+                    };
+                    record_loaded(&quarto);
+                    quarto
+                }
+                None => Quarto::from_share_code(&code.unwrap_or_default())?,
+            };
+            show_position(&quarto.transformed(symmetry), describe, format, analysis)?;
+            Ok(())
+        }
+        Command::Play {
+            uuid,
+            autocommit,
+            no_resume,
+        } => play_session(uuid, autocommit, no_resume, &mut store),
+        Command::Templates => print_templates(),
+        #[cfg(feature = "setup")]
+        Command::Edit { from, from_board } => {
+            let quarto = match from {
+                Some(uuid) => {
+                    let Some(quarto) = store.load(&uuid)? else {
+                        error!(uuid = %game_id::logged(&uuid), "unknown uuid");
+                        return Err(QuartoError::AnyOther)?;
+                    };
+                    quarto
+                }
+                None => read_board(&from_board)?,
+            };
+            let mut editor = edit::Editor::new(quarto);
+            edit::run(&mut editor, &mut store, io::stdin().lock(), io::stdout())?;
+            Ok(())
+        }
+        command => {
+            error!(name = command.name(), "not available with a file store");
+            Err(QuartoError::AnyOther.into())
+        }
+    }
+}
+
+/* Opens the game asked for, or offers the last session back when there is none.
+   The session is recorded for resuming whichever way it started.
+*/
+fn play_session(
+    uuid: Option<GameId>,
+    autocommit: bool,
+    no_resume: bool,
+    store: &mut dyn play::Store,
+) -> Result<(), Box<dyn Error>> {
+    let record = resume::path();
+    let mut input = io::stdin().lock();
+    let mut output = io::stdout();
+    let resumed = match (&uuid, &record) {
+        (None, Some(path)) if !no_resume => {
+            resume::offer(path, store, autocommit, &mut input, &mut output)?
+        }
+        _ => None,
+    };
+    let mut session = match (resumed, uuid) {
+        (Some(session), _) => session,
+        (None, Some(uuid)) => {
+            let Some(quarto) = store.load(&uuid)? else {
+                error!("unknown uuid");
+                return Err(QuartoError::AnyOther)?;
+            };
+            record_loaded(&quarto);
+            play::Session::new(uuid, quarto, autocommit)
+        }
+        (None, None) => {
+            let (game, quarto) = store.create(false)?;
+            println!("{}", game);
+            play::Session::new(game, quarto, autocommit)
+        }
+    };
+    session.record = record;
+    let cpu = template::load()?.analysis_cpu.unwrap_or_default();
+    // Hints keep to the seed of the game the session opened with
+    let rng = store.seed(session.game())?;
+    let engine = Arc::new(analysis::Playouts { rng });
+    session.analysis = Some(analysis::Scheduler::new(Handle::current(), engine, cpu));
+    play::run(&mut session, store, input, output)?;
+    Ok(())
+}
+
+/* A board file written by the editor, or an empty board */
+#[cfg(feature = "setup")]
+fn read_board(path: &Option<PathBuf>) -> Result<Quarto, QuartoError> {
+    match path {
+        Some(path) => file_store::decode(&std::fs::read_to_string(path).map_err(QuartoError::Io)?),
+        None => Ok(Quarto::new()),
+    }
+}
+
+/* new-game's options with --template expanded under them */
+fn new_game_settings(template: &Option<String>, flags: &Template) -> Result<Settings, QuartoError> {
+    let Some(name) = template else {
+        return Ok(Settings::resolve(None, flags));
+    };
+    let templates = template::load()?.templates;
+    let template = template::lookup(&templates, name).inspect_err(|_| {
+        error!(%name, known = ?templates.keys().collect::<Vec<_>>(), "unknown template");
+    })?;
+    Ok(Settings::resolve(Some(template), flags))
+}
+
+fn print_templates() -> Result<(), Box<dyn Error>> {
+    for (name, template) in template::load()?.templates {
+        println!(
+            "{}: {}",
+            name,
+            Settings::resolve(Some(&template), &Template::default())
+        );
+    }
+    Ok(())
+}
+
+/* An empty board with BSCF to place first, or a shared position with its piece in hand */
+fn starting_position(
+    from_code: &Option<String>,
+    from_file: &Option<PathBuf>,
+) -> Result<(Quarto, Piece), QuartoError> {
+    let shared = match (from_code, from_file) {
+        (Some(code), _) => {
+            let (shared, revision) = Quarto::read_share_code(code)?;
+            if let Some(mismatch) = quarto::revision_mismatch(revision) {
+                warn!(%code, "shared position {}", mismatch);
+            }
+            shared
+        }
+        (None, Some(path)) => import_game(path)?,
+        // We are sure BSCF is valid Piece.
+        (None, None) => return Ok((Quarto::new(), Piece::try_from("BSCF".to_string()).unwrap())),
+    };
+    let Some(hand) = shared.next_piece else {
+        error!(code = %shared.to_share_code(), "shared position has no piece in hand");
+        return Err(QuartoError::InvalidPieceError);
+    };
+    Ok((shared, hand))
+}
+
+/* Game text for `new-game --from-file`, read leniently with each fix logged as a
+   warning
+*/
+fn import_game(path: &Path) -> Result<Quarto, QuartoError> {
+    let text = std::fs::read_to_string(path).map_err(QuartoError::Io)?;
+    let (quarto, warnings) = Quarto::parse(&text, ParseOptions::default())?;
+    for warning in &warnings {
+        warn!(path = %path.display(), %warning, "game text was not canonical");
+    }
+    Ok(quarto)
+}
+
+fn orientation_symmetry(orientation: u16) -> Result<Symmetry, QuartoError> {
+    match orientation {
+        0 => Ok(Symmetry::Identity),
+        90 => Ok(Symmetry::Rotate90),
+        180 => Ok(Symmetry::Rotate180),
+        270 => Ok(Symmetry::Rotate270),
+        _ => {
+            error!(orientation, "invalid orientation");
+            Err(QuartoError::OutOfRange)
+        }
+    }
+}
+
+fn print_position(quarto: &Quarto, describe: bool, analysis: bool) {
+    if describe {
+        println!("{}", quarto.describe());
+        return;
+    }
+    let notation = notation::current();
+    println!("{}", notation.board(&quarto.board_state));
+    println!("next piece: {}", notation.hand(quarto.next_piece));
+    let losing = if analysis {
+        quarto.losing_pieces()
+    } else {
+        Vec::new()
+    };
+    println!(
+        "free pieces:\n{}",
+        notation.free_pieces(quarto.free(), &losing)
+    );
+    if quarto.rules.variant == Variant::Advanced {
+        println!("variant: advanced");
+    }
+}
+
+/* A position for `show`. As JSON it is the whole output: the game as rpc states
+   it, its free pieces each with whether it is safe to hand over, and with
+   --analysis its cell heat.
+*/
+fn show_position(
+    quarto: &Quarto,
+    describe: bool,
+    format: OutputFormat,
+    analysis: bool,
+) -> Result<(), serde_json::Error> {
+    if format == OutputFormat::Json {
+        let mut position = serde_json::json!({
+            "state": quarto,
+            "quarto": quarto.is_quarto(),
+        });
+        let losing = quarto.losing_pieces();
+        position["free_pieces_annotated"] = quarto
+            .free()
+            .iter()
+            .map(|p| serde_json::json!({"piece": p, "safe": !losing.contains(p)}))
+            .collect();
+        if analysis {
+            position["heat"] = serde_json::json!(quarto.cell_heat());
+        }
+        println!("{}", serde_json::to_string_pretty(&position)?);
+        return Ok(());
+    }
+    print_position(quarto, describe, analysis);
+    if analysis {
+        println!("{}", heat_grid(&quarto.cell_heat()));
+    }
+    Ok(())
+}
+
+/* Laid out and labelled like the board */
+fn heat_grid(heat: &[[u8; 4]; 4]) -> String {
+    let rows: Vec<String> = heat
+        .iter()
+        .zip((1..=4).rev())
+        .map(|(row, rank)| {
+            let cells: Vec<String> = row.iter().map(|h| format!("{:>4}", h)).collect();
+            format!("{} {}", rank, cells.join(" "))
+        })
+        .collect();
+    format!("heat:\n{}\n  a    b    c    d", rows.join("\n"))
+}
+
+/* What `move` asks for. Its piece is required, but a placement which ends the
+   game hands nothing over, so then the piece is left out.
+*/
+fn requested_move(quarto: &Quarto, at: Coord, piece: Piece) -> Move {
+    let placed = Move {
+        place: Some(at),
+        hand: None,
+    };
+    match quarto.check_move(&placed) {
+        Ok(preview) if preview.status != GameStatus::InProgress => placed,
+        _ => Move {
+            hand: Some(piece),
+            ..placed
+        },
+    }
+}
+
+/* Previews a move before anything is written. Every rule it breaks is reported
+   in one message, and on stdout as well with --format json.
+*/
+fn checked_move(
+    quarto: &Quarto,
+    mv: &Move,
+    format: OutputFormat,
+) -> Result<MovePreview, Box<dyn Error>> {
+    match quarto.check_move(mv) {
+        Ok(preview) => Ok(preview),
+        Err(QuartoError::BrokenRules(broken)) => {
+            error!(%broken, "illegal move");
+            if format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&broken.to_json())?);
+            }
+            Err(QuartoError::BrokenRules(broken).into())
+        }
+        Err(e) => {
+            error!(%mv, "illegal move");
+            Err(e.into())
+        }
+    }
+}
+
+/* The cell named on the command line, warning when it was given as x y */
+fn cell_argument(places: &[Place]) -> Result<Coord, QuartoError> {
+    let at = Coord::from_places(places)?;
+    if places.iter().any(Place::is_deprecated) {
+        warn!(%at, "x y coordinates are deprecated; give the square instead");
+    }
+    Ok(at)
+}
+
+/* How often `watch` looks for new moves */
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+fn record_loaded(quarto: &Quarto) {
+    Span::current().record("moves", quarto.placed_pieces());
+    debug!(?quarto, "loaded game");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    /* Tests which store games need store.rs's queries, which the init feature
+       leaves out, so they are built without it
+    */
+    #[cfg(not(feature = "init"))]
+    use crate::time::test::FakeClock;
+    #[cfg(not(feature = "init"))]
+    use crate::time::Clock as _;
+    use std::sync::Mutex;
+
+    const GAME: &str = "d3b07384-0000-4000-8000-000000000001";
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_command_events_carry_uuid() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .without_time()
+            .finish();
+        let show = Command::Show {
+            uuid: Some(GAME.parse().unwrap()),
+            code: None,
+            describe: false,
+            orientation: 45,
+            at: None,
+            format: OutputFormat::Text,
+            analysis: false,
+        };
+        tracing::subscriber::with_default(subscriber, || {
+            show.span().in_scope(|| {
+                record_loaded(&Quarto::new());
+                error!(orientation = 45, "invalid orientation");
+            });
+            Command::NewGame {
+                template: None,
+                variant: None,
+                clock: None,
+                deadline: None,
+                spectator_delay: None,
+                from_code: None,
+                from_file: None,
+                seed: None,
+            }
+            .span()
+            .in_scope(|| error!("no game yet"));
+        });
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", output);
+        assert!(
+            lines[0].contains(&format!(
+                "command{{name=\"show\" uuid={} moves=0}}",
+                &GAME[..game_id::SHORT_LEN]
+            )),
+            "{}",
+            lines[0]
+        );
+        assert!(
+            lines[0].contains("invalid orientation orientation=45"),
+            "{}",
+            lines[0]
+        );
+        assert!(
+            lines[1].contains("command{name=\"new-game\"}"),
+            "{}",
+            lines[1]
+        );
+    }
+
+    #[test]
+    fn test_heat_grid_is_laid_out_like_the_board() {
+        let mut heat = [[0; 4]; 4];
+        heat[0][3] = 128;
+        heat[3][0] = 255;
+        assert_eq!(
+            heat_grid(&heat),
+            indoc::indoc! {"
+            heat:
+            4    0    0    0  128
+            3    0    0    0    0
+            2    0    0    0    0
+            1  255    0    0    0
+              a    b    c    d"}
+        );
+    }
+
+    #[cfg(not(feature = "init"))]
+    fn quiet() -> Box<dyn Progress> {
+        Box::new(progress::Silent)
+    }
+
+    #[cfg(not(feature = "init"))]
+    /* A fresh database, set up by `init`, in a directory removed with the TempDir */
+    async fn init_db(dir: &file_store::test::TempDir, clock: &FakeClock) -> String {
+        let db_url = format!("sqlite://{}", dir.0.join("games.sqlite").display());
+        let init = Command::Init { force: false };
+        run(
+            init,
+            db_url.clone(),
+            DbPolicy::default(),
+            quiet(),
+            Arc::new(clock.clone()),
+            None,
+        )
+        .await
+        .unwrap();
+        db_url
+    }
+
+    #[cfg(not(feature = "init"))]
+    fn new_game(clock: Option<&str>, deadline: Option<&str>) -> Command {
+        Command::NewGame {
+            template: None,
+            variant: None,
+            clock: clock.map(|clock| clock.parse().unwrap()),
+            deadline: deadline.map(|deadline| deadline.parse().unwrap()),
+            spectator_delay: None,
+            from_code: None,
+            from_file: None,
+            seed: None,
+        }
+    }
+
+    #[cfg(not(feature = "init"))]
+    fn play_move(uuid: &str, at: &str, piece: &str) -> Command {
+        Command::Move {
+            uuid: uuid.parse().unwrap(),
+            at: vec![at.parse().unwrap()],
+            piece: piece.parse().unwrap(),
+            check: false,
+            key: None,
+            format: OutputFormat::Text,
+        }
+    }
+
+    #[cfg(not(feature = "init"))]
+    #[tokio::test]
+    async fn test_time_control_runs_on_the_fake_clock() {
+        let dir = file_store::test::TempDir::new();
+        let clock = FakeClock::at(1_000_000);
+        let db_url = init_db(&dir, &clock).await;
+        let policy = DbPolicy::default();
+        let command = |command| {
+            run(
+                command,
+                db_url.clone(),
+                policy,
+                quiet(),
+                Arc::new(clock.clone()),
+                None,
+            )
+        };
+        command(new_game(Some("1m"), None)).await.unwrap();
+        let db = SqlitePool::connect(&db_url).await.unwrap();
+        let uuid = event::games(&db).await.unwrap().remove(0);
+
+        clock.advance(Duration::from_secs(20));
+        command(play_move(&uuid, "a4", "WSCF")).await.unwrap();
+        let game_clock = clock::load(&db, &uuid).await.unwrap().unwrap();
+        assert_eq!(game_clock.remaining, [60_000, 40_000]);
+        assert_eq!(game_clock.last_move_at, 1_020_000);
+        let events = event::load(&db, &uuid, 0).await.unwrap();
+        assert_eq!(events[1].think_ms, Some(20_000));
+
+        // The 1st player runs out a minute later
+        clock.advance(Duration::from_secs(61));
+        let e = command(play_move(&uuid, "b3", "WTCF")).await.unwrap_err();
+        assert!(matches!(e.downcast_ref(), Some(QuartoError::LostOnTime)));
+        let game_clock = clock::load(&db, &uuid).await.unwrap().unwrap();
+        assert_eq!(game_clock.flagged, Some(0));
+        assert_eq!(event::load(&db, &uuid, 0).await.unwrap().len(), 2);
+    }
+
+    #[cfg(not(feature = "init"))]
+    #[tokio::test]
+    async fn test_sweep_forfeits_on_the_fake_clock() {
+        let dir = file_store::test::TempDir::new();
+        let clock = FakeClock::at(1_000_000);
+        let db_url = init_db(&dir, &clock).await;
+        let policy = DbPolicy::default();
+        let command = |command| {
+            run(
+                command,
+                db_url.clone(),
+                policy,
+                quiet(),
+                Arc::new(clock.clone()),
+                None,
+            )
+        };
+        command(new_game(None, Some("1h"))).await.unwrap();
+        let db = SqlitePool::connect(&db_url).await.unwrap();
+        let uuid = event::games(&db).await.unwrap().remove(0);
+
+        clock.advance(Duration::from_secs(50 * 60));
+        command(play_move(&uuid, "a4", "WSCF")).await.unwrap();
+        // The move renewed the deadline, so 20 minutes on nothing is overdue
+        clock.advance(Duration::from_secs(20 * 60));
+        command(Command::Sweep).await.unwrap();
+        assert_eq!(
+            deadline::load(&db, &uuid).await.unwrap().unwrap().forfeited,
+            None
+        );
+
+        clock.advance(Duration::from_secs(41 * 60));
+        command(Command::Sweep).await.unwrap();
+        let due = deadline::load(&db, &uuid).await.unwrap().unwrap();
+        assert_eq!(due.forfeited, Some(0));
+        let e = command(play_move(&uuid, "b3", "WTCF")).await.unwrap_err();
+        assert!(matches!(e.downcast_ref(), Some(QuartoError::Forfeited)));
+    }
+
+    #[cfg(not(feature = "init"))]
+    #[tokio::test]
+    async fn test_move_retried_with_key_is_played_once() {
+        let dir = file_store::test::TempDir::new();
+        let clock = FakeClock::default();
+        let db_url = init_db(&dir, &clock).await;
+        let policy = DbPolicy::default();
+        let db = SqlitePool::connect(&db_url).await.unwrap();
+        let game = GameId::parse(GAME).unwrap();
+        let bscf = Piece::try_from("BSCF".to_string()).unwrap();
+        store::create_game(
+            &db,
+            &mut Quarto::new(),
+            &game,
+            &bscf,
+            GameRng(0),
+            &Settings::default(),
+            clock.now(),
+        )
+        .await
+        .unwrap();
+
+        let submit = |key: &str| Command::Move {
+            uuid: game.clone(),
+            at: vec!["a4".parse().unwrap()],
+            piece: "WSCF".parse().unwrap(),
+            check: false,
+            key: Some(key.to_string()),
+            format: OutputFormat::Text,
+        };
+        for _ in 0..2 {
+            run(
+                submit("k1"),
+                db_url.clone(),
+                policy,
+                quiet(),
+                Arc::new(clock.clone()),
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        let events = event::load(&db, GAME, 0).await.unwrap();
+        assert_eq!(events.len(), 2);
+        let reply = idempotency::lookup(&db, GAME, "k1").await.unwrap();
+        assert_eq!(reply.as_deref(), Some("move 2: a4 WSCF"));
+        // Another key is another move, which is no longer legal
+        assert!(run(
+            submit("k2"),
+            db_url.clone(),
+            policy,
+            quiet(),
+            Arc::new(clock),
+            None,
+        )
+        .await
+        .is_err());
+        assert_eq!(event::load(&db, GAME, 0).await.unwrap().len(), 2);
+    }
+
+    #[cfg(not(feature = "init"))]
+    #[tokio::test]
+    async fn test_stray_whitespace_is_repaired_on_the_next_move() {
+        let dir = file_store::test::TempDir::new();
+        let clock = FakeClock::at(1_000_000);
+        let db_url = init_db(&dir, &clock).await;
+        let command = |command| {
+            run(
+                command,
+                db_url.clone(),
+                DbPolicy::default(),
+                quiet(),
+                Arc::new(clock.clone()),
+                None,
+            )
+        };
+        command(new_game(None, None)).await.unwrap();
+        let db = SqlitePool::connect(&db_url).await.unwrap();
+        let uuid = event::games(&db).await.unwrap().remove(0);
+        command(play_move(&uuid, "a4", "BSCH")).await.unwrap();
+        let game = GameId::parse(&uuid).unwrap();
+        let before = store::find_game(&db, &game).await.unwrap().unwrap();
+
+        // As a tool syncing the file might leave it: \r\n and non-breaking spaces
+        sqlx::query(
+            r#"
+            UPDATE game
+            SET board_state = replace(replace(board_state, char(10), char(13, 10)),
+                                      ' ', char(160))
+            WHERE uuid = ?1;
+            "#,
+        )
+        .bind(&uuid)
+        .execute(&db)
+        .await
+        .unwrap();
+        assert_eq!(store::find_game(&db, &game).await.unwrap(), Some(before));
+        let findings = compat::survey(&db).await.unwrap();
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].stray_whitespace);
+
+        command(play_move(&uuid, "b4", "BSSF")).await.unwrap();
+        assert!(compat::survey(&db).await.unwrap().is_empty());
+        let played = store::find_game(&db, &game).await.unwrap().unwrap();
+        assert_eq!(played.placed_pieces(), 2);
+    }
+
+    #[cfg(not(feature = "init"))]
+    #[tokio::test]
+    async fn test_failed_finalization_rolls_back_the_final_move() {
+        let dir = file_store::test::TempDir::new();
+        let clock = FakeClock::at(1_000_000);
+        let db_url = init_db(&dir, &clock).await;
+        let command = |command| {
+            run(
+                command,
+                db_url.clone(),
+                DbPolicy::default(),
+                quiet(),
+                Arc::new(clock.clone()),
+                None,
+            )
+        };
+        command(new_game(Some("10m"), Some("1h"))).await.unwrap();
+        let db = SqlitePool::connect(&db_url).await.unwrap();
+        let uuid = event::games(&db).await.unwrap().remove(0);
+        for (at, piece) in [("a4", "BSCH"), ("b4", "BSSF"), ("c4", "BTSH")] {
+            command(play_move(&uuid, at, piece)).await.unwrap();
+        }
+        let game = GameId::parse(&uuid).unwrap();
+        let before = store::find_game(&db, &game).await.unwrap();
+        let clock_before = clock::load(&db, &uuid).await.unwrap();
+
+        // The last step of finalization fails, after the others have run
+        sqlx::query(
+            r#"
+            CREATE TRIGGER fail_seal BEFORE UPDATE OF result_hash ON game
+            BEGIN SELECT RAISE(ABORT, 'injected failure'); END;
+            "#,
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        clock.advance(Duration::from_secs(5));
+        let finish = || Command::Move {
+            uuid: game.clone(),
+            at: vec!["d4".parse().unwrap()],
+            piece: "WTCH".parse().unwrap(),
+            check: false,
+            key: Some("last".to_string()),
+            format: OutputFormat::Text,
+        };
+        assert!(command(finish()).await.is_err());
+        let game_after = store::find_game(&db, &game).await.unwrap();
+        assert_eq!(game_after, before);
+        assert_eq!(clock::load(&db, &uuid).await.unwrap(), clock_before);
+        assert_eq!(event::load(&db, &uuid, 0).await.unwrap().len(), 4);
+        assert_eq!(idempotency::lookup(&db, &uuid, "last").await.unwrap(), None);
+        assert_eq!(verify::load(&db, &uuid).await.unwrap(), None);
+        assert!(deadline::load(&db, &uuid).await.unwrap().is_some());
+
+        sqlx::query("DROP TRIGGER fail_seal;")
+            .execute(&db)
+            .await
+            .unwrap();
+        command(finish()).await.unwrap();
+        let finished = store::find_game(&db, &game).await.unwrap().unwrap();
+        assert_eq!(Outcome::of(&finished), Some(Outcome::Won(0)));
+        let sealed = verify::load(&db, &uuid).await.unwrap().unwrap();
+        assert_eq!(sealed, verify::result_hash(&finished, Outcome::Won(0)));
+        assert_eq!(deadline::load(&db, &uuid).await.unwrap(), None);
+        let reply = idempotency::lookup(&db, &uuid, "last").await.unwrap();
+        assert_eq!(reply.as_deref(), Some("move 5: d4"));
+
+        // Finishing again writes nothing and gives back the first result
+        let again = store::finalize_game(
+            &db,
+            &finished,
+            &game,
+            &"d4".parse::<Move>().unwrap(),
+            None,
+            None,
+            clock.now(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            again,
+            store::FinalizedGame {
+                outcome: Outcome::Won(0),
+                result_hash: sealed,
+                reply: "move 5: d4".to_string(),
+                repeated: true,
+            }
+        );
+        assert_eq!(
+            again.to_string(),
+            format!("won by the 1st player, result {}", again.result_hash)
+        );
+        assert_eq!(event::load(&db, &uuid, 0).await.unwrap().len(), 5);
+    }
+
+    #[cfg(not(feature = "init"))]
+    #[tokio::test]
+    async fn test_identities_take_seats_and_list_their_games() {
+        let dir = file_store::test::TempDir::new();
+        let clock = FakeClock::default();
+        let db_url = init_db(&dir, &clock).await;
+        let command = |command, identity| {
+            run(
+                command,
+                db_url.clone(),
+                DbPolicy::default(),
+                quiet(),
+                Arc::new(clock.clone()),
+                identity,
+            )
+        };
+        command(new_game(None, None), Some("alice")).await.unwrap();
+        command(new_game(None, None), Some("carol")).await.unwrap();
+        let db = SqlitePool::connect(&db_url).await.unwrap();
+        let (game,) =
+            sqlx::query_as::<_, (String,)>("SELECT uuid FROM game WHERE player_1st = 'alice';")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+
+        // The 2nd player places first, and bob switches in to take that seat
+        command(play_move(&game, "a4", "WSCF"), Some("bob"))
+            .await
+            .unwrap();
+        let players = identity::players(&db, &game).await.unwrap();
+        assert_eq!(players, [Some("alice".into()), Some("bob".into())]);
+        let e = command(play_move(&game, "b3", "BTCF"), Some("carol"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(e.downcast_ref(), Some(QuartoError::SeatTaken(taken)) if taken == "1st seat is alice's"),
+            "{:?}",
+            e
+        );
+        command(play_move(&game, "b3", "BTCF"), Some("alice"))
+            .await
+            .unwrap();
+
+        let resign = || Command::Resign {
+            uuid: game.parse().unwrap(),
+            seat: None,
+        };
+        let e = command(resign(), Some("carol")).await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(QuartoError::InvalidSeat(_))
+        ));
+        let e = command(resign(), None).await.unwrap_err();
+        assert!(matches!(e.downcast_ref(), Some(QuartoError::NotLoggedIn)));
+        command(resign(), Some("bob")).await.unwrap();
+        let events = event::load(&db, &game, 0).await.unwrap();
+        assert_eq!(events.last().unwrap().event, Event::Resigned { seat: 1 });
+
+        for (player, games) in [("alice", 1), ("bob", 1), ("carol", 1), ("dave", 0)] {
+            let page = page::games(&db, 0, 10, Some(player)).await.unwrap();
+            assert_eq!(page.items.len(), games, "{}", player);
+        }
+        let bobs = page::games(&db, 0, 10, Some("bob")).await.unwrap();
+        assert_eq!(bobs.items[0].uuid, game);
+        let mine = Command::List {
+            limit: 10,
+            cursor: 0,
+            mine: true,
+        };
+        let e = command(mine, None).await.unwrap_err();
+        assert!(matches!(e.downcast_ref(), Some(QuartoError::NotLoggedIn)));
+    }
+
+    #[cfg(not(feature = "init"))]
+    #[tokio::test]
+    async fn test_read_only_database_reads_and_refuses_writes() {
+        let dir = file_store::test::TempDir::new();
+        let clock = FakeClock::default();
+        let db_url = init_db(&dir, &clock).await;
+        let db = SqlitePool::connect(&db_url).await.unwrap();
+        let game = GameId::parse(GAME).unwrap();
+        let bscf = Piece::try_from("BSCF".to_string()).unwrap();
+        store::create_game(
+            &db,
+            &mut Quarto::new(),
+            &game,
+            &bscf,
+            GameRng(0),
+            &Settings::default(),
+            clock.now(),
+        )
+        .await
+        .unwrap();
+        let writable = |command| {
+            run(
+                command,
+                db_url.clone(),
+                DbPolicy::default(),
+                quiet(),
+                Arc::new(clock.clone()),
+                None,
+            )
+        };
+        writable(play_move(GAME, "a4", "WSCF")).await.unwrap();
+
+        let read_only_url = db_policy::read_only_url(&db_url);
+        let read_only = |command| {
+            run(
+                command,
+                read_only_url.clone(),
+                DbPolicy {
+                    read_only: true,
+                    ..DbPolicy::default()
+                },
+                quiet(),
+                Arc::new(clock.clone()),
+                None,
+            )
+        };
+        let uuid = || GAME.parse::<GameId>().unwrap();
+        let reads = [
+            Command::Show {
+                uuid: Some(uuid()),
+                code: None,
+                describe: false,
+                orientation: 0,
+                at: None,
+                format: OutputFormat::Text,
+                analysis: false,
+            },
+            Command::List {
+                limit: 10,
+                cursor: 0,
+                mine: false,
+            },
+            Command::History {
+                uuid: uuid(),
+                limit: 10,
+                cursor: 0,
+                long: false,
+            },
+            Command::Log {
+                uuid: uuid(),
+                time: TimeStyle::Relative,
+            },
+            Command::Replay {
+                uuid: uuid(),
+                evaluate: true,
+                depth: 1,
+            },
+            Command::Analyze {
+                uuid: Some(uuid()),
+                all: false,
+                filter: vec![],
+                depth: 5,
+                jobs: None,
+                playouts: 10,
+                seed: Some(1),
+                no_cache: false,
+                explain: false,
+            },
+            Command::Prove {
+                target: uuid().as_str().to_string(),
+                plies: 3,
+            },
+            Command::Prove {
+                target: Quarto::new().to_share_code(),
+                plies: 3,
+            },
+            Command::CountPositions {
+                plies: 1,
+                canonical: true,
+                bloom: None,
+            },
+            Command::Stats {
+                heatmap: false,
+                include_aborted: false,
+                analysis: false,
+                format: OutputFormat::Text,
+            },
+        ];
+        for command in reads {
+            let name = command.name();
+            if let Err(e) = read_only(command).await {
+                panic!("{}: {:?}", name, e);
+            }
+        }
+        // Analysing kept nothing in the cache
+        assert!(cache::stats(&db).await.unwrap().is_empty());
+
+        for command in [play_move(GAME, "b3", "WTCF"), new_game(None, None)] {
+            let name = command.name();
+            let e = read_only(command).await.unwrap_err();
+            assert!(
+                matches!(e.downcast_ref(), Some(QuartoError::ReadOnly(command)) if command == name),
+                "{:?}",
+                e
+            );
+        }
+        assert_eq!(event::load(&db, GAME, 0).await.unwrap().len(), 2);
+        assert_eq!(page::uuids(&db).await.unwrap().len(), 1);
+    }
+
+    #[cfg(not(feature = "init"))]
+    #[tokio::test]
+    async fn test_missing_database_is_reported_not_panicked_on() {
+        let dir = file_store::test::TempDir::new();
+        let db_url = format!("sqlite://{}", dir.0.join("absent/sqlite.db").display());
+        let missing = |command| {
+            run(
+                command,
+                db_url.clone(),
+                DbPolicy::default(),
+                quiet(),
+                Arc::new(FakeClock::default()),
+                None,
+            )
+        };
+        let e = missing(play_move(GAME, "a4", "WSCF")).await.unwrap_err();
+        assert!(
+            matches!(e.downcast_ref(), Some(QuartoError::StoreUnavailable(_))),
+            "{:?}",
+            e
+        );
+        // Commands without a store never open it
+        missing(Command::Templates).await.unwrap();
+    }
+
+    #[cfg(not(feature = "init"))]
+    #[tokio::test]
+    async fn test_debug_sql_logs_the_binds_of_a_move() {
+        let dir = file_store::test::TempDir::new();
+        let clock = FakeClock::default();
+        let db_url = init_db(&dir, &clock).await;
+        let db = SqlitePool::connect(&db_url).await.unwrap();
+        let game = GameId::parse(GAME).unwrap();
+        let bscf = Piece::try_from("BSCF".to_string()).unwrap();
+        store::create_game(
+            &db,
+            &mut Quarto::new(),
+            &game,
+            &bscf,
+            GameRng(0),
+            &Settings::default(),
+            clock.now(),
+        )
+        .await
+        .unwrap();
+
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let mut filter = EnvFilter::new("off");
+        for directive in sql_log::DIRECTIVES {
+            filter = filter.add_directive(directive.parse().unwrap());
+        }
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .without_time()
+            .finish();
+        let guard = tracing::subscriber::set_default(subscriber);
+        run(
+            play_move(GAME, "a4", "WSCF"),
+            db_url.clone(),
+            DbPolicy::default(),
+            quiet(),
+            Arc::new(clock),
+            None,
+        )
+        .await
+        .unwrap();
+        drop(guard);
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let update = output
+            .lines()
+            .find(|line| line.contains("statement=\"UPDATE game\""))
+            .unwrap_or_else(|| panic!("no UPDATE in {}", output));
+        assert!(update.contains(&format!("uuid={} ", GAME)), "{}", update);
+        assert!(update.contains("next_piece=WSCF "), "{}", update);
+    }
+
+    #[cfg(not(feature = "init"))]
+    #[tokio::test]
+    async fn test_illegal_move_reports_every_broken_rule() {
+        let dir = file_store::test::TempDir::new();
+        let clock = FakeClock::default();
+        let db_url = init_db(&dir, &clock).await;
+        let policy = DbPolicy::default();
+        let command = |command| {
+            run(
+                command,
+                db_url.clone(),
+                policy,
+                quiet(),
+                Arc::new(clock.clone()),
+                None,
+            )
+        };
+        let db = SqlitePool::connect(&db_url).await.unwrap();
+        let game = GameId::parse(GAME).unwrap();
+        let bscf = Piece::try_from("BSCF".to_string()).unwrap();
+        store::create_game(
+            &db,
+            &mut Quarto::new(),
+            &game,
+            &bscf,
+            GameRng(0),
+            &Settings::default(),
+            clock.now(),
+        )
+        .await
+        .unwrap();
+        command(play_move(GAME, "a4", "WSCF")).await.unwrap();
+
+        // a4 now holds BSCF and WSCF is in hand
+        let e = command(play_move(GAME, "a4", "WSCF")).await.unwrap_err();
+        let Some(QuartoError::BrokenRules(broken)) = e.downcast_ref() else {
+            panic!("expected broken rules, got {:?}", e);
+        };
+        assert_eq!(
+            broken.to_string(),
+            "a4 WSCF: a4 is occupied; WSCF is not free"
+        );
+        assert_eq!(
+            broken.to_json(),
+            serde_json::json!({
+                "move": "a4 WSCF",
+                "breaks": [
+                    {"rule": "occupied", "message": "a4 is occupied"},
+                    {"rule": "not_free", "message": "WSCF is not free"},
+                ],
+            })
+        );
+        assert_eq!(event::load(&db, GAME, 0).await.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_offline_game_flow() {
+        let dir = file_store::test::TempDir::new();
+        let new_game = Command::NewGame {
+            template: None,
+            variant: None,
+            clock: None,
+            deadline: None,
+            spectator_delay: None,
+            from_code: None,
+            from_file: None,
+            seed: None,
+        };
+        run_offline(new_game, &dir.0).unwrap();
+        let store = FileStore::open(&dir.0).unwrap();
+        let games = store.list().unwrap();
+        assert_eq!(games.len(), 1);
+        let uuid = games[0].0.clone();
+        let play = |at: &str, piece: &str, check| {
+            let command = Command::Move {
+                uuid: uuid.clone(),
+                at: at.split(' ').map(|place| place.parse().unwrap()).collect(),
+                piece: piece.parse().unwrap(),
+                check,
+                key: None,
+                format: OutputFormat::Text,
+            };
+            run_offline(command, &dir.0)
+        };
+
+        play("a4", "WSCF", true).unwrap();
+        assert_eq!(store.load(&uuid).unwrap().unwrap().placed_pieces(), 0);
+        play("a4", "WSCF", false).unwrap();
+        // The deprecated x y names the same cell
+        assert!(play("0 0", "WTCF", false).is_err());
+        let quarto = store.load(&uuid).unwrap().unwrap();
+        assert_eq!(quarto.placed_pieces(), 1);
+        assert_eq!(quarto.next_piece.map(String::from).as_deref(), Some("WSCF"));
+
+        // Another process is changing the game
+        let lock = store.lock(&uuid).unwrap();
+        let e = play("b3", "WTCF", false).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<QuartoError>(),
+            Some(QuartoError::GameLocked(_))
+        ));
+        drop(lock);
+        play("1 1", "WTCF", false).unwrap();
+
+        let show = Command::Show {
+            uuid: Some(uuid.clone()),
+            code: None,
+            describe: false,
+            orientation: 0,
+            at: None,
+            format: OutputFormat::Text,
+            analysis: true,
+        };
+        run_offline(show, &dir.0).unwrap();
+        assert!(run_offline(Command::Sweep, &dir.0).is_err());
+    }
+
+    #[test]
+    fn test_offline_game_from_file() {
+        let dir = file_store::test::TempDir::new();
+        let path = dir.0.join("opening.txt");
+        // Lowercase and a stripped line, which import reads leniently
+        std::fs::write(&path, "bscf\n\n\n\nhand: wtch\nvariant: advanced\n").unwrap();
+        let new_game = Command::NewGame {
+            template: None,
+            variant: None,
+            clock: None,
+            deadline: None,
+            spectator_delay: None,
+            from_code: None,
+            from_file: Some(path.clone()),
+            seed: None,
+        };
+        run_offline(new_game, &dir.0).unwrap();
+        let store = FileStore::open(&dir.0).unwrap();
+        let uuid = store.list().unwrap()[0].0.clone();
+        let quarto = store.load(&uuid).unwrap().unwrap();
+        assert_eq!(quarto.placed_pieces(), 1);
+        assert_eq!(quarto.next_piece.map(String::from).as_deref(), Some("WTCH"));
+        assert_eq!(quarto.rules.variant, Variant::Advanced);
+
+        std::fs::write(&path, "BSCF\n\n\n\nhand: none\n").unwrap();
+        assert!(starting_position(&None, &Some(path)).is_err());
+    }
+
+    #[test]
+    fn test_show_at_counts_back_with_negative_moves() {
+        for (args, expected) in [
+            (&["--at", "-1"][..], Some(-1)),
+            (&["--at", "3"], Some(3)),
+            (&[], None),
+        ] {
+            match Cli::try_parse_from([&["quarto", "show", GAME], args].concat())
+                .unwrap()
+                .command
+            {
+                Command::Show { at, .. } => assert_eq!(at, expected),
+                command => panic!("{:?}", command),
+            }
+        }
+        assert!(Cli::try_parse_from(["quarto", "show", "--code", "x", "--at", "1"]).is_err());
+    }
+
+    #[test]
+    fn test_move_arguments_are_validated_by_clap() {
+        let parse = |args: &[&str]| Cli::try_parse_from([&["quarto", "move", GAME], args].concat());
+        for args in [&["a1", "WTCH"][..], &["3", "0", "WTCH"]] {
+            match parse(args).unwrap().command {
+                Command::Move { at, piece, .. } => {
+                    assert_eq!(Coord::from_places(&at).unwrap().to_string(), "a1");
+                    assert_eq!(String::from(piece), "WTCH");
+                }
+                command => panic!("{:?}", command),
+            }
+        }
+        for args in [["4", "0", "WTCH"], ["0", "x", "WTCH"], ["0", "0", "WTCX"]] {
+            let e = parse(&args).unwrap_err();
+            assert_eq!(
+                e.kind(),
+                clap::error::ErrorKind::ValueValidation,
+                "{:?}",
+                args
+            );
+        }
+        let e = parse(&["a1", "BIG"]).unwrap_err();
+        assert!(
+            e.to_string().contains("invalid value 'BIG' for '<PIECE>'"),
+            "{}",
+            e
+        );
+        // A malformed uuid never reaches the store
+        for command in ["move", "show", "play"] {
+            let e = Cli::try_parse_from(["quarto", command, "d3b07384"]).unwrap_err();
+            assert_eq!(e.kind(), clap::error::ErrorKind::ValueValidation, "{}", e);
+        }
     }
 }
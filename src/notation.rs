@@ -0,0 +1,388 @@
+use crate::quarto::{BoardState, Move, Piece, QuartoError};
+use std::sync::OnceLock;
+
+/* Property slots in the order their letters are written, as in BSCF */
+pub const SLOTS: [&str; 4] = ["color", "height", "shape", "top"];
+
+/* The letters of the canonical scheme, per slot and value. Values are numbered
+   as the bits of Piece::to_index: brown, short, circular and flat are 0.
+*/
+const CANONICAL: [[char; 2]; 4] = [['B', 'W'], ['S', 'T'], ['C', 'S'], ['F', 'H']];
+
+/* The words for each slot and value, numbered as CANONICAL */
+const WORDS: [[&str; 2]; 4] = [
+    ["brown", "white"],
+    ["short", "tall"],
+    ["circle", "square"],
+    ["flat", "hole"],
+];
+
+/* How pieces and empty cells are written for people. The provided methods are
+   the canonical scheme; the database, game files, transcripts and exports always
+   use that one, whatever the config file says.
+*/
+pub trait Notation: Send + Sync {
+    /* Letters for each slot of SLOTS, indexed by property value */
+    fn letters(&self) -> [[char; 2]; 4] {
+        CANONICAL
+    }
+
+    /* Stands for an empty cell in board text; as wide as a piece */
+    fn empty(&self) -> &str {
+        "----"
+    }
+
+    fn piece(&self, p: &Piece) -> String {
+        let index = p.to_index();
+        self.letters()
+            .iter()
+            .enumerate()
+            .map(|(slot, letters)| letters[(index >> (3 - slot) & 1) as usize])
+            .collect()
+    }
+
+    fn parse_piece(&self, code: &str) -> Result<Piece, QuartoError> {
+        (0..16)
+            .filter_map(Piece::from_index)
+            .find(|p| self.piece(p) == code)
+            .ok_or(QuartoError::InvalidPieceError)
+    }
+
+    /* The piece in hand, or none */
+    fn hand(&self, p: Option<Piece>) -> String {
+        p.map_or("none".to_string(), |p| self.piece(&p))
+    }
+
+    /* Laid out as BoardState::to_labelled_string */
+    fn board(&self, board: &BoardState) -> String {
+        let lines: Vec<String> = board
+            .cells()
+            .iter()
+            .zip((1..=4).rev())
+            .map(|(row, rank)| {
+                let cells: Vec<String> = row
+                    .iter()
+                    .map(|c| c.map_or(self.empty().to_string(), |p| self.piece(&p)))
+                    .collect();
+                format!("{} {}", rank, cells.join(" "))
+            })
+            .collect();
+        format!("{}\n  a    b    c    d", lines.join("\n"))
+    }
+
+    /* The free pieces as a 2x8 grid: a row per color and four columns per height,
+       each cell the piece's shape and top letters, or the start of an empty cell
+       where the piece is not free. Pieces in `losing` are marked with a !.
+    */
+    fn free_pieces(&self, free: &[Piece], losing: &[Piece]) -> String {
+        let letters = self.letters();
+        let taken: String = self.empty().chars().take(2).collect();
+        let cell = |index: u8| match Piece::from_index(index) {
+            Some(p) if free.contains(&p) => {
+                let marker = if losing.contains(&p) { '!' } else { ' ' };
+                format!(
+                    "{}{}{}",
+                    letters[2][(index >> 1 & 1) as usize],
+                    letters[3][(index & 1) as usize],
+                    marker
+                )
+            }
+            _ => format!("{} ", taken),
+        };
+        let header = format!("  {:<15}  {}", letters[1][0], letters[1][1]);
+        let rows = (0..2u8).map(|color| {
+            let groups: Vec<String> = (0..2u8)
+                .map(|height| {
+                    let cells: Vec<String> = (0..4u8)
+                        .map(|n| cell(color << 3 | height << 2 | n))
+                        .collect();
+                    cells.join(" ")
+                })
+                .collect();
+            format!("{} {}", letters[0][color as usize], groups.join("  "))
+        });
+        std::iter::once(header)
+            .chain(rows)
+            .map(|line| line.trim_end().to_string())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /* Laid out as Move's Display */
+    fn mv(&self, mv: &Move) -> String {
+        let mut parts = Vec::new();
+        if let Some(at) = mv.place {
+            parts.push(at.to_string());
+        }
+        if let Some(p) = mv.hand {
+            parts.push(self.piece(&p));
+        }
+        parts.join(" ")
+    }
+}
+
+pub struct Canonical;
+
+impl Notation for Canonical {}
+
+/* A scheme from the [notation] table of the config file */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Letters {
+    letters: [[char; 2]; 4],
+    empty: String,
+}
+
+impl Default for Letters {
+    fn default() -> Letters {
+        Letters {
+            letters: CANONICAL,
+            empty: Canonical.empty().to_string(),
+        }
+    }
+}
+
+impl Letters {
+    /* Refuses schemes which could not be read back: a slot with one letter for
+       both values, or an empty cell which is not four letters or reads as a piece
+    */
+    pub fn new(letters: [[char; 2]; 4], empty: &str) -> Result<Letters, String> {
+        for (slot, [first, second]) in SLOTS.iter().zip(letters) {
+            if first == second {
+                return Err(format!("{}: {} stands for both values", slot, first));
+            }
+            if first.is_whitespace() || second.is_whitespace() {
+                return Err(format!("{}: letters cannot be blank", slot));
+            }
+        }
+        let notation = Letters {
+            letters,
+            empty: empty.to_string(),
+        };
+        if empty.chars().count() != 4 || empty.chars().any(char::is_whitespace) {
+            return Err(format!("empty: expected four letters, found {:?}", empty));
+        }
+        if notation.parse_piece(empty).is_ok() {
+            return Err(format!("empty: {} is also a piece", empty));
+        }
+        Ok(notation)
+    }
+}
+
+impl Notation for Letters {
+    fn letters(&self) -> [[char; 2]; 4] {
+        self.letters
+    }
+
+    fn empty(&self) -> &str {
+        &self.empty
+    }
+}
+
+static NOTATION: OnceLock<Letters> = OnceLock::new();
+
+/* Sets the scheme for the rest of the process; called once, before arguments are read */
+pub fn install(letters: Letters) {
+    let _ = NOTATION.set(letters);
+}
+
+/* The installed scheme, canonical until one is installed */
+pub fn current() -> &'static dyn Notation {
+    match NOTATION.get() {
+        Some(letters) => letters,
+        None => &Canonical,
+    }
+}
+
+/* A piece as its code in the installed scheme, or else as a word per property in
+   any order: white tall circle hole. A word may be cut short while it names one
+   property, as in w t c h, but s could be short or square.
+*/
+pub fn parse_piece_description(text: &str) -> Result<Piece, QuartoError> {
+    if let Ok(piece) = current().parse_piece(text.trim()) {
+        return Ok(piece);
+    }
+    let invalid = |reason: String| Err(QuartoError::InvalidPieceDescription(reason));
+    // The value given for each slot, and the word it was given by
+    let mut given: [Option<(usize, &str)>; 4] = [None; 4];
+    for word in text.split_whitespace() {
+        let prefix = word.to_lowercase();
+        let named: Vec<(usize, usize)> = (0..4)
+            .flat_map(|slot| (0..2).map(move |value| (slot, value)))
+            .filter(|&(slot, value)| WORDS[slot][value].starts_with(&prefix))
+            .collect();
+        let (slot, value) = match named[..] {
+            [one] => one,
+            [] => return invalid(format!("{} is not a color, height, shape or top", word)),
+            _ => {
+                let words: Vec<&str> = named.iter().map(|&(s, v)| WORDS[s][v]).collect();
+                return invalid(format!("{} could be {}", word, words.join(" or ")));
+            }
+        };
+        match given[slot] {
+            Some((before, _)) if before == value => {
+                return invalid(format!("{} is given twice", WORDS[slot][value]))
+            }
+            Some((_, before)) => {
+                return invalid(format!(
+                    "{} and {} conflict: both give the {}",
+                    before, word, SLOTS[slot]
+                ))
+            }
+            None => given[slot] = Some((value, word)),
+        }
+    }
+    let missing: Vec<&str> = SLOTS
+        .iter()
+        .zip(&given)
+        .filter(|(_, value)| value.is_none())
+        .map(|(slot, _)| *slot)
+        .collect();
+    if !missing.is_empty() {
+        return invalid(format!("no {} given", missing.join(", ")));
+    }
+    let index = given
+        .iter()
+        .flatten()
+        .fold(0, |index, &(value, _)| index << 1 | value as u8);
+    Ok(Piece::from_index(index).unwrap())
+}
+
+/* Reads a piece given on the command line, as a code or in words */
+pub fn piece_argument(text: &str) -> Result<Piece, String> {
+    parse_piece_description(text).map_err(|e| match e {
+        QuartoError::InvalidPieceDescription(reason) => reason,
+        e => e.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::quarto::Quarto;
+
+    /* Foncé/clair, petit/grand, rond/carré, plein/troué */
+    fn french() -> Letters {
+        Letters::new([['F', 'C'], ['P', 'G'], ['R', 'C'], ['P', 'T']], "····").unwrap()
+    }
+
+    #[test]
+    fn test_custom_notation_round_trips() {
+        let french = french();
+        for p in (0..16).filter_map(Piece::from_index) {
+            assert_eq!(french.parse_piece(&french.piece(&p)).unwrap(), p);
+            assert_eq!(Canonical.piece(&p), String::from(p));
+        }
+        let wtch = french.parse_piece("CGRT").unwrap();
+        assert_eq!(String::from(wtch), "WTCH");
+        assert!(french.parse_piece("WTCH").is_err());
+        assert!(Canonical.parse_piece("CGRT").is_err());
+
+        let mut quarto = Quarto::new();
+        quarto.pick_piece(&french.parse_piece("FPRP").unwrap());
+        quarto.move_piece(3, 0);
+        quarto.pick_piece(&wtch);
+        assert_eq!(
+            Canonical.board(&quarto.board_state),
+            quarto.board_state.to_labelled_string()
+        );
+        assert_eq!(
+            french.board(&quarto.board_state),
+            "4 ···· ···· ···· ····\n\
+             3 ···· ···· ···· ····\n\
+             2 ···· ···· ···· ····\n\
+             1 FPRP ···· ···· ····\n  \
+             a    b    c    d"
+        );
+        assert_eq!(french.hand(quarto.next_piece), "CGRT");
+        let mv = Move {
+            place: "b1".parse().ok(),
+            hand: Some(wtch),
+        };
+        assert_eq!(french.mv(&mv), "b1 CGRT");
+        assert_eq!(Canonical.mv(&mv), mv.to_string());
+    }
+
+    #[test]
+    fn test_free_pieces_grouped_by_color_and_height() {
+        let mut quarto = Quarto::new();
+        for (code, y) in [("BSCF", 0), ("WSCF", 1), ("BTCF", 2)] {
+            quarto.pick_piece(&Canonical.parse_piece(code).unwrap());
+            quarto.move_piece(3, y);
+        }
+        quarto.pick_piece(&Canonical.parse_piece("WTCH").unwrap());
+        // a1 b1 c1 are all circular and flat, so only square holed pieces are safe
+        assert_eq!(
+            Canonical.free_pieces(quarto.free(), &quarto.losing_pieces()),
+            "  S                T\n\
+             B --  CH! SF! SH   --  CH! SF! SH\n\
+             W --  CH! SF! SH   CF! --  SF! SH"
+        );
+        assert_eq!(
+            french().free_pieces(Quarto::new().free(), &[]),
+            "  P                G\n\
+             F RP  RT  CP  CT   RP  RT  CP  CT\n\
+             C RP  RT  CP  CT   RP  RT  CP  CT"
+        );
+    }
+
+    #[test]
+    fn test_ambiguous_notation_is_refused() {
+        assert_eq!(Letters::new(CANONICAL, "----"), Ok(Letters::default()));
+        for (letters, empty) in [
+            ([['B', 'B'], ['S', 'T'], ['C', 'S'], ['F', 'H']], "----"),
+            ([['B', 'W'], ['S', 'T'], ['C', 'S'], ['H', 'H']], "----"),
+            ([['B', 'W'], [' ', 'T'], ['C', 'S'], ['F', 'H']], "----"),
+            (CANONICAL, "BSCF"),
+            (CANONICAL, "--"),
+            (CANONICAL, "- -."),
+        ] {
+            assert!(
+                Letters::new(letters, empty).is_err(),
+                "{:?} {}",
+                letters,
+                empty
+            );
+        }
+    }
+
+    #[test]
+    fn test_pieces_described_in_words() {
+        let described = |text: &str| parse_piece_description(text).map(String::from);
+        let reason = |text: &str| match parse_piece_description(text) {
+            Err(QuartoError::InvalidPieceDescription(reason)) => reason,
+            other => panic!("{:?} read as {:?}", text, other),
+        };
+        assert_eq!(described("WTCH").unwrap(), "WTCH");
+        assert_eq!(described("white tall circle hole").unwrap(), "WTCH");
+        assert_eq!(described("brown short square flat").unwrap(), "BSSF");
+        assert_eq!(described("Hole  CIRCLE tall white").unwrap(), "WTCH");
+        assert_eq!(described("w t c h").unwrap(), "WTCH");
+        assert_eq!(described("br sh sq fl").unwrap(), "BSSF");
+        for p in (0..16).filter_map(Piece::from_index) {
+            let index = p.to_index();
+            let words: Vec<&str> = (0..4)
+                .map(|slot| WORDS[slot][(index >> (3 - slot) & 1) as usize])
+                .collect();
+            assert_eq!(parse_piece_description(&words.join(" ")).unwrap(), p);
+        }
+
+        assert_eq!(reason("w t s h"), "s could be short or square");
+        assert_eq!(
+            reason("short tall circle"),
+            "short and tall conflict: both give the height"
+        );
+        assert_eq!(reason("white tall tall circle hole"), "tall is given twice");
+        assert_eq!(reason("white tall circle"), "no top given");
+        assert_eq!(reason(""), "no color, height, shape, top given");
+        // Codes and words are not mixed
+        assert_eq!(
+            reason("WTCH hole"),
+            "WTCH is not a color, height, shape or top"
+        );
+        assert_eq!(
+            reason("WT circle hole"),
+            "WT is not a color, height, shape or top"
+        );
+        assert_eq!(piece_argument("round").unwrap_err(), reason("round"));
+    }
+}
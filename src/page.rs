@@ -0,0 +1,280 @@
+use crate::event::{self, Record};
+use crate::game_id;
+use crate::quarto::{Quarto, QuartoError};
+use crate::store;
+use futures::TryStreamExt;
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite};
+
+/* Keyset pagination for `list` and `history`. Games come in the order they were
+   created, by game.id, and a game's events in the order they happened, by seq; a
+   cursor is the key of the last row of a page and the next page starts after it.
+   Keys only grow, so rows added while paging turn up on later pages and no row is
+   given twice or skipped. Rows are streamed, so a page holds at most `limit` rows
+   whatever the size of the table.
+*/
+pub const DEFAULT_LIMIT: u32 = 50;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /* Where the next page starts, or None after the last one */
+    pub next: Option<i64>,
+}
+
+/* A stored game as listed: its board, or why there is none to show */
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameEntry {
+    pub id: i64,
+    pub uuid: String,
+    pub board: Result<Quarto, String>,
+}
+
+impl GameEntry {
+    /* e.g. 6b1f2a3c  in progress, 5 placed, with the uuid shortened among `known` */
+    pub fn line(&self, known: &[String]) -> String {
+        let uuid = game_id::short(&self.uuid, known);
+        match &self.board {
+            Ok(quarto) => format!(
+                "{}  {}, {} placed",
+                uuid,
+                quarto.status(),
+                quarto.placed_pieces()
+            ),
+            Err(problem) => format!("{}  {}", uuid, problem),
+        }
+    }
+}
+
+type GameRow = (
+    i64,
+    String,
+    Option<String>,
+    Option<String>,
+    bool,
+    Option<i64>,
+);
+
+fn entry((id, uuid, board_state, next_piece, advanced, board_format): GameRow) -> GameEntry {
+    let board = match board_state {
+        None => Err("no moves yet".to_string()),
+        Some(_) => store::from_row(&board_state, &next_piece, advanced, board_format)
+            .map_err(|e: QuartoError| format!("unreadable, {:?}", e)),
+    };
+    GameEntry { id, uuid, board }
+}
+
+/* Every stored game's uuid, which printed ids must not be confused with */
+pub async fn uuids(db: &Pool<Sqlite>) -> Result<Vec<String>, SqlxError> {
+    let rows = sqlx::query_as::<_, (String,)>("SELECT uuid FROM game WHERE uuid IS NOT NULL;")
+        .fetch_all(db)
+        .await?;
+    Ok(rows.into_iter().map(|(uuid,)| uuid).collect())
+}
+
+/* Reads up to limit + 1 rows: the last only tells whether there is another page */
+fn paged<T>(mut items: Vec<T>, limit: u32, key: fn(&T) -> i64) -> Page<T> {
+    let more = items.len() > limit as usize;
+    items.truncate(limit as usize);
+    let next = more.then(|| items.last().map(key)).flatten();
+    Page { items, next }
+}
+
+/* Games after the cursor, oldest first; with a player, only those with a seat of theirs */
+pub async fn games(
+    db: &Pool<Sqlite>,
+    after: i64,
+    limit: u32,
+    player: Option<&str>,
+) -> Result<Page<GameEntry>, SqlxError> {
+    let mut rows = sqlx::query_as::<_, GameRow>(
+        r#"
+        SELECT id, uuid, board_state, next_piece, advanced, board_format
+        FROM game
+        WHERE id > ?1 AND uuid IS NOT NULL
+              AND (?3 IS NULL OR player_1st = ?3 OR player_2nd = ?3)
+        ORDER BY id
+        LIMIT ?2
+        "#,
+    )
+    .bind(after)
+    .bind(i64::from(limit) + 1)
+    .bind(player)
+    .fetch(db);
+    let mut items = Vec::new();
+    while let Some(row) = rows.try_next().await? {
+        items.push(entry(row));
+    }
+    Ok(paged(items, limit, |entry| entry.id))
+}
+
+/* The game's events after the cursor, oldest first */
+pub async fn history(
+    db: &Pool<Sqlite>,
+    uuid: &str,
+    after: i64,
+    limit: u32,
+) -> Result<Page<Record>, SqlxError> {
+    let mut rows = sqlx::query_as::<_, (i64, String, i64, Option<i64>, Option<i64>)>(
+        r#"
+        SELECT seq, payload, created_at, think_ms, handed_by
+        FROM event
+        WHERE uuid = ?1 AND seq > ?2
+        ORDER BY seq
+        LIMIT ?3
+        "#,
+    )
+    .bind(uuid)
+    .bind(after)
+    .bind(i64::from(limit) + 1)
+    .fetch(db);
+    let mut items = Vec::new();
+    while let Some((seq, payload, created_at, think_ms, handed_by)) = rows.try_next().await? {
+        items.push(event::record(
+            seq, &payload, created_at, think_ms, handed_by,
+        )?);
+    }
+    Ok(paged(items, limit, |record| record.seq))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event::Event;
+    use crate::memory_db;
+
+    /* 3000 games, every seventh without moves and every thirteenth deleted so
+       that ids have gaps, and one game with 250 events
+    */
+    async fn fixture_db() -> Pool<Sqlite> {
+        let db = memory_db().await;
+        sqlx::query(
+            r#"
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 3000)
+            INSERT INTO game (uuid, next_piece, board_state, board_format, player_1st, player_2nd)
+            SELECT printf('game-%04d', i), 'WTCH',
+                   CASE WHEN i % 7 = 0 THEN NULL ELSE ?1 END, 1,
+                   CASE WHEN i % 5 = 0 THEN 'alice' END,
+                   CASE WHEN i % 11 = 0 THEN 'alice' ELSE 'bob' END
+            FROM n
+            "#,
+        )
+        .bind("BSCF ---- ---- ----\n---- ---- ---- ----\n---- ---- ---- ----\n---- ---- ---- ----")
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query("DELETE FROM game WHERE id % 13 = 0;")
+            .execute(&db)
+            .await
+            .unwrap();
+        let payload = serde_json::to_string(&Event::Position {
+            position: Quarto::new().to_share_code(),
+        })
+        .unwrap();
+        sqlx::query(
+            r#"
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 250)
+            INSERT INTO event (uuid, seq, kind, payload, created_at)
+            SELECT 'game-0001', i, 'position', ?1, i FROM n
+            "#,
+        )
+        .bind(payload)
+        .execute(&db)
+        .await
+        .unwrap();
+        db
+    }
+
+    #[tokio::test]
+    async fn test_player_pages_hold_only_their_games() {
+        let db = fixture_db().await;
+        let theirs = sqlx::query_as::<_, (i64,)>(
+            "SELECT id FROM game WHERE player_1st = 'alice' OR player_2nd = 'alice' ORDER BY id;",
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        let (mut seen, mut cursor) = (Vec::new(), 0);
+        loop {
+            let page = games(&db, cursor, 100, Some("alice")).await.unwrap();
+            seen.extend(page.items.iter().map(|entry| (entry.id,)));
+            match page.next {
+                Some(next) => cursor = next,
+                None => break,
+            }
+        }
+        assert!(theirs.len() > 700);
+        assert_eq!(seen, theirs);
+        let carol = games(&db, 0, 100, Some("carol")).await.unwrap();
+        assert_eq!(carol.items, Vec::new());
+    }
+
+    #[tokio::test]
+    async fn test_pages_neither_skip_nor_repeat_rows() {
+        let db = fixture_db().await;
+        let ids: Vec<i64> = sqlx::query_as::<_, (i64,)>("SELECT id FROM game ORDER BY id;")
+            .fetch_all(&db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(id,)| id)
+            .collect();
+        assert!(ids.len() > 2700);
+
+        for limit in [97, 100, 5000] {
+            let (mut seen, mut cursor, mut pages) = (Vec::new(), 0, 0);
+            loop {
+                let page = games(&db, cursor, limit, None).await.unwrap();
+                assert!(page.items.len() <= limit as usize);
+                seen.extend(page.items.iter().map(|entry| entry.id));
+                pages += 1;
+                match page.next {
+                    Some(next) => {
+                        assert_eq!(Some(next), page.items.last().map(|entry| entry.id));
+                        cursor = next;
+                    }
+                    None => break,
+                }
+            }
+            assert_eq!(seen, ids, "limit {}", limit);
+            assert_eq!(pages, ids.len().div_ceil(limit as usize), "limit {}", limit);
+        }
+
+        let page = games(&db, 0, 7, None).await.unwrap();
+        let known = uuids(&db).await.unwrap();
+        assert_eq!(
+            page.items[0].line(&known),
+            "game-0001  in progress, 1 placed"
+        );
+        assert_eq!(page.items[6].line(&known), "game-0007  no moves yet");
+        assert_eq!(page.items[6].line(&[]), "game-000  no moves yet");
+        // A game added while paging turns up at the end
+        sqlx::query("INSERT INTO game (uuid) VALUES ('late');")
+            .execute(&db)
+            .await
+            .unwrap();
+        let last = games(&db, *ids.last().unwrap(), 10, None).await.unwrap();
+        assert_eq!(last.items.len(), 1);
+        assert_eq!(last.items[0].uuid, "late");
+        assert_eq!(last.next, None);
+
+        let (mut seqs, mut cursor) = (Vec::new(), 0);
+        loop {
+            let page = history(&db, "game-0001", cursor, 40).await.unwrap();
+            seqs.extend(page.items.iter().map(|record| record.seq));
+            match page.next {
+                Some(next) => cursor = next,
+                None => break,
+            }
+        }
+        assert_eq!(seqs, (1..=250).collect::<Vec<_>>());
+        let empty = history(&db, "game-0002", 0, 40).await.unwrap();
+        assert_eq!(
+            empty,
+            Page {
+                items: Vec::new(),
+                next: None
+            }
+        );
+    }
+}
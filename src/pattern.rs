@@ -0,0 +1,370 @@
+use crate::db_policy::DbPolicy;
+use crate::event::{self, Record};
+use crate::game_id;
+use crate::quarto::{Piece, Quarto, QuartoError, LINE_WIDTH};
+use crate::replay;
+use sqlx::{Pool, Sqlite};
+use std::error::Error;
+use tracing::warn;
+
+/* A board pattern for `search`, written like the board text with each cell one of
+
+       BSCF  that piece
+       ?T??  any piece with the properties given; ? leaves a slot open
+       ****  any piece
+       ----  an empty cell, as do four spaces
+       ....  anything, piece or not
+*/
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CellPattern {
+    Anything,
+    Empty,
+    /* The property bits the cell fixes, laid out as in Piece::to_index, and the
+       values they must have
+    */
+    Piece { mask: u8, value: u8 },
+}
+
+impl CellPattern {
+    pub fn matches(&self, cell: Option<Piece>) -> bool {
+        match (self, cell) {
+            (CellPattern::Anything, _) => true,
+            (CellPattern::Empty, cell) => cell.is_none(),
+            (CellPattern::Piece { mask, value }, Some(p)) => p.to_index() & mask == *value,
+            (CellPattern::Piece { .. }, None) => false,
+        }
+    }
+}
+
+/* Letters for each slot of a piece code whose property bit is 0 */
+const OPEN_SLOTS: [char; 4] = ['B', 'S', 'C', 'F'];
+
+impl std::str::FromStr for CellPattern {
+    type Err = QuartoError;
+    fn from_str(text: &str) -> Result<CellPattern, QuartoError> {
+        match text {
+            "...." => return Ok(CellPattern::Anything),
+            "----" | "    " => return Ok(CellPattern::Empty),
+            _ => {}
+        }
+        if text.chars().count() != 4 {
+            return Err(QuartoError::InvalidPieceError);
+        }
+        // Open slots are read as the 0 letter and left out of the mask
+        let mut mask = 0;
+        let mut code = String::new();
+        for (slot, c) in text.chars().enumerate() {
+            let bit = 1 << (3 - slot);
+            match c {
+                '?' | '*' => code.push(OPEN_SLOTS[slot]),
+                c => {
+                    mask |= bit;
+                    code.push(c);
+                }
+            }
+        }
+        if text.contains('*') && text != "****" {
+            return Err(QuartoError::InvalidPieceError);
+        }
+        let piece = Piece::try_from(code)?;
+        Ok(CellPattern::Piece {
+            mask,
+            value: piece.to_index() & mask,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pattern([[CellPattern; 4]; 4]);
+
+/* Lines and columns in errors are 1-based, as for the board text */
+impl std::str::FromStr for Pattern {
+    type Err = QuartoError;
+    fn from_str(text: &str) -> Result<Pattern, QuartoError> {
+        let error = |line: usize, column: usize, reason: String| QuartoError::ParseError {
+            line,
+            column,
+            reason,
+        };
+        let lines: Vec<&str> = text.lines().collect();
+        if lines.len() != 4 {
+            return Err(error(
+                lines.len().min(4) + 1,
+                1,
+                format!("expected 4 lines, found {}", lines.len()),
+            ));
+        }
+        let mut cells = [[CellPattern::Anything; 4]; 4];
+        for (x, line) in lines.into_iter().enumerate() {
+            let mut chars: Vec<char> = line.chars().collect();
+            if chars.len() < LINE_WIDTH {
+                // Editors strip the trailing spaces of empty cells
+                chars.resize(LINE_WIDTH, ' ');
+            }
+            if chars.len() != LINE_WIDTH {
+                return Err(error(
+                    x + 1,
+                    LINE_WIDTH + 1,
+                    format!("expected {} characters, found {}", LINE_WIDTH, chars.len()),
+                ));
+            }
+            for (y, cell) in cells[x].iter_mut().enumerate() {
+                let token: String = chars[5 * y..5 * y + 4].iter().collect();
+                *cell = token
+                    .parse()
+                    .map_err(|_| error(x + 1, 5 * y + 1, format!("invalid cell {:?}", token)))?;
+            }
+        }
+        Ok(Pattern(cells))
+    }
+}
+
+impl Pattern {
+    pub fn matches(&self, quarto: &Quarto) -> bool {
+        let cells = quarto.board_state.cells();
+        (0..4).all(|x| (0..4).all(|y| self.0[x][y].matches(cells[x][y])))
+    }
+}
+
+/* The board at creation and after each move, counted from when it was last set */
+pub fn positions(records: &[Record]) -> Result<Vec<Quarto>, QuartoError> {
+    let mut positions: Vec<Quarto> = replay::plies(records)?
+        .into_iter()
+        .map(|(quarto, _)| quarto)
+        .collect();
+    positions.extend(event::replay(records)?);
+    Ok(positions)
+}
+
+/* A position matching the pattern: the game, and the moves played to reach it */
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Match {
+    pub uuid: String,
+    pub number: usize,
+}
+
+impl Match {
+    /* e.g. 6b1f2a3c  move 5, with the uuid shortened among `known` */
+    pub fn line(&self, known: &[String]) -> String {
+        format!(
+            "{}  move {}",
+            game_id::short(&self.uuid, known),
+            self.number
+        )
+    }
+}
+
+/* Replays every game with events, oldest first, one game at a time. For databases
+   without the position index; index::search reads the positions from it instead.
+*/
+pub async fn search(
+    db: &Pool<Sqlite>,
+    policy: &DbPolicy,
+    pattern: &Pattern,
+) -> Result<Vec<Match>, Box<dyn Error>> {
+    let mut matches = Vec::new();
+    for uuid in policy.run("list games", || event::games(db)).await? {
+        let events = policy
+            .run("load events", || event::load(db, &uuid, 0))
+            .await?;
+        let positions = match positions(&events) {
+            Ok(positions) => positions,
+            Err(e) => {
+                warn!(uuid = %game_id::logged(&uuid), ?e, "skipping unreadable game");
+                continue;
+            }
+        };
+        for (number, quarto) in positions.iter().enumerate() {
+            if pattern.matches(quarto) {
+                matches.push(Match {
+                    uuid: uuid.clone(),
+                    number,
+                });
+            }
+        }
+    }
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event::Event;
+    use crate::memory_db;
+    use indoc::indoc;
+
+    fn cell(text: &str) -> CellPattern {
+        text.parse().unwrap()
+    }
+
+    fn piece(code: &str) -> Piece {
+        code.parse().unwrap()
+    }
+
+    fn board(text: &str) -> Quarto {
+        Quarto::try_from(&text.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_cell_patterns() {
+        assert_eq!(cell("...."), CellPattern::Anything);
+        assert_eq!(cell("----"), CellPattern::Empty);
+        assert_eq!(cell("    "), CellPattern::Empty);
+        assert_eq!(cell("****"), CellPattern::Piece { mask: 0, value: 0 });
+        assert_eq!(
+            cell("WTSH"),
+            CellPattern::Piece {
+                mask: 0xF,
+                value: 0xF
+            }
+        );
+        // Each slot on its own, with either letter
+        for (text, mask, value) in [
+            ("B???", 0b1000, 0),
+            ("W???", 0b1000, 0b1000),
+            ("?S??", 0b0100, 0),
+            ("?T??", 0b0100, 0b0100),
+            ("??C?", 0b0010, 0),
+            ("??S?", 0b0010, 0b0010),
+            ("???F", 0b0001, 0),
+            ("???H", 0b0001, 0b0001),
+            ("W?S?", 0b1010, 0b1010),
+            ("?SCH", 0b0111, 0b0001),
+        ] {
+            assert_eq!(cell(text), CellPattern::Piece { mask, value }, "{}", text);
+        }
+        for text in [
+            "X???", "?B??", "??T?", "???C", "*T**", "??", "?????", "bscf",
+        ] {
+            assert!(text.parse::<CellPattern>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_cell_pattern_matches() {
+        let all: Vec<Piece> = (0..16).map(|i| Piece::from_index(i).unwrap()).collect();
+        let count = |text: &str| all.iter().filter(|p| cell(text).matches(Some(**p))).count();
+        assert_eq!(count("****"), 16);
+        assert_eq!(count("...."), 16);
+        assert_eq!(count("----"), 0);
+        assert_eq!(count("BSCF"), 1);
+        for text in [
+            "B???", "W???", "?S??", "?T??", "??C?", "??S?", "???F", "???H",
+        ] {
+            assert_eq!(count(text), 8, "{}", text);
+        }
+        assert_eq!(count("W?S?"), 4);
+        assert!(cell("?T??").matches(Some(piece("BTCF"))));
+        assert!(!cell("?T??").matches(Some(piece("WSSH"))));
+        assert!(cell("??S?").matches(Some(piece("BSSF"))));
+        assert!(!cell("??S?").matches(Some(piece("BSCF"))));
+
+        assert!(cell("....").matches(None));
+        assert!(cell("----").matches(None));
+        assert!(!cell("****").matches(None));
+        assert!(!cell("?T??").matches(None));
+    }
+
+    #[test]
+    fn test_pattern_parse_errors() {
+        let at = |text: &str| match text.parse::<Pattern>() {
+            Err(QuartoError::ParseError { line, column, .. }) => (line, column),
+            other => panic!("{:?}", other),
+        };
+        let rows = |row2: &str| format!("....\n{}\n....\n....", row2);
+        assert_eq!(at("....\n...."), (3, 1));
+        assert_eq!(at(&rows(".... ?X?? .... ....")), (2, 6));
+        assert_eq!(at(&rows(".... .... .... ....  x")), (2, 20));
+        // Short lines are padded with empty cells
+        let pattern: Pattern = rows("BSCF").parse().unwrap();
+        assert_eq!(pattern.0[1][0], cell("BSCF"));
+        assert_eq!(pattern.0[1][1], CellPattern::Empty);
+        assert_eq!(pattern.0[0][0], CellPattern::Anything);
+        assert_eq!(pattern.0[0][3], CellPattern::Empty);
+    }
+
+    #[test]
+    fn test_pattern_matches_board() {
+        /* BSCF on b2 and any tall piece on c3 */
+        let pattern: Pattern = indoc! {
+        r#".... .... .... ....
+           .... .... ?T?? ....
+           .... BSCF .... ....
+           .... .... .... ...."#}
+        .parse()
+        .unwrap();
+        let matching = board(indoc! {
+        r#"---- ---- ---- ----
+           ---- ---- WTSH ----
+           ---- BSCF ---- ----
+           WSCF ---- ---- ----"#});
+        assert!(pattern.matches(&matching));
+        let short = board(indoc! {
+        r#"---- ---- ---- ----
+           ---- ---- WSSH ----
+           ---- BSCF ---- ----
+           ---- ---- ---- ----"#});
+        assert!(!pattern.matches(&short));
+        let empty = board(indoc! {
+        r#"---- ---- ---- ----
+           ---- ---- ---- ----
+           ---- BSCF ---- ----
+           ---- ---- ---- ----"#});
+        assert!(!pattern.matches(&empty));
+
+        let only: Pattern = indoc! {
+        r#"---- ---- ---- ----
+           ---- ---- **** ----
+           ---- BSCF ---- ----
+           ---- ---- ---- ----"#}
+        .parse()
+        .unwrap();
+        assert!(only.matches(&short));
+        assert!(!only.matches(&matching));
+    }
+
+    #[tokio::test]
+    async fn test_search_reports_each_matching_move() {
+        let db = memory_db().await;
+        let mut opening = Quarto::new();
+        opening.apply_move(&"BSCF".parse().unwrap());
+        let games: [(&str, &[&str]); 3] = [
+            ("first", &["b2 WTSH", "c3 WSCF", "a1 BTCH"]),
+            ("second", &["c3 WTSH", "b2 WSCF"]),
+            ("bare", &[]),
+        ];
+        for (uuid, plies) in games {
+            sqlx::query("INSERT INTO game (uuid) VALUES (?1);")
+                .bind(uuid)
+                .execute(&db)
+                .await
+                .unwrap();
+            let mut tx = db.begin().await.unwrap();
+            let created = Event::Created {
+                position: opening.to_share_code(),
+            };
+            event::append(&mut tx, uuid, &created, 0).await.unwrap();
+            for ply in plies {
+                let ply = Event::Move {
+                    ply: ply.parse().unwrap(),
+                };
+                event::append(&mut tx, uuid, &ply, 0).await.unwrap();
+            }
+            tx.commit().await.unwrap();
+        }
+        let pattern: Pattern = indoc! {
+        r#".... .... .... ....
+           .... .... ?T?? ....
+           .... BSCF .... ....
+           .... .... .... ...."#}
+        .parse()
+        .unwrap();
+        let found: Vec<String> = search(&db, &DbPolicy::default(), &pattern)
+            .await
+            .unwrap()
+            .iter()
+            .map(|found| found.line(&[]))
+            .collect();
+        assert_eq!(found, vec!["first  move 2", "first  move 3"]);
+    }
+}
@@ -0,0 +1,868 @@
+use crate::analysis::{self, Scheduler};
+use crate::clock::{seat_name, seat_to_move};
+use crate::game_id::GameId;
+use crate::notation;
+use crate::quarto::{square, Coord, GameRng, GameStatus, Move, Piece, Place, Quarto};
+use crate::resume::{self, SavedSession};
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use tracing::warn;
+
+/* Interactive play on one game at a time. Moves land on an in-memory stack first and
+   only reach the store on `commit`, so lines can be explored and taken back freely.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameSnapshot {
+    pub quarto: Quarto,
+}
+
+/* Where games are read from and committed positions written; the database outside of tests */
+pub trait Store {
+    /* Games still being played, in a stable order */
+    fn games(&mut self) -> Result<Vec<(GameId, Quarto)>, Box<dyn Error>>;
+    fn load(&mut self, game: &GameId) -> Result<Option<Quarto>, Box<dyn Error>>;
+    fn create(&mut self, advanced: bool) -> Result<(GameId, Quarto), Box<dyn Error>>;
+    fn commit(&mut self, game: &GameId, quarto: &Quarto) -> Result<(), Box<dyn Error>>;
+    /* A composed position saved as a new game */
+    #[cfg_attr(not(feature = "setup"), allow(dead_code))]
+    fn create_from(&mut self, quarto: &Quarto) -> Result<GameId, Box<dyn Error>>;
+    /* The game's seed, for stores which keep one */
+    fn seed(&mut self, _game: &GameId) -> Result<Option<GameRng>, Box<dyn Error>> {
+        Ok(None)
+    }
+}
+
+pub struct Session {
+    game: GameId,
+    current: GameSnapshot,
+    undo: Vec<GameSnapshot>,
+    redo: Vec<GameSnapshot>,
+    /* Commit at quit instead of discarding uncommitted plies */
+    pub autocommit: bool,
+    /* Session file rewritten after every command, so the session can be resumed */
+    pub record: Option<PathBuf>,
+    /* Background analysis of the current position, for `hint` */
+    pub analysis: Option<Scheduler>,
+    /* Shade empty cells by Quarto::cell_heat when showing the board, set by `heat` */
+    heat: bool,
+    /* Games in progress as last listed, which printed ids must not be confused with */
+    known: Vec<GameId>,
+}
+
+impl Session {
+    pub fn new(game: GameId, quarto: Quarto, autocommit: bool) -> Session {
+        Session {
+            game,
+            current: GameSnapshot { quarto },
+            undo: Vec::new(),
+            redo: Vec::new(),
+            autocommit,
+            record: None,
+            analysis: None,
+            heat: false,
+            known: Vec::new(),
+        }
+    }
+
+    pub fn game(&self) -> &GameId {
+        &self.game
+    }
+
+    /* The game's id as printed, long enough to name it alone among the games known */
+    pub fn shown_id(&self) -> &str {
+        self.game.short(&self.known)
+    }
+
+    /* Lists the games in progress again; a store error leaves the last list */
+    fn refresh(&mut self, store: &mut dyn Store) {
+        if let Ok(games) = store.games() {
+            self.known = games.into_iter().map(|(game, _)| game).collect();
+        }
+    }
+
+    pub fn current(&self) -> &Quarto {
+        &self.current.quarto
+    }
+
+    /* Makes another game active as loaded; the stacks of the previous one are dropped */
+    pub fn switch(&mut self, game: GameId, quarto: Quarto) {
+        self.game = game;
+        self.current = GameSnapshot { quarto };
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /* Committing clears the undo stack, so everything on it is uncommitted */
+    pub fn uncommitted(&self) -> usize {
+        self.undo.len()
+    }
+
+    /* The position as last committed or loaded, under the uncommitted plies */
+    pub fn committed(&self) -> &Quarto {
+        &self.undo.first().unwrap_or(&self.current).quarto
+    }
+
+    /* The uncommitted plies in the order they were played */
+    pub fn uncommitted_plies(&self) -> Vec<Move> {
+        let positions: Vec<&Quarto> = self
+            .undo
+            .iter()
+            .chain([&self.current])
+            .map(|snapshot| &snapshot.quarto)
+            .collect();
+        positions
+            .windows(2)
+            .map(|pair| {
+                let (before, after) = (pair[0].board_state.cells(), pair[1].board_state.cells());
+                let place = (0..16)
+                    .map(|i| (i / 4, i % 4))
+                    .find(|(x, y)| before[*x][*y].is_none() && after[*x][*y].is_some())
+                    .map(square);
+                Move {
+                    place,
+                    hand: pair[1].next_piece,
+                }
+            })
+            .collect()
+    }
+
+    /* Places the piece in hand at `at`, then hands over `piece` unless the game is over.
+       Nothing changes when any part is illegal; a new move drops the redo stack.
+    */
+    pub fn play(&mut self, at: Coord, piece: Option<Piece>) -> Result<(), String> {
+        let mut quarto = self.current.quarto.clone();
+        if quarto.is_quarto() {
+            return Err("game is over".to_string());
+        }
+        let (x, y) = at.xy();
+        if !quarto.move_piece(x, y) {
+            return Err(format!("cannot place at {}", at));
+        }
+        match piece {
+            Some(piece) if !quarto.is_quarto() && !quarto.pick_piece(&piece) => {
+                let code = notation::current().piece(&piece);
+                return Err(format!("piece is not free: {}", code));
+            }
+            None if !quarto.is_quarto() && quarto.placed_pieces() < 16 => {
+                return Err("a piece to hand over is required".to_string());
+            }
+            _ => {}
+        }
+        let previous = std::mem::replace(&mut self.current, GameSnapshot { quarto });
+        self.undo.push(previous);
+        self.redo.clear();
+        Ok(())
+    }
+
+    pub fn undo(&mut self) -> bool {
+        match self.undo.pop() {
+            Some(snapshot) => {
+                self.redo
+                    .push(std::mem::replace(&mut self.current, snapshot));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(snapshot) => {
+                self.undo
+                    .push(std::mem::replace(&mut self.current, snapshot));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /* Writes the current position and returns how many plies it covered */
+    pub fn commit(&mut self, store: &mut dyn Store) -> Result<usize, Box<dyn Error>> {
+        let plies = self.uncommitted();
+        if plies > 0 {
+            store.commit(&self.game, &self.current.quarto)?;
+            self.undo.clear();
+        }
+        Ok(plies)
+    }
+
+    pub fn prompt(&self) -> String {
+        let uncommitted = match self.uncommitted() {
+            0 => String::new(),
+            n => format!(" [{} uncommitted]", n),
+        };
+        format!(
+            "quarto {} {}{}> ",
+            self.shown_id(),
+            turn(self.current()),
+            uncommitted
+        )
+    }
+}
+
+pub(crate) fn turn(quarto: &Quarto) -> String {
+    if quarto.is_quarto() || quarto.placed_pieces() == 16 {
+        "finished".to_string()
+    } else {
+        format!("{} to move", seat_name(seat_to_move(quarto)))
+    }
+}
+
+pub(crate) fn plies(n: usize) -> String {
+    match n {
+        1 => "1 ply".to_string(),
+        n => format!("{} plies", n),
+    }
+}
+
+const HELP: &str = "commands: move <square> [piece], undo, redo, commit, show, \
+                    hint [--wait <amount>], heat on|off, games, open <id>, new [--advanced], \
+                    help, quit";
+
+/* The square may still be given as the deprecated x y */
+fn parse_move(args: &[&str]) -> Result<(Coord, Option<Piece>), String> {
+    let places: Vec<Place> = args.iter().map_while(|arg| arg.parse().ok()).collect();
+    if places.is_empty() {
+        return Err("usage: move <square> [piece]".to_string());
+    }
+    let at = Coord::from_places(&places)
+        .map_err(|_| format!("invalid square: {}", args[..places.len()].join(" ")))?;
+    let piece = match &args[places.len()..] {
+        [] => None,
+        words => Some(
+            notation::piece_argument(&words.join(" "))
+                .map_err(|reason| format!("invalid piece: {}", reason))?,
+        ),
+    };
+    Ok((at, piece))
+}
+
+pub(crate) fn show<W: Write>(output: &mut W, quarto: &Quarto) -> io::Result<()> {
+    let notation = notation::current();
+    writeln!(output, "{}", notation.board(&quarto.board_state))?;
+    writeln!(output, "next piece: {}", notation.hand(quarto.next_piece))?;
+    writeln!(
+        output,
+        "free pieces:\n{}",
+        notation.free_pieces(quarto.free(), &[])
+    )?;
+    if quarto.is_quarto() {
+        writeln!(output, "quarto!")?;
+    }
+    Ok(())
+}
+
+/* Light to dark for cell heat in quarters of its range */
+const SHADES: [char; 4] = ['\u{2591}', '\u{2592}', '\u{2593}', '\u{2588}'];
+
+/* The board with each empty cell shaded by how dangerous it is to fill */
+fn shaded(quarto: &Quarto) -> String {
+    let notation = notation::current();
+    let width = notation.empty().chars().count();
+    let rows: Vec<String> = quarto
+        .board_state
+        .cells()
+        .iter()
+        .zip(quarto.cell_heat())
+        .zip((1..=4).rev())
+        .map(|((row, heat), rank)| {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(heat)
+                .map(|(cell, heat)| match cell {
+                    Some(p) => notation.piece(p),
+                    None if heat == 0 => notation.empty().to_string(),
+                    None => SHADES[heat as usize / 64].to_string().repeat(width),
+                })
+                .collect();
+            format!("{} {}", rank, cells.join(" "))
+        })
+        .collect();
+    format!("{}\n  a    b    c    d", rows.join("\n"))
+}
+
+/* The current position, shaded and with the losing free pieces marked after `heat on` */
+fn show_current<W: Write>(output: &mut W, session: &Session) -> io::Result<()> {
+    let quarto = session.current();
+    if !session.heat {
+        return show(output, quarto);
+    }
+    let notation = notation::current();
+    writeln!(output, "{}", shaded(quarto))?;
+    writeln!(output, "next piece: {}", notation.hand(quarto.next_piece))?;
+    writeln!(
+        output,
+        "free pieces:\n{}",
+        notation.free_pieces(quarto.free(), &quarto.losing_pieces())
+    )?;
+    if quarto.is_quarto() {
+        writeln!(output, "quarto!")?;
+    }
+    Ok(())
+}
+
+/* A full uuid or the start of one naming a single game in progress, such as the
+   ids `games` prints
+*/
+fn resolve(store: &mut dyn Store, id: &str) -> Result<(GameId, Quarto), Box<dyn Error>> {
+    if let Ok(game) = GameId::parse(id) {
+        if let Some(quarto) = store.load(&game)? {
+            return Ok((game, quarto));
+        }
+    }
+    let prefix = id.to_ascii_lowercase();
+    let mut matches: Vec<(GameId, Quarto)> = store
+        .games()?
+        .into_iter()
+        .filter(|(game, _)| game.as_str().starts_with(&prefix))
+        .collect();
+    match matches.len() {
+        0 => Err(format!("no game in progress matches {}", id).into()),
+        1 => Ok(matches.remove(0)),
+        n => Err(format!("{} games match {}", n, id).into()),
+    }
+}
+
+/* Whether the active game can be left: uncommitted plies are committed with
+   autocommit, and otherwise the switch is refused until they are dealt with.
+*/
+fn leave<W: Write>(
+    session: &mut Session,
+    store: &mut dyn Store,
+    output: &mut W,
+) -> io::Result<bool> {
+    let uncommitted = session.uncommitted();
+    if uncommitted == 0 {
+        return Ok(true);
+    }
+    if !session.autocommit {
+        writeln!(
+            output,
+            "{} uncommitted on {}; commit or undo first",
+            plies(uncommitted),
+            session.shown_id()
+        )?;
+        return Ok(false);
+    }
+    match session.commit(store) {
+        Ok(n) => writeln!(output, "committed {}", plies(n))?,
+        Err(e) => {
+            writeln!(output, "commit failed: {}", e)?;
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/* The deepest analysis finished so far, after waiting for a deeper one with --wait */
+fn hint<W: Write>(output: &mut W, session: &Session, args: &[&str]) -> io::Result<()> {
+    let wait = match analysis::parse_wait(args) {
+        Ok(wait) => wait,
+        Err(e) => return writeln!(output, "{}", e),
+    };
+    let Some(analysis) = &session.analysis else {
+        return writeln!(output, "no analysis in this session");
+    };
+    let best = match wait {
+        Some(wait) => analysis.wait(wait),
+        None => analysis.best(),
+    };
+    match best {
+        Some(best) => writeln!(output, "{}", best),
+        None if session.current().status() != GameStatus::InProgress => {
+            writeln!(output, "the game is over")
+        }
+        None => writeln!(output, "no analysis yet, try hint --wait 5s"),
+    }
+}
+
+/* Reads commands until `quit` or end of input. Uncommitted plies are committed at
+   the end with autocommit and discarded otherwise, except that input ending without
+   `quit` leaves them in the session file when there is one. Store errors are
+   reported and leave the session as it was.
+*/
+pub fn run<R: BufRead, W: Write>(
+    session: &mut Session,
+    store: &mut dyn Store,
+    input: R,
+    mut output: W,
+) -> io::Result<()> {
+    let mut lines = input.lines();
+    let mut quit = false;
+    session.refresh(store);
+    loop {
+        if let Some(analysis) = &mut session.analysis {
+            analysis.follow(&session.current.quarto);
+        }
+        write!(output, "{}", session.prompt())?;
+        output.flush()?;
+        let Some(line) = lines.next() else {
+            writeln!(output)?;
+            break;
+        };
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {}
+            ["move", args @ ..] => {
+                match parse_move(args).and_then(|(at, piece)| session.play(at, piece)) {
+                    Ok(()) => show_current(&mut output, session)?,
+                    Err(e) => writeln!(output, "{}", e)?,
+                }
+            }
+            ["undo"] => {
+                if session.undo() {
+                    show_current(&mut output, session)?;
+                } else {
+                    writeln!(output, "nothing to undo")?;
+                }
+            }
+            ["redo"] => {
+                if session.redo() {
+                    show_current(&mut output, session)?;
+                } else {
+                    writeln!(output, "nothing to redo")?;
+                }
+            }
+            ["commit"] => match session.commit(store) {
+                Ok(0) => writeln!(output, "nothing to commit")?,
+                Ok(n) => writeln!(output, "committed {}", plies(n))?,
+                Err(e) => writeln!(output, "commit failed: {}", e)?,
+            },
+            ["show"] => show_current(&mut output, session)?,
+            ["hint", args @ ..] => hint(&mut output, session, args)?,
+            ["heat", setting @ ("on" | "off")] => {
+                session.heat = *setting == "on";
+                show_current(&mut output, session)?;
+            }
+            ["games"] => match store.games() {
+                Ok(games) => {
+                    session.known = games.iter().map(|(game, _)| game.clone()).collect();
+                    for (game, quarto) in &games {
+                        let active = if game == session.game() { "*" } else { " " };
+                        writeln!(
+                            output,
+                            "{} {} {:>2} placed, {}",
+                            active,
+                            game.short(&session.known),
+                            quarto.placed_pieces(),
+                            turn(quarto)
+                        )?;
+                    }
+                }
+                Err(e) => writeln!(output, "cannot list games: {}", e)?,
+            },
+            ["open", id] => {
+                if leave(session, store, &mut output)? {
+                    match resolve(store, id) {
+                        Ok((game, quarto)) => {
+                            session.switch(game, quarto);
+                            session.refresh(store);
+                            show_current(&mut output, session)?;
+                        }
+                        Err(e) => writeln!(output, "{}", e)?,
+                    }
+                }
+            }
+            ["new"] | ["new", "--advanced"] => {
+                if leave(session, store, &mut output)? {
+                    match store.create(words.len() == 2) {
+                        Ok((game, quarto)) => {
+                            writeln!(output, "{}", game)?;
+                            session.switch(game, quarto);
+                            session.refresh(store);
+                            show_current(&mut output, session)?;
+                        }
+                        Err(e) => writeln!(output, "cannot create game: {}", e)?,
+                    }
+                }
+            }
+            ["help"] => writeln!(output, "{}", HELP)?,
+            ["quit"] | ["exit"] => {
+                quit = true;
+                break;
+            }
+            _ => writeln!(output, "unknown command: {}", line.trim())?,
+        }
+        keep_record(session, SavedSession::of(session));
+    }
+    let uncommitted = session.uncommitted();
+    if uncommitted > 0 {
+        if session.autocommit {
+            match session.commit(store) {
+                Ok(n) => writeln!(output, "committed {}", plies(n))?,
+                Err(e) => writeln!(output, "commit failed: {}", e)?,
+            }
+        } else if !quit && session.record.is_some() {
+            writeln!(output, "{} left uncommitted to resume", plies(uncommitted))?;
+        } else {
+            writeln!(output, "discarded {}", plies(uncommitted))?;
+        }
+    }
+    // Plies discarded at quit are not offered again; ones left at end of input are
+    let mut saved = SavedSession::of(session);
+    if quit && !session.autocommit {
+        saved.plies.clear();
+    }
+    keep_record(session, saved);
+    output.flush()
+}
+
+/* A session file which cannot be written costs the resume, not the session */
+fn keep_record(session: &Session, saved: SavedSession) {
+    if let Some(path) = &session.record {
+        if let Err(e) = resume::save(path, &saved) {
+            warn!(path = %path.display(), ?e, "cannot write session file");
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::analysis::CpuShare;
+    use crate::quarto::Variant;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use tokio::runtime::Handle;
+
+    const FIRST: &str = "1a2b3c4d-0000-4000-8000-000000000001";
+    const SECOND: &str = "5e6f7a8b-0000-4000-8000-000000000002";
+
+    fn id(game: &str) -> GameId {
+        GameId::parse(game).unwrap()
+    }
+
+    #[derive(Default)]
+    pub(crate) struct MemoryStore {
+        pub games: Vec<(GameId, Quarto)>,
+        pub commits: Vec<(GameId, Quarto)>,
+    }
+
+    impl MemoryStore {
+        fn with_games(games: &[&str]) -> MemoryStore {
+            MemoryStore {
+                games: games.iter().map(|g| (id(g), opening())).collect(),
+                commits: Vec::new(),
+            }
+        }
+
+        fn get(&mut self, game: &str) -> &mut Quarto {
+            let game = id(game);
+            &mut self.games.iter_mut().find(|(g, _)| *g == game).unwrap().1
+        }
+    }
+
+    impl Store for MemoryStore {
+        fn games(&mut self) -> Result<Vec<(GameId, Quarto)>, Box<dyn Error>> {
+            Ok(self.games.clone())
+        }
+
+        fn load(&mut self, game: &GameId) -> Result<Option<Quarto>, Box<dyn Error>> {
+            Ok(self
+                .games
+                .iter()
+                .find(|(g, _)| g == game)
+                .map(|(_, q)| q.clone()))
+        }
+
+        fn create(&mut self, advanced: bool) -> Result<(GameId, Quarto), Box<dyn Error>> {
+            let game = id(&format!(
+                "9c9c9c9c-0000-4000-8000-00000000000{}",
+                self.games.len()
+            ));
+            let mut quarto = opening();
+            if advanced {
+                quarto.rules.variant = Variant::Advanced;
+            }
+            self.games.push((game.clone(), quarto.clone()));
+            Ok((game, quarto))
+        }
+
+        fn commit(&mut self, game: &GameId, quarto: &Quarto) -> Result<(), Box<dyn Error>> {
+            *self.get(game.as_str()) = quarto.clone();
+            self.commits.push((game.clone(), quarto.clone()));
+            Ok(())
+        }
+
+        fn create_from(&mut self, quarto: &Quarto) -> Result<GameId, Box<dyn Error>> {
+            let game = id(&format!(
+                "7e7e7e7e-0000-4000-8000-00000000000{}",
+                self.games.len()
+            ));
+            self.games.push((game.clone(), quarto.clone()));
+            Ok(game)
+        }
+    }
+    fn piece(code: &str) -> Piece {
+        Piece::try_from(code.to_string()).unwrap()
+    }
+
+    fn at(name: &str) -> Coord {
+        name.parse().unwrap()
+    }
+
+    fn opening() -> Quarto {
+        let mut quarto = Quarto::new();
+        quarto.pick_piece(&piece("BSCF"));
+        quarto
+    }
+
+    fn open_session(store: &mut MemoryStore, game: &str, autocommit: bool) -> Session {
+        let quarto = store.load(&id(game)).unwrap().unwrap();
+        Session::new(id(game), quarto, autocommit)
+    }
+
+    fn drive(session: &mut Session, store: &mut MemoryStore, input: &str) -> String {
+        let mut output = Vec::new();
+        run(session, store, input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_undo_redo_commit() {
+        let mut store = MemoryStore::with_games(&[FIRST]);
+        let mut session = open_session(&mut store, FIRST, false);
+        let output = drive(
+            &mut session,
+            &mut store,
+            "move a4 WSCF\nmove b3 BTCF\nundo\nredo\nundo\ncommit\nquit\n",
+        );
+
+        assert!(
+            output.contains("quarto 1a2b3c4d 2nd to move [2 uncommitted]> "),
+            "{}",
+            output
+        );
+        assert!(output.contains("committed 1 ply\n"), "{}", output);
+
+        let mut expected = opening();
+        expected.move_piece(0, 0);
+        expected.pick_piece(&piece("WSCF"));
+        assert_eq!(store.commits, vec![(id(FIRST), expected.clone())]);
+        assert_eq!(session.current(), &expected);
+        assert_eq!(session.uncommitted(), 0);
+        // Nothing before the commit can be taken back
+        assert!(!session.undo());
+        // The undone ply is still there to redo
+        assert!(session.redo());
+        assert_eq!(session.uncommitted(), 1);
+    }
+
+    #[test]
+    fn test_pieces_given_in_words() {
+        let mut store = MemoryStore::with_games(&[FIRST]);
+        let mut session = open_session(&mut store, FIRST, false);
+        let output = drive(
+            &mut session,
+            &mut store,
+            "move a4 white short circle\nmove a4 s t c h\nmove a4 white short circle flat\n\
+             move b3 b t c f\n",
+        );
+        assert!(
+            output.contains("invalid piece: no top given\n"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("invalid piece: s could be short or square\n"),
+            "{}",
+            output
+        );
+        let mut expected = opening();
+        expected.move_piece(0, 0);
+        expected.pick_piece(&piece("WSCF"));
+        expected.move_piece(1, 1);
+        expected.pick_piece(&piece("BTCF"));
+        assert_eq!(session.current(), &expected);
+    }
+
+    #[test]
+    fn test_heat_shades_open_lines() {
+        let mut store = MemoryStore::with_games(&[FIRST]);
+        let mut session = open_session(&mut store, FIRST, false);
+        let output = drive(
+            &mut session,
+            &mut store,
+            "move a4 WSCF\nheat on\nheat off\nheat\nquit\n",
+        );
+        let boards: Vec<&str> = output.matches("4 BSCF").collect();
+        assert_eq!(boards.len(), 3, "{}", output);
+        // Lines through a4 hold one piece each; c3 is on none of them
+        assert!(
+            output.contains("4 BSCF ░░░░ ░░░░ ░░░░\n3 ░░░░ ░░░░ ---- ----\n"),
+            "{}",
+            output
+        );
+        assert!(output.contains("unknown command: heat\n"), "{}", output);
+    }
+
+    #[test]
+    fn test_new_move_invalidates_redo() {
+        let mut session = Session::new(id(FIRST), opening(), false);
+        session.play(at("a4"), Some(piece("WSCF"))).unwrap();
+        session.play(at("b3"), Some(piece("BTCF"))).unwrap();
+        assert!(session.undo());
+        session.play(at("c2"), Some(piece("BTCF"))).unwrap();
+        assert!(!session.redo());
+        assert_eq!(session.uncommitted(), 2);
+
+        // Illegal moves leave the session untouched
+        let before = session.current().clone();
+        assert!(session.play(at("c2"), Some(piece("WTCF"))).is_err());
+        assert!(session.play(at("d1"), Some(piece("BSCF"))).is_err());
+        assert!(session.play(at("d1"), None).is_err());
+        assert_eq!(session.current(), &before);
+        assert_eq!(session.uncommitted(), 2);
+    }
+
+    #[test]
+    fn test_quit_discards_or_autocommits() {
+        let mut store = MemoryStore::with_games(&[FIRST]);
+        let mut session = open_session(&mut store, FIRST, false);
+        let output = drive(&mut session, &mut store, "move 0 0 WSCF\n");
+        assert!(output.contains("discarded 1 ply"), "{}", output);
+        assert!(store.commits.is_empty());
+
+        let mut session = open_session(&mut store, FIRST, true);
+        let output = drive(&mut session, &mut store, "move 0 0 WSCF\nbogus\nquit\n");
+        assert!(output.contains("unknown command: bogus"), "{}", output);
+        assert!(output.contains("committed 1 ply"), "{}", output);
+        assert_eq!(store.commits.len(), 1);
+    }
+
+    #[test]
+    fn test_switch_between_games() {
+        let mut store = MemoryStore::with_games(&[FIRST, SECOND]);
+        let mut session = open_session(&mut store, FIRST, false);
+        let output = drive(
+            &mut session,
+            &mut store,
+            "move 0 0 WSCF\nopen 5e6f\ngames\n",
+        );
+        // Uncommitted plies keep the session on the first game
+        assert!(
+            output.contains("1 ply uncommitted on 1a2b3c4d; commit or undo first"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("* 1a2b3c4d  0 placed, 2nd to move"),
+            "{}",
+            output
+        );
+        assert_eq!(session.game(), &id(FIRST));
+
+        let output = drive(&mut session, &mut store, "commit\nopen 5e6f\nopen 0000\n");
+        assert!(
+            output.contains("quarto 5e6f7a8b 2nd to move> "),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("no game in progress matches 0000"),
+            "{}",
+            output
+        );
+        assert_eq!(session.game(), &id(SECOND));
+
+        // Moves made elsewhere show up when switching back
+        store.get(FIRST).move_piece(1, 1);
+        store.get(FIRST).pick_piece(&piece("BTCF"));
+        drive(
+            &mut session,
+            &mut store,
+            &format!("open {}\n", FIRST.to_uppercase()),
+        );
+        assert_eq!(session.game(), &id(FIRST));
+        assert_eq!(session.current().placed_pieces(), 2);
+        assert_eq!(session.uncommitted(), 0);
+    }
+
+    #[test]
+    fn test_new_game_inline() {
+        let mut store = MemoryStore::with_games(&[FIRST]);
+        let mut session = open_session(&mut store, FIRST, true);
+        let output = drive(&mut session, &mut store, "move 0 0 WSCF\nnew --advanced\n");
+        // Autocommit commits before leaving
+        assert!(output.contains("committed 1 ply"), "{}", output);
+        assert_eq!(store.get(FIRST).placed_pieces(), 1);
+        assert_eq!(session.game(), &store.games[1].0);
+        assert_eq!(session.current().rules.variant, Variant::Advanced);
+    }
+
+    #[test]
+    fn test_ids_lengthen_to_stay_unambiguous() {
+        const TWIN: &str = "1a2b3c4d-9000-4000-8000-000000000003";
+        let mut store = MemoryStore::with_games(&[FIRST, SECOND]);
+        let mut session = open_session(&mut store, FIRST, false);
+        let output = drive(&mut session, &mut store, "games\n");
+        assert!(
+            output.starts_with("quarto 1a2b3c4d 2nd to move> "),
+            "{}",
+            output
+        );
+        assert!(output.contains("* 1a2b3c4d  0 placed"), "{}", output);
+
+        store.games.push((id(TWIN), opening()));
+        let output = drive(&mut session, &mut store, "games\nopen 1a2b3c4d-9\n");
+        assert!(
+            output.starts_with("quarto 1a2b3c4d-0 2nd to move> "),
+            "{}",
+            output
+        );
+        assert!(output.contains("* 1a2b3c4d-0  0 placed"), "{}", output);
+        assert!(output.contains("  1a2b3c4d-9  0 placed"), "{}", output);
+        assert!(output.contains("  5e6f7a8b  0 placed"), "{}", output);
+        assert_eq!(session.game(), &id(TWIN));
+    }
+
+    /* Suggests the first legal move at once, scored by depth */
+    struct FirstMove;
+
+    impl analysis::Engine for FirstMove {
+        fn analyze(&self, quarto: &Quarto, depth: u32, _stop: &AtomicBool) -> Option<(Move, f64)> {
+            let mv = quarto.legal_moves().into_iter().next()?;
+            Some((mv, f64::from(depth)))
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_hint_follows_position() {
+        let mut store = MemoryStore::with_games(&[FIRST]);
+        let mut session = open_session(&mut store, FIRST, false);
+        let output = drive(&mut session, &mut store, "hint\n");
+        assert!(output.contains("no analysis in this session"), "{}", output);
+
+        session.analysis = Some(Scheduler::new(
+            Handle::current(),
+            Arc::new(FirstMove),
+            CpuShare::default(),
+        ));
+        let output = drive(
+            &mut session,
+            &mut store,
+            "hint --wait 5s\nmove a4 WSCF\nhint --wait 5s\nhint --wait\nquit\n",
+        );
+        let mut after = opening();
+        let first = after.legal_moves()[0];
+        after.apply_move(&"a4 WSCF".parse().unwrap());
+        let next = after.legal_moves()[0];
+        assert!(
+            output.contains(&format!("depth 8: {} (score 8.000)\n", first)),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains(&format!("depth 8: {} (score 8.000)\n", next)),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("usage: hint [--wait <amount>]"),
+            "{}",
+            output
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::{self, IsTerminal};
+use std::time::Duration;
+
+/* Feedback from work which can take minutes: generate, index rebuild, book
+   build --from-solver and analyze --all. The work reports into a Progress and knows nothing of how
+   it is shown; the command line draws a bar on stderr, and tests record what was
+   reported. Phases follow one another, each counting its own items from 0.
+*/
+pub trait Progress: Send + Sync {
+    /* Starts the next phase, with its number of items when known */
+    fn phase(&self, name: &str, total: Option<u64>);
+    /* `items` more of the current phase are done */
+    fn advance(&self, items: u64);
+    /* All phases are done */
+    fn finish(&self);
+}
+
+/* For --quiet, and for output which is not read at a terminal */
+pub struct Silent;
+
+impl Progress for Silent {
+    fn phase(&self, _name: &str, _total: Option<u64>) {}
+    fn advance(&self, _items: u64) {}
+    fn finish(&self) {}
+}
+
+/* A bar when the phase has a total, a spinner when it does not */
+pub struct Bar(ProgressBar);
+
+const TICK: Duration = Duration::from_millis(100);
+
+impl Bar {
+    pub fn new() -> Bar {
+        let bar = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr());
+        bar.enable_steady_tick(TICK);
+        Bar(bar)
+    }
+}
+
+impl Progress for Bar {
+    fn phase(&self, name: &str, total: Option<u64>) {
+        let (template, length) = match total {
+            Some(total) => ("{msg} [{bar:30}] {pos}/{len}, {per_sec}", Some(total)),
+            None => ("{spinner} {msg} {pos}", None),
+        };
+        self.0
+            .set_style(ProgressStyle::with_template(template).expect("valid template"));
+        self.0.reset();
+        match length {
+            Some(length) => self.0.set_length(length),
+            None => self.0.unset_length(),
+        }
+        self.0.set_message(name.to_string());
+    }
+
+    fn advance(&self, items: u64) {
+        self.0.inc(items);
+    }
+
+    fn finish(&self) {
+        self.0.finish_and_clear();
+    }
+}
+
+/* What the command line reports into: nothing with --quiet or when stdout is not
+   a terminal, since the output is then being kept rather than watched
+*/
+pub fn for_cli(quiet: bool) -> Box<dyn Progress> {
+    if quiet || !io::stdout().is_terminal() {
+        Box::new(Silent)
+    } else {
+        Box::new(Bar::new())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    /* Every phase reported, with its total and the items done in it */
+    #[derive(Default)]
+    pub(crate) struct Recording {
+        pub phases: Mutex<Vec<(String, Option<u64>, u64)>>,
+        pub finished: Mutex<bool>,
+    }
+
+    impl Recording {
+        pub(crate) fn phases(&self) -> Vec<(String, Option<u64>, u64)> {
+            self.phases.lock().unwrap().clone()
+        }
+
+        pub(crate) fn finished(&self) -> bool {
+            *self.finished.lock().unwrap()
+        }
+    }
+
+    impl Progress for Recording {
+        fn phase(&self, name: &str, total: Option<u64>) {
+            self.phases
+                .lock()
+                .unwrap()
+                .push((name.to_string(), total, 0));
+        }
+
+        fn advance(&self, items: u64) {
+            let mut phases = self.phases.lock().unwrap();
+            let current = phases.last_mut().expect("advanced before any phase");
+            current.2 += items;
+        }
+
+        fn finish(&self) {
+            *self.finished.lock().unwrap() = true;
+        }
+    }
+
+    #[test]
+    fn test_recording_counts_per_phase() {
+        let recording = Recording::default();
+        recording.phase("reading", None);
+        recording.advance(2);
+        recording.advance(1);
+        recording.phase("writing", Some(4));
+        recording.advance(4);
+        assert!(!recording.finished());
+        recording.finish();
+        assert_eq!(
+            recording.phases(),
+            vec![
+                ("reading".to_string(), None, 3),
+                ("writing".to_string(), Some(4), 4)
+            ]
+        );
+        assert!(recording.finished());
+    }
+}
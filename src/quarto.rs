@@ -1,8 +1,11 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
-use std::hash::Hash;
+use std::fmt::Write;
+use std::sync::OnceLock;
 
-use serde::{Deserialize, Serialize};
+use crate::intersperse::intersperse;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use strum::IntoEnumIterator;
 use strum_macros::Display;
 use strum_macros::EnumIter;
@@ -15,6 +18,52 @@ pub enum QuartoError {
     FileExists,
     OutOfRange,
     InvalidQuarto,
+    ParseError {
+        line: usize,
+        column: usize,
+        reason: String,
+    },
+    DecodeError {
+        offset: usize,
+        reason: String,
+    },
+    InvalidTimeControl(String),
+    LostOnTime,
+    InvalidDeadline(String),
+    Forfeited,
+    IllegalMove(String),
+    CorruptRecord(String),
+    InvalidEvent(String),
+    DuplicateGame(String),
+    GameLocked(String),
+    InvalidSpectatorDelay(String),
+    InvalidVariant(String),
+    TamperedResult(String),
+    UnknownTemplate {
+        name: String,
+        known: Vec<String>,
+    },
+    InvalidGameId(String),
+    InvalidTimeout(String),
+    Timeout(String),
+    StoreUnavailable(String),
+    InvalidSeat(String),
+    GameOver,
+    NoDrawOffer,
+    InvalidEngine(String),
+    InvalidManifest(String),
+    InvalidMoveNumber(String),
+    InvalidCpuShare(String),
+    BrokenRules(RuleBreaks),
+    InvalidFilter(String),
+    InvalidAdjudication(String),
+    ReadOnly(String),
+    InvalidIdentity(String),
+    UnknownIdentity(String),
+    NotLoggedIn,
+    SeatTaken(String),
+    InvalidPieceDescription(String),
+    Io(std::io::Error),
     AnyOther,
 }
 
@@ -127,7 +176,7 @@ impl TryFrom<&str> for Top {
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Serialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct Piece {
     color: Color,
     height: Height,
@@ -137,21 +186,50 @@ pub struct Piece {
 
 impl From<Piece> for String {
     fn from(p: Piece) -> Self {
-        return format!(
-            "{}{}{}{}",
-            Self::from(p.color),
-            Self::from(p.height),
-            Self::from(p.shape),
-            Self::from(p.top)
-        )
-        .to_string();
+        p.to_string()
+    }
+}
+
+/* The code as From<Piece> for String gives it, written without allocating */
+impl std::fmt::Display for Piece {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let color = match self.color {
+            Color::Brown => 'B',
+            Color::White => 'W',
+        };
+        let height = match self.height {
+            Height::Short => 'S',
+            Height::Tall => 'T',
+        };
+        let shape = match self.shape {
+            Shape::Circle => 'C',
+            Shape::Square => 'S',
+        };
+        let top = match self.top {
+            Top::Flat => 'F',
+            Top::Hole => 'H',
+        };
+        write!(f, "{}{}{}{}", color, height, shape, top)
+    }
+}
+
+/* A board cell as text: the piece, or `empty` */
+struct CellText<'a>(Option<Piece>, &'a str);
+
+impl std::fmt::Display for CellText<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.0 {
+            Some(piece) => piece.fmt(f),
+            None => f.write_str(self.1),
+        }
     }
 }
 
 impl TryFrom<String> for Piece {
     type Error = QuartoError;
     fn try_from(text: String) -> Result<Piece, Self::Error> {
-        if text.len() != 4 {
+        // Non-ASCII text could not be sliced per character below
+        if text.len() != 4 || !text.is_ascii() {
             return Err(QuartoError::InvalidPieceError);
         }
         let color = &text[0..1];
@@ -168,14 +246,237 @@ impl TryFrom<String> for Piece {
     }
 }
 
+impl std::str::FromStr for Piece {
+    type Err = QuartoError;
+    fn from_str(s: &str) -> Result<Piece, QuartoError> {
+        Piece::try_from(s.to_string())
+    }
+}
+
+/* Index in 0..16 with one bit per property: color, height, shape, top
+   from the most significant bit. Brown, Short, Circle and Flat are 0.
+*/
+impl Piece {
+    pub fn to_index(self) -> u8 {
+        (self.color as u8) << 3
+            | (self.height as u8) << 2
+            | (self.shape as u8) << 1
+            | self.top as u8
+    }
+    pub fn from_index(index: u8) -> Option<Piece> {
+        if index >= 16 {
+            return None;
+        }
+        Some(Piece {
+            color: [Color::Brown, Color::White][(index >> 3 & 1) as usize],
+            height: [Height::Short, Height::Tall][(index >> 2 & 1) as usize],
+            shape: [Shape::Circle, Shape::Square][(index >> 1 & 1) as usize],
+            top: [Top::Flat, Top::Hole][(index & 1) as usize],
+        })
+    }
+}
+
+/* A piece is serialized as its four-letter code, e.g. "BSCF" */
+impl Serialize for Piece {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from(*self))
+    }
+}
+
+/* Derived serde used to write pieces as structs; keep reading that form */
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PieceRepr {
+    Code(String),
+    Fields {
+        color: Color,
+        height: Height,
+        shape: Shape,
+        top: Top,
+    },
+}
+
+impl<'de> Deserialize<'de> for Piece {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match PieceRepr::deserialize(deserializer)? {
+            PieceRepr::Code(code) => Piece::try_from(code.to_uppercase())
+                .map_err(|_| serde::de::Error::custom(format!("invalid piece {:?}", code))),
+            PieceRepr::Fields {
+                color,
+                height,
+                shape,
+                top,
+            } => Ok(Piece {
+                color,
+                height,
+                shape,
+                top,
+            }),
+        }
+    }
+}
+
 /* Nothing corresponded to empty cell */
 type CellState = Option<Piece>;
+/* Indexed [x][y] like the board text: x is the line from the top and y the cell
+   along it. Coord::from_xy turns that into a square.
+*/
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct BoardState([[CellState; 4]; 4]);
 
-impl TryFrom<&String> for BoardState {
-    type Error = QuartoError;
-    fn try_from(text: &String) -> Result<Self, Self::Error> {
+/* Width of a board line: four cells of four characters joined by spaces */
+pub const LINE_WIDTH: usize = 3 * (4 + 1) + 4;
+
+fn parse_error(line: usize, column: usize, reason: String) -> QuartoError {
+    QuartoError::ParseError {
+        line,
+        column,
+        reason,
+    }
+}
+
+/* Tokens accepted for an empty cell. Spaces are the canonical form stored in the DB. */
+const EMPTY_CELLS: [&str; 3] = ["    ", "----", "...."];
+
+/* How board and game text is read. Lenient reading, the default, puts right what
+   WarningKind lists and reports each fix. Strict reading rejects the same text:
+   it is for boards as stored in the DB, which are canonical once written.
+*/
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ParseOptions {
+    pub strict: bool,
+}
+
+/* Text which lenient reading accepts but which is not canonical */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WarningKind {
+    /* \r\n instead of \n */
+    LineEnding,
+    /* Whitespace after the last cell of a line or after a value */
+    TrailingWhitespace,
+    /* A board line cut short, as editors do with the spaces of empty last cells */
+    ShortLine,
+    /* bscf for BSCF */
+    Lowercase,
+    /* ---- or .... for an empty cell */
+    Placeholder,
+    /* A non-breaking or other non-ASCII space for a plain one; tabs are not read */
+    UnusualSpace,
+}
+
+impl WarningKind {
+    /* What tools which sync or edit files do to whitespace, rather than what
+       people write
+    */
+    pub fn is_whitespace(&self) -> bool {
+        matches!(
+            self,
+            WarningKind::LineEnding | WarningKind::TrailingWhitespace | WarningKind::UnusualSpace
+        )
+    }
+}
+
+impl std::fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            WarningKind::LineEnding => "\\r\\n line ending",
+            WarningKind::TrailingWhitespace => "trailing whitespace",
+            WarningKind::ShortLine => "short line padded with empty cells",
+            WarningKind::Lowercase => "lowercase piece",
+            WarningKind::Placeholder => "placeholder for an empty cell",
+            WarningKind::UnusualSpace => "non-ASCII space",
+        })
+    }
+}
+
+/* Where lenient reading put the text right; 1-based like ParseError */
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseWarning {
+    pub line: usize,
+    pub column: usize,
+    pub kind: WarningKind,
+}
+
+/* e.g. line 2, column 6: placeholder for an empty cell */
+impl std::fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.kind
+        )
+    }
+}
+
+/* The warnings of one reading, or the error which ends it when strict */
+struct Recovery {
+    options: ParseOptions,
+    warnings: Vec<ParseWarning>,
+}
+
+impl Recovery {
+    fn new(options: ParseOptions) -> Self {
+        Recovery {
+            options,
+            warnings: Vec::new(),
+        }
+    }
+    fn warn(&mut self, line: usize, column: usize, kind: WarningKind) -> Result<(), QuartoError> {
+        if self.options.strict {
+            return Err(parse_error(
+                line,
+                column,
+                format!("{} in strict mode", kind),
+            ));
+        }
+        self.warnings.push(ParseWarning { line, column, kind });
+        Ok(())
+    }
+    /* lines() strips the \r of \r\n line endings; this notes the first one */
+    fn line_ending(&mut self, text: &str) -> Result<(), QuartoError> {
+        let Some(at) = text.find("\r\n") else {
+            return Ok(());
+        };
+        let before = &text[..at];
+        let line_start = before.rfind('\n').map_or(0, |n| n + 1);
+        self.warn(
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+            WarningKind::LineEnding,
+        )
+    }
+    /* A piece as given, or its uppercase form with a warning; None when neither is one */
+    fn piece(
+        &mut self,
+        text: &str,
+        line: usize,
+        column: usize,
+    ) -> Result<Option<Piece>, QuartoError> {
+        if let Ok(piece) = text.parse() {
+            return Ok(Some(piece));
+        }
+        let Ok(piece) = text.to_uppercase().parse() else {
+            return Ok(None);
+        };
+        self.warn(line, column, WarningKind::Lowercase)?;
+        Ok(Some(piece))
+    }
+}
+
+impl BoardState {
+    /* Lines and columns in errors and warnings are 1-based and count characters,
+       not bytes
+    */
+    pub fn parse(
+        text: &str,
+        options: ParseOptions,
+    ) -> Result<(BoardState, Vec<ParseWarning>), QuartoError> {
+        let mut recovery = Recovery::new(options);
+        let board = BoardState::read(text, &mut recovery)?;
+        Ok((board, recovery.warnings))
+    }
+
+    fn read(text: &str, recovery: &mut Recovery) -> Result<BoardState, QuartoError> {
         let mut bs = [
             [None, None, None, None],
             [None, None, None, None],
@@ -183,598 +484,4289 @@ impl TryFrom<&String> for BoardState {
             [None, None, None, None],
         ];
 
+        recovery.line_ending(text)?;
         let lines: Vec<&str> = text.lines().collect();
         if lines.len() != 4 {
-            return Err(QuartoError::InvalidPieceError);
+            return Err(parse_error(
+                lines.len().min(4) + 1,
+                1,
+                format!("expected 4 lines, found {}", lines.len()),
+            ));
         }
-        let mut x: usize = 0;
         let mut piece_count: HashMap<Piece, usize> = HashMap::new();
-        for line in lines {
-            if line.len() != 3 * (4 + 1) + 4 {
-                return Err(QuartoError::InvalidPieceError);
+        for (x, line) in lines.into_iter().enumerate() {
+            let mut chars: Vec<char> = line.chars().collect();
+            if chars.len() > LINE_WIDTH && chars[LINE_WIDTH..].iter().all(|c| c.is_whitespace()) {
+                recovery.warn(x + 1, LINE_WIDTH + 1, WarningKind::TrailingWhitespace)?;
+                chars.truncate(LINE_WIDTH);
+            }
+            if chars.len() < LINE_WIDTH {
+                recovery.warn(x + 1, chars.len() + 1, WarningKind::ShortLine)?;
+                chars.resize(LINE_WIDTH, ' ');
+            }
+            let unusual = |c: &char| c.is_whitespace() && !c.is_ascii();
+            if let Some(at) = chars.iter().position(unusual) {
+                recovery.warn(x + 1, at + 1, WarningKind::UnusualSpace)?;
+                for c in chars.iter_mut().filter(|c| unusual(c)) {
+                    *c = ' ';
+                }
+            }
+            if chars.len() != LINE_WIDTH {
+                return Err(parse_error(
+                    x + 1,
+                    LINE_WIDTH + 1,
+                    format!("expected {} characters, found {}", LINE_WIDTH, chars.len()),
+                ));
             }
 
             for y in 0..4 {
-                let piece_text = &line[5 * y..5 * y + 4];
-                bs[x][y] = Piece::try_from(piece_text.to_string()).ok();
-                if let Some(piece) = &bs[x][y] {
-                    if let Some(_count) = piece_count.get(piece) {
-                        return Err(QuartoError::InvalidPieceError);
-                    } else {
-                        piece_count.insert(piece.clone(), 0);
+                let piece_text: String = chars[5 * y..5 * y + 4].iter().collect();
+                if EMPTY_CELLS[1..].contains(&piece_text.as_str()) {
+                    recovery.warn(x + 1, 5 * y + 1, WarningKind::Placeholder)?;
+                } else if piece_text != EMPTY_CELLS[0] {
+                    let Some(piece) = recovery.piece(&piece_text, x + 1, 5 * y + 1)? else {
+                        return Err(parse_error(
+                            x + 1,
+                            5 * y + 1,
+                            format!("invalid piece {:?}", piece_text),
+                        ));
+                    };
+                    if piece_count.contains_key(&piece) {
+                        return Err(parse_error(
+                            x + 1,
+                            5 * y + 1,
+                            format!("duplicate piece {}", piece_text),
+                        ));
                     }
+                    piece_count.insert(piece, 0);
+                    bs[x][y] = Some(piece);
                 }
 
-                if y != 3 {
-                    let spacer = &line[5 * y + 4..5 * y + 5];
-                    if !spacer.eq(" ") {
-                        /* spacer can be any character but this makes board state normalized */
-                        return Err(QuartoError::InvalidPieceError);
-                    }
+                if y != 3 && chars[5 * y + 4] != ' ' {
+                    /* spacer can be any character but this makes board state normalized */
+                    return Err(parse_error(
+                        x + 1,
+                        5 * y + 5,
+                        format!("expected a space, found {:?}", chars[5 * y + 4]),
+                    ));
                 }
             }
-
-            x += 1;
         }
         Ok(BoardState(bs))
     }
 }
 
-impl From<BoardState> for String {
-    fn from(bs: BoardState) -> Self {
-        let vv: String =
-            bs.0.into_iter()
-                .map(|r| {
-                    r.into_iter()
-                        .map(|c| c.map_or("    ".to_string(), Into::into))
-                        .collect::<Vec<_>>()
-                        .join(" ")
-                })
-                .collect::<Vec<_>>()
-                .join("\n");
-        vv
+/* Reads leniently and drops the warnings */
+impl TryFrom<&String> for BoardState {
+    type Error = QuartoError;
+    fn try_from(text: &String) -> Result<Self, Self::Error> {
+        BoardState::parse(text, ParseOptions::default()).map(|(board, _)| board)
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
-pub struct Quarto {
-    /* Only 4x4 board size is allowed */
-    /* A piece resides one of board_state, avaiable_pieces or next_piece */
-    pub board_state: BoardState,
-    free_pieces: Vec<Piece>,
-    pub next_piece: Option<Piece>,
+/* The eight symmetries of the square board. x is the line (row) and
+   y the column, as in the board text. Rotations are clockwise,
+   MirrorHorizontal swaps left and right, MirrorVertical swaps top and bottom,
+   Transpose mirrors along the (0,0)-(3,3) diagonal and AntiTranspose along
+   the (3,0)-(0,3) one.
+*/
+#[derive(Clone, Copy, Debug, EnumIter, Eq, Hash, PartialEq)]
+pub enum Symmetry {
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    MirrorHorizontal,
+    MirrorVertical,
+    Transpose,
+    AntiTranspose,
 }
 
-fn all_pieces() -> Vec<Piece> {
-    let mut pieces: Vec<Piece> = Vec::new();
-    for c in Color::iter() {
-        for s in Shape::iter() {
-            for t in Top::iter() {
-                for h in Height::iter() {
-                    pieces.push(Piece {
-                        color: c.clone(),
-                        shape: s.clone(),
-                        top: t.clone(),
-                        height: h.clone(),
-                    });
-                }
-            }
+impl Symmetry {
+    /* Where the cell (x, y) ends up under this symmetry */
+    pub fn map(&self, x: usize, y: usize) -> (usize, usize) {
+        match self {
+            Symmetry::Identity => (x, y),
+            Symmetry::Rotate90 => (y, 3 - x),
+            Symmetry::Rotate180 => (3 - x, 3 - y),
+            Symmetry::Rotate270 => (3 - y, x),
+            Symmetry::MirrorHorizontal => (x, 3 - y),
+            Symmetry::MirrorVertical => (3 - x, y),
+            Symmetry::Transpose => (y, x),
+            Symmetry::AntiTranspose => (3 - y, 3 - x),
         }
     }
-    pieces
 }
 
-impl TryFrom<&String> for Quarto {
-    type Error = QuartoError;
-    fn try_from(text: &String) -> Result<Self, Self::Error> {
-        let mut quarto = Quarto::new();
-        let bs = BoardState::try_from(text)?;
-        quarto.free_pieces = Quarto::free_pieces(&bs);
-        quarto.board_state = bs;
-        Ok(quarto)
+impl BoardState {
+    /* Whether the pieces on every cell of the line share the property.
+       A line with an empty cell never matches.
+    */
+    fn matches<S: PartialEq>(&self, line: &Line, prop: fn(&Piece) -> S) -> bool {
+        let cells = &self.0;
+        let first = match &cells[line[0].0][line[0].1] {
+            Some(p) => prop(p),
+            None => return false,
+        };
+        line[1..].iter().all(|(x, y)| match &cells[*x][*y] {
+            Some(p) => prop(p) == first,
+            None => false,
+        })
     }
-}
 
-impl Quarto {
-    pub fn new() -> Self {
-        Quarto {
-            board_state: BoardState([[CellState::None; 4]; 4]),
-            free_pieces: all_pieces(),
-            next_piece: None,
+    fn summarize_line(&self, line: &Line) -> LineSummary {
+        LineSummary {
+            line: *line,
+            pieces: line.map(|(x, y)| self.0[x][y]),
+            full: line.iter().all(|(x, y)| self.0[*x][*y].is_some()),
+            color: self.matches(line, |p| p.color),
+            height: self.matches(line, |p| p.height),
+            shape: self.matches(line, |p| p.shape),
+            top: self.matches(line, |p| p.top),
         }
     }
-    fn free_pieces(bs: &BoardState) -> Vec<Piece> {
-        let mut pieces = all_pieces();
-        for row in &bs.0 {
-            for cell in row {
-                if let Some(a_piece) = cell {
-                    pieces.retain(|x| *x != *a_piece);
-                }
+
+    pub fn transform(&self, symmetry: Symmetry) -> BoardState {
+        let mut bs = [[None; 4]; 4];
+        for x in 0..4 {
+            for y in 0..4 {
+                let (tx, ty) = symmetry.map(x, y);
+                bs[tx][ty] = self.0[x][y];
             }
         }
-        pieces
+        BoardState(bs)
+    }
+    pub fn rotate90(&self) -> BoardState {
+        self.transform(Symmetry::Rotate90)
+    }
+    pub fn rotate180(&self) -> BoardState {
+        self.transform(Symmetry::Rotate180)
+    }
+    pub fn rotate270(&self) -> BoardState {
+        self.transform(Symmetry::Rotate270)
+    }
+    pub fn mirror_horizontal(&self) -> BoardState {
+        self.transform(Symmetry::MirrorHorizontal)
+    }
+    pub fn mirror_vertical(&self) -> BoardState {
+        self.transform(Symmetry::MirrorVertical)
+    }
+    pub fn transpose(&self) -> BoardState {
+        self.transform(Symmetry::Transpose)
     }
 
-    fn count_elements<S: Clone + Eq + PartialEq + Hash>(
-        &self,
-        coords: &[(usize, usize); 4],
-        prop: fn(Piece) -> S,
-    ) -> (bool, HashMap<Option<S>, usize>) {
-        let picked: Vec<_> = coords
-            .into_iter()
-            .map(|(x, y)| self.board_state.0[*x][*y].clone())
-            .collect();
-        let picked_property: Vec<Option<S>> = picked
-            .clone()
+    /* Sized up front, so the whole board takes one allocation */
+    fn render(&self, empty: &str) -> String {
+        let rows = self
+            .0
             .iter()
-            .map(|opt| opt.as_ref().map(|p| prop(p.clone())))
+            .map(|row| intersperse(row.iter().map(|c| CellText(*c, empty)), ' '));
+        let mut text = String::with_capacity(4 * (4 * 4 + 3) + 3);
+        let _ = write!(text, "{}", intersperse(rows, '\n'));
+        text
+    }
+
+    pub fn cells(&self) -> &[[Option<Piece>; 4]; 4] {
+        &self.0
+    }
+
+    /* Uses ---- for empty cells so the text survives editors stripping trailing spaces */
+    pub fn to_display_string(&self) -> String {
+        self.render("----")
+    }
+
+    /* The display string with ranks down the side and files along the bottom, so
+       a1 is the bottom-left cell just as it is typed
+    */
+    pub fn to_labelled_string(&self) -> String {
+        let lines: Vec<String> = self
+            .to_display_string()
+            .lines()
+            .zip((1..=4).rev())
+            .map(|(line, rank)| format!("{} {}", rank, line))
             .collect();
+        format!("{}\n  a    b    c    d", lines.join("\n"))
+    }
+}
 
-        let mut hmap: HashMap<Option<S>, usize> = HashMap::new();
-        let mut found_none = false;
-        for v in picked_property {
-            if let None = v {
-                found_none = true;
-            }
-            if let Some(count) = hmap.get(&v) {
-                hmap.insert(v, count + 1);
-            } else {
-                hmap.insert(v, 1);
-            }
+impl From<BoardState> for String {
+    fn from(bs: BoardState) -> Self {
+        bs.render("    ")
+    }
+}
+
+/* Four cells which win when their pieces share a property */
+pub type Line = [(usize, usize); 4];
+
+/* Rows, columns and the two diagonals */
+pub const CLASSIC_LINES: [Line; 10] = [
+    [(0, 0), (0, 1), (0, 2), (0, 3)],
+    [(1, 0), (1, 1), (1, 2), (1, 3)],
+    [(2, 0), (2, 1), (2, 2), (2, 3)],
+    [(3, 0), (3, 1), (3, 2), (3, 3)],
+    [(0, 0), (1, 0), (2, 0), (3, 0)],
+    [(0, 1), (1, 1), (2, 1), (3, 1)],
+    [(0, 2), (1, 2), (2, 2), (3, 2)],
+    [(0, 3), (1, 3), (2, 3), (3, 3)],
+    [(0, 0), (1, 1), (2, 2), (3, 3)],
+    [(3, 0), (2, 1), (1, 2), (0, 3)],
+];
+
+/* The nine 2x2 squares which also win in the advanced variant */
+pub const SQUARE_LINES: [Line; 9] = [
+    [(0, 0), (0, 1), (1, 0), (1, 1)],
+    [(0, 1), (0, 2), (1, 1), (1, 2)],
+    [(0, 2), (0, 3), (1, 2), (1, 3)],
+    [(1, 0), (1, 1), (2, 0), (2, 1)],
+    [(1, 1), (1, 2), (2, 1), (2, 2)],
+    [(1, 2), (1, 3), (2, 2), (2, 3)],
+    [(2, 0), (2, 1), (3, 0), (3, 1)],
+    [(2, 1), (2, 2), (3, 1), (3, 2)],
+    [(2, 2), (2, 3), (3, 2), (3, 3)],
+];
+
+type LinesThrough = [[Vec<Line>; 4]; 4];
+
+/* Lines passing through each cell, built on first use from a line table */
+fn lines_through(lines: &[Line]) -> LinesThrough {
+    std::array::from_fn(|x| {
+        std::array::from_fn(|y| {
+            lines
+                .iter()
+                .filter(|line| line.contains(&(x, y)))
+                .copied()
+                .collect()
+        })
+    })
+}
+
+static CLASSIC_LINES_THROUGH: OnceLock<LinesThrough> = OnceLock::new();
+static SQUARE_LINES_THROUGH: OnceLock<LinesThrough> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum Variant {
+    #[default]
+    Classic,
+    /* 2x2 squares count as winning lines too */
+    Advanced,
+}
+
+/* Lower case names, as written on the command line and in the config file */
+impl std::str::FromStr for Variant {
+    type Err = QuartoError;
+    fn from_str(s: &str) -> Result<Variant, QuartoError> {
+        match s {
+            "classic" => Ok(Variant::Classic),
+            "advanced" => Ok(Variant::Advanced),
+            _ => Err(QuartoError::InvalidVariant(s.to_string())),
         }
-        (found_none, hmap)
     }
+}
 
-    pub fn pick_piece(&mut self, p: &Piece) -> bool {
-        if self.free_pieces.contains(p) {
-            self.free_pieces.retain(|pc| *pc != *p);
-            self.next_piece = Some(p.clone());
-            true
-        } else {
-            false
+impl std::fmt::Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Variant::Classic => "classic",
+            Variant::Advanced => "advanced",
+        })
+    }
+}
+
+/* Bumped whenever a change to the rules could play a recorded game differently:
+   games, score sheets and share codes carry the revision they were made under
+*/
+pub const RULES_REVISION: u8 = 1;
+
+/* The crate version and rules revision, e.g. 0.1.0+rules.1 */
+pub fn engine_version() -> String {
+    format!("{}+rules.{}", env!("CARGO_PKG_VERSION"), RULES_REVISION)
+}
+
+/* The rules revision an engine version was recorded under, if it names one */
+pub fn rules_revision(engine_version: &str) -> Option<u8> {
+    engine_version.rsplit_once("+rules.")?.1.parse().ok()
+}
+
+/* Why a game recorded under `recorded` may not replay as it was played. Games
+   from before revisions were recorded carry none and are taken on trust.
+*/
+pub fn revision_mismatch(recorded: Option<u8>) -> Option<String> {
+    match recorded {
+        Some(revision) if revision != RULES_REVISION => Some(format!(
+            "recorded under rules revision {}, replaying under {}",
+            revision, RULES_REVISION
+        )),
+        _ => None,
+    }
+}
+
+/* How house rules score a dead position, for ending the game there instead of
+   playing out the hand-over which can only lose
+*/
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Adjudication {
+    /* Lost by the player left to hand over a piece */
+    Loss,
+    Draw,
+}
+
+impl Adjudication {
+    /* The score of the player who made the last placement */
+    pub fn placer_score(self) -> f64 {
+        match self {
+            Adjudication::Loss => 0.0,
+            Adjudication::Draw => 0.5,
         }
     }
-    pub fn move_piece(&mut self, x: usize, y: usize) -> bool {
-        if x >= 4 || y >= 4 {
-            // Out of board access
-            return false;
+}
+
+impl std::str::FromStr for Adjudication {
+    type Err = QuartoError;
+    fn from_str(s: &str) -> Result<Adjudication, QuartoError> {
+        match s {
+            "loss" => Ok(Adjudication::Loss),
+            "draw" => Ok(Adjudication::Draw),
+            _ => Err(QuartoError::InvalidAdjudication(s.to_string())),
         }
-        if let None = self.board_state.0[x][y] {
-            if let Some(p) = &self.next_piece {
-                assert!(!self.free_pieces.contains(&p));
-                self.board_state.0[x][y] = Some(p.clone());
-                self.next_piece = None;
-                return true;
-            } else {
-                return false;
-            }
-        } else {
-            // A piece already occupies the position
-            return false;
+    }
+}
+
+impl std::fmt::Display for Adjudication {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Adjudication::Loss => "loss",
+            Adjudication::Draw => "draw",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Rules {
+    pub variant: Variant,
+    /* Off in the rules as published; games with it on end at a dead position */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dead_position_adjudication: Option<Adjudication>,
+}
+
+impl Rules {
+    fn square_lines(&self) -> &'static [Line] {
+        match self.variant {
+            Variant::Classic => &[],
+            Variant::Advanced => &SQUARE_LINES,
         }
     }
 
-    fn check_quarto<S: Eq + PartialEq + Hash>(ls: &(bool, HashMap<S, usize>)) -> bool {
-        let set = ls.1.values().collect::<HashSet<_>>();
-        !ls.0 && set.contains(&(4 as usize))
+    /* Every winning line under these rules */
+    pub fn lines(&self) -> impl Iterator<Item = &'static Line> {
+        CLASSIC_LINES.iter().chain(self.square_lines())
     }
-    fn summarize(
-        vv: &Vec<(
-            [(usize, usize); 4],
-            (
-                (bool, HashMap<Option<Color>, usize>),
-                (bool, HashMap<Option<Height>, usize>),
-                (bool, HashMap<Option<Shape>, usize>),
-                (bool, HashMap<Option<Top>, usize>),
-            ),
-        )>,
-    ) -> Vec<[(usize, usize); 4]> {
-        let r = vv
-            .into_iter()
-            .filter(|(_, (cls, hls, sls, tls))| {
-                Self::check_quarto(cls)
-                    || Self::check_quarto(hls)
-                    || Self::check_quarto(sls)
-                    || Self::check_quarto(tls)
-            })
-            .collect::<Vec<_>>()
-            .into_iter()
-            .map(|(l, _)| l.clone())
-            .collect::<Vec<_>>();
-        r
+
+    /* Winning lines under these rules which pass through (x, y) */
+    pub fn lines_through(&self, x: usize, y: usize) -> impl Iterator<Item = &'static Line> {
+        let classic = &CLASSIC_LINES_THROUGH.get_or_init(|| lines_through(&CLASSIC_LINES))[x][y];
+        let square: &'static [Line] = match self.variant {
+            Variant::Classic => &[],
+            Variant::Advanced => {
+                &SQUARE_LINES_THROUGH.get_or_init(|| lines_through(&SQUARE_LINES))[x][y]
+            }
+        };
+        classic.iter().chain(square)
     }
+}
+
+/* Which properties all four pieces on a line share */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineSummary {
+    pub line: Line,
+    pub pieces: [CellState; 4],
+    pub full: bool,
+    pub color: bool,
+    pub height: bool,
+    pub shape: bool,
+    pub top: bool,
+}
+
+impl LineSummary {
     pub fn is_quarto(&self) -> bool {
-        let vs = self.parse_quarto(vec![
-            [(0, 0), (0, 1), (0, 2), (0, 3)],
-            [(1, 0), (1, 1), (1, 2), (1, 3)],
-            [(2, 0), (2, 1), (2, 2), (2, 3)],
-            [(3, 0), (3, 1), (3, 2), (3, 3)],
-            [(0, 0), (1, 0), (2, 0), (3, 0)],
-            [(0, 1), (1, 1), (2, 1), (3, 1)],
-            [(0, 2), (1, 2), (2, 2), (3, 2)],
-            [(0, 3), (1, 3), (2, 3), (3, 3)],
-            [(0, 0), (1, 1), (2, 2), (3, 3)],
-            [(3, 0), (2, 1), (1, 2), (0, 3)],
-        ]);
-        let res = Self::summarize(&vs);
-        res.len() > 0
-    }
-
-    fn parse_quarto(
-        &self,
-        coords_vec: Vec<[(usize, usize); 4]>,
-    ) -> Vec<(
-        [(usize, usize); 4],
-        (
-            (bool, HashMap<Option<Color>, usize>),
-            (bool, HashMap<Option<Height>, usize>),
-            (bool, HashMap<Option<Shape>, usize>),
-            (bool, HashMap<Option<Top>, usize>),
-        ),
-    )> {
-        let mut ret: Vec<(
-            [(usize, usize); 4],
-            (
-                (bool, HashMap<Option<Color>, usize>),
-                (bool, HashMap<Option<Height>, usize>),
-                (bool, HashMap<Option<Shape>, usize>),
-                (bool, HashMap<Option<Top>, usize>),
-            ),
-        )> = Vec::new();
-        for coords in coords_vec {
-            let color_count = &self.count_elements(&coords, |piece| piece.color);
-            let height_count = &self.count_elements(&coords, |piece| piece.height);
-            let shape_count = &self.count_elements(&coords, |piece| piece.shape);
-            let top_count = &self.count_elements(&coords, |piece| piece.top);
-            let quarto = (
-                color_count.clone(),
-                height_count.clone(),
-                shape_count.clone(),
-                top_count.clone(),
-            );
-            ret.push((coords, quarto));
+        self.full && (self.color || self.height || self.shape || self.top)
+    }
+}
+
+/* One line per report: cells, pieces and the properties they share, e.g.
+   (0,0) (0,1) (0,2) (0,3) BSCF BSCH BSSF BTSH: color, quarto
+*/
+impl std::fmt::Display for LineSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cells = intersperse(self.line.iter().map(|(x, y)| format!("({},{})", x, y)), ' ');
+        let pieces = intersperse(self.pieces.iter().map(|c| CellText(*c, "----")), ' ');
+        let mut shared = Vec::new();
+        for (matched, name) in [
+            (self.color, "color"),
+            (self.height, "height"),
+            (self.shape, "shape"),
+            (self.top, "top"),
+        ] {
+            if matched {
+                shared.push(name);
+            }
+        }
+        if shared.is_empty() {
+            shared.push("none");
         }
+        if self.is_quarto() {
+            shared.push("quarto");
+        }
+        write!(f, "{} {}: {}", cells, pieces, intersperse(shared, ", "))
+    }
+}
+
+/* What the board alone decides under the rules, for frontends which keep their own
+   copy of it: a completed line wins, and a full board without one is drawn. The
+   turn, the piece in hand and the free pieces play no part.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoardEvaluation {
+    pub winning_lines: Vec<Line>,
+    pub full: bool,
+    /* Every line under the rules, in Rules::lines order */
+    pub lines: Vec<LineSummary>,
+}
 
-        ret
+impl BoardEvaluation {
+    pub fn is_quarto(&self) -> bool {
+        !self.winning_lines.is_empty()
     }
 }
 
-#[cfg(test)]
+pub fn evaluate_board(board: &BoardState, rules: &Rules) -> BoardEvaluation {
+    let lines: Vec<LineSummary> = rules
+        .lines()
+        .map(|line| board.summarize_line(line))
+        .collect();
+    BoardEvaluation {
+        winning_lines: lines
+            .iter()
+            .filter(|summary| summary.is_quarto())
+            .map(|summary| summary.line)
+            .collect(),
+        full: board.0.iter().flatten().all(Option::is_some),
+        lines,
+    }
+}
+
+/* How the pieces on a line stand on one property */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropertyState {
+    /* Four pieces sharing a value: a quarto */
+    Matched,
+    /* The pieces placed so far share a value and a cell is free */
+    Open,
+    /* Both values are on the line, so it can never match on this property */
+    Blocked,
+}
+
+impl PropertyState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PropertyState::Matched => "match",
+            PropertyState::Open => "open",
+            PropertyState::Blocked => "blocked",
+        }
+    }
+}
+
+/* One winning line and where it stands on each property, in notation::SLOTS order */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineExplanation {
+    pub line: Line,
+    pub pieces: [CellState; 4],
+    pub properties: [PropertyState; 4],
+}
+
+impl LineExplanation {
+    pub fn is_quarto(&self) -> bool {
+        self.properties.contains(&PropertyState::Matched)
+    }
+
+    /* Blocked on every property: no piece can ever complete it */
+    pub fn is_dead(&self) -> bool {
+        self.properties.iter().all(|s| *s == PropertyState::Blocked)
+    }
+}
+
+/* Every winning line under the current rules, for `analyze --explain` */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Explanation {
+    pub lines: Vec<LineExplanation>,
+}
+
+/* A row per line, e.g.
+   a4 b4 c4 d4  BSCF WTSH ---- ----  blocked blocked blocked blocked
+*/
+impl std::fmt::Display for Explanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:<11}  {:<19}", "line", "pieces")?;
+        for slot in crate::notation::SLOTS {
+            write!(f, "  {:<7}", slot)?;
+        }
+        for line in &self.lines {
+            let cells = squares(&line.line);
+            let pieces = line.pieces.iter().map(|c| CellText(*c, "----"));
+            write!(
+                f,
+                "\n{}  {}",
+                intersperse(&cells, ' '),
+                intersperse(pieces, ' ')
+            )?;
+            for state in line.properties {
+                write!(f, "  {:<7}", state.as_str())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/* Names of property values for prose, indexed as notation::SLOTS and the bits of
+   Piece::to_index
+*/
+const VALUE_NAMES: [[&str; 2]; 4] = [
+    ["brown", "white"],
+    ["short", "tall"],
+    ["circular", "square"],
+    ["flat", "holed"],
+];
+
+/* A line holding three pieces which share a property: the empty cell, and the
+   property bits, as in Piece::to_index, which a piece there must have or lack
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Threat {
+    pub line: Line,
+    pub cell: (usize, usize),
+    pub have: u8,
+    pub lack: u8,
+}
+
+impl Threat {
+    pub fn completed_by(&self, p: &Piece) -> bool {
+        p.to_index() & self.have != 0 || !p.to_index() & self.lack != 0
+    }
+}
+
+/* What completes it, e.g. d1 with any white piece */
+impl std::fmt::Display for Threat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut needs = Vec::new();
+        for (slot, names) in VALUE_NAMES.iter().enumerate() {
+            let bit = 1 << (3 - slot);
+            if self.have & bit != 0 {
+                needs.push(names[1]);
+            }
+            if self.lack & bit != 0 {
+                needs.push(names[0]);
+            }
+        }
+        write!(
+            f,
+            "{} with any {} piece",
+            square(self.cell),
+            needs.join(" or ")
+        )
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Quarto {
+    /* Only 4x4 board size is allowed */
+    /* A piece resides one of board_state, avaiable_pieces or next_piece */
+    pub board_state: BoardState,
+    free_pieces: Vec<Piece>,
+    pub next_piece: Option<Piece>,
+    #[serde(default)]
+    pub rules: Rules,
+}
+
+fn all_pieces() -> Vec<Piece> {
+    let mut pieces: Vec<Piece> = Vec::new();
+    for c in Color::iter() {
+        for s in Shape::iter() {
+            for t in Top::iter() {
+                for h in Height::iter() {
+                    pieces.push(Piece {
+                        color: c.clone(),
+                        shape: s.clone(),
+                        top: t.clone(),
+                        height: h.clone(),
+                    });
+                }
+            }
+        }
+    }
+    pieces
+}
+
+/* A whole game as text: the board, then a line for each of the piece in hand,
+   the player to move and the rules, as Display writes them:
+
+       BSCF ---- ---- ----
+       ...
+       hand: WTSH
+       turn: 2
+       variant: classic
+
+   Each line after the board may be left out: no piece in hand, the turn the
+   board gives and classic rules. A bare board is read as before. Lines and
+   columns in errors are 1-based and count the whole text.
+*/
+impl Quarto {
+    /* Options and warnings as for BoardState::parse. The board is read as one
+       stored in the DB, so strict reading rejects the ---- which Display writes.
+    */
+    pub fn parse(
+        text: &str,
+        options: ParseOptions,
+    ) -> Result<(Quarto, Vec<ParseWarning>), QuartoError> {
+        let mut recovery = Recovery::new(options);
+        recovery.line_ending(text)?;
+        let lines: Vec<&str> = text.lines().collect();
+        // Each board line ends in a newline, so a blank last row still counts
+        let board: String = lines
+            .iter()
+            .take(4)
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let board = BoardState::read(&board, &mut recovery)?;
+        let mut fields: HashMap<&str, (usize, usize, &str)> = HashMap::new();
+        for (n, line) in lines.iter().enumerate().skip(4) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let trimmed = line.trim_end();
+            if trimmed.len() != line.len() {
+                recovery.warn(
+                    n + 1,
+                    trimmed.chars().count() + 1,
+                    WarningKind::TrailingWhitespace,
+                )?;
+            }
+            let Some((key, value)) = trimmed.split_once(':') else {
+                return Err(parse_error(n + 1, 1, "expected key: value".to_string()));
+            };
+            let key = key.trim();
+            if !["hand", "turn", "variant"].contains(&key) {
+                return Err(parse_error(n + 1, 1, format!("unknown key {:?}", key)));
+            }
+            let column = trimmed.chars().count() - value.trim_start().chars().count() + 1;
+            if fields.insert(key, (n + 1, column, value.trim())).is_some() {
+                return Err(parse_error(n + 1, 1, format!("{} given twice", key)));
+            }
+        }
+        let field = |key: &str| fields.get(key).copied();
+        let rules = match field("variant") {
+            Some((line, column, value)) => Rules {
+                variant: value
+                    .parse()
+                    .map_err(|_| parse_error(line, column, format!("unknown variant {}", value)))?,
+                ..Rules::default()
+            },
+            None => Rules::default(),
+        };
+        let hand = match field("hand") {
+            Some((_, _, "none")) | None => None,
+            Some((line, column, value)) => match recovery.piece(value, line, column)? {
+                Some(piece) => Some(piece),
+                None => {
+                    return Err(parse_error(
+                        line,
+                        column,
+                        format!("invalid piece {:?}", value),
+                    ))
+                }
+            },
+        };
+        let quarto = Quarto::from_parts(board, hand, rules).map_err(|_| {
+            let (line, column, _) = field("hand").unwrap_or_default();
+            parse_error(
+                line,
+                column,
+                "piece in hand is also on the board".to_string(),
+            )
+        })?;
+        if let Some((line, column, value)) = field("turn") {
+            if value != quarto.turn().to_string() {
+                return Err(parse_error(
+                    line,
+                    column,
+                    format!("turn {} does not match the board", value),
+                ));
+            }
+        }
+        Ok((quarto, recovery.warnings))
+    }
+}
+
+/* Reads leniently and drops the warnings */
+impl TryFrom<&String> for Quarto {
+    type Error = QuartoError;
+    fn try_from(text: &String) -> Result<Self, Self::Error> {
+        Quarto::parse(text, ParseOptions::default()).map(|(quarto, _)| quarto)
+    }
+}
+
+impl std::fmt::Display for Quarto {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let hand: String = self.next_piece.map_or("none".to_string(), Into::into);
+        write!(
+            f,
+            "{}\nhand: {}\nturn: {}\nvariant: {}",
+            self.board_state.to_display_string(),
+            hand,
+            self.turn(),
+            self.rules.variant
+        )
+    }
+}
+
+/* A broken game, as Quarto::validate finds it. Every one of the sixteen pieces is
+   in exactly one place: on the board, in hand or among the free pieces. Games
+   being played are also checked for a piece handed on after the game ended.
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /* In more than one place, e.g. at b2 and free */
+    Duplicated { piece: Piece, places: Vec<String> },
+    Missing(Piece),
+    HandedAfterEnd(Piece),
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Violation::Duplicated { piece, places } => {
+                write!(f, "{} is {}", piece, intersperse(places, " and "))
+            }
+            Violation::Missing(piece) => {
+                write!(f, "{} is neither on the board, in hand nor free", piece)
+            }
+            Violation::HandedAfterEnd(piece) => {
+                write!(f, "{} is in hand although the game is over", piece)
+            }
+        }
+    }
+}
+
+impl Quarto {
+    pub fn new() -> Self {
+        Quarto {
+            board_state: BoardState([[CellState::None; 4]; 4]),
+            free_pieces: all_pieces(),
+            next_piece: None,
+            rules: Rules::default(),
+        }
+    }
+    pub fn with_rules(rules: Rules) -> Self {
+        Quarto {
+            rules,
+            ..Quarto::new()
+        }
+    }
+    /* Rebuilds a game from its stored parts, checking that they fit together */
+    pub fn from_parts(
+        board: BoardState,
+        hand: Option<Piece>,
+        rules: Rules,
+    ) -> Result<Quarto, QuartoError> {
+        let mut free_pieces = Quarto::free_pieces(&board);
+        if let Some(p) = hand {
+            if !free_pieces.contains(&p) {
+                return Err(QuartoError::CorruptRecord(format!(
+                    "piece in hand {} is also on the board",
+                    String::from(p)
+                )));
+            }
+            free_pieces.retain(|pc| *pc != p);
+        }
+        let quarto = Quarto {
+            board_state: board,
+            free_pieces,
+            next_piece: hand,
+            rules,
+        };
+        quarto.validate().map_err(|violations| {
+            QuartoError::CorruptRecord(intersperse(&violations, "; ").to_string())
+        })?;
+        Ok(quarto)
+    }
+    /* Every way the game breaks the one-place-per-piece rule, for callers which
+       take a game from outside, like the editor and stored games. Allocates only
+       when there is something to report.
+    */
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut counts = [0u8; 16];
+        let on_board = self.board_state.0.iter().flatten().flatten();
+        for p in on_board.chain(&self.next_piece).chain(&self.free_pieces) {
+            counts[p.to_index() as usize] += 1;
+        }
+        if counts.iter().all(|n| *n == 1) {
+            return Ok(());
+        }
+        let mut violations = Vec::new();
+        for (index, count) in (0..).zip(counts) {
+            let piece = Piece::from_index(index).unwrap();
+            if count == 0 {
+                violations.push(Violation::Missing(piece));
+                continue;
+            }
+            if count == 1 {
+                continue;
+            }
+            let mut places = Vec::new();
+            for (x, row) in self.board_state.0.iter().enumerate() {
+                for (y, cell) in row.iter().enumerate() {
+                    if *cell == Some(piece) {
+                        places.push(format!("at {}", square((x, y))));
+                    }
+                }
+            }
+            if self.next_piece == Some(piece) {
+                places.push("in hand".to_string());
+            }
+            for _ in self.free_pieces.iter().filter(|p| **p == piece) {
+                places.push("free".to_string());
+            }
+            violations.push(Violation::Duplicated { piece, places });
+        }
+        Err(violations)
+    }
+
+    /* validate, and a game that has ended hands nothing on: the move which ends
+       it is a placement alone
+    */
+    fn validate_play(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = self.validate().err().unwrap_or_default();
+        if let Some(piece) = self.next_piece {
+            if self.status() != GameStatus::InProgress {
+                violations.push(Violation::HandedAfterEnd(piece));
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /* Run after every change to the game in debug builds, or with the paranoid
+       feature; compiled out of release builds. pick_piece and move_piece also fill
+       boards past the end of a game, so the phase is only checked after a whole
+       move, by play_legal.
+    */
+    fn check_invariants(&self, phase: bool) {
+        if cfg!(any(debug_assertions, feature = "paranoid")) {
+            let checked = if phase {
+                self.validate_play()
+            } else {
+                self.validate()
+            };
+            if let Err(violations) = checked {
+                panic!("broken game: {}", intersperse(&violations, "; "));
+            }
+        }
+    }
+
+    fn free_pieces(bs: &BoardState) -> Vec<Piece> {
+        let mut pieces = all_pieces();
+        for row in &bs.0 {
+            for cell in row {
+                if let Some(a_piece) = cell {
+                    pieces.retain(|x| *x != *a_piece);
+                }
+            }
+        }
+        pieces
+    }
+
+    /* Board text followed by the piece in hand, used to key cached analysis */
+    pub fn position_key(&self) -> String {
+        let board: String = self.board_state.clone().into();
+        let hand: String = self.next_piece.map_or("    ".to_string(), Into::into);
+        format!("{}\n{}", board, hand)
+    }
+
+    /* Puts a free piece straight onto the board, skipping the pick/move
+       turn order. Meant for composing positions, not for playing.
+    */
+    #[cfg(feature = "setup")]
+    pub fn place_arbitrary(&mut self, p: &Piece, x: usize, y: usize) -> bool {
+        if x >= 4 || y >= 4 || self.board_state.0[x][y].is_some() {
+            return false;
+        }
+        if !self.free_pieces.contains(p) {
+            return false;
+        }
+        self.free_pieces.retain(|pc| *pc != *p);
+        self.board_state.0[x][y] = Some(*p);
+        self.check_invariants(false);
+        true
+    }
+
+    /* Takes a piece off the board and makes it free again */
+    #[cfg(feature = "setup")]
+    pub fn remove_arbitrary(&mut self, x: usize, y: usize) -> Option<Piece> {
+        let p = self.board_state.0.get_mut(x)?.get_mut(y)?.take()?;
+        self.free_again(p);
+        self.check_invariants(false);
+        Some(p)
+    }
+
+    /* Puts the piece in hand back among the free pieces and takes `p` instead */
+    #[cfg(feature = "setup")]
+    pub fn set_hand(&mut self, p: Option<Piece>) -> bool {
+        if p.is_some_and(|p| !self.free_pieces.contains(&p) && self.next_piece != Some(p)) {
+            return false;
+        }
+        if let Some(held) = self.next_piece.take() {
+            self.free_again(held);
+        }
+        if let Some(p) = p {
+            self.free_pieces.retain(|pc| *pc != p);
+            self.next_piece = Some(p);
+        }
+        self.check_invariants(false);
+        true
+    }
+
+    /* Keeps free_pieces in the order all_pieces gives, as a board read from text has it */
+    #[cfg(feature = "setup")]
+    fn free_again(&mut self, p: Piece) {
+        self.free_pieces.push(p);
+        let free = std::mem::take(&mut self.free_pieces);
+        self.free_pieces = all_pieces()
+            .into_iter()
+            .filter(|pc| free.contains(pc))
+            .collect();
+    }
+
+    pub fn pick_piece(&mut self, p: &Piece) -> bool {
+        if self.free_pieces.contains(p) {
+            self.free_pieces.retain(|pc| *pc != *p);
+            self.next_piece = Some(p.clone());
+            self.check_invariants(false);
+            true
+        } else {
+            false
+        }
+    }
+    /* Places the piece in hand at board indices; squares go through Coord::xy */
+    pub fn move_piece(&mut self, x: usize, y: usize) -> bool {
+        if x >= 4 || y >= 4 {
+            // Out of board access
+            return false;
+        }
+        if let None = self.board_state.0[x][y] {
+            if let Some(p) = &self.next_piece {
+                assert!(!self.free_pieces.contains(&p));
+                self.board_state.0[x][y] = Some(p.clone());
+                self.next_piece = None;
+                self.check_invariants(false);
+                return true;
+            } else {
+                return false;
+            }
+        } else {
+            // A piece already occupies the position
+            return false;
+        }
+    }
+
+    pub fn is_quarto(&self) -> bool {
+        self.rules
+            .lines()
+            .any(|line| self.board_state.summarize_line(line).is_quarto())
+    }
+
+    /* Lines completed under the current rules */
+    pub fn winning_lines(&self) -> Vec<Line> {
+        evaluate_board(&self.board_state, &self.rules).winning_lines
+    }
+
+    /* The same game seen under a board symmetry. Winning lines map with
+       symmetry.map since every rule's line set is closed under symmetries.
+    */
+    pub fn transformed(&self, symmetry: Symmetry) -> Quarto {
+        Quarto {
+            board_state: self.board_state.transform(symmetry),
+            ..self.clone()
+        }
+    }
+
+    /* A summary of every line under the current rules, for analysis tooling */
+    pub fn line_reports(&self) -> Vec<LineSummary> {
+        evaluate_board(&self.board_state, &self.rules).lines
+    }
+
+    /* Where every line stands on each property: matched, still open, or blocked
+       by two pieces which differ on it
+    */
+    pub fn explain(&self) -> Explanation {
+        let lines = self
+            .rules
+            .lines()
+            .map(|line| {
+                let pieces = line.map(|(x, y)| self.board_state.0[x][y]);
+                let (mut all, mut none, mut placed) = (0xF, 0xF, 0);
+                for p in pieces.iter().flatten() {
+                    all &= p.to_index();
+                    none &= !p.to_index();
+                    placed += 1;
+                }
+                let properties = [3, 2, 1, 0].map(|bit| {
+                    if (all | none) >> bit & 1 == 0 {
+                        PropertyState::Blocked
+                    } else if placed == 4 {
+                        PropertyState::Matched
+                    } else {
+                        PropertyState::Open
+                    }
+                });
+                LineExplanation {
+                    line: *line,
+                    pieces,
+                    properties,
+                }
+            })
+            .collect();
+        Explanation { lines }
+    }
+
+    /* How dangerous each cell is to fill, 0 to 255, for interfaces to shade the
+       board with. Every line through an empty cell which some piece could still
+       complete adds LINE_HEAT for the pieces already on it: 128 for three, 32 for
+       two and 8 for one. A cell which completes one line is at least 128, one
+       which completes two is 255, as the sum is capped there. Filled cells are 0.
+    */
+    pub fn cell_heat(&self) -> [[u8; 4]; 4] {
+        let mut heat = [[0u32; 4]; 4];
+        for line in self.explain().lines {
+            if line.is_quarto() || line.is_dead() {
+                continue;
+            }
+            let placed = line.pieces.iter().flatten().count();
+            for (cell, (x, y)) in line.pieces.iter().zip(line.line) {
+                if cell.is_none() {
+                    heat[x][y] += LINE_HEAT[placed];
+                }
+            }
+        }
+        heat.map(|row| row.map(|h| h.min(255) as u8))
+    }
+}
+
+/* What a line adds to cell_heat, by the pieces on it */
+const LINE_HEAT: [u32; 4] = [0, 8, 32, 128];
+
+/* What best_move adds when ranking a move onto a double threat: the motif usually
+   wins, more surely than random playouts can tell
+*/
+const DOUBLE_THREAT_BONUS: f64 = 0.25;
+
+/* Outcome frequencies of random playouts, seen from the side to move.
+   std_error is the standard error of the expected score (win = 1, draw = 1/2).
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimate {
+    pub playouts: u32,
+    pub win: f64,
+    pub draw: f64,
+    pub loss: f64,
+    pub std_error: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Outcome {
+    Win,
+    Draw,
+    Loss,
+}
+
+/* What an exhaustive search proves for the player to move. Unclear covers both
+   draws and positions decided beyond the search's depth.
+*/
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Proof {
+    Loss,
+    Unclear,
+    Win,
+}
+
+impl Proof {
+    /* The same result seen by the other player */
+    pub fn flip(self) -> Proof {
+        match self {
+            Proof::Loss => Proof::Win,
+            Proof::Unclear => Proof::Unclear,
+            Proof::Win => Proof::Loss,
+        }
+    }
+
+    /* As a score in the analysis cache: win = 1, unclear = 1/2 */
+    pub fn score(self) -> f64 {
+        match self {
+            Proof::Loss => 0.0,
+            Proof::Unclear => 0.5,
+            Proof::Win => 1.0,
+        }
+    }
+
+    pub fn from_score(score: f64) -> Proof {
+        if score >= 1.0 {
+            Proof::Win
+        } else if score <= 0.0 {
+            Proof::Loss
+        } else {
+            Proof::Unclear
+        }
+    }
+}
+
+impl std::fmt::Display for Proof {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Proof::Loss => "loses",
+            Proof::Unclear => "unclear",
+            Proof::Win => "wins",
+        })
+    }
+}
+
+/* SplitMix64, good enough for playouts and reproducible from a seed */
+pub struct PlayoutRng(u64);
+
+impl PlayoutRng {
+    pub fn new(seed: u64) -> PlayoutRng {
+        PlayoutRng(seed)
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/* The randomness of one stored game. What is drawn at a move comes from the game's
+   seed and the number of moves played alone, so the game row and its history are
+   enough to repeat every random choice made in it.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameRng(pub u64);
+
+impl GameRng {
+    /* A seed from the OS, for games created without one */
+    pub fn from_entropy() -> GameRng {
+        use std::hash::{BuildHasher, Hasher};
+        GameRng(
+            std::collections::hash_map::RandomState::new()
+                .build_hasher()
+                .finish(),
+        )
+    }
+
+    /* The seed for whatever is drawn in this position. Moves played are the pieces
+       placed, plus one while a piece is in hand, as every move hands one over.
+    */
+    pub fn seed_for(&self, quarto: &Quarto) -> u64 {
+        let played = quarto.placed_pieces() + usize::from(quarto.next_piece.is_some());
+        PlayoutRng::new(self.0 ^ (played as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)).next_u64()
+    }
+
+    pub fn rng_for(&self, quarto: &Quarto) -> PlayoutRng {
+        PlayoutRng::new(self.seed_for(quarto))
+    }
+}
+
+fn share_property(pieces: &[Piece; 4]) -> bool {
+    let p = pieces[0];
+    pieces.iter().all(|q| q.color == p.color)
+        || pieces.iter().all(|q| q.height == p.height)
+        || pieces.iter().all(|q| q.shape == p.shape)
+        || pieces.iter().all(|q| q.top == p.top)
+}
+
+impl Quarto {
+    fn empty_cells(&self) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
+        for x in 0..4 {
+            for y in 0..4 {
+                if self.board_state.0[x][y].is_none() {
+                    cells.push((x, y));
+                }
+            }
+        }
+        cells
+    }
+
+    /* Pieces neither on the board nor in hand */
+    pub fn free(&self) -> &[Piece] {
+        &self.free_pieces
+    }
+
+    /* Number of pieces already on the board */
+    pub fn placed_pieces(&self) -> usize {
+        self.board_state.0.iter().flatten().flatten().count()
+    }
+
+    /* The player to move, 1 or 2. The 1st player hands the opening piece, so
+       placements alternate starting with the 2nd.
+    */
+    pub fn turn(&self) -> usize {
+        if self.placed_pieces().is_multiple_of(2) {
+            2
+        } else {
+            1
+        }
+    }
+
+    /* Whether placing p on the empty cell (x, y) completes a line */
+    fn wins_at(&self, x: usize, y: usize, p: &Piece) -> bool {
+        self.rules.lines_through(x, y).any(|line| {
+            let mut pieces = [*p; 4];
+            for (i, (lx, ly)) in line.iter().enumerate() {
+                if (*lx, *ly) == (x, y) {
+                    continue;
+                }
+                match self.board_state.0[*lx][*ly] {
+                    Some(q) => pieces[i] = q,
+                    None => return false,
+                }
+            }
+            share_property(&pieces)
+        })
+    }
+
+    pub fn winning_cell(&self, p: &Piece) -> Option<(usize, usize)> {
+        self.empty_cells()
+            .into_iter()
+            .find(|(x, y)| self.wins_at(*x, *y, p))
+    }
+
+    /* Free pieces which can be handed over without giving an immediate win */
+    pub fn safe_pieces(&self) -> Vec<Piece> {
+        self.free_pieces
+            .iter()
+            .filter(|p| self.winning_cell(p).is_none())
+            .cloned()
+            .collect()
+    }
+
+    /* Free pieces which give the opponent an immediate win: those safe_pieces leaves out */
+    pub fn losing_pieces(&self) -> Vec<Piece> {
+        let safe = self.safe_pieces();
+        self.free_pieces
+            .iter()
+            .filter(|p| !safe.contains(p))
+            .cloned()
+            .collect()
+    }
+
+    /* Property bits, laid out as in Piece::to_index, which a piece needs to have or
+       to lack for an immediate win: a line holding three pieces is completed by
+       any piece sharing one of the properties they all have or all lack.
+    */
+    fn threat_masks(&self) -> (u8, u8) {
+        let (mut have, mut lack) = (0, 0);
+        for line in self.rules.lines() {
+            let (mut all, mut none, mut placed) = (0xF, 0xF, 0);
+            for (x, y) in line {
+                if let Some(p) = self.board_state.0[*x][*y] {
+                    all &= p.to_index();
+                    none &= !p.to_index();
+                    placed += 1;
+                }
+            }
+            if placed == 3 {
+                have |= all;
+                lack |= none;
+            }
+        }
+        (have, lack)
+    }
+
+    /* Whether every free piece gives its receiver an immediate win, so that
+       safe_pieces is empty while there is still a piece to hand over
+    */
+    pub fn is_hot(&self) -> bool {
+        let (have, lack) = self.threat_masks();
+        !self.free_pieces.is_empty()
+            && self
+                .free_pieces
+                .iter()
+                .all(|p| p.to_index() & have != 0 || !p.to_index() & lack != 0)
+    }
+
+    /* Whether the player to move cannot win at once and must then hand over a
+       piece which wins for the opponent, wherever the piece in hand goes
+    */
+    pub fn every_piece_loses(&self) -> bool {
+        let Some(p) = self.next_piece else {
+            return self.is_hot();
+        };
+        self.winning_cell(&p).is_none()
+            && self.empty_cells().into_iter().all(|(x, y)| {
+                let mut after = self.clone();
+                after.move_piece(x, y);
+                after.is_hot()
+            })
+    }
+
+    /* The threats which placing p on the empty cell (x, y) makes, through that
+       cell, and which a free piece other than p can still complete
+    */
+    pub fn threats_after(&self, x: usize, y: usize, p: &Piece) -> Vec<Threat> {
+        let mut threats = Vec::new();
+        for line in self.rules.lines_through(x, y) {
+            let (mut all, mut none, mut empty) = (0xF, 0xF, Vec::new());
+            for (lx, ly) in line {
+                let cell = if (*lx, *ly) == (x, y) {
+                    Some(*p)
+                } else {
+                    self.board_state.0[*lx][*ly]
+                };
+                match cell {
+                    Some(q) => {
+                        all &= q.to_index();
+                        none &= !q.to_index();
+                    }
+                    None => empty.push((*lx, *ly)),
+                }
+            }
+            let [cell] = empty[..] else {
+                continue;
+            };
+            let threat = Threat {
+                line: *line,
+                cell,
+                have: all,
+                lack: none,
+            };
+            if self
+                .free_pieces
+                .iter()
+                .any(|q| q != p && threat.completed_by(q))
+            {
+                threats.push(threat);
+            }
+        }
+        threats
+    }
+
+    /* Empty cells where placing p makes threats on two or more different cells
+       while some other piece can still be handed over safely: the opponent can
+       block only one cell and is then left to supply the piece for another.
+       Cells where p wins at once are not listed.
+    */
+    pub fn double_threats(&self, p: &Piece) -> Vec<(usize, usize)> {
+        self.empty_cells()
+            .into_iter()
+            .filter(|(x, y)| {
+                if self.wins_at(*x, *y, p) {
+                    return false;
+                }
+                // Lines through one cell share no other, so the threats are
+                // on different cells
+                if self.threats_after(*x, *y, p).len() < 2 {
+                    return false;
+                }
+                let mut after = self.clone();
+                after.board_state.0[*x][*y] = Some(*p);
+                after.free_pieces.retain(|q| q != p);
+                !after.safe_pieces().is_empty()
+            })
+            .collect()
+    }
+
+    pub fn estimate(&self, playouts: u32, seed: u64) -> Estimate {
+        let mut rng = PlayoutRng(seed);
+        let (mut win, mut draw, mut loss) = (0u32, 0u32, 0u32);
+        for _ in 0..playouts {
+            match self.clone().playout(&mut rng) {
+                Outcome::Win => win += 1,
+                Outcome::Draw => draw += 1,
+                Outcome::Loss => loss += 1,
+            }
+        }
+        let n = f64::from(playouts.max(1));
+        let (win, draw, loss) = (f64::from(win) / n, f64::from(draw) / n, f64::from(loss) / n);
+        let score = win + draw / 2.0;
+        let variance = (win + draw / 4.0 - score * score).max(0.0);
+        Estimate {
+            playouts,
+            win,
+            draw,
+            loss,
+            std_error: (variance / n).sqrt(),
+        }
+    }
+
+    /* The legal move with the best expected score for the player to move, judged by
+       `playouts` playouts from the position after each move. A move which wins at
+       once scores 1 without playouts. Moves onto a double threat are ranked
+       DOUBLE_THREAT_BONUS higher, though their score is reported as estimated.
+       Ties keep the earlier move in legal_moves order.
+    */
+    pub fn best_move(&self, playouts: u32, seed: u64) -> Option<(Move, f64)> {
+        let double_threats = self
+            .next_piece
+            .map_or(Vec::new(), |p| self.double_threats(&p));
+        let mut best: Option<(Move, f64, f64)> = None;
+        for mv in self.legal_moves() {
+            let mut after = self.clone();
+            after.apply_move(&mv);
+            let score = match after.status() {
+                GameStatus::Won => 1.0,
+                GameStatus::Drawn => 0.5,
+                GameStatus::Adjudicated(adjudication) => adjudication.placer_score(),
+                GameStatus::InProgress => {
+                    let estimate = after.estimate(playouts, seed);
+                    1.0 - (estimate.win + estimate.draw / 2.0)
+                }
+            };
+            let rank = match mv.place {
+                Some(at) if double_threats.contains(&at.xy()) => score + DOUBLE_THREAT_BONUS,
+                _ => score,
+            };
+            if !matches!(best, Some((_, _, r)) if r >= rank) {
+                best = Some((mv, score, rank));
+            }
+        }
+        best.map(|(mv, score, _)| (mv, score))
+    }
+
+    /* What the player to move can force within `depth` moves, and the first move
+       in legal_moves order which does it. Finished games and depth 0 give no move.
+    */
+    pub fn search(&self, depth: u32) -> (Proof, Option<Move>) {
+        if depth == 0 || self.status() != GameStatus::InProgress {
+            return (self.prove(depth), None);
+        }
+        let mut best = (Proof::Loss, None);
+        for mv in self.legal_moves() {
+            let mut next = self.clone();
+            next.play_legal(&mv);
+            let proof = next.prove(depth - 1).flip();
+            if best.1.is_none() || proof > best.0 {
+                best = (proof, Some(mv));
+            }
+            if proof == Proof::Win {
+                break;
+            }
+        }
+        best
+    }
+
+    fn prove(&self, depth: u32) -> Proof {
+        match self.status() {
+            // The previous player completed a line
+            GameStatus::Won => return Proof::Loss,
+            GameStatus::Drawn => return Proof::Unclear,
+            GameStatus::Adjudicated(adjudication) => {
+                return Proof::from_score(1.0 - adjudication.placer_score())
+            }
+            GameStatus::InProgress => {}
+        }
+        if self
+            .next_piece
+            .is_some_and(|p| self.winning_cell(&p).is_some())
+        {
+            return Proof::Win;
+        }
+        if depth <= 1 || !self.decidable_within(depth) {
+            return Proof::Unclear;
+        }
+        // With no immediate win, two moves can at best keep the game going
+        let ceiling = if depth == 2 {
+            Proof::Unclear
+        } else {
+            Proof::Win
+        };
+        let mut best = Proof::Loss;
+        for mv in self.legal_moves() {
+            let mut next = self.clone();
+            next.play_legal(&mv);
+            best = best.max(next.prove(depth - 1).flip());
+            if best == ceiling {
+                break;
+            }
+        }
+        best
+    }
+
+    /* Whether some line can be completed within `depth` moves: each move places at
+       most one piece, and a line whose pieces share nothing never completes
+    */
+    fn decidable_within(&self, depth: u32) -> bool {
+        self.rules.lines().any(|line| {
+            let (mut all, mut none, mut placed) = (0xF, 0xF, 0);
+            for p in line.iter().filter_map(|(x, y)| self.board_state.0[*x][*y]) {
+                all &= p.to_index();
+                none &= !p.to_index();
+                placed += 1;
+            }
+            (all | none) & 0xF != 0 && placed + depth >= 4
+        })
+    }
+
+    /* The first move in legal_moves order which wins within `plies` moves whatever
+       the other player does. Unlike search, a claim holds only if it survives every
+       reply: each cell and each piece handed back. Positions met along the way are
+       remembered by canonical_key, so a position reached in another orientation or
+       by another order of moves is only checked once.
+    */
+    pub fn has_forced_win(&self, plies: u8) -> Option<Move> {
+        let mut memo = HashMap::new();
+        if !self.forces_win(plies, &mut memo) {
+            return None;
+        }
+        self.legal_moves().into_iter().find(|mv| {
+            let mut next = self.clone();
+            next.play_legal(mv);
+            next.holds_win(plies - 1, &mut memo)
+        })
+    }
+
+    /* The smallest position key over the board symmetries, and a symmetry giving
+       it. The book and the position memo both key on it.
+    */
+    pub fn canonical(&self) -> (String, Symmetry) {
+        Symmetry::iter()
+            .map(|s| (self.transformed(s).position_key(), s))
+            .min_by(|a, b| a.0.cmp(&b.0))
+            .unwrap()
+    }
+
+    pub fn canonical_key(&self) -> String {
+        self.canonical().0
+    }
+
+    /* Whether the player to move wins within `plies` moves against any defence */
+    fn forces_win(&self, plies: u8, memo: &mut HashMap<(String, u8), bool>) -> bool {
+        if plies == 0 || self.status() != GameStatus::InProgress {
+            return false;
+        }
+        if self
+            .next_piece
+            .is_some_and(|p| self.winning_cell(&p).is_some())
+        {
+            return true;
+        }
+        // Without an immediate win the next chance comes two moves later
+        if plies < 3 || !self.decidable_within(plies.into()) {
+            return false;
+        }
+        let key = (self.canonical_key(), plies);
+        if let Some(forced) = memo.get(&key) {
+            return *forced;
+        }
+        let forced = self.legal_moves().iter().any(|mv| {
+            let mut next = self.clone();
+            next.play_legal(mv);
+            next.holds_win(plies - 1, memo)
+        });
+        memo.insert(key, forced);
+        forced
+    }
+
+    /* Whether the player who just moved has won, or wins within `plies` more moves
+       whatever the player to move places and hands back
+    */
+    fn holds_win(&self, plies: u8, memo: &mut HashMap<(String, u8), bool>) -> bool {
+        match self.status() {
+            GameStatus::Won => return true,
+            GameStatus::Drawn | GameStatus::Adjudicated(_) => return false,
+            GameStatus::InProgress => {}
+        }
+        if plies < 2
+            || self
+                .next_piece
+                .is_some_and(|p| self.winning_cell(&p).is_some())
+        {
+            return false;
+        }
+        self.legal_moves().iter().all(|reply| {
+            let mut next = self.clone();
+            next.play_legal(reply);
+            match next.status() {
+                GameStatus::Adjudicated(adjudication) => adjudication.placer_score() == 0.0,
+                GameStatus::InProgress => next.forces_win(plies - 1, memo),
+                GameStatus::Won | GameStatus::Drawn => false,
+            }
+        })
+    }
+
+    /* Plays randomly until the game ends. The policy takes immediate wins
+       and avoids handing over pieces that lose at once when it can.
+    */
+    fn playout(mut self, rng: &mut PlayoutRng) -> Outcome {
+        if self.is_quarto() {
+            // The previous player already won.
+            return Outcome::Loss;
+        }
+        let mut us = true;
+        loop {
+            if let Some(p) = self.next_piece {
+                if self.winning_cell(&p).is_some() {
+                    return if us { Outcome::Win } else { Outcome::Loss };
+                }
+                let cells = self.empty_cells();
+                let (x, y) = cells[rng.below(cells.len())];
+                self.move_piece(x, y);
+            }
+            if self.free_pieces.is_empty() {
+                return Outcome::Draw;
+            }
+            match self.rules.dead_position_adjudication {
+                Some(Adjudication::Loss) if self.is_hot() => {
+                    return if us { Outcome::Loss } else { Outcome::Win };
+                }
+                Some(Adjudication::Draw) if self.is_hot() => return Outcome::Draw,
+                _ => {}
+            }
+            let candidates = if self.is_hot() {
+                self.free_pieces.clone()
+            } else {
+                self.safe_pieces()
+            };
+            let p = candidates[rng.below(candidates.len())];
+            self.pick_piece(&p);
+            us = !us;
+        }
+    }
+}
+
+/* A cell named algebraically: files a-d run left to right and ranks 1-4 bottom to
+   top, so a1 is the bottom-left cell of the board as printed. Both count from 0.
+*/
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Coord {
+    pub file: usize,
+    pub rank: usize,
+}
+
+impl Coord {
+    pub fn new(file: usize, rank: usize) -> Option<Coord> {
+        (file < 4 && rank < 4).then_some(Coord { file, rank })
+    }
+
+    /* The one conversion between squares and board indices. x counts the lines of
+       the board text from the top and y the pieces along a line, so the board is
+       indexed [x][y]; it is also how the deprecated `x y` arguments are read.
+    */
+    pub fn from_xy(x: usize, y: usize) -> Option<Coord> {
+        (x < 4).then(|| Coord::new(y, 3 - x)).flatten()
+    }
+
+    pub fn xy(self) -> (usize, usize) {
+        (3 - self.rank, self.file)
+    }
+
+    /* The cell named by `places`: one square, or an x and a y */
+    pub fn from_places(places: &[Place]) -> Result<Coord, QuartoError> {
+        match places {
+            [Place::Square(at)] => Ok(*at),
+            [Place::Index(x), Place::Index(y)] => {
+                Coord::from_xy(*x, *y).ok_or(QuartoError::OutOfRange)
+            }
+            _ => Err(QuartoError::OutOfRange),
+        }
+    }
+}
+
+impl std::fmt::Display for Coord {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}{}", (b'a' + self.file as u8) as char, self.rank + 1)
+    }
+}
+
+impl std::str::FromStr for Coord {
+    type Err = QuartoError;
+    fn from_str(s: &str) -> Result<Coord, QuartoError> {
+        match s.to_ascii_lowercase().as_bytes() {
+            [file @ b'a'..=b'd', rank @ b'1'..=b'4'] => {
+                Ok(Coord::new((file - b'a') as usize, (rank - b'1') as usize).unwrap())
+            }
+            _ => Err(QuartoError::OutOfRange),
+        }
+    }
+}
+
+/* A cell is serialized as its square, e.g. "a1" */
+impl Serialize for Coord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/* Cells used to be written as [x, y]; keep reading that form */
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CoordRepr {
+    Square(String),
+    Xy(usize, usize),
+}
+
+impl<'de> Deserialize<'de> for Coord {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let invalid = |cell: String| serde::de::Error::custom(format!("invalid cell {}", cell));
+        match CoordRepr::deserialize(deserializer)? {
+            CoordRepr::Square(square) => square.parse().map_err(|_| invalid(square)),
+            CoordRepr::Xy(x, y) => {
+                Coord::from_xy(x, y).ok_or_else(|| invalid(format!("{} {}", x, y)))
+            }
+        }
+    }
+}
+
+/* The square of a cell given by board indices, which must be on the board */
+pub fn square((x, y): (usize, usize)) -> Coord {
+    Coord::from_xy(x, y).expect("cell on the board")
+}
+
+pub fn squares(line: &Line) -> [Coord; 4] {
+    line.map(square)
+}
+
+/* One argument naming a cell: a square, or half of the deprecated x y pair */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Place {
+    Square(Coord),
+    Index(usize),
+}
+
+impl Place {
+    pub fn is_deprecated(&self) -> bool {
+        matches!(self, Place::Index(_))
+    }
+}
+
+impl std::str::FromStr for Place {
+    type Err = QuartoError;
+    fn from_str(s: &str) -> Result<Place, QuartoError> {
+        match s.parse::<usize>() {
+            Ok(index) if index < 4 => Ok(Place::Index(index)),
+            Ok(_) => Err(QuartoError::OutOfRange),
+            Err(_) => s.parse().map(Place::Square),
+        }
+    }
+}
+
+/* One turn: place the piece in hand, hand over the next piece, or both in that order.
+   The opening turn only hands over. The last placement and a winning one hand
+   over nothing, since the game ends with them.
+*/
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Move {
+    pub place: Option<Coord>,
+    pub hand: Option<Piece>,
+}
+
+/* Written like the arguments of `quarto move`: the square and the piece to hand over */
+impl std::fmt::Display for Move {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.place, self.hand) {
+            (Some(at), Some(p)) => write!(f, "{} {}", at, p),
+            (Some(at), None) => write!(f, "{}", at),
+            (None, Some(p)) => write!(f, "{}", p),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/* Reads what Display writes, and the deprecated x y in place of the square; the
+   legality of the move is checked when it is played
+*/
+impl std::str::FromStr for Move {
+    type Err = QuartoError;
+    fn from_str(s: &str) -> Result<Move, QuartoError> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let places: Vec<Place> = words.iter().map_while(|w| w.parse().ok()).collect();
+        let place = match places.as_slice() {
+            [] => None,
+            places => Some(Coord::from_places(places)?),
+        };
+        match words[places.len()..] {
+            [] if place.is_some() => Ok(Move { place, hand: None }),
+            [piece] => Ok(Move {
+                place,
+                hand: Some(piece.parse()?),
+            }),
+            _ => Err(QuartoError::IllegalMove(s.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameStatus {
+    InProgress,
+    Won,
+    Drawn,
+    /* Ended at a dead position by the rules' dead_position_adjudication */
+    Adjudicated(Adjudication),
+}
+
+impl std::fmt::Display for GameStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            GameStatus::InProgress => "in progress",
+            GameStatus::Won => "won",
+            GameStatus::Drawn => "drawn",
+            GameStatus::Adjudicated(Adjudication::Loss) => "adjudicated lost",
+            GameStatus::Adjudicated(Adjudication::Draw) => "adjudicated drawn",
+        })
+    }
+}
+
+/* What a move would lead to, worked out without playing it */
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MovePreview {
+    pub status: GameStatus,
+    /* Lines completed by the placement */
+    pub winning_lines: Vec<[Coord; 4]>,
+    /* Where the opponent could win at once with the handed piece */
+    pub opponent_wins_at: Option<Coord>,
+}
+
+impl std::fmt::Display for MovePreview {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "result: {}", self.status)?;
+        for line in &self.winning_lines {
+            let cells: Vec<String> = line.iter().map(Coord::to_string).collect();
+            write!(f, "\ncompletes {}", cells.join(" "))?;
+        }
+        if let Some(at) = self.opponent_wins_at {
+            write!(f, "\nhands over a win at {}", at)?;
+        }
+        Ok(())
+    }
+}
+
+/* One way a move breaks the rules, as Quarto::rule_breaks finds it */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuleBreak {
+    GameOver,
+    Occupied(Coord),
+    /* On the board or in hand already */
+    NotFree(Piece),
+    /* A piece is in hand, so the turn starts by placing it */
+    MustPlace,
+    /* Nothing is in hand at the opening, so the turn only hands over */
+    NothingToPlace,
+    MustHandOver,
+    /* The placement ends the game */
+    NothingToHandOver,
+}
+
+impl RuleBreak {
+    /* Names the rule for JSON output */
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RuleBreak::GameOver => "game_over",
+            RuleBreak::Occupied(_) => "occupied",
+            RuleBreak::NotFree(_) => "not_free",
+            RuleBreak::MustPlace => "must_place",
+            RuleBreak::NothingToPlace => "nothing_to_place",
+            RuleBreak::MustHandOver => "must_hand_over",
+            RuleBreak::NothingToHandOver => "nothing_to_hand_over",
+        }
+    }
+}
+
+impl std::fmt::Display for RuleBreak {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RuleBreak::GameOver => write!(f, "the game is over"),
+            RuleBreak::Occupied(at) => write!(f, "{} is occupied", at),
+            RuleBreak::NotFree(p) => write!(f, "{} is not free", p),
+            RuleBreak::MustPlace => write!(f, "the piece in hand must be placed"),
+            RuleBreak::NothingToPlace => write!(f, "there is no piece in hand to place"),
+            RuleBreak::MustHandOver => write!(f, "a piece must be handed over"),
+            RuleBreak::NothingToHandOver => {
+                write!(f, "the placement ends the game, so nothing is handed over")
+            }
+        }
+    }
+}
+
+/* Everything wrong with a move at once, e.g.
+   a4 WTSH: a4 is occupied; WTSH is not free
+*/
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuleBreaks {
+    pub mv: Move,
+    pub breaks: Vec<RuleBreak>,
+}
+
+impl RuleBreaks {
+    pub fn to_json(&self) -> serde_json::Value {
+        let breaks: Vec<serde_json::Value> = self
+            .breaks
+            .iter()
+            .map(|b| serde_json::json!({"rule": b.kind(), "message": b.to_string()}))
+            .collect();
+        serde_json::json!({"move": self.mv.to_string(), "breaks": breaks})
+    }
+}
+
+impl std::fmt::Display for RuleBreaks {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.mv, intersperse(&self.breaks, "; "))
+    }
+}
+
+impl Quarto {
+    /* What the board decides as evaluate_board reports it, then the rules' dead
+       position adjudication
+    */
+    pub fn status(&self) -> GameStatus {
+        let board = evaluate_board(&self.board_state, &self.rules);
+        if board.is_quarto() {
+            GameStatus::Won
+        } else if board.full {
+            GameStatus::Drawn
+        } else if let Some(adjudication) = self
+            .rules
+            .dead_position_adjudication
+            .filter(|_| self.is_dead_position())
+        {
+            GameStatus::Adjudicated(adjudication)
+        } else {
+            GameStatus::InProgress
+        }
+    }
+
+    /* A piece has just been placed without a quarto and every free piece lets
+       whoever receives it complete a line. The game goes on under the published
+       rules; house rules may end it here instead.
+    */
+    pub fn is_dead_position(&self) -> bool {
+        self.next_piece.is_none() && !self.is_quarto() && self.is_hot()
+    }
+
+    /* Whether placing p on the empty cell (x, y) ends the game before a hand-over */
+    fn ends_at(&self, x: usize, y: usize, p: &Piece) -> bool {
+        self.wins_at(x, y, p)
+            || self.free_pieces.is_empty()
+            || self.rules.dead_position_adjudication.is_some() && {
+                let mut after = self.clone();
+                after.move_piece(x, y);
+                after.is_dead_position()
+            }
+    }
+
+    /* Every rule mv breaks, in the order a turn goes; none for a legal move */
+    pub fn rule_breaks(&self, mv: &Move) -> Vec<RuleBreak> {
+        let mut breaks = Vec::new();
+        if self.status() != GameStatus::InProgress {
+            breaks.push(RuleBreak::GameOver);
+        }
+        // Whether the turn ends the game, when its placement leaves that clear
+        let mut ends = None;
+        match (mv.place, self.next_piece) {
+            (Some(at), Some(p)) => {
+                let (x, y) = at.xy();
+                if self.board_state.0[x][y].is_some() {
+                    breaks.push(RuleBreak::Occupied(at));
+                } else {
+                    ends = Some(self.ends_at(x, y, &p));
+                }
+            }
+            (Some(_), None) => {
+                breaks.push(RuleBreak::NothingToPlace);
+                ends = Some(false);
+            }
+            (None, Some(_)) => breaks.push(RuleBreak::MustPlace),
+            (None, None) => ends = Some(false),
+        }
+        match (mv.hand, ends) {
+            (Some(p), _) if !self.free_pieces.contains(&p) => breaks.push(RuleBreak::NotFree(p)),
+            (Some(_), Some(true)) => breaks.push(RuleBreak::NothingToHandOver),
+            (None, Some(false)) => breaks.push(RuleBreak::MustHandOver),
+            _ => {}
+        }
+        breaks
+    }
+
+    /* Validates mv and previews it on a copy, leaving self untouched. A move which
+       breaks the rules is refused with all of them.
+    */
+    pub fn check_move(&self, mv: &Move) -> Result<MovePreview, QuartoError> {
+        let breaks = self.rule_breaks(mv);
+        if !breaks.is_empty() {
+            return Err(QuartoError::BrokenRules(RuleBreaks { mv: *mv, breaks }));
+        }
+        let mut scratch = self.clone();
+        if !scratch.apply_move(mv) {
+            return Err(QuartoError::IllegalMove(mv.to_string()));
+        }
+        Ok(MovePreview {
+            status: scratch.status(),
+            winning_lines: scratch.winning_lines().iter().map(squares).collect(),
+            opponent_wins_at: mv.hand.and_then(|p| scratch.winning_cell(&p)).map(square),
+        })
+    }
+
+    /* Every complete move for the current turn, cells first in board order */
+    pub fn legal_moves(&self) -> Vec<Move> {
+        if self.status() != GameStatus::InProgress {
+            return Vec::new();
+        }
+        let hands = |place| {
+            self.free_pieces.iter().map(move |p| Move {
+                place,
+                hand: Some(*p),
+            })
+        };
+        let Some(p) = self.next_piece else {
+            return hands(None).collect();
+        };
+        let mut moves = Vec::new();
+        for (x, y) in self.empty_cells() {
+            if self.ends_at(x, y, &p) {
+                moves.push(Move {
+                    place: Some(square((x, y))),
+                    hand: None,
+                });
+            } else {
+                moves.extend(hands(Some(square((x, y)))));
+            }
+        }
+        moves
+    }
+
+    /* Plays a move; false, leaving the game as it was, when it is not legal */
+    pub fn apply_move(&mut self, mv: &Move) -> bool {
+        if !self.legal_moves().contains(mv) {
+            return false;
+        }
+        self.play_legal(mv);
+        true
+    }
+
+    /* Plays a move taken from legal_moves without checking it again */
+    pub fn play_legal(&mut self, mv: &Move) {
+        if let Some(at) = mv.place {
+            let (x, y) = at.xy();
+            self.move_piece(x, y);
+        }
+        if let Some(p) = mv.hand {
+            self.pick_piece(&p);
+        }
+        self.check_invariants(true);
+    }
+
+    /* Number of move sequences of the given length, for checking move generation */
+    #[cfg(test)]
+    pub fn perft(&self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        moves
+            .iter()
+            .map(|mv| {
+                let mut next = self.clone();
+                next.apply_move(mv);
+                next.perft(depth - 1)
+            })
+            .sum()
+    }
+}
+
+/* Compact binary encoding, 13 bytes:
+   byte 0      format version (BINARY_VERSION)
+   byte 1      rules flags, bit 0 set for the advanced variant
+   bytes 2-11  the 16 cells, 5 bits each, little-endian, cell (x, y) at bit 5 * (4x + y)
+   byte 12     piece in hand
+   A cell or hand value is 0 when empty, otherwise Piece::to_index + 1.
+   Free pieces are whatever is neither on the board nor in hand.
+*/
+pub const BINARY_VERSION: u8 = 1;
+const BINARY_LEN: usize = 13;
+const FLAG_ADVANCED: u8 = 1;
+
+fn decode_error(offset: usize, reason: String) -> QuartoError {
+    QuartoError::DecodeError { offset, reason }
+}
+
+fn encode_cell(cell: &CellState) -> u8 {
+    cell.map_or(0, |p| p.to_index() + 1)
+}
+
+fn decode_cell(offset: usize, value: u8) -> Result<CellState, QuartoError> {
+    match value {
+        0 => Ok(None),
+        1..=16 => Ok(Piece::from_index(value - 1)),
+        _ => Err(decode_error(
+            offset,
+            format!("invalid piece value {}", value),
+        )),
+    }
+}
+
+impl Quarto {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let flags = match self.rules.variant {
+            Variant::Classic => 0,
+            Variant::Advanced => FLAG_ADVANCED,
+        };
+        let mut cells: u128 = 0;
+        for x in 0..4 {
+            for y in 0..4 {
+                cells |= (encode_cell(&self.board_state.0[x][y]) as u128) << (5 * (4 * x + y));
+            }
+        }
+        let mut bytes = vec![BINARY_VERSION, flags];
+        bytes.extend_from_slice(&cells.to_le_bytes()[..10]);
+        bytes.push(encode_cell(&self.next_piece));
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Quarto, QuartoError> {
+        match bytes.first() {
+            Some(&BINARY_VERSION) => {}
+            Some(version) => {
+                return Err(decode_error(0, format!("unsupported version {}", version)));
+            }
+            None => return Err(decode_error(0, "empty input".to_string())),
+        }
+        if bytes.len() != BINARY_LEN {
+            return Err(decode_error(
+                bytes.len().min(BINARY_LEN),
+                format!("expected {} bytes, found {}", BINARY_LEN, bytes.len()),
+            ));
+        }
+        let variant = match bytes[1] {
+            0 => Variant::Classic,
+            FLAG_ADVANCED => Variant::Advanced,
+            flags => {
+                return Err(decode_error(
+                    1,
+                    format!("unknown rules flags {:#04x}", flags),
+                ))
+            }
+        };
+        let mut quarto = Quarto::with_rules(Rules {
+            variant,
+            ..Rules::default()
+        });
+
+        let mut cells = [0u8; 16];
+        cells[..10].copy_from_slice(&bytes[2..12]);
+        let cells = u128::from_le_bytes(cells);
+        for x in 0..4 {
+            for y in 0..4 {
+                let bit = 5 * (4 * x + y);
+                let value = (cells >> bit & 0x1f) as u8;
+                if let Some(p) = decode_cell(2 + bit / 8, value)? {
+                    if !quarto.free_pieces.contains(&p) {
+                        return Err(decode_error(
+                            2 + bit / 8,
+                            format!("duplicate piece {}", String::from(p)),
+                        ));
+                    }
+                    quarto.free_pieces.retain(|pc| *pc != p);
+                    quarto.board_state.0[x][y] = Some(p);
+                }
+            }
+        }
+        if let Some(p) = decode_cell(12, bytes[12])? {
+            if !quarto.pick_piece(&p) {
+                return Err(decode_error(
+                    12,
+                    format!("piece in hand {} is on the board", String::from(p)),
+                ));
+            }
+        }
+        Ok(quarto)
+    }
+}
+
+impl Piece {
+    /* e.g. "white tall circle with a hole" */
+    pub fn describe(&self) -> String {
+        let color = match self.color {
+            Color::Brown => "brown",
+            Color::White => "white",
+        };
+        let height = match self.height {
+            Height::Short => "short",
+            Height::Tall => "tall",
+        };
+        let shape = match self.shape {
+            Shape::Circle => "circle",
+            Shape::Square => "square",
+        };
+        let top = match self.top {
+            Top::Flat => "with a flat top",
+            Top::Hole => "with a hole",
+        };
+        format!("{} {} {} {}", color, height, shape, top)
+    }
+}
+
+/* The pieces that would complete a line already holding these three */
+fn completing_pieces(pieces: &[Piece]) -> Vec<&'static str> {
+    let first = pieces[0];
+    let mut phrases = Vec::new();
+    if pieces.iter().all(|p| p.color == first.color) {
+        phrases.push(match first.color {
+            Color::Brown => "any brown piece",
+            Color::White => "any white piece",
+        });
+    }
+    if pieces.iter().all(|p| p.height == first.height) {
+        phrases.push(match first.height {
+            Height::Short => "any short piece",
+            Height::Tall => "any tall piece",
+        });
+    }
+    if pieces.iter().all(|p| p.shape == first.shape) {
+        phrases.push(match first.shape {
+            Shape::Circle => "any circular piece",
+            Shape::Square => "any square piece",
+        });
+    }
+    if pieces.iter().all(|p| p.top == first.top) {
+        phrases.push(match first.top {
+            Top::Flat => "any flat-topped piece",
+            Top::Hole => "any piece with a hole",
+        });
+    }
+    phrases
+}
+
+impl Quarto {
+    /* Prose instead of a grid, one statement per line, in board order */
+    pub fn describe(&self) -> String {
+        let mut lines = Vec::new();
+        for x in 0..4 {
+            for y in 0..4 {
+                if let Some(p) = &self.board_state.0[x][y] {
+                    lines.push(format!("{}: {}", square((x, y)), p.describe()));
+                }
+            }
+        }
+        let hand = self.next_piece.map_or("none".to_string(), |p| p.describe());
+        lines.push(format!("piece in hand: {}", hand));
+        lines.push(format!("pieces remaining: {}", self.free_pieces.len()));
+
+        let mut threats: BTreeMap<(usize, usize), Vec<&'static str>> = BTreeMap::new();
+        for report in self.line_reports() {
+            let present: Vec<Piece> = report.pieces.iter().flatten().copied().collect();
+            if present.len() != 3 {
+                continue;
+            }
+            let Some(i) = report.pieces.iter().position(|c| c.is_none()) else {
+                continue;
+            };
+            for phrase in completing_pieces(&present) {
+                let phrases = threats.entry(report.line[i]).or_default();
+                if !phrases.contains(&phrase) {
+                    phrases.push(phrase);
+                }
+            }
+        }
+        let threats: Vec<String> = threats
+            .iter()
+            .map(|((x, y), phrases)| format!("{} with {}", square((*x, *y)), phrases.join(" or ")))
+            .collect();
+        if threats.is_empty() {
+            lines.push("threats: none".to_string());
+        } else {
+            lines.push(format!("threats: {}", threats.join("; ")));
+        }
+        lines.join("\n")
+    }
+}
+
+const BASE64URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+fn base64url_encode(bytes: &[u8]) -> String {
+    let mut code = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            code.push(BASE64URL[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    code
+}
+
+fn base64url_decode(code: &str) -> Result<Vec<u8>, QuartoError> {
+    let mut bytes = Vec::new();
+    let chars: Vec<char> = code.chars().collect();
+    for (c, chunk) in chars.chunks(4).enumerate() {
+        if chunk.len() == 1 {
+            return Err(decode_error(4 * c, "truncated share code".to_string()));
+        }
+        let mut n = 0u32;
+        for (i, ch) in chunk.iter().enumerate() {
+            let Some(v) = BASE64URL.iter().position(|b| *b as char == *ch) else {
+                return Err(decode_error(
+                    4 * c + i,
+                    format!("invalid character {:?}", ch),
+                ));
+            };
+            n |= (v as u32) << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            bytes.push((n >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+fn fletcher16(bytes: &[u8]) -> [u8; 2] {
+    let (mut sum1, mut sum2) = (0u16, 0u16);
+    for b in bytes {
+        sum1 = (sum1 + *b as u16) % 255;
+        sum2 = (sum2 + sum1) % 255;
+    }
+    [sum2 as u8, sum1 as u8]
+}
+
+impl Quarto {
+    /* Share codes are the binary encoding plus a Fletcher-16 checksum in URL-safe
+       base64 without padding: 15 bytes make exactly 20 characters. Codes handed to
+       players are stamped with RULES_REVISION between the two; events and the
+       position index keep unstamped codes, which compare equal whatever revision
+       wrote them.
+    */
+    pub fn to_share_code(&self) -> String {
+        share_code(self.to_bytes())
+    }
+
+    pub fn to_stamped_share_code(&self) -> String {
+        let mut bytes = self.to_bytes();
+        bytes.push(RULES_REVISION);
+        share_code(bytes)
+    }
+
+    pub fn from_share_code(code: &str) -> Result<Quarto, QuartoError> {
+        Quarto::read_share_code(code).map(|(quarto, _)| quarto)
+    }
+
+    /* The position and, for a stamped code, the rules revision it was made under.
+       Offsets in errors are characters for malformed text, bytes otherwise.
+    */
+    pub fn read_share_code(code: &str) -> Result<(Quarto, Option<u8>), QuartoError> {
+        let mut bytes = base64url_decode(code.trim())?;
+        if bytes.len() < 2 {
+            return Err(decode_error(0, "share code too short".to_string()));
+        }
+        let checksum = bytes.split_off(bytes.len() - 2);
+        if checksum != fletcher16(&bytes) {
+            return Err(decode_error(bytes.len(), "checksum mismatch".to_string()));
+        }
+        let revision = if bytes.len() == BINARY_LEN + 1 {
+            bytes.pop()
+        } else {
+            None
+        };
+        Ok((Quarto::from_bytes(&bytes)?, revision))
+    }
+}
+
+fn share_code(mut bytes: Vec<u8>) -> String {
+    let checksum = fletcher16(&bytes);
+    bytes.extend_from_slice(&checksum);
+    base64url_encode(&bytes)
+}
+
+#[cfg(test)]
 mod test {
     use super::*;
     use indoc::indoc;
+
+    #[test]
+    fn test_board_new() {
+        let quarto = Quarto::new();
+        assert_eq!(quarto.board_state.0.len(), 4);
+        assert_eq!(quarto.board_state.0[0].len(), 4);
+        assert_eq!(quarto.board_state.0[1].len(), 4);
+        assert_eq!(quarto.board_state.0[2].len(), 4);
+        assert_eq!(quarto.board_state.0[3].len(), 4);
+        assert_eq!(quarto.board_state.0[0][0], None);
+        assert_eq!(quarto.free_pieces.len(), 16);
+    }
+
+    #[test]
+    fn test_parse_board_from_to() {
+        /* WB TS SC HF */
+        let board_text = indoc! {
+        r#"BSCF BSCH BSSF BSSH
+           BTCF BTCH BTSF BTSH
+           WSCF WSCH WSSF WSSH
+           WTCF WTCH WTSF WTSH"#};
+
+        let quarto = Quarto::try_from(&board_text.to_string()).ok();
+        let board_text2: String = BoardState::from(quarto.unwrap().board_state).into();
+        assert_eq!(board_text, board_text2)
+    }
+
+    #[test]
+    fn test_parse_all_pieces() {
+        /* WB TS SC HF */
+        let board_text = indoc! {
+        r#"BSCF BSCH BSSF BSSH
+               BTCF BTCH BTSF BTSH
+               WSCF WSCH WSSF WSSH
+               WTCF WTCH WTSF WTSH"#};
+
+        let quarto = Quarto::try_from(&board_text.to_string()).ok();
+        assert_ne!(quarto, None);
+
+        let expected = vec![
+            vec![
+                Some(Piece {
+                    color: Color::Brown,
+                    height: Height::Short,
+                    shape: Shape::Circle,
+                    top: Top::Flat,
+                }),
+                Some(Piece {
+                    color: Color::Brown,
+                    height: Height::Short,
+                    shape: Shape::Circle,
+                    top: Top::Hole,
+                }),
+                Some(Piece {
+                    color: Color::Brown,
+                    height: Height::Short,
+                    shape: Shape::Square,
+                    top: Top::Flat,
+                }),
+                Some(Piece {
+                    color: Color::Brown,
+                    height: Height::Short,
+                    shape: Shape::Square,
+                    top: Top::Hole,
+                }),
+            ],
+            vec![
+                Some(Piece {
+                    color: Color::Brown,
+                    height: Height::Tall,
+                    shape: Shape::Circle,
+                    top: Top::Flat,
+                }),
+                Some(Piece {
+                    color: Color::Brown,
+                    height: Height::Tall,
+                    shape: Shape::Circle,
+                    top: Top::Hole,
+                }),
+                Some(Piece {
+                    color: Color::Brown,
+                    height: Height::Tall,
+                    shape: Shape::Square,
+                    top: Top::Flat,
+                }),
+                Some(Piece {
+                    color: Color::Brown,
+                    height: Height::Tall,
+                    shape: Shape::Square,
+                    top: Top::Hole,
+                }),
+            ],
+            vec![
+                Some(Piece {
+                    color: Color::White,
+                    height: Height::Short,
+                    shape: Shape::Circle,
+                    top: Top::Flat,
+                }),
+                Some(Piece {
+                    color: Color::White,
+                    height: Height::Short,
+                    shape: Shape::Circle,
+                    top: Top::Hole,
+                }),
+                Some(Piece {
+                    color: Color::White,
+                    height: Height::Short,
+                    shape: Shape::Square,
+                    top: Top::Flat,
+                }),
+                Some(Piece {
+                    color: Color::White,
+                    height: Height::Short,
+                    shape: Shape::Square,
+                    top: Top::Hole,
+                }),
+            ],
+            vec![
+                Some(Piece {
+                    color: Color::White,
+                    height: Height::Tall,
+                    shape: Shape::Circle,
+                    top: Top::Flat,
+                }),
+                Some(Piece {
+                    color: Color::White,
+                    height: Height::Tall,
+                    shape: Shape::Circle,
+                    top: Top::Hole,
+                }),
+                Some(Piece {
+                    color: Color::White,
+                    height: Height::Tall,
+                    shape: Shape::Square,
+                    top: Top::Flat,
+                }),
+                Some(Piece {
+                    color: Color::White,
+                    height: Height::Tall,
+                    shape: Shape::Square,
+                    top: Top::Hole,
+                }),
+            ],
+        ];
+        assert_eq!(expected, quarto.clone().unwrap().board_state.0);
+        assert_eq!(quarto.unwrap().free_pieces.len(), 0)
+    }
+    #[test]
+    fn test_use_a_piece_multiple_times() {
+        /* WB TS SC HF */
+        let board_text = indoc! {
+        r#"BSCF BSCH BSSF BSSH
+           BTCF BTCH BTSF BTSH
+           WSCF WSCH WSSF WSSH
+           WTCF WTCH WTSF BSCF"#};
+
+        let quarto = Quarto::try_from(&board_text.to_string()).ok();
+        assert_eq!(quarto, None)
+    }
+    #[test]
+    fn test_empty_board() {
+        let board_text = indoc! {
+        r#"
+                 ---- ---- ---- ----
+                 ---- ---- ---- ----
+                 ---- ---- ---- ----
+                 ---- ---- ---- ----"#};
+
+        let quarto = Quarto::try_from(&board_text.to_string()).ok();
+        let expected = vec![
+            vec![None, None, None, None],
+            vec![None, None, None, None],
+            vec![None, None, None, None],
+            vec![None, None, None, None],
+        ];
+        assert_eq!(expected, quarto.clone().unwrap().board_state.0);
+        assert_eq!(quarto.unwrap().free_pieces.len(), 16);
+    }
+
+    #[test]
+    fn test_is_quarto() {
+        let board_text = indoc! {
+        r#"BSCF BSCH BSSF WTSH
+           ---- ---- ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#};
+
+        let quarto = &mut Quarto::try_from(&board_text.to_string()).unwrap();
+        let no_quarto = quarto.is_quarto();
+        assert!(!no_quarto);
+
+        let board_texts = vec![
+            indoc! {
+            r#"BSCF BSCH BSSF BTSH
+                   ---- ---- ---- ----
+                   ---- ---- ---- ----
+                   ---- ---- ---- ----"#},
+            indoc! {
+            r#"---- ---- ---- ----
+                   BSCF BSCH BSSF BTSH
+                   ---- ---- ---- ----
+                   ---- ---- ---- ----"#},
+            indoc! {
+                r#"---- ---- ---- ----
+                   ---- ---- ---- ----
+                   BSCF BSCH BSSF BTSH
+                   ---- ---- ---- ----"#
+            },
+            indoc! {
+                r#"---- ---- ---- ----
+                   ---- ---- ---- ----
+                   ---- ---- ---- ----
+                   BSCF BSCH BSSF BTSH"#
+            },
+            indoc! {
+                r#"BSCF ---- ---- ----
+                   ---- BSCH ---- ----
+                   ---- ---- BSSF ----
+                   ---- ---- ---- BTSH"#
+            },
+            indoc! {
+                r#"---- ---- ---- BTSH
+                   ---- ---- BSSF ----
+                   ---- BSCH ---- ----
+                   BTCF ---- ---- ----"#
+
+            },
+            indoc! {
+                r#"BSCF ---- ---- ----
+                   BSCH ---- ---- ----
+                   BSSF ---- ---- ----
+                   BTSH ---- ---- ----"#
+
+            },
+            indoc! {
+                r#"---- BSCF ---- ----
+                   ---- BSCH ---- ----
+                   ---- BSSF ---- ----
+                   ---- BTSH ---- ----"#
+
+            },
+            indoc! {
+                r#"---- ---- BSCF ----
+                   ---- ---- BSCH ----
+                   ---- ---- BSSF ----
+                   ---- ---- BTSH ----"#
+
+            },
+            indoc! {
+                r#"---- ---- ---- BSCF
+                   ---- ---- ---- BSCH
+                   ---- ---- ---- BSSF
+                   ---- ---- ---- BTSH"#
+
+            },
+        ];
+
+        for board_text in board_texts {
+            let quarto = &mut Quarto::try_from(&board_text.to_string()).unwrap();
+            let yes_quarto = quarto.is_quarto();
+
+            assert!(yes_quarto);
+        }
+    }
+
+    #[test]
+    fn test_pick_and_move() {
+        let board_text = indoc! {
+        r#"
+               ---- ---- ---- ----
+               ---- ---- ---- ----
+               ---- ---- ---- ----
+               ---- ---- ---- ----"#};
+
+        let quarto = &mut Quarto::try_from(&board_text.to_string()).unwrap();
+        let expected: Vec<Vec<Option<Piece>>> = vec![
+            vec![None, None, None, None],
+            vec![None, None, None, None],
+            vec![None, None, None, None],
+            vec![None, None, None, None],
+        ];
+        assert_eq!(expected, quarto.board_state.0);
+
+        let bscf = Piece {
+            color: Color::Brown,
+            height: Height::Short,
+            shape: Shape::Circle,
+            top: Top::Flat,
+        };
+
+        let succeess = quarto.pick_piece(&bscf);
+        assert_eq!(succeess, true);
+        let fail = quarto.pick_piece(&bscf);
+        assert_eq!(fail, false);
+        let success = quarto.move_piece(0, 0);
+        assert_eq!(success, true);
+
+        let expected = vec![
+            vec![
+                Some(Piece {
+                    color: Color::Brown,
+                    height: Height::Short,
+                    shape: Shape::Circle,
+                    top: Top::Flat,
+                }),
+                None,
+                None,
+                None,
+            ],
+            vec![None, None, None, None],
+            vec![None, None, None, None],
+            vec![None, None, None, None],
+        ];
+        assert_eq!(expected, quarto.board_state.0);
+
+        let bssf = Piece {
+            color: Color::Brown,
+            height: Height::Short,
+            shape: Shape::Square,
+            top: Top::Flat,
+        };
+        let success = quarto.pick_piece(&bssf);
+        assert!(success);
+        let success = quarto.move_piece(0, 2);
+        assert!(success);
+    }
+
+    #[test]
+    fn test_forced_win_holds_against_every_reply() {
+        let board_text = indoc! {
+        r#"BTSF ---- ---- ----
+           ---- BSSF ---- ----
+           ---- ---- ---- WSSH
+           BSSH WTSF ---- ----"#};
+        let mut refuted = Quarto::try_from(&board_text.to_string()).unwrap();
+        assert!(refuted.pick_piece(&Piece::try_from("BSCH".to_string()).unwrap()));
+
+        // Some move leaves a reply after which the next move wins at once
+        let greedy = refuted.legal_moves().into_iter().find(|mv| {
+            let mut next = refuted.clone();
+            next.play_legal(mv);
+            next.has_forced_win(1).is_none()
+                && next.legal_moves().iter().any(|reply| {
+                    let mut after = next.clone();
+                    after.play_legal(reply);
+                    after.has_forced_win(1).is_some()
+                })
+        });
+        assert!(greedy.is_some());
+        // but every such move has a reply which holds
+        assert_eq!(refuted.has_forced_win(3), None);
+        assert_eq!(refuted.search(3).0, Proof::Unclear);
+
+        let board_text = indoc! {
+        r#"---- ---- ---- ----
+           BTSF WSSH ---- ----
+           ---- WSCF ---- WSCH
+           BTCF ---- ---- BTSH"#};
+        let mut forced = Quarto::try_from(&board_text.to_string()).unwrap();
+        assert!(forced.pick_piece(&Piece::try_from("WTCF".to_string()).unwrap()));
+        assert_eq!(forced.has_forced_win(1), None);
+        let mv = forced.has_forced_win(3).unwrap();
+        assert_eq!(mv.to_string(), "a2 BSSH");
+        assert_eq!(forced.has_forced_win(5), Some(mv));
+        forced.play_legal(&mv);
+        for reply in forced.legal_moves() {
+            let mut after = forced.clone();
+            after.play_legal(&reply);
+            assert!(after.has_forced_win(1).is_some(), "{}", reply);
+        }
+        // The same position turned around is found the same way
+        let turned = forced.transformed(Symmetry::Rotate90);
+        assert_eq!(turned.canonical_key(), forced.canonical_key());
+    }
+
+    #[test]
+    fn test_estimate_forced_quarto() {
+        let board_text = indoc! {
+        r#"BSCF BSCH BSSF ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#};
+
+        let quarto = &mut Quarto::try_from(&board_text.to_string()).unwrap();
+        let btsh = Piece::try_from("BTSH".to_string()).unwrap();
+        assert!(quarto.pick_piece(&btsh));
+
+        let estimate = quarto.estimate(200, 42);
+        assert_eq!(estimate.playouts, 200);
+        assert_eq!(estimate.win, 1.0);
+        assert_eq!(estimate.std_error, 0.0);
+        assert_eq!(
+            quarto.best_move(10, 42),
+            Some((
+                Move {
+                    place: "d4".parse().ok(),
+                    hand: None
+                },
+                1.0
+            ))
+        );
+
+        let empty = Quarto::new().estimate(50, 7);
+        assert!((empty.win + empty.draw + empty.loss - 1.0).abs() < 1e-9);
+        assert_eq!(empty, Quarto::new().estimate(50, 7));
+    }
+
+    #[test]
+    fn test_lines_through_cells() {
+        let classic = Rules::default();
+        for x in 0..4 {
+            for y in 0..4 {
+                let corner = (x == 0 || x == 3) && (y == 0 || y == 3);
+                let center = (1..3).contains(&x) && (1..3).contains(&y);
+                let expected = if corner || center { 3 } else { 2 };
+                assert_eq!(classic.lines_through(x, y).count(), expected, "({x}, {y})");
+                for line in classic.lines_through(x, y) {
+                    assert!(line.contains(&(x, y)));
+                }
+            }
+        }
+        assert_eq!(classic.lines().count(), 10);
+
+        let advanced = Rules {
+            variant: Variant::Advanced,
+            ..Rules::default()
+        };
+        assert_eq!(advanced.lines().count(), 19);
+        assert_eq!(advanced.lines_through(0, 0).count(), 3 + 1);
+        assert_eq!(advanced.lines_through(0, 1).count(), 2 + 2);
+        assert_eq!(advanced.lines_through(1, 1).count(), 3 + 4);
+    }
+
+    #[test]
+    fn test_is_quarto_advanced_square() {
+        let board_text = indoc! {
+        r#"BSCF BSCH ---- ----
+           BSSF BTSH ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#};
+
+        let classic = Quarto::try_from(&board_text.to_string()).unwrap();
+        assert!(!classic.is_quarto());
+        let advanced = Quarto {
+            rules: Rules {
+                variant: Variant::Advanced,
+                ..Rules::default()
+            },
+            ..classic
+        };
+        assert!(advanced.is_quarto());
+    }
+
+    #[test]
+    fn test_board_evaluated_without_a_game() {
+        let board = |text: &str| BoardState::try_from(&text.to_string()).unwrap();
+        let classic = Rules::default();
+        let advanced = Rules {
+            variant: Variant::Advanced,
+            ..Rules::default()
+        };
+
+        let square = board(indoc! {
+        r#"BSCF BSCH ---- ----
+           BSSF BTSH ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#});
+        let open = evaluate_board(&square, &classic);
+        assert!(!open.is_quarto() && !open.full);
+        assert_eq!(open.lines.len(), 10);
+        let won = evaluate_board(&square, &advanced);
+        assert_eq!(won.winning_lines, vec![[(0, 0), (0, 1), (1, 0), (1, 1)]]);
+        assert_eq!(won.lines.len(), 19);
+        let report = won.lines.iter().find(|line| line.is_quarto()).unwrap();
+        assert!(report.color && !report.height);
+
+        let row = board(indoc! {
+        r#"BSCF BSCH BSSF WTSH
+           ---- ---- ---- ----
+           ---- ---- ---- ----
+           BTCF BTCH BTSF BTSH"#});
+        let won = evaluate_board(&row, &classic);
+        assert_eq!(won.winning_lines, vec![[(3, 0), (3, 1), (3, 2), (3, 3)]]);
+
+        // Every cell filled and no line shared: the board alone says drawn
+        let full = board(indoc! {
+        r#"BSSF WTSF BSCF WSSH
+           WSCF BSSH BTCF WTCH
+           WSCH BTCH BTSF BSCH
+           BTSH WSSF WTSH WTCF"#});
+        let drawn = evaluate_board(&full, &classic);
+        assert!(drawn.full && !drawn.is_quarto());
+        assert!(!evaluate_board(&Quarto::new().board_state, &classic).full);
+    }
+
+    /* Its allocations are counted in tests/allocations.rs */
+    #[test]
+    fn test_is_quarto_finds_the_winning_line() {
+        let board_text = indoc! {
+        r#"BSCF BSCH BSSF WTSH
+           ---- ---- ---- ----
+           ---- ---- ---- ----
+           BTCF BTCH BTSF BTSH"#};
+        let quarto = Quarto::try_from(&board_text.to_string()).unwrap();
+        assert!(quarto.is_quarto());
+        assert_eq!(
+            quarto.winning_lines(),
+            vec![[(3, 0), (3, 1), (3, 2), (3, 3)]]
+        );
+
+        let mut quarto = quarto;
+        quarto.board_state.0[3][3] = None;
+        assert!(!quarto.is_quarto());
+        assert!(quarto.winning_lines().is_empty());
+    }
+
+    #[test]
+    fn test_validate_names_every_broken_piece() {
+        let piece = |code: &str| code.parse::<Piece>().unwrap();
+        let mut quarto = Quarto::new();
+        quarto.apply_move(&"BSCF".parse().unwrap());
+        quarto.apply_move(&"a1 WTCH".parse().unwrap());
+        assert_eq!(quarto.validate(), Ok(()));
+
+        // Written past the methods which keep every piece in one place
+        quarto.board_state.0[0][0] = Some(piece("WTCH"));
+        quarto.board_state.0[0][1] = Some(piece("BTSH"));
+        quarto.board_state.0[3][0] = Some(piece("BTCF"));
+        quarto.free_pieces.retain(|p| *p != piece("WSSF"));
+        let violations = quarto.validate().unwrap_err();
+        assert_eq!(
+            violations
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "BSCF is neither on the board, in hand nor free",
+                "BTCF is at a1 and free",
+                "BTSH is at b4 and free",
+                "WSSF is neither on the board, in hand nor free",
+                "WTCH is at a4 and in hand",
+            ]
+        );
+        assert_eq!(
+            violations[4],
+            Violation::Duplicated {
+                piece: piece("WTCH"),
+                places: vec!["at a4".to_string(), "in hand".to_string()]
+            }
+        );
+
+        // Stored boards are checked the same way
+        let mut twice = BoardState([[None; 4]; 4]);
+        twice.0[0][0] = Some(piece("BSCF"));
+        twice.0[1][1] = Some(piece("BSCF"));
+        assert!(matches!(
+            Quarto::from_parts(twice, None, Rules::default()),
+            Err(QuartoError::CorruptRecord(reason)) if reason == "BSCF is at a4 and at b3"
+        ));
+    }
+
+    #[test]
+    #[cfg_attr(not(any(debug_assertions, feature = "paranoid")), ignore)]
+    #[should_panic(expected = "broken game: WTCH is at a4 and at b4")]
+    fn test_changing_a_broken_game_panics_when_checked() {
+        let piece = |code: &str| code.parse::<Piece>().unwrap();
+        let mut quarto = Quarto::new();
+        quarto.pick_piece(&piece("WTCH"));
+        quarto.board_state.0[0][0] = Some(piece("WTCH"));
+        quarto.move_piece(0, 1);
+    }
+
+    /* BSCF, BSCH, BSSF and BSSH down the a file, the last placed alone */
+    fn won_down_the_a_file() -> Quarto {
+        let mut quarto = Quarto::new();
+        for ply in ["BSCF", "a1 BSCH", "a2 BSSF", "a3 BSSH", "a4"] {
+            assert!(quarto.apply_move(&ply.parse().unwrap()), "{}", ply);
+        }
+        assert_eq!(quarto.status(), GameStatus::Won);
+        quarto
+    }
+
+    #[test]
+    fn test_validate_play_names_a_hand_after_the_end() {
+        let piece = |code: &str| code.parse::<Piece>().unwrap();
+        let mut quarto = won_down_the_a_file();
+        assert_eq!(quarto.validate_play(), Ok(()));
+
+        // Handed on past pick_piece, and duplicated for good measure
+        quarto.free_pieces.retain(|p| *p != piece("WTCH"));
+        quarto.next_piece = Some(piece("WTCH"));
+        assert_eq!(quarto.validate(), Ok(()));
+        assert_eq!(
+            quarto.validate_play(),
+            Err(vec![Violation::HandedAfterEnd(piece("WTCH"))])
+        );
+        quarto.board_state.0[3][3] = Some(piece("WTCH"));
+        let violations = quarto.validate_play().unwrap_err();
+        assert_eq!(
+            intersperse(&violations, "; ").to_string(),
+            "WTCH is at d1 and in hand; WTCH is in hand although the game is over"
+        );
+    }
+
+    #[test]
+    #[cfg_attr(not(any(debug_assertions, feature = "paranoid")), ignore)]
+    #[should_panic(expected = "broken game: WTCH is in hand although the game is over")]
+    fn test_handing_on_after_the_end_panics_when_checked() {
+        let mut quarto = won_down_the_a_file();
+        // A board filled past its end is no broken game, a move played there is
+        quarto.pick_piece(&"BTCH".parse().unwrap());
+        quarto.move_piece(3, 3);
+        quarto.play_legal(&Move {
+            place: None,
+            hand: Some("WTCH".parse().unwrap()),
+        });
+    }
+
+    /* Its allocations are counted in tests/allocations.rs */
+    #[test]
+    fn test_rendering() {
+        let text =
+            "BSCF ---- BSSF ----\n---- WTCH ---- ----\n---- ---- ---- ----\n---- ---- ---- WTSH";
+        let board = BoardState::try_from(&text.to_string()).unwrap();
+        assert_eq!(board.to_display_string(), text);
+        assert_eq!(String::from(board), text.replace("----", "    "));
+
+        let piece: Piece = "WTCH".parse().unwrap();
+        assert_eq!(String::from(piece), "WTCH");
+        let mv: Move = "b2 WTSH".parse().unwrap();
+        assert_eq!(mv.to_string(), "b2 WTSH");
+    }
+
     #[test]
-    fn test_board_new() {
-        let quarto = Quarto::new();
-        assert_eq!(quarto.board_state.0.len(), 4);
-        assert_eq!(quarto.board_state.0[0].len(), 4);
-        assert_eq!(quarto.board_state.0[1].len(), 4);
-        assert_eq!(quarto.board_state.0[2].len(), 4);
-        assert_eq!(quarto.board_state.0[3].len(), 4);
-        assert_eq!(quarto.board_state.0[0][0], None);
+    fn test_parse_errors_are_positioned() {
+        fn error_at(text: &str) -> (usize, usize) {
+            match BoardState::try_from(&text.to_string()) {
+                Err(QuartoError::ParseError { line, column, .. }) => (line, column),
+                other => panic!("expected a parse error, got {:?}", other),
+            }
+        }
+        let full =
+            "BSCF BSCH BSSF BSSH\nBTCF BTCH BTSF BTSH\nWSCF WSCH WSSF WSSH\nWTCF WTCH WTSF WTSH";
+
+        // Too few and too many lines
+        assert_eq!(error_at("BSCF BSCH BSSF BSSH"), (2, 1));
+        assert_eq!(
+            error_at(&format!("{}\n{}", full, "BSCF BSCH BSSF BSSH")),
+            (5, 1)
+        );
+        // Short and long lines
+        assert_eq!(error_at(&full.replace("BTSH", "BTS")), (2, 16));
+        assert_eq!(error_at(&full.replace("BTSH", "BTSHX")), (2, 20));
+        // Tab instead of a space between cells
+        assert_eq!(error_at(&full.replace("WSCF ", "WSCF\t")), (3, 5));
+        // Multi-byte characters are counted, not sliced
+        assert_eq!(error_at(&full.replace("WTCH", "WT\u{1F600}H")), (4, 6));
+        assert_eq!(
+            error_at(&full.replace("WTCH", "\u{1F600}\u{1F600}\u{1F600}\u{1F600}")),
+            (4, 6)
+        );
+        // Unknown letters and duplicates
+        assert_eq!(error_at(&full.replace("BSSH", "XSSH")), (1, 16));
+        assert_eq!(error_at(&full.replace("WTSH", "BSCF")), (4, 16));
+    }
+
+    #[test]
+    fn test_parse_crlf_board() {
+        let board_text = "BSCF BSCH BSSF BSSH\r\nBTCF BTCH BTSF BTSH\r\nWSCF WSCH WSSF WSSH\r\nWTCF WTCH WTSF WTSH\r\n";
+        let bs = BoardState::try_from(&board_text.to_string()).unwrap();
+        let text: String = bs.into();
+        assert_eq!(text, board_text.replace("\r\n", "\n").trim_end());
+    }
+
+    #[test]
+    fn test_parse_mixed_empty_notations() {
+        let board_text = indoc! {
+        r#"BSCF .... ---- BSSH
+           ---- BTCH
+           ....      WSSF
+           WTCF ---- .... WTSH"#};
+        let bs = BoardState::try_from(&board_text.to_string()).unwrap();
+        let expected = indoc! {
+        r#"BSCF ---- ---- BSSH
+           ---- BTCH ---- ----
+           ---- ---- WSSF ----
+           WTCF ---- ---- WTSH"#};
+        assert_eq!(bs.to_display_string(), expected);
+
+        let canonical: String = bs.clone().into();
+        assert_eq!(canonical, expected.replace("-", " "));
+        assert_eq!(BoardState::try_from(&canonical).unwrap(), bs);
+        assert_eq!(BoardState::try_from(&bs.to_display_string()).unwrap(), bs);
+    }
+
+    #[test]
+    fn test_lenient_warnings_are_strict_errors() {
+        let canonical = [
+            "BSCF BSCH BSSF BSSH",
+            "BTCF               ",
+            "                   ",
+            "WTCF WTCH WTSF WTSH",
+        ]
+        .join("\n");
+        let strict = ParseOptions { strict: true };
+        let (board, warnings) = BoardState::parse(&canonical, strict).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(String::from(board.clone()), canonical);
+
+        let cases = [
+            (
+                canonical.replacen('\n', "\r\n", 1),
+                (1, 20),
+                WarningKind::LineEnding,
+            ),
+            (
+                canonical.replace("BSSH\n", "BSSH \t\n"),
+                (1, 20),
+                WarningKind::TrailingWhitespace,
+            ),
+            (
+                canonical.replace(&format!("{:<19}", "BTCF"), "BTCF"),
+                (2, 5),
+                WarningKind::ShortLine,
+            ),
+            (
+                canonical.replace("WTCH", "wtch"),
+                (4, 6),
+                WarningKind::Lowercase,
+            ),
+            (
+                canonical.replace("BTCF     ", "BTCF ...."),
+                (2, 6),
+                WarningKind::Placeholder,
+            ),
+            (
+                canonical.replace("WTCF WTCH", "WTCF\u{a0}WTCH"),
+                (4, 5),
+                WarningKind::UnusualSpace,
+            ),
+        ];
+        for (text, (line, column), kind) in cases {
+            let (read, warnings) = BoardState::parse(&text, ParseOptions::default()).unwrap();
+            assert_eq!(read, board, "{:?}", text);
+            assert_eq!(
+                warnings,
+                vec![ParseWarning { line, column, kind }],
+                "{:?}",
+                text
+            );
+            match BoardState::parse(&text, strict) {
+                Err(QuartoError::ParseError {
+                    line: l, column: c, ..
+                }) => assert_eq!((l, c), (line, column), "{:?}", text),
+                other => panic!("expected a parse error for {:?}, got {:?}", text, other),
+            }
+        }
+
+        let game = format!("{}\nhand: wssh\nvariant: classic  \n", canonical);
+        let (quarto, warnings) = Quarto::parse(&game, ParseOptions::default()).unwrap();
+        assert_eq!(quarto.next_piece, Some("WSSH".parse().unwrap()));
+        assert_eq!(
+            warnings,
+            vec![
+                ParseWarning {
+                    line: 6,
+                    column: 17,
+                    kind: WarningKind::TrailingWhitespace
+                },
+                ParseWarning {
+                    line: 5,
+                    column: 7,
+                    kind: WarningKind::Lowercase
+                },
+            ]
+        );
+        assert_eq!(warnings[1].to_string(), "line 5, column 7: lowercase piece");
+        assert!(matches!(
+            Quarto::parse(&game, strict),
+            Err(QuartoError::ParseError {
+                line: 6,
+                column: 17,
+                ..
+            })
+        ));
+        let game = format!("{}\nhand: wssh\n", canonical);
+        assert!(matches!(
+            Quarto::parse(&game, strict),
+            Err(QuartoError::ParseError {
+                line: 5,
+                column: 7,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_game_text_round_trips() {
+        let mut quarto = Quarto::with_rules(Rules {
+            variant: Variant::Advanced,
+            ..Rules::default()
+        });
+        for ply in ["BSCF", "a1 WTSH", "c3 BTCH"] {
+            assert!(quarto.apply_move(&ply.parse().unwrap()));
+        }
+        let text = quarto.to_string();
+        assert_eq!(
+            text,
+            indoc! {
+            r#"---- ---- ---- ----
+               ---- ---- WTSH ----
+               ---- ---- ---- ----
+               BSCF ---- ---- ----
+               hand: BTCH
+               turn: 2
+               variant: advanced"#}
+        );
+        assert_eq!(Quarto::try_from(&text).unwrap(), quarto);
+        // A trailing newline, blank lines and spaces around values are fine
+        let loose = text.replace("hand: BTCH", "\nhand:BTCH  ") + "\n";
+        assert_eq!(Quarto::try_from(&loose).unwrap(), quarto);
+
+        let opening = Quarto::new();
+        assert_eq!(Quarto::try_from(&opening.to_string()).unwrap(), opening);
+        assert!(opening
+            .to_string()
+            .ends_with("hand: none\nturn: 2\nvariant: classic"));
+    }
+
+    #[test]
+    fn test_game_text_optional_lines() {
+        let board = indoc! {
+        r#"BSCF ---- ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#}
+        .to_string();
+        let bare = Quarto::try_from(&board).unwrap();
+        assert_eq!(bare.next_piece, None);
+        assert_eq!(bare.rules, Rules::default());
+        assert_eq!(bare.placed_pieces(), 1);
+
+        let hand_only = Quarto::try_from(&format!("{}\nhand: WTCH", board)).unwrap();
+        assert_eq!(
+            hand_only.next_piece,
+            Some(Piece::try_from("WTCH".to_string()).unwrap())
+        );
+        assert_eq!(hand_only.rules, Rules::default());
+        let variant_only = Quarto::try_from(&format!("{}\nvariant: advanced", board)).unwrap();
+        assert_eq!(variant_only.next_piece, None);
+        assert_eq!(variant_only.rules.variant, Variant::Advanced);
+        let turn_only = Quarto::try_from(&format!("{}\nturn: 1", board)).unwrap();
+        assert_eq!(turn_only, bare);
+    }
+
+    #[test]
+    fn test_game_text_errors() {
+        let board = "BSCF ---- ---- ----\n\n\n";
+        let error_at = |tail: &str| match Quarto::try_from(&format!("{}\n{}", board, tail)) {
+            Err(QuartoError::ParseError { line, column, .. }) => (line, column),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(error_at("colour: white"), (5, 1));
+        assert_eq!(error_at("hand: WTCH\n\nplayer: 2"), (7, 1));
+        assert_eq!(error_at("hand WTCH"), (5, 1));
+        assert_eq!(error_at("hand: WTCH\nhand: WTSH"), (6, 1));
+        assert_eq!(error_at("hand: WXCH"), (5, 7));
+        assert_eq!(error_at("hand:  BSCF"), (5, 8));
+        assert_eq!(error_at("variant: huge"), (5, 10));
+        assert_eq!(error_at("turn: 2"), (5, 7));
+        assert_eq!(error_at("turn: third"), (5, 7));
+        // The board is still checked first
+        let short = Quarto::try_from(&"BSCF\nhand: WTCH".to_string());
+        assert!(matches!(
+            short,
+            Err(QuartoError::ParseError { line: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn test_line_reports() {
+        let board_text = indoc! {
+        r#"BSCF BSCH BSSF BTSH
+           ---- WTCH ---- ----
+           ---- ---- WSSH ----
+           ---- ---- ---- WTSF"#};
+        let quarto = Quarto::try_from(&board_text.to_string()).unwrap();
+        let reports = quarto.line_reports();
+        assert_eq!(reports.len(), 10);
+        assert_eq!(
+            reports[0].to_string(),
+            "(0,0) (0,1) (0,2) (0,3) BSCF BSCH BSSF BTSH: color, quarto"
+        );
+        assert_eq!(
+            reports[1].to_string(),
+            "(1,0) (1,1) (1,2) (1,3) ---- WTCH ---- ----: none"
+        );
+        assert_eq!(
+            reports[8].to_string(),
+            "(0,0) (1,1) (2,2) (3,3) BSCF WTCH WSSH WTSF: none"
+        );
+    }
+
+    #[test]
+    fn test_explain() {
+        let board_text = indoc! {
+        r#"BSCF WTSH ---- ----
+           BTCH WSSH ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#};
+        let quarto = Quarto::try_from(&board_text.to_string()).unwrap();
+        let explanation = quarto.explain();
+        assert_eq!(explanation.lines.len(), 10);
+        let blocked = &explanation.lines[0];
+        assert!(blocked.is_dead());
+        assert!(!blocked.is_quarto());
+        use PropertyState::*;
+        assert_eq!(
+            explanation.lines[1].properties,
+            [Blocked, Blocked, Blocked, Open]
+        );
+        assert!(!explanation.lines[1].is_dead());
+        assert_eq!(explanation.lines[3].properties, [Open; 4]);
+        let table = explanation.to_string();
+        let rows: Vec<&str> = table.lines().collect();
+        assert_eq!(rows.len(), 11);
+        assert_eq!(
+            rows[0].trim_end(),
+            "line         pieces               color    height   shape    top"
+        );
+        assert_eq!(
+            rows[2].trim_end(),
+            "a3 b3 c3 d3  BTCH WSSH ---- ----  blocked  blocked  blocked  open"
+        );
+
+        let mut full = quarto.clone();
+        let board_text = indoc! {
+        r#"BSCF BSCH BSSF BTSH
+           ---- ---- ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#};
+        full.board_state = BoardState::try_from(&board_text.to_string()).unwrap();
+        let line = &full.explain().lines[0];
+        assert_eq!(line.properties, [Matched, Blocked, Blocked, Blocked]);
+        assert!(line.is_quarto());
+    }
+
+    #[cfg(feature = "setup")]
+    #[test]
+    fn test_place_arbitrary() {
+        let mut quarto = Quarto::new();
+        let bscf = Piece::try_from("BSCF".to_string()).unwrap();
+        assert!(quarto.place_arbitrary(&bscf, 1, 2));
+        assert_eq!(quarto.board_state.0[1][2], Some(bscf));
+        assert_eq!(quarto.free_pieces.len(), 15);
+        assert_eq!(quarto.next_piece, None);
+        // Pieces stay unique and cells stay single
+        assert!(!quarto.place_arbitrary(&bscf, 0, 0));
+        let bsch = Piece::try_from("BSCH".to_string()).unwrap();
+        assert!(!quarto.place_arbitrary(&bsch, 1, 2));
+        assert!(!quarto.place_arbitrary(&bsch, 4, 0));
+
+        assert!(quarto.set_hand(Some(bsch)));
+        assert!(!quarto.set_hand(Some(bscf)));
+        assert_eq!(quarto.remove_arbitrary(1, 2), Some(bscf));
+        assert_eq!(quarto.remove_arbitrary(1, 2), None);
+        assert!(quarto.set_hand(Some(bscf)));
+        assert_eq!(quarto.next_piece, Some(bscf));
+        assert!(quarto.set_hand(None));
         assert_eq!(quarto.free_pieces.len(), 16);
     }
 
     #[test]
-    fn test_parse_board_from_to() {
-        /* WB TS SC HF */
+    fn test_board_transforms() {
         let board_text = indoc! {
-        r#"BSCF BSCH BSSF BSSH
-           BTCF BTCH BTSF BTSH
-           WSCF WSCH WSSF WSSH
-           WTCF WTCH WTSF WTSH"#};
+        r#"BSCF BSCH ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- WTSH"#};
+        let bs = BoardState::try_from(&board_text.to_string()).unwrap();
+        assert_eq!(
+            bs.rotate90().to_display_string(),
+            indoc! {
+            r#"---- ---- ---- BSCF
+               ---- ---- ---- BSCH
+               ---- ---- ---- ----
+               WTSH ---- ---- ----"#}
+        );
+        assert_eq!(
+            bs.mirror_horizontal().to_display_string(),
+            indoc! {
+            r#"---- ---- BSCH BSCF
+               ---- ---- ---- ----
+               ---- ---- ---- ----
+               WTSH ---- ---- ----"#}
+        );
+        assert_eq!(
+            bs.mirror_vertical().to_display_string(),
+            indoc! {
+            r#"---- ---- ---- WTSH
+               ---- ---- ---- ----
+               ---- ---- ---- ----
+               BSCF BSCH ---- ----"#}
+        );
+        assert_eq!(bs.rotate90().rotate90(), bs.rotate180());
+        assert_eq!(bs.rotate180().rotate90(), bs.rotate270());
+        assert_eq!(bs.rotate270().rotate90(), bs);
+        assert_eq!(bs.transpose().transpose(), bs);
+        assert_eq!(
+            bs.transform(Symmetry::AntiTranspose),
+            bs.transpose().rotate180()
+        );
+    }
 
-        let quarto = Quarto::try_from(&board_text.to_string()).ok();
-        let board_text2: String = BoardState::from(quarto.unwrap().board_state).into();
-        assert_eq!(board_text, board_text2)
+    #[test]
+    fn test_is_quarto_invariant_under_symmetries() {
+        let mut rng = PlayoutRng(2024);
+        for variant in [Variant::Classic, Variant::Advanced] {
+            for _ in 0..200 {
+                let mut quarto = Quarto::with_rules(Rules {
+                    variant,
+                    ..Rules::default()
+                });
+                for _ in 0..rng.below(17) {
+                    let p = quarto.free_pieces[rng.below(quarto.free_pieces.len())];
+                    let cells = quarto.empty_cells();
+                    let (x, y) = cells[rng.below(cells.len())];
+                    quarto.pick_piece(&p);
+                    quarto.move_piece(x, y);
+                }
+                let mut lines: Vec<Line> = quarto.winning_lines();
+                lines.iter_mut().for_each(|l| l.sort());
+                lines.sort();
+                for symmetry in Symmetry::iter() {
+                    let transformed = quarto.transformed(symmetry);
+                    assert_eq!(transformed.is_quarto(), quarto.is_quarto());
+
+                    let mut mapped: Vec<Line> = lines
+                        .iter()
+                        .map(|l| {
+                            let mut m = l.map(|(x, y)| symmetry.map(x, y));
+                            m.sort();
+                            m
+                        })
+                        .collect();
+                    mapped.sort();
+                    let mut expected = transformed.winning_lines();
+                    expected.iter_mut().for_each(|l| l.sort());
+                    expected.sort();
+                    assert_eq!(mapped, expected);
+                }
+            }
+        }
     }
 
     #[test]
-    fn test_parse_all_pieces() {
-        /* WB TS SC HF */
+    fn test_piece_serde() {
+        let bscf = Piece::try_from("BSCF".to_string()).unwrap();
+        assert_eq!(serde_json::to_string(&bscf).unwrap(), r#""BSCF""#);
+        assert_eq!(serde_json::from_str::<Piece>(r#""BSCF""#).unwrap(), bscf);
+        assert_eq!(serde_json::from_str::<Piece>(r#""bscf""#).unwrap(), bscf);
+        assert!(serde_json::from_str::<Piece>(r#""XSCF""#).is_err());
+        assert!(serde_json::from_str::<Piece>(r#""BSC""#).is_err());
+
+        // The struct form written before pieces were serialized as codes
+        let old = r#"{"color":"Brown","height":"Short","shape":"Circle","top":"Flat"}"#;
+        assert_eq!(serde_json::from_str::<Piece>(old).unwrap(), bscf);
+    }
+
+    #[test]
+    fn test_board_state_serde() {
         let board_text = indoc! {
-        r#"BSCF BSCH BSSF BSSH
-               BTCF BTCH BTSF BTSH
-               WSCF WSCH WSSF WSSH
-               WTCF WTCH WTSF WTSH"#};
+        r#"BSCF ---- ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- WTSH"#};
+        let mut quarto = Quarto::try_from(&board_text.to_string()).unwrap();
+        let json = serde_json::to_string(&quarto.board_state).unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"[["BSCF",null,null,null],[null,null,null,null],"#,
+                r#"[null,null,null,null],[null,null,null,"WTSH"]]"#
+            )
+        );
+        assert_eq!(
+            serde_json::from_str::<BoardState>(&json).unwrap(),
+            quarto.board_state
+        );
 
-        let quarto = Quarto::try_from(&board_text.to_string()).ok();
-        assert_ne!(quarto, None);
+        quarto.pick_piece(&Piece::try_from("WTCF".to_string()).unwrap());
+        let json = serde_json::to_string(&quarto).unwrap();
+        assert_eq!(serde_json::from_str::<Quarto>(&json).unwrap(), quarto);
+    }
 
-        let expected = vec![
-            vec![
-                Some(Piece {
-                    color: Color::Brown,
-                    height: Height::Short,
-                    shape: Shape::Circle,
-                    top: Top::Flat,
-                }),
-                Some(Piece {
-                    color: Color::Brown,
-                    height: Height::Short,
-                    shape: Shape::Circle,
-                    top: Top::Hole,
-                }),
-                Some(Piece {
-                    color: Color::Brown,
-                    height: Height::Short,
-                    shape: Shape::Square,
-                    top: Top::Flat,
-                }),
-                Some(Piece {
-                    color: Color::Brown,
-                    height: Height::Short,
-                    shape: Shape::Square,
-                    top: Top::Hole,
-                }),
-            ],
-            vec![
-                Some(Piece {
-                    color: Color::Brown,
-                    height: Height::Tall,
-                    shape: Shape::Circle,
-                    top: Top::Flat,
-                }),
-                Some(Piece {
-                    color: Color::Brown,
-                    height: Height::Tall,
-                    shape: Shape::Circle,
-                    top: Top::Hole,
-                }),
-                Some(Piece {
-                    color: Color::Brown,
-                    height: Height::Tall,
-                    shape: Shape::Square,
-                    top: Top::Flat,
-                }),
-                Some(Piece {
-                    color: Color::Brown,
-                    height: Height::Tall,
-                    shape: Shape::Square,
-                    top: Top::Hole,
-                }),
-            ],
-            vec![
-                Some(Piece {
-                    color: Color::White,
-                    height: Height::Short,
-                    shape: Shape::Circle,
-                    top: Top::Flat,
-                }),
-                Some(Piece {
-                    color: Color::White,
-                    height: Height::Short,
-                    shape: Shape::Circle,
-                    top: Top::Hole,
-                }),
-                Some(Piece {
-                    color: Color::White,
-                    height: Height::Short,
-                    shape: Shape::Square,
-                    top: Top::Flat,
-                }),
-                Some(Piece {
-                    color: Color::White,
-                    height: Height::Short,
-                    shape: Shape::Square,
-                    top: Top::Hole,
-                }),
-            ],
-            vec![
-                Some(Piece {
-                    color: Color::White,
-                    height: Height::Tall,
-                    shape: Shape::Circle,
-                    top: Top::Flat,
-                }),
-                Some(Piece {
-                    color: Color::White,
-                    height: Height::Tall,
-                    shape: Shape::Circle,
-                    top: Top::Hole,
-                }),
-                Some(Piece {
-                    color: Color::White,
-                    height: Height::Tall,
-                    shape: Shape::Square,
-                    top: Top::Flat,
-                }),
-                Some(Piece {
-                    color: Color::White,
-                    height: Height::Tall,
-                    shape: Shape::Square,
-                    top: Top::Hole,
-                }),
-            ],
-        ];
-        assert_eq!(expected, quarto.clone().unwrap().board_state.0);
-        assert_eq!(quarto.unwrap().free_pieces.len(), 0)
+    #[test]
+    fn test_piece_index() {
+        for (i, p) in all_pieces().iter().enumerate() {
+            assert_eq!(Piece::from_index(p.to_index()), Some(*p), "{}", i);
+        }
+        assert_eq!(
+            Piece::from_index(0),
+            Some(Piece::try_from("BSCF".to_string()).unwrap())
+        );
+        assert_eq!(
+            Piece::from_index(15),
+            Some(Piece::try_from("WTSH".to_string()).unwrap())
+        );
+        assert_eq!(Piece::from_index(16), None);
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let mut rng = PlayoutRng(7);
+        for variant in [Variant::Classic, Variant::Advanced] {
+            for _ in 0..50 {
+                // Every ply of a random game, both with and without a piece in hand
+                let mut quarto = Quarto::with_rules(Rules {
+                    variant,
+                    ..Rules::default()
+                });
+                loop {
+                    let bytes = quarto.to_bytes();
+                    assert_eq!(bytes.len(), 13);
+                    assert_eq!(bytes[0], BINARY_VERSION);
+                    assert_eq!(Quarto::from_bytes(&bytes).unwrap(), quarto);
+                    if quarto.free_pieces.is_empty() && quarto.next_piece.is_none() {
+                        break;
+                    }
+                    if quarto.next_piece.is_none() {
+                        let p = quarto.free_pieces[rng.below(quarto.free_pieces.len())];
+                        quarto.pick_piece(&p);
+                    } else {
+                        let cells = quarto.empty_cells();
+                        let (x, y) = cells[rng.below(cells.len())];
+                        quarto.move_piece(x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_binary_rejects_invalid_input() {
+        fn offset(bytes: &[u8]) -> usize {
+            match Quarto::from_bytes(bytes) {
+                Err(QuartoError::DecodeError { offset, .. }) => offset,
+                other => panic!("expected a decode error, got {:?}", other),
+            }
+        }
+        let mut quarto = Quarto::new();
+        let bscf = Piece::try_from("BSCF".to_string()).unwrap();
+        quarto.pick_piece(&bscf);
+        quarto.move_piece(0, 0);
+        let bytes = quarto.to_bytes();
+
+        assert_eq!(offset(&[]), 0);
+        assert_eq!(offset(&[BINARY_VERSION + 1]), 0);
+        assert_eq!(offset(&bytes[..12]), 12);
+        assert_eq!(offset(&[bytes.clone(), vec![0]].concat()), 13);
+
+        let mut flags = bytes.clone();
+        flags[1] = 0x80;
+        assert_eq!(offset(&flags), 1);
+
+        // Cell (0, 1) holding 31, which is no piece
+        let mut cell = bytes.clone();
+        cell[2] |= 0xe0;
+        cell[3] |= 0x03;
+        assert_eq!(offset(&cell), 2);
+
+        // BSCF both on (0, 0) and (0, 1)
+        let mut duplicate = bytes.clone();
+        duplicate[2] |= 1 << 5;
+        assert_eq!(offset(&duplicate), 2);
+
+        // BSCF both on (0, 0) and in hand
+        let mut hand = bytes.clone();
+        hand[12] = 1;
+        assert_eq!(offset(&hand), 12);
+    }
+
+    #[test]
+    fn test_share_code_round_trip() {
+        let mut rng = PlayoutRng(11);
+        for variant in [Variant::Classic, Variant::Advanced] {
+            for _ in 0..20 {
+                let mut quarto = Quarto::with_rules(Rules {
+                    variant,
+                    ..Rules::default()
+                });
+                loop {
+                    let code = quarto.to_share_code();
+                    assert_eq!(code.len(), 20);
+                    assert!(code
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+                    assert_eq!(Quarto::from_share_code(&code).unwrap(), quarto);
+                    if quarto.free_pieces.is_empty() && quarto.next_piece.is_none() {
+                        break;
+                    }
+                    if quarto.next_piece.is_none() {
+                        let p = quarto.free_pieces[rng.below(quarto.free_pieces.len())];
+                        quarto.pick_piece(&p);
+                    } else {
+                        let cells = quarto.empty_cells();
+                        let (x, y) = cells[rng.below(cells.len())];
+                        quarto.move_piece(x, y);
+                    }
+                }
+            }
+        }
     }
+
     #[test]
-    fn test_use_a_piece_multiple_times() {
-        /* WB TS SC HF */
-        let board_text = indoc! {
-        r#"BSCF BSCH BSSF BSSH
-           BTCF BTCH BTSF BTSH
-           WSCF WSCH WSSF WSSH
-           WTCF WTCH WTSF BSCF"#};
+    fn test_share_code_rejects_tampering() {
+        let mut quarto = Quarto::with_rules(Rules {
+            variant: Variant::Advanced,
+            ..Rules::default()
+        });
+        quarto.pick_piece(&Piece::try_from("WTSH".to_string()).unwrap());
+        quarto.move_piece(2, 1);
+        quarto.pick_piece(&Piece::try_from("BSCF".to_string()).unwrap());
+        let code = quarto.to_share_code();
+        let bytes = base64url_decode(&code).unwrap();
+        for bit in 0..bytes.len() * 8 {
+            let mut flipped = bytes.clone();
+            flipped[bit / 8] ^= 1 << (bit % 8);
+            assert!(
+                Quarto::from_share_code(&base64url_encode(&flipped)).is_err(),
+                "bit {}",
+                bit
+            );
+        }
+        assert!(Quarto::from_share_code(&code[..19]).is_err());
+        assert!(Quarto::from_share_code(&code[..16]).is_err());
+        assert!(Quarto::from_share_code(&code.replace(&code[..1], "+")).is_err());
+        assert!(Quarto::from_share_code("").is_err());
+    }
 
-        let quarto = Quarto::try_from(&board_text.to_string()).ok();
-        assert_eq!(quarto, None)
+    #[test]
+    fn test_stamped_share_codes_carry_the_rules_revision() {
+        let mut quarto = Quarto::new();
+        quarto.pick_piece(&Piece::try_from("WTSH".to_string()).unwrap());
+        let stamped = quarto.to_stamped_share_code();
+        assert_eq!(stamped.len(), 22);
+        assert_eq!(
+            Quarto::read_share_code(&stamped).unwrap(),
+            (quarto.clone(), Some(RULES_REVISION))
+        );
+        assert_eq!(
+            Quarto::read_share_code(&quarto.to_share_code()).unwrap(),
+            (quarto.clone(), None)
+        );
+        assert!(Quarto::from_share_code(&stamped[..21]).is_err());
+
+        // A code from before the first revision, as an older build would stamp it
+        let mut bytes = quarto.to_bytes();
+        bytes.push(0);
+        let older = Quarto::read_share_code(&share_code(bytes)).unwrap();
+        assert_eq!(older, (quarto, Some(0)));
+        assert_eq!(
+            revision_mismatch(older.1).unwrap(),
+            format!(
+                "recorded under rules revision 0, replaying under {}",
+                RULES_REVISION
+            )
+        );
+        assert_eq!(revision_mismatch(Some(RULES_REVISION)), None);
+        assert_eq!(revision_mismatch(None), None);
     }
+
     #[test]
-    fn test_empty_board() {
-        let dummy_text = indoc! {
-        /* - will be replaced to space */
-        r#"
-                 ---- ---- ---- ----
-                 ---- ---- ---- ----
-                 ---- ---- ---- ----
-                 ---- ---- ---- ----"#};
-        let board_text = dummy_text.replace("-", " ");
+    fn test_engine_version_names_the_rules_revision() {
+        assert_eq!(rules_revision(&engine_version()), Some(RULES_REVISION));
+        assert_eq!(rules_revision("0.1.0+rules.0"), Some(0));
+        assert_eq!(rules_revision("0.1.0"), None);
+    }
 
-        let quarto = Quarto::try_from(&board_text.to_string()).ok();
-        let expected = vec![
-            vec![None, None, None, None],
-            vec![None, None, None, None],
-            vec![None, None, None, None],
-            vec![None, None, None, None],
-        ];
-        assert_eq!(expected, quarto.clone().unwrap().board_state.0);
-        assert_eq!(quarto.unwrap().free_pieces.len(), 16);
+    #[test]
+    fn test_base64url() {
+        assert_eq!(base64url_encode(b""), "");
+        assert_eq!(base64url_encode(b"f"), "Zg");
+        assert_eq!(base64url_encode(b"fo"), "Zm8");
+        assert_eq!(base64url_encode(b"foo"), "Zm9v");
+        assert_eq!(base64url_encode(&[0xfb, 0xff]), "-_8");
+        assert_eq!(base64url_decode("Zm9vYg").unwrap(), b"foob");
+        assert_eq!(base64url_decode("-_8").unwrap(), vec![0xfb, 0xff]);
     }
 
     #[test]
-    fn test_is_quarto() {
-        let dummy_text = indoc! {
-        /* - will be replaced to space */
-        r#"BSCF BSCH BSSF WTSH
+    fn test_describe() {
+        let board_text = indoc! {
+        r#"BSCF BSCH BSSF BTSH
+           ---- WTCH ---- ----
+           ---- ---- WSSH ----
+           ---- ---- ---- WTSF"#};
+        let quarto = Quarto::try_from(&board_text.to_string()).unwrap();
+        assert_eq!(
+            quarto.describe(),
+            indoc! {"
+            a4: brown short circle with a flat top
+            b4: brown short circle with a hole
+            c4: brown short square with a flat top
+            d4: brown tall square with a hole
+            b3: white tall circle with a hole
+            c2: white short square with a hole
+            d1: white tall square with a flat top
+            piece in hand: none
+            pieces remaining: 9
+            threats: none"}
+        );
+
+        let board_text = indoc! {
+        r#"WTCH ---- WTSH BTCH
            ---- ---- ---- ----
+           WSCF ---- ---- ----
+           WTCF ---- ---- ----"#};
+        let mut quarto = Quarto::try_from(&board_text.to_string()).unwrap();
+        quarto.pick_piece(&Piece::try_from("BTSF".to_string()).unwrap());
+        assert_eq!(
+            quarto.describe(),
+            indoc! {"
+            a4: white tall circle with a hole
+            c4: white tall square with a hole
+            d4: brown tall circle with a hole
+            a2: white short circle with a flat top
+            a1: white tall circle with a flat top
+            piece in hand: brown tall square with a flat top
+            pieces remaining: 10
+            threats: b4 with any tall piece or any piece with a hole; a3 with any white piece or any circular piece"}
+        );
+
+        let empty = Quarto::new();
+        assert_eq!(
+            empty.describe(),
+            "piece in hand: none\npieces remaining: 16\nthreats: none"
+        );
+    }
+
+    #[test]
+    fn test_describe_advanced_squares() {
+        let board_text = indoc! {
+        r#"BSCF BTCF ---- ----
+           WSCF ---- ---- ----
            ---- ---- ---- ----
            ---- ---- ---- ----"#};
-        let board_text = dummy_text.replace("-", " ");
+        let mut quarto = Quarto::with_rules(Rules {
+            variant: Variant::Advanced,
+            ..Rules::default()
+        });
+        quarto.board_state = BoardState::try_from(&board_text.to_string()).unwrap();
+        let description = quarto.describe();
+        assert!(
+            description.ends_with("threats: b3 with any circular piece or any flat-topped piece"),
+            "{}",
+            description
+        );
+        quarto.rules = Rules::default();
+        assert!(quarto.describe().ends_with("threats: none"));
+    }
 
-        let quarto = &mut Quarto::try_from(&board_text.to_string()).unwrap();
-        let no_quarto = quarto.is_quarto();
-        assert!(!no_quarto);
+    #[test]
+    fn test_legal_moves() {
+        let mut quarto = Quarto::new();
+        // The opening turn only hands over a piece
+        assert_eq!(quarto.legal_moves().len(), 16);
+        assert!(quarto.legal_moves().iter().all(|mv| mv.place.is_none()));
+        quarto.pick_piece(&Piece::try_from("BSCF".to_string()).unwrap());
+        assert_eq!(quarto.legal_moves().len(), 16 * 15);
+        assert_eq!(Quarto::new().perft(2), 16 * 16 * 15);
 
-        let dummy_texts = vec![
-            indoc! {
-            r#"BSCF BSCH BSSF BTSH
-                   ---- ---- ---- ----
-                   ---- ---- ---- ----
-                   ---- ---- ---- ----"#},
-            indoc! {
-            r#"---- ---- ---- ----
-                   BSCF BSCH BSSF BTSH
-                   ---- ---- ---- ----
-                   ---- ---- ---- ----"#},
-            indoc! {
-                r#"---- ---- ---- ----
-                   ---- ---- ---- ----
-                   BSCF BSCH BSSF BTSH
-                   ---- ---- ---- ----"#
-            },
-            indoc! {
-                r#"---- ---- ---- ----
-                   ---- ---- ---- ----
-                   ---- ---- ---- ----
-                   BSCF BSCH BSSF BTSH"#
-            },
-            indoc! {
-                r#"BSCF ---- ---- ----
-                   ---- BSCH ---- ----
-                   ---- ---- BSSF ----
-                   ---- ---- ---- BTSH"#
-            },
-            indoc! {
-                r#"---- ---- ---- BTSH
-                   ---- ---- BSSF ----
-                   ---- BSCH ---- ----
-                   BTCF ---- ---- ----"#
+        // Placing BTSH on d4 wins, so that move hands nothing over
+        let mut threat = Quarto::try_from(
+            &indoc! {
+                         r#"BSCF BSCH BSSF ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#}
+            .to_string(),
+        )
+        .unwrap();
+        threat.pick_piece(&Piece::try_from("BTSH".to_string()).unwrap());
+        let moves = threat.legal_moves();
+        assert_eq!(moves.len(), 1 + 12 * 12);
+        let winning = Move {
+            place: "d4".parse().ok(),
+            hand: None,
+        };
+        assert_eq!(moves[0], winning);
+        assert_eq!(winning.to_string(), "d4");
+        assert!(moves[1].to_string().starts_with("a3 "));
+        threat.rules.variant = Variant::Advanced;
+        assert_eq!(threat.legal_moves().len(), 1 + 12 * 12);
+        threat.rules = Rules::default();
 
-            },
-            indoc! {
-                r#"BSCF ---- ---- ----
-                   BSCH ---- ---- ----
-                   BSSF ---- ---- ----
-                   BTSH ---- ---- ----"#
+        let mut won = threat.clone();
+        assert!(won.apply_move(&winning));
+        assert!(won.legal_moves().is_empty());
+        assert!(!threat.clone().apply_move(&Move {
+            place: "a4".parse().ok(),
+            hand: None,
+        }));
 
-            },
-            indoc! {
-                r#"---- BSCF ---- ----
-                   ---- BSCH ---- ----
-                   ---- BSSF ---- ----
-                   ---- BTSH ---- ----"#
+        // The last placement on a drawn board
+        let mut last = Quarto::try_from(
+            &indoc! {
+                         r#"BSSF WTSF BSCF WSSH
+           WSCF BSSH BTCF WTCH
+           WSCH BTCH BTSF BSCH
+           BTSH WSSF WTSH ----"#}
+            .to_string(),
+        )
+        .unwrap();
+        last.pick_piece(&Piece::try_from("WTCF".to_string()).unwrap());
+        assert_eq!(
+            last.legal_moves(),
+            vec![Move {
+                place: "d1".parse().ok(),
+                hand: None,
+            }]
+        );
+        assert_eq!((last.perft(1), last.perft(2)), (1, 0));
 
-            },
-            indoc! {
-                r#"---- ---- BSCF ----
-                   ---- ---- BSCH ----
-                   ---- ---- BSSF ----
-                   ---- ---- BTSH ----"#
+        for quarto in [Quarto::new(), threat, last] {
+            assert_eq!(quarto.perft(1), quarto.legal_moves().len() as u64);
+        }
+    }
 
-            },
-            indoc! {
-                r#"---- ---- ---- BSCF
-                   ---- ---- ---- BSCH
-                   ---- ---- ---- BSSF
-                   ---- ---- ---- BTSH"#
+    #[test]
+    fn test_cell_heat() {
+        // Row 4 needs any brown piece at d4; row 3 holds pieces differing on everything
+        let board_text = indoc! {
+        r#"BSCF BSCH BTSF ----
+           BTCH WSSF ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#};
+        let quarto = Quarto::try_from(&board_text.to_string()).unwrap();
+        let heat = quarto.cell_heat();
+        assert_eq!(heat[0], [0, 0, 0, 128]);
+        assert_eq!(heat[1], [0, 0, 8, 0]);
+        // Columns a and b and the long diagonal hold two pieces each, column c one
+        assert_eq!(heat[2], [32, 32, 32 + 8, 0]);
+        assert_eq!(heat[3], [32, 32, 8, 32]);
+        assert_eq!(heat, quarto.cell_heat());
 
-            },
-        ];
+        // d1 completes both column d and the long diagonal
+        let board_text = indoc! {
+        r#"BSCF ---- ---- BSCH
+           ---- BTCF ---- BTSH
+           ---- ---- BSSF BSSH
+           ---- ---- ---- ----"#};
+        let quarto = Quarto::try_from(&board_text.to_string()).unwrap();
+        let heat = quarto.cell_heat();
+        assert_eq!(heat[3][3], 255);
+        assert_eq!(heat[3][0], 8 + 8);
+        assert_eq!(heat[0][0], 0);
+    }
 
-        for bt in dummy_texts {
-            let board_text = bt.replace("-", " ");
+    #[test]
+    fn test_double_threats() {
+        // WTCF on a1 leaves d1 winning with any white piece and a4 with any tall
+        // one, and the brown short pieces are still there to hand over
+        let board_text = indoc! {
+        r#"---- ---- ---- ----
+           BTCF ---- ---- ----
+           BTSH ---- ---- ----
+           ---- WSSH WSSF ----"#};
+        let mut quarto = Quarto::try_from(&board_text.to_string()).unwrap();
+        let wtcf = Piece::try_from("WTCF".to_string()).unwrap();
+        assert!(quarto.pick_piece(&wtcf));
+        let a1 = square((3, 0)).xy();
+        assert!(quarto.double_threats(&wtcf).contains(&a1));
+        let threats: Vec<String> = quarto
+            .threats_after(a1.0, a1.1, &wtcf)
+            .iter()
+            .map(|threat| threat.to_string())
+            .collect();
+        assert_eq!(
+            threats,
+            vec!["d1 with any white piece", "a4 with any tall piece"]
+        );
+        assert!(quarto.double_threats(&wtcf).iter().all(|(x, y)| {
+            let mut after = quarto.clone();
+            after.move_piece(*x, *y);
+            !after.safe_pieces().is_empty()
+        }));
 
-            let quarto = &mut Quarto::try_from(&board_text.to_string()).unwrap();
-            let yes_quarto = quarto.is_quarto();
+        // The same two lines, but with the brown short pieces on the board one
+        // needs white and the other tall pieces between them take every piece
+        // left, so a1 would hand the opponent a win
+        let board_text = indoc! {
+        r#"---- BSCH ---- BSSH
+           BTCF ---- BSCF ----
+           BTSH ---- ---- BSSF
+           ---- WSSH WSSF ----"#};
+        let mut quarto = Quarto::try_from(&board_text.to_string()).unwrap();
+        assert!(quarto.pick_piece(&wtcf));
+        assert_eq!(quarto.threats_after(a1.0, a1.1, &wtcf).len(), 2);
+        assert!(!quarto.double_threats(&wtcf).contains(&a1));
+    }
 
-            assert!(yes_quarto);
+    #[test]
+    fn test_game_rng_repeats_choices() {
+        // A random bot playing a whole game from the game's seed
+        let random_game = |rng: GameRng| {
+            let mut quarto = Quarto::new();
+            let mut played = Vec::new();
+            while quarto.status() == GameStatus::InProgress {
+                let moves = quarto.legal_moves();
+                let mv = moves[rng.rng_for(&quarto).below(moves.len())];
+                assert!(quarto.apply_move(&mv));
+                played.push(mv);
+            }
+            (played, quarto)
+        };
+        let (played, quarto) = random_game(GameRng(2154));
+        assert_eq!(random_game(GameRng(2154)), (played.clone(), quarto));
+        assert_ne!(random_game(GameRng(2155)).0, played);
+
+        // The same history reached again, here from its share code, draws the same
+        let rng = GameRng(2154);
+        let mut midgame = Quarto::new();
+        for mv in &played[..5] {
+            midgame.apply_move(mv);
         }
+        let rebuilt = Quarto::from_share_code(&midgame.to_share_code()).unwrap();
+        assert_eq!(rng.seed_for(&rebuilt), rng.seed_for(&midgame));
+        assert_eq!(
+            rebuilt.best_move(10, rng.seed_for(&rebuilt)),
+            midgame.best_move(10, rng.seed_for(&midgame))
+        );
+        // Each move draws afresh, the opening hand included
+        let mut opened = Quarto::new();
+        opened.apply_move(&played[0]);
+        assert_ne!(rng.seed_for(&opened), rng.seed_for(&Quarto::new()));
+        let mut after = midgame.clone();
+        after.apply_move(&played[5]);
+        assert_ne!(rng.seed_for(&after), rng.seed_for(&midgame));
     }
 
     #[test]
-    fn test_pick_and_move() {
-        let dummy_text = indoc! {
-        /* - will be replaced to space */
-        r#"
-               ---- ---- ---- ----
-               ---- ---- ---- ----
-               ---- ---- ---- ----
-               ---- ---- ---- ----"#};
-        let board_text = dummy_text.replace("-", " ");
+    fn test_hot_positions() {
+        // The top row wins with any brown or short piece, the next with any
+        // square or holed one, which leaves WTCF as the only safe piece
+        let one_safe = Quarto::try_from(
+            &indoc! {
+                         r#"BSCF BSCH BSSF ----
+           WTSH BTSH WSSH ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#}
+            .to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            one_safe.safe_pieces(),
+            vec![Piece::try_from("WTCF".to_string()).unwrap()]
+        );
+        assert!(!one_safe.is_hot());
+        assert!(!one_safe.every_piece_loses());
 
-        let quarto = &mut Quarto::try_from(&board_text.to_string()).unwrap();
-        let expected: Vec<Vec<Option<Piece>>> = vec![
-            vec![None, None, None, None],
-            vec![None, None, None, None],
-            vec![None, None, None, None],
-            vec![None, None, None, None],
-        ];
-        assert_eq!(expected, quarto.board_state.0);
+        // Now the second row wins with any tall piece, so nothing is safe
+        let mut none_safe = Quarto::try_from(
+            &indoc! {
+                         r#"BSCF BSCH BSSF ----
+           WTSF BTCH WTCH ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#}
+            .to_string(),
+        )
+        .unwrap();
+        assert!(none_safe.safe_pieces().is_empty());
+        assert!(none_safe.is_hot());
+        assert!(none_safe.every_piece_loses());
+        // Holding a piece which wins is no zugzwang
+        none_safe.pick_piece(&Piece::try_from("BTSH".to_string()).unwrap());
+        assert!(!none_safe.every_piece_loses());
 
-        let bscf = Piece {
-            color: Color::Brown,
-            height: Height::Short,
-            shape: Shape::Circle,
-            top: Top::Flat,
+        let mut rng = PlayoutRng(11);
+        for variant in [Variant::Classic, Variant::Advanced] {
+            for _ in 0..50 {
+                let mut quarto = Quarto::with_rules(Rules {
+                    variant,
+                    ..Rules::default()
+                });
+                while !quarto.free_pieces.is_empty() && !quarto.is_quarto() {
+                    assert_eq!(quarto.is_hot(), quarto.safe_pieces().is_empty());
+                    let p = quarto.free_pieces[rng.below(quarto.free_pieces.len())];
+                    quarto.pick_piece(&p);
+                    let cells = quarto.empty_cells();
+                    let (x, y) = cells[rng.below(cells.len())];
+                    quarto.move_piece(x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_dead_positions_end_the_game_only_when_adjudicated() {
+        // WTCH on c3 leaves tall pieces winning the second row and brown or short
+        // ones the first, so every piece left wins for its receiver
+        let mut before = Quarto::try_from(
+            &indoc! {
+                         r#"BSCF BSCH BSSF ----
+           WTSF BTCH ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#}
+            .to_string(),
+        )
+        .unwrap();
+        let wtch = Piece::try_from("WTCH".to_string()).unwrap();
+        before.pick_piece(&wtch);
+        let dead = square((1, 2));
+        let mut after = before.clone();
+        after.move_piece(1, 2);
+        assert!(after.is_dead_position());
+        assert!(!before.is_dead_position());
+
+        // As published, the player must still hand over a piece
+        let handed = Move {
+            place: Some(dead),
+            hand: Some(Piece::try_from("BTSH".to_string()).unwrap()),
         };
+        let placed = Move {
+            place: Some(dead),
+            hand: None,
+        };
+        assert_eq!(after.status(), GameStatus::InProgress);
+        assert_eq!(after.legal_moves().len(), after.free_pieces.len());
+        assert!(before.legal_moves().contains(&handed));
+        assert!(!before.legal_moves().contains(&placed));
+        assert_eq!(before.rule_breaks(&placed), vec![RuleBreak::MustHandOver]);
+        let classic_moves = before.perft(2);
 
-        let succeess = quarto.pick_piece(&bscf);
-        assert_eq!(succeess, true);
-        let fail = quarto.pick_piece(&bscf);
-        assert_eq!(fail, false);
-        let success = quarto.move_piece(0, 0);
-        assert_eq!(success, true);
+        for adjudication in [Adjudication::Loss, Adjudication::Draw] {
+            let rules = Rules {
+                dead_position_adjudication: Some(adjudication),
+                ..before.rules
+            };
+            let mut adjudicated = before.clone();
+            adjudicated.rules = rules;
+            assert!(adjudicated.legal_moves().contains(&placed));
+            assert!(!adjudicated.legal_moves().contains(&handed));
+            assert_eq!(
+                adjudicated.rule_breaks(&handed),
+                vec![RuleBreak::NothingToHandOver]
+            );
+            let preview = adjudicated.check_move(&placed).unwrap();
+            assert_eq!(preview.status, GameStatus::Adjudicated(adjudication));
+            assert!(adjudicated.apply_move(&placed));
+            assert!(adjudicated.legal_moves().is_empty());
+            assert!(adjudicated.perft(2) < classic_moves);
+        }
 
-        let expected = vec![
-            vec![
-                Some(Piece {
-                    color: Color::Brown,
-                    height: Height::Short,
-                    shape: Shape::Circle,
-                    top: Top::Flat,
-                }),
-                None,
-                None,
-                None,
-            ],
-            vec![None, None, None, None],
-            vec![None, None, None, None],
-            vec![None, None, None, None],
-        ];
-        assert_eq!(expected, quarto.board_state.0);
+        // Placed there, the game is lost for the placer, so the search avoids it
+        let mut lost = before.clone();
+        lost.rules.dead_position_adjudication = Some(Adjudication::Loss);
+        lost.play_legal(&placed);
+        assert_eq!(lost.search(2).0, Proof::Win);
+        assert_eq!(after.search(2).0, Proof::Loss);
+    }
 
-        let bssf = Piece {
-            color: Color::Brown,
-            height: Height::Short,
-            shape: Shape::Square,
-            top: Top::Flat,
+    #[test]
+    fn test_check_move_previews_without_playing() {
+        let mut quarto = Quarto::try_from(
+            &indoc! {
+                      r#"BSCF BSCH BSSF ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#}
+            .to_string(),
+        )
+        .unwrap();
+        quarto.pick_piece(&Piece::try_from("WTCF".to_string()).unwrap());
+        let before = quarto.clone();
+        let bytes = quarto.to_bytes();
+        let piece = |code: &str| Some(Piece::try_from(code.to_string()).unwrap());
+
+        // Handing over a brown piece lets the opponent finish the top row
+        let preview = quarto
+            .check_move(&Move {
+                place: "d1".parse().ok(),
+                hand: piece("BTSH"),
+            })
+            .unwrap();
+        assert_eq!(preview.status, GameStatus::InProgress);
+        assert!(preview.winning_lines.is_empty());
+        assert_eq!(preview.opponent_wins_at, "d4".parse().ok());
+        assert_eq!(
+            preview.to_string(),
+            "result: in progress\nhands over a win at d4"
+        );
+
+        let preview = quarto
+            .check_move(&Move {
+                place: "d1".parse().ok(),
+                hand: piece("WTSH"),
+            })
+            .unwrap();
+        assert_eq!(preview.opponent_wins_at, None);
+
+        let illegal = Move {
+            place: "a4".parse().ok(),
+            hand: piece("WTSH"),
         };
-        let success = quarto.pick_piece(&bssf);
-        assert!(success);
-        let success = quarto.move_piece(0, 2);
-        assert!(success);
+        match quarto.check_move(&illegal) {
+            Err(QuartoError::BrokenRules(broken)) => {
+                assert_eq!(broken.to_string(), "a4 WTSH: a4 is occupied")
+            }
+            other => panic!("expected broken rules, got {:?}", other),
+        }
+        let unplaced = Move {
+            place: None,
+            hand: piece("BSCF"),
+        };
+        assert_eq!(
+            quarto.rule_breaks(&unplaced),
+            [
+                RuleBreak::MustPlace,
+                RuleBreak::NotFree(piece("BSCF").unwrap())
+            ]
+        );
+        assert_eq!(
+            Quarto::new().rule_breaks(&"a4".parse().unwrap()),
+            [RuleBreak::NothingToPlace, RuleBreak::MustHandOver]
+        );
+        assert_eq!(quarto, before);
+        assert_eq!(quarto.to_bytes(), bytes);
+
+        let mut last = Quarto::try_from(
+            &indoc! {
+                      r#"BSSF WTSF BSCF WSSH
+           WSCF BSSH BTCF WTCH
+           WSCH BTCH BTSF BSCH
+           BTSH WSSF WTSH ----"#}
+            .to_string(),
+        )
+        .unwrap();
+        last.pick_piece(&Piece::try_from("WTCF".to_string()).unwrap());
+        let preview = last
+            .check_move(&Move {
+                place: "d1".parse().ok(),
+                hand: None,
+            })
+            .unwrap();
+        assert_eq!(preview.status, GameStatus::Drawn);
+        assert_eq!(last.status(), GameStatus::InProgress);
+    }
+
+    #[test]
+    fn test_from_parts() {
+        let board = BoardState::try_from(
+            &indoc! {
+                    r#"BSCF ---- ---- ----
+           ---- WTCH ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#}
+            .to_string(),
+        )
+        .unwrap();
+        let piece = |code: &str| Piece::try_from(code.to_string()).unwrap();
+        let rules = Rules {
+            variant: Variant::Advanced,
+            ..Rules::default()
+        };
+
+        let quarto = Quarto::from_parts(board.clone(), Some(piece("WSSF")), rules).unwrap();
+        let mut expected = Quarto::with_rules(rules);
+        expected.pick_piece(&piece("BSCF"));
+        expected.move_piece(0, 0);
+        expected.pick_piece(&piece("WTCH"));
+        expected.move_piece(1, 1);
+        expected.pick_piece(&piece("WSSF"));
+        assert_eq!(quarto, expected);
+        assert_eq!(quarto.legal_moves().len(), 14 * 13);
+
+        let finished = Quarto::from_parts(board.clone(), None, rules).unwrap();
+        assert_eq!(finished.next_piece, None);
+        assert!(matches!(
+            Quarto::from_parts(board, Some(piece("WTCH")), rules),
+            Err(QuartoError::CorruptRecord(reason))
+                if reason == "piece in hand WTCH is also on the board"
+        ));
+    }
+
+    #[test]
+    fn test_parse_piece_and_coord() {
+        assert_eq!(
+            "WTCH".parse::<Piece>().unwrap(),
+            Piece::try_from("WTCH".to_string()).unwrap()
+        );
+        for invalid in ["", "WTC", "WTCHX", "XTCH", "wtch"] {
+            assert!(invalid.parse::<Piece>().is_err(), "{}", invalid);
+        }
+        assert_eq!("b3".parse::<Coord>().unwrap(), Coord::new(1, 2).unwrap());
+        assert_eq!("B3".parse::<Coord>().unwrap().to_string(), "b3");
+        for invalid in ["e1", "a0", "a5", "3", "b", ""] {
+            assert!(
+                matches!(invalid.parse::<Coord>(), Err(QuartoError::OutOfRange)),
+                "{}",
+                invalid
+            );
+        }
+        for text in ["WTCH", "c3", "c3 WTCH"] {
+            assert_eq!(text.parse::<Move>().unwrap().to_string(), text);
+        }
+        // The deprecated x y form names the same squares
+        assert_eq!("1 2 WTCH".parse::<Move>().unwrap().to_string(), "c3 WTCH");
+        for invalid in [
+            "",
+            "1",
+            "1 2 3",
+            "4 0 WTCH",
+            "c3 1",
+            "e5 WTCH",
+            "1 2 WTCH x",
+        ] {
+            assert!(invalid.parse::<Move>().is_err(), "{}", invalid);
+        }
+    }
+    #[test]
+    fn test_squares_match_the_board_text() {
+        let board_text = indoc! {
+        r#"---- ---- ---- WTCH
+           ---- ---- ---- ----
+           ---- ---- ---- ----
+           BSCF ---- ---- ----"#};
+        let board = BoardState::try_from(&board_text.to_string()).unwrap();
+        let at = |name: &str| {
+            let (x, y) = name.parse::<Coord>().unwrap().xy();
+            board.cells()[x][y].map(String::from)
+        };
+        assert_eq!(at("a1").as_deref(), Some("BSCF"));
+        assert_eq!(at("d4").as_deref(), Some("WTCH"));
+        assert_eq!(at("a4"), None);
+        assert_eq!(
+            board.to_labelled_string(),
+            indoc! {"
+            4 ---- ---- ---- WTCH
+            3 ---- ---- ---- ----
+            2 ---- ---- ---- ----
+            1 BSCF ---- ---- ----
+              a    b    c    d"}
+        );
+
+        // Moves typed either way land on the cell the board text names
+        for typed in ["a1 WTCH", "3 0 WTCH"] {
+            let mut quarto = Quarto::new();
+            quarto.pick_piece(&Piece::try_from("BSCF".to_string()).unwrap());
+            assert!(quarto.apply_move(&typed.parse().unwrap()), "{}", typed);
+            assert_eq!(
+                quarto.board_state.to_display_string().lines().last(),
+                Some("BSCF ---- ---- ----")
+            );
+        }
+        for x in 0..4 {
+            for y in 0..4 {
+                let at = Coord::from_xy(x, y).unwrap();
+                assert_eq!(at.xy(), (x, y));
+                assert_eq!(at.to_string().parse::<Coord>().unwrap(), at);
+            }
+        }
+        assert_eq!(Coord::from_xy(4, 0), None);
+
+        // Cells are squares in JSON, and the old [x, y] pairs still read
+        let a1 = Coord::new(0, 0).unwrap();
+        assert_eq!(serde_json::to_string(&a1).unwrap(), r#""a1""#);
+        assert_eq!(serde_json::from_str::<Coord>("[3, 0]").unwrap(), a1);
+        assert!(serde_json::from_str::<Coord>("[4, 0]").is_err());
     }
 }
@@ -0,0 +1,339 @@
+use crate::cache::{self, CachedAnalysis};
+use crate::clock;
+use crate::db_policy::DbPolicy;
+use crate::event::{self, Event, Record};
+use crate::quarto::{Move, Proof, Quarto, QuartoError};
+use sqlx::{Pool, Sqlite};
+use std::error::Error;
+use std::fmt;
+
+/* `replay --evaluate` searches every position of a game and compares what the player
+   could force before each move against what they could force after it. A move
+   which gives any of it up is a blunder: a win let slip, or a game which was not
+   lost thrown away.
+*/
+pub const ENGINE: &str = "search";
+
+/* The moves since the board was last set, each with the position it was played in */
+pub fn plies(records: &[Record]) -> Result<Vec<(Quarto, Move)>, QuartoError> {
+    let mut quarto: Option<Quarto> = None;
+    let mut plies = Vec::new();
+    for record in records {
+        match &record.event {
+            Event::Created { position } | Event::Position { position } => {
+                quarto = Some(Quarto::from_share_code(position)?);
+                plies.clear();
+            }
+            Event::Move { ply } => {
+                let Some(quarto) = quarto.as_mut() else {
+                    return Err(QuartoError::CorruptRecord(format!(
+                        "event {}: move before the game was created",
+                        record.seq
+                    )));
+                };
+                let before = quarto.clone();
+                if !quarto.apply_move(ply) {
+                    return Err(QuartoError::CorruptRecord(format!(
+                        "event {}: illegal move {}",
+                        record.seq, ply
+                    )));
+                }
+                plies.push((before, *ply));
+            }
+            Event::Forfeited { .. }
+            | Event::Resigned { .. }
+            | Event::DrawOffered { .. }
+            | Event::DrawAgreed { .. }
+            | Event::AbortRequested { .. }
+            | Event::Aborted { .. } => {}
+        }
+    }
+    Ok(plies)
+}
+
+/* `of` each move record, lined up with plies() */
+fn per_ply<T>(records: &[Record], of: impl Fn(&Record) -> T) -> Vec<T> {
+    let mut values = Vec::new();
+    for record in records {
+        match &record.event {
+            Event::Created { .. } | Event::Position { .. } => values.clear(),
+            Event::Move { .. } => values.push(of(record)),
+            _ => {}
+        }
+    }
+    values
+}
+
+/* How long each of plies() took, where the events recorded it */
+pub fn think_times(records: &[Record]) -> Vec<Option<i64>> {
+    per_ply(records, |record| record.think_ms)
+}
+
+/* The seat which handed each of plies()' pieces, where the events recorded it */
+pub fn handed_by(records: &[Record]) -> Vec<Option<usize>> {
+    per_ply(records, |record| record.handed_by)
+}
+
+/* The game as it stood after `at` of the moves since the board was last set,
+   with `at` resolved to that count. Negative values count back from the latest
+   position, so -1 is one move ago.
+*/
+pub fn position_at(records: &[Record], at: i64) -> Result<(usize, Quarto), QuartoError> {
+    let plies = plies(records)?;
+    let moves = plies.len();
+    let number = if at < 0 {
+        moves.checked_sub(at.unsigned_abs() as usize)
+    } else {
+        Some(at as usize).filter(|n| *n <= moves)
+    };
+    let Some(number) = number else {
+        return Err(QuartoError::InvalidMoveNumber(format!(
+            "move {}: the game has moves 0 to {}, or -{} to -1",
+            at, moves, moves
+        )));
+    };
+    let quarto = match plies.get(number) {
+        Some((before, _)) => before.clone(),
+        None => event::replay(records)?
+            .ok_or_else(|| QuartoError::CorruptRecord("the game was never created".to_string()))?,
+    };
+    Ok((number, quarto))
+}
+
+/* The search result for the player to move, from the cache when it was searched
+   at least this deep before. The search runs on a blocking thread, so games
+   evaluated side by side search in parallel.
+*/
+pub async fn evaluate(
+    db: &Pool<Sqlite>,
+    policy: &DbPolicy,
+    quarto: &Quarto,
+    depth: u32,
+) -> Result<(Proof, Option<Move>), Box<dyn Error>> {
+    let position = quarto.position_key();
+    let cached = policy
+        .run("look up analysis", || {
+            cache::lookup(db, &position, ENGINE, depth.into())
+        })
+        .await?;
+    if let Some(cached) = cached {
+        let best = cached.best_move.and_then(|mv| mv.parse().ok());
+        return Ok((Proof::from_score(cached.score), best));
+    }
+    let searched = quarto.clone();
+    let (proof, best) = tokio::task::spawn_blocking(move || searched.search(depth))
+        .await
+        .expect("search thread panicked");
+    let analysis = CachedAnalysis {
+        engine: ENGINE.to_string(),
+        depth: depth.into(),
+        score: proof.score(),
+        best_move: best.as_ref().map(Move::to_string),
+    };
+    policy
+        .run("cache analysis", || cache::store(db, &position, &analysis))
+        .await?;
+    Ok((proof, best))
+}
+
+/* Every move of `plies` annotated with searches `depth` moves deep */
+pub async fn evaluate_game(
+    db: &Pool<Sqlite>,
+    policy: &DbPolicy,
+    plies: &[(Quarto, Move)],
+    depth: u32,
+) -> Result<Vec<Annotated>, Box<dyn Error>> {
+    let mut evaluations = Vec::new();
+    for (quarto, _) in plies {
+        evaluations.push(evaluate(db, policy, quarto, depth).await?);
+    }
+    if let Some((quarto, ply)) = plies.last() {
+        let mut end = quarto.clone();
+        end.play_legal(ply);
+        evaluations.push(evaluate(db, policy, &end, depth).await?);
+    }
+    Ok(annotate(plies, &evaluations))
+}
+
+/* A move with what its player could force before and after it */
+#[derive(Clone, Debug, PartialEq)]
+pub struct Annotated {
+    pub number: usize,
+    pub seat: usize,
+    pub ply: Move,
+    pub before: Proof,
+    pub after: Proof,
+    /* The search's move, when it would have kept more */
+    pub better: Option<Move>,
+}
+
+impl Annotated {
+    pub fn is_blunder(&self) -> bool {
+        self.after < self.before
+    }
+}
+
+/* e.g.  7. 2nd c3 BTSH  wins -> loses  blunder, better d4 WSCF */
+impl fmt::Display for Annotated {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>3}. {} {}  {} -> {}",
+            self.number,
+            clock::seat_name(self.seat),
+            self.ply,
+            self.before,
+            self.after
+        )?;
+        if self.is_blunder() {
+            f.write_str("  blunder")?;
+            if let Some(better) = &self.better {
+                write!(f, ", better {}", better)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/* `evaluations` holds the search result in each position of `plies` followed by
+   the one in the position the last move left
+*/
+pub fn annotate(plies: &[(Quarto, Move)], evaluations: &[(Proof, Option<Move>)]) -> Vec<Annotated> {
+    plies
+        .iter()
+        .zip(evaluations.windows(2))
+        .zip(1..)
+        .map(|(((quarto, ply), pair), number)| {
+            let (before, best) = &pair[0];
+            let after = pair[1].0.flip();
+            Annotated {
+                number,
+                seat: clock::seat_to_move(quarto),
+                ply: *ply,
+                before: *before,
+                after,
+                better: best.filter(|_| after < *before),
+            }
+        })
+        .collect()
+}
+
+/* Blunders per seat, 1st player first */
+pub fn blunders(annotated: &[Annotated]) -> [usize; 2] {
+    let mut counts = [0; 2];
+    for a in annotated.iter().filter(|a| a.is_blunder()) {
+        counts[a.seat] += 1;
+    }
+    counts
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn records(position: &str, moves: &[&str]) -> Vec<Record> {
+        let created = Event::Created {
+            position: position.to_string(),
+        };
+        let moves = moves.iter().map(|ply| Event::Move {
+            ply: ply.parse().unwrap(),
+        });
+        std::iter::once(created)
+            .chain(moves)
+            .zip(1..)
+            .map(|(event, seq)| Record {
+                seq,
+                event,
+                created_at: 0,
+                think_ms: None,
+                handed_by: None,
+            })
+            .collect()
+    }
+
+    fn annotated(records: &[Record], depth: u32) -> Vec<Annotated> {
+        let plies = plies(records).unwrap();
+        let mut evaluations: Vec<_> = plies.iter().map(|(q, _)| q.search(depth)).collect();
+        let mut end = plies.last().unwrap().0.clone();
+        end.apply_move(&plies.last().unwrap().1);
+        evaluations.push(end.search(depth));
+        annotate(&plies, &evaluations)
+    }
+
+    #[test]
+    fn test_missed_win_is_flagged() {
+        /* After move 6 row 1 holds three brown pieces and the 2nd player has the
+           brown BSSH in hand, so d1 wins. Move 7 plays it on c2 instead and hands
+           over another brown piece.
+        */
+        let mut opening = Quarto::new();
+        opening.apply_move(&"BSCF".parse().unwrap());
+        let plies = [
+            "a1 BTSH", "b1 WSCH", "d4 BTCF", "c1 WTSF", "a4 WSSF", "b3 BSSH", "c2 BTSF",
+        ];
+        let annotated = annotated(&records(&opening.to_share_code(), &plies), 2);
+        assert_eq!(annotated.len(), 7);
+        let missed = &annotated[6];
+        assert_eq!(missed.seat, 1);
+        assert_eq!((missed.before, missed.after), (Proof::Win, Proof::Loss));
+        assert_eq!(
+            missed.to_string(),
+            "  7. 2nd c2 BTSF  wins -> loses  blunder, better d1"
+        );
+        assert!(!annotated[..5].iter().any(Annotated::is_blunder));
+        assert_eq!(blunders(&annotated)[1], 1);
+    }
+
+    #[test]
+    fn test_position_at_past_moves() {
+        let mut opening = Quarto::new();
+        opening.apply_move(&"BSCF".parse().unwrap());
+        let records = records(&opening.to_share_code(), &["a1 BTSH", "b1 WSCH", "c1 WTSF"]);
+
+        let (number, start) = position_at(&records, 0).unwrap();
+        assert_eq!((number, start), (0, opening.clone()));
+        let (number, latest) = position_at(&records, 3).unwrap();
+        assert_eq!(number, 3);
+        assert_eq!(Some(latest), event::replay(&records).unwrap());
+
+        let (number, before_last) = position_at(&records, -1).unwrap();
+        assert_eq!(number, 2);
+        assert_eq!(before_last.placed_pieces(), 2);
+        assert_eq!(before_last.next_piece, Some("WSCH".parse().unwrap()));
+        assert_eq!(before_last.turn(), 2);
+        assert_eq!(position_at(&records, -3).unwrap(), (0, opening));
+
+        for at in [4, -4] {
+            match position_at(&records, at) {
+                Err(QuartoError::InvalidMoveNumber(reason)) => assert_eq!(
+                    reason,
+                    format!("move {}: the game has moves 0 to 3, or -3 to -1", at)
+                ),
+                other => panic!("expected an invalid move number, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_plies_start_at_last_position() {
+        let mut opening = Quarto::new();
+        opening.apply_move(&"BSCF".parse().unwrap());
+        let mut records = records(&opening.to_share_code(), &["a1 BTSH", "b1 WSCH"]);
+        records[1].think_ms = Some(3_000);
+        assert_eq!(think_times(&records), [Some(3_000), None]);
+        let mut set = opening.clone();
+        set.apply_move(&"d4 WTSF".parse().unwrap());
+        records.push(Record {
+            seq: 4,
+            event: Event::Position {
+                position: set.to_share_code(),
+            },
+            created_at: 0,
+            think_ms: None,
+            handed_by: None,
+        });
+        let plies = plies(&records).unwrap();
+        assert!(plies.is_empty());
+        assert!(think_times(&records).is_empty());
+    }
+}
@@ -0,0 +1,347 @@
+use crate::game_id::GameId;
+use crate::play::{self, Session, Store};
+use crate::quarto::{Move, Quarto, QuartoError};
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/* The last `quarto play` session, so it can be picked up after the terminal is lost.
+   The file holds a format line, the game, the position the uncommitted plies were
+   played on as a share code, and the plies as written by `quarto move`:
+
+       quarto-session 1
+       game 1a2b3c4d-0000-4000-8000-000000000001
+       base AQAAAAAA...
+       ply a4 WSCF
+
+   Plies written as x y, from before squares, still read. The file is QUARTO_STATE,
+   or quarto/session under XDG_STATE_HOME or ~/.local/state.
+*/
+pub const STATE_ENV: &str = "QUARTO_STATE";
+
+pub const SESSION_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SavedSession {
+    pub game: GameId,
+    pub base: Quarto,
+    pub plies: Vec<Move>,
+}
+
+pub fn path() -> Option<PathBuf> {
+    if let Ok(path) = env::var(STATE_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    state_file("session")
+}
+
+/* A file of ours under XDG_STATE_HOME or ~/.local/state */
+pub fn state_file(name: &str) -> Option<PathBuf> {
+    let state = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+    Some(state.join("quarto").join(name))
+}
+
+impl SavedSession {
+    pub fn of(session: &Session) -> SavedSession {
+        SavedSession {
+            game: session.game().clone(),
+            base: session.committed().clone(),
+            plies: session.uncommitted_plies(),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let mut text = format!(
+            "quarto-session {}\ngame {}\nbase {}\n",
+            SESSION_VERSION,
+            self.game,
+            self.base.to_share_code()
+        );
+        for ply in &self.plies {
+            text.push_str(&format!("ply {}\n", ply));
+        }
+        text
+    }
+
+    pub fn decode(text: &str) -> Result<SavedSession, QuartoError> {
+        let corrupt = |reason: String| QuartoError::CorruptRecord(reason);
+        let mut lines = text.lines();
+        let mut field = |name: &str| match lines.next().and_then(|line| line.split_once(' ')) {
+            Some((key, value)) if key == name => Ok(value.trim().to_string()),
+            _ => Err(corrupt(format!("expected a {} line", name))),
+        };
+        let version = field("quarto-session")?;
+        if version != SESSION_VERSION.to_string() {
+            return Err(corrupt(format!("unsupported format version {}", version)));
+        }
+        let game = GameId::parse(&field("game")?)?;
+        let base = Quarto::from_share_code(&field("base")?)?;
+        let mut plies = Vec::new();
+        for line in lines {
+            let ply = match line.split_once(' ') {
+                Some(("ply", ply)) => ply.trim(),
+                _ => return Err(corrupt(format!("expected a ply line: {}", line))),
+            };
+            let mv: Move = ply
+                .parse()
+                .map_err(|_| corrupt(format!("invalid ply {}", ply)))?;
+            if mv.place.is_none() {
+                return Err(corrupt(format!("ply places nothing: {}", ply)));
+            }
+            plies.push(mv);
+        }
+        Ok(SavedSession { game, base, plies })
+    }
+}
+
+/* Replaced in one rename, like the game files */
+pub fn save(path: &Path, saved: &SavedSession) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(saved.encode().as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/* A missing file means there is nothing to resume; one that cannot be read is
+   reported and treated the same, so a damaged file never stops `play`
+*/
+pub fn load(path: &Path) -> Option<SavedSession> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!(path = %path.display(), ?e, "ignoring unreadable session file");
+            return None;
+        }
+    };
+    match SavedSession::decode(&text) {
+        Ok(saved) => Some(saved),
+        Err(e) => {
+            warn!(path = %path.display(), ?e, "ignoring unreadable session file");
+            None
+        }
+    }
+}
+
+/* Opens the saved game as it is stored now and plays the uncommitted plies again.
+   They are dropped with a warning when the game has moved on since, or from the
+   first one which is no longer legal. None when the game is gone.
+*/
+pub fn restore<W: Write>(
+    saved: SavedSession,
+    store: &mut dyn Store,
+    autocommit: bool,
+    output: &mut W,
+) -> Result<Option<Session>, Box<dyn Error>> {
+    let Some(quarto) = store.load(&saved.game)? else {
+        writeln!(output, "game {} no longer exists", saved.game)?;
+        return Ok(None);
+    };
+    let mut session = Session::new(saved.game, quarto, autocommit);
+    if saved.plies.is_empty() {
+        return Ok(Some(session));
+    }
+    if session.current() != &saved.base {
+        writeln!(
+            output,
+            "the game has moved on; dropped {}",
+            play::plies(saved.plies.len())
+        )?;
+        return Ok(Some(session));
+    }
+    for (i, ply) in saved.plies.iter().enumerate() {
+        // Plies which place nothing were refused when the file was read
+        let Some(at) = ply.place else {
+            break;
+        };
+        if let Err(e) = session.play(at, ply.hand) {
+            writeln!(
+                output,
+                "{}: {}; dropped {}",
+                ply,
+                e,
+                play::plies(saved.plies.len() - i)
+            )?;
+            break;
+        }
+    }
+    Ok(Some(session))
+}
+
+/* Asks whether to pick the last session up again; an empty answer means yes */
+pub fn offer<R: BufRead, W: Write>(
+    path: &Path,
+    store: &mut dyn Store,
+    autocommit: bool,
+    input: &mut R,
+    output: &mut W,
+) -> Result<Option<Session>, Box<dyn Error>> {
+    let Some(saved) = load(path) else {
+        return Ok(None);
+    };
+    let pending = match saved.plies.len() {
+        0 => String::new(),
+        n => format!(" with {} uncommitted", play::plies(n)),
+    };
+    let known: Vec<GameId> = store
+        .games()
+        .map(|games| games.into_iter().map(|(game, _)| game).collect())
+        .unwrap_or_default();
+    write!(
+        output,
+        "resume {}{}? [Y/n] ",
+        saved.game.short(&known),
+        pending
+    )?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    match answer.trim() {
+        "" | "y" | "Y" | "yes" => restore(saved, store, autocommit, output),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file_store::test::TempDir;
+    use crate::play::test::MemoryStore;
+    use crate::quarto::Piece;
+
+    const GAME: &str = "1a2b3c4d-0000-4000-8000-000000000001";
+
+    fn store() -> MemoryStore {
+        let mut quarto = Quarto::new();
+        quarto.pick_piece(&"BSCF".parse::<Piece>().unwrap());
+        MemoryStore {
+            games: vec![(GameId::parse(GAME).unwrap(), quarto)],
+            commits: Vec::new(),
+        }
+    }
+
+    /* Plays `input` in a session recorded to `path`, ending without `quit` */
+    fn interrupted(store: &mut MemoryStore, path: &Path, input: &str) -> Session {
+        let (game, quarto) = store.games[0].clone();
+        let mut session = Session::new(game, quarto, false);
+        session.record = Some(path.to_path_buf());
+        let mut output = Vec::new();
+        play::run(&mut session, store, input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("left uncommitted to resume"), "{}", output);
+        session
+    }
+
+    fn offer_with(path: &Path, store: &mut MemoryStore, answer: &str) -> (Option<Session>, String) {
+        let mut output = Vec::new();
+        let session = offer(path, store, false, &mut answer.as_bytes(), &mut output).unwrap();
+        (session, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_resume_replays_uncommitted_plies() {
+        let dir = TempDir::new();
+        let path = dir.0.join("state").join("session");
+        let mut store = store();
+        let played = interrupted(&mut store, &path, "move 0 0 WSCF\nmove 1 1 BTCF\n");
+        let saved = load(&path).unwrap();
+        assert_eq!(saved.plies.len(), 2);
+        assert_eq!(SavedSession::decode(&saved.encode()).unwrap(), saved);
+
+        let (session, output) = offer_with(&path, &mut store, "\n");
+        assert_eq!(output, "resume 1a2b3c4d with 2 plies uncommitted? [Y/n] ");
+        let session = session.unwrap();
+        assert_eq!(session.current(), played.current());
+        assert_eq!(session.uncommitted(), 2);
+
+        let (session, _) = offer_with(&path, &mut store, "n\n");
+        assert!(session.is_none());
+
+        // Quitting discards the plies, and nothing is offered with them again
+        let (game, quarto) = store.games[0].clone();
+        let mut session = Session::new(game, quarto, false);
+        session.record = Some(path.clone());
+        play::run(
+            &mut session,
+            &mut store,
+            "move 2 2 WSCF\nquit\n".as_bytes(),
+            io::sink(),
+        )
+        .unwrap();
+        assert!(load(&path).unwrap().plies.is_empty());
+    }
+
+    #[test]
+    fn test_resume_drops_plies_after_the_game_moved_on() {
+        let dir = TempDir::new();
+        let path = dir.0.join("session");
+        let mut store = store();
+        interrupted(&mut store, &path, "move 0 0 WSCF\n");
+
+        // The opponent placed BSCF elsewhere meanwhile
+        store.games[0].1.move_piece(3, 3);
+        store.games[0]
+            .1
+            .pick_piece(&"WTCH".parse::<Piece>().unwrap());
+        let (session, output) = offer_with(&path, &mut store, "y\n");
+        assert!(
+            output.contains("the game has moved on; dropped 1 ply"),
+            "{}",
+            output
+        );
+        let session = session.unwrap();
+        assert_eq!(session.current(), &store.games[0].1);
+        assert_eq!(session.uncommitted(), 0);
+
+        // A deleted game is not resumed
+        store.games.clear();
+        let (session, output) = offer_with(&path, &mut store, "\n");
+        assert!(session.is_none());
+        assert!(output.contains("no longer exists"), "{}", output);
+    }
+
+    #[test]
+    fn test_corrupt_session_file_starts_fresh() {
+        let dir = TempDir::new();
+        let path = dir.0.join("session");
+        let mut store = store();
+        let valid = SavedSession {
+            game: GameId::parse(GAME).unwrap(),
+            base: store.games[0].1.clone(),
+            plies: vec!["a4 WSCF".parse().unwrap()],
+        }
+        .encode();
+        for text in [
+            "",
+            "garbage",
+            "quarto-session 2\n",
+            &valid.replace(GAME, "nope"),
+            &valid.replace("base ", "base x"),
+            &valid.replace("ply a4 WSCF", "ply WSCF"),
+            &valid.replace("ply a4 WSCF", "ply e5 WSCF"),
+            &format!("{}move 1 1\n", valid),
+        ] {
+            fs::write(&path, text).unwrap();
+            assert_eq!(load(&path), None, "{}", text);
+            let (session, output) = offer_with(&path, &mut store, "\n");
+            assert!(session.is_none() && output.is_empty());
+        }
+        fs::write(&path, [0xff, 0xfe, 0x00]).unwrap();
+        assert_eq!(load(&path), None);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(load(&path), None);
+        fs::write(&path, &valid).unwrap();
+        let saved = load(&path).unwrap();
+        fs::write(&path, valid.replace("ply a4 WSCF", "ply 0 0 WSCF")).unwrap();
+        assert_eq!(load(&path), Some(saved));
+    }
+}
@@ -0,0 +1,516 @@
+use crate::game_id::GameId;
+use crate::quarto::{self, square, Coord, Move, Piece, Quarto, QuartoError, Rules, Variant};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Write};
+
+/* Newline-delimited JSON-RPC 2.0 for GUI frontends driving quarto as a child process.
+   One request per input line, one response per output line.
+   Games are kept in memory for the lifetime of the process.
+*/
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+const ILLEGAL_MOVE: i64 = -32000;
+const UNKNOWN_GAME: i64 = -32001;
+
+#[derive(Debug, PartialEq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> RpcError {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Default, Deserialize)]
+struct NewGameParams {
+    #[serde(default)]
+    variant: Variant,
+}
+
+#[derive(Deserialize)]
+struct GameParams {
+    game: GameId,
+}
+
+/* Places the piece in hand `at` a square such as "b3", hands over `piece`, or both
+   in that order. x and y in place of `at` are deprecated.
+*/
+#[derive(Deserialize)]
+struct MoveParams {
+    game: GameId,
+    at: Option<Coord>,
+    x: Option<usize>,
+    y: Option<usize>,
+    piece: Option<String>,
+}
+
+impl MoveParams {
+    fn place(&self) -> Result<Option<Coord>, RpcError> {
+        match (self.at, self.x, self.y) {
+            (at, None, None) => Ok(at),
+            (None, Some(x), Some(y)) => Coord::from_xy(x, y).map(Some).ok_or_else(|| {
+                RpcError::new(ILLEGAL_MOVE, format!("cannot place at ({}, {})", x, y))
+            }),
+            _ => Err(RpcError::new(
+                INVALID_PARAMS,
+                "give either at, or x and y together",
+            )),
+        }
+    }
+
+    fn to_move(&self) -> Result<Move, RpcError> {
+        let place = self.place()?;
+        let hand =
+            match &self.piece {
+                Some(code) => Some(Piece::try_from(code.clone()).map_err(|_| {
+                    RpcError::new(INVALID_PARAMS, format!("invalid piece: {}", code))
+                })?),
+                None => None,
+            };
+        Ok(Move { place, hand })
+    }
+}
+
+fn optional_params<T: serde::de::DeserializeOwned + Default>(params: Value) -> Result<T, RpcError> {
+    if params.is_null() {
+        return Ok(T::default());
+    }
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn required_params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn state(game: &GameId, quarto: &Quarto) -> Result<Value, RpcError> {
+    let state =
+        serde_json::to_value(quarto).map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))?;
+    Ok(json!({
+        "game": game,
+        "state": state,
+        "quarto": quarto.is_quarto(),
+        "description": quarto.describe(),
+    }))
+}
+
+#[derive(Default)]
+pub struct Server {
+    games: HashMap<GameId, Quarto>,
+    shutdown: bool,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server::default()
+    }
+
+    /* Answers one request line, or nothing for a notification without an id */
+    pub fn handle_line(&mut self, line: &str) -> Option<String> {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => {
+                return Some(response(
+                    Value::Null,
+                    Err(RpcError::new(PARSE_ERROR, e.to_string())),
+                ))
+            }
+        };
+        let request: Request = match serde_json::from_value(request) {
+            Ok(request) => request,
+            Err(e) => {
+                return Some(response(
+                    Value::Null,
+                    Err(RpcError::new(INVALID_REQUEST, e.to_string())),
+                ))
+            }
+        };
+        let id = request.id;
+        let result = self.dispatch(&request.method, request.params);
+        if id.is_null() {
+            return None;
+        }
+        Some(response(id, result))
+    }
+
+    fn dispatch(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "new_game" => self.new_game(optional_params(params)?),
+            "apply_move" => self.apply_move(required_params(params)?),
+            "get_state" => {
+                let GameParams { game } = required_params(params)?;
+                state(&game, self.game(&game)?)
+            }
+            "check_move" => self.check_move(required_params(params)?),
+            "hint" => self.hint(required_params(params)?),
+            "list_games" => {
+                let mut games: Vec<&GameId> = self.games.keys().collect();
+                games.sort();
+                Ok(json!(games))
+            }
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method: {}", method),
+            )),
+        }
+    }
+
+    fn game(&self, game: &GameId) -> Result<&Quarto, RpcError> {
+        self.games
+            .get(game)
+            .ok_or_else(|| RpcError::new(UNKNOWN_GAME, format!("unknown game: {}", game)))
+    }
+
+    fn new_game(&mut self, params: NewGameParams) -> Result<Value, RpcError> {
+        let game = GameId::random();
+        let quarto = Quarto::with_rules(Rules {
+            variant: params.variant,
+            ..Rules::default()
+        });
+        let mut result = state(&game, &quarto)?;
+        result["engine_version"] = json!(quarto::engine_version());
+        self.games.insert(game, quarto);
+        Ok(result)
+    }
+
+    /* The stored game is only replaced when the whole move is legal, as the
+       library judges it for the command line too
+    */
+    fn apply_move(&mut self, params: MoveParams) -> Result<Value, RpcError> {
+        let mut quarto = self.game(&params.game)?.clone();
+        let mv = params.to_move()?;
+        quarto.check_move(&mv).map_err(|e| illegal(&mv, e))?;
+        quarto.play_legal(&mv);
+        let result = state(&params.game, &quarto);
+        self.games.insert(params.game, quarto);
+        result
+    }
+
+    /* Previews a move the way apply_move would play it, without storing anything */
+    fn check_move(&self, params: MoveParams) -> Result<Value, RpcError> {
+        let quarto = self.game(&params.game)?;
+        let mv = params.to_move()?;
+        let preview = quarto.check_move(&mv).map_err(|e| illegal(&mv, e))?;
+        serde_json::to_value(preview).map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))
+    }
+
+    fn hint(&self, params: GameParams) -> Result<Value, RpcError> {
+        let quarto = self.game(&params.game)?;
+        let winning_cell = quarto
+            .next_piece
+            .and_then(|p| quarto.winning_cell(&p))
+            .map(square);
+        let safe_pieces: Vec<String> = quarto.safe_pieces().into_iter().map(String::from).collect();
+        Ok(json!({
+            "winning_cell": winning_cell,
+            "safe_pieces": safe_pieces,
+        }))
+    }
+}
+
+/* check_move's refusal, naming every rule the move breaks */
+fn illegal(mv: &Move, e: QuartoError) -> RpcError {
+    match e {
+        QuartoError::BrokenRules(broken) => {
+            RpcError::new(ILLEGAL_MOVE, format!("illegal move {}", broken))
+        }
+        _ => RpcError::new(ILLEGAL_MOVE, format!("illegal move: {}", mv)),
+    }
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> String {
+    let response = match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(RpcError { code, message }) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": code, "message": message},
+        }),
+    };
+    response.to_string()
+}
+
+/* Serves until `shutdown` or end of input, flushing after every response */
+pub fn serve<R: BufRead, W: Write>(input: R, mut output: W) -> io::Result<()> {
+    let mut server = Server::new();
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = server.handle_line(&line) {
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+        if server.shutdown {
+            break;
+        }
+    }
+    output.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::quarto::GameStatus;
+
+    fn call(input: &str) -> Vec<Value> {
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn request(server: &mut Server, id: i64, method: &str, params: Value) -> Value {
+        let line = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        let response = server.handle_line(&line.to_string()).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["id"], json!(id));
+        response
+    }
+
+    #[test]
+    fn test_rpc_scripted_game() {
+        let mut server = Server::new();
+        let created = request(&mut server, 1, "new_game", Value::Null);
+        let game = created["result"]["game"].as_str().unwrap().to_string();
+        assert_eq!(
+            created["result"]["engine_version"],
+            json!(quarto::engine_version())
+        );
+        assert_eq!(
+            request(&mut server, 2, "list_games", Value::Null)["result"],
+            json!([game])
+        );
+
+        // Four brown pieces along the first line
+        let moves = [
+            json!({"game": game, "piece": "BSCF"}),
+            json!({"game": game, "at": "a4", "piece": "WSCF"}),
+            json!({"game": game, "at": "a3", "piece": "BSCH"}),
+            json!({"game": game, "at": "b4", "piece": "WSCH"}),
+            json!({"game": game, "at": "b3", "piece": "BSSF"}),
+            json!({"game": game, "at": "c4", "piece": "WSSF"}),
+            // The deprecated x and y
+            json!({"game": game, "x": 1, "y": 2, "piece": "BTSH"}),
+        ];
+        for (i, params) in moves.into_iter().enumerate() {
+            let response = request(&mut server, 10 + i as i64, "apply_move", params);
+            assert_eq!(response["result"]["quarto"], json!(false), "{:?}", response);
+        }
+        let hint = request(&mut server, 20, "hint", json!({"game": game}));
+        assert_eq!(hint["result"]["winning_cell"], json!("d4"));
+        let preview = request(
+            &mut server,
+            30,
+            "check_move",
+            json!({"game": game, "at": "d4"}),
+        );
+        assert_eq!(preview["result"]["status"], json!("won"));
+        assert_eq!(
+            preview["result"]["winning_lines"],
+            json!([["a4", "b4", "c4", "d4"]])
+        );
+        let refused = request(
+            &mut server,
+            31,
+            "check_move",
+            json!({"game": game, "at": "a4"}),
+        );
+        assert_eq!(refused["error"]["code"], json!(ILLEGAL_MOVE));
+        assert_eq!(
+            refused["error"]["message"],
+            json!("illegal move a4: a4 is occupied")
+        );
+
+        let response = request(
+            &mut server,
+            21,
+            "apply_move",
+            json!({"game": game, "at": "d4"}),
+        );
+        assert_eq!(response["result"]["quarto"], json!(true));
+        let state = request(&mut server, 22, "get_state", json!({"game": game}));
+        assert_eq!(state["result"], response["result"]);
+
+        let over = request(
+            &mut server,
+            23,
+            "apply_move",
+            json!({"game": game, "at": "d1"}),
+        );
+        assert_eq!(over["error"]["code"], json!(ILLEGAL_MOVE));
+    }
+
+    #[test]
+    fn test_rpc_moves_are_judged_as_on_the_command_line() {
+        let mut server = Server::new();
+        let created = request(&mut server, 1, "new_game", Value::Null);
+        let game = created["result"]["game"].as_str().unwrap().to_string();
+        request(
+            &mut server,
+            2,
+            "apply_move",
+            json!({"game": game, "piece": "BSCF"}),
+        );
+        request(
+            &mut server,
+            3,
+            "apply_move",
+            json!({"game": game, "at": "a4", "piece": "WSCF"}),
+        );
+        let placed = request(
+            &mut server,
+            4,
+            "apply_move",
+            json!({"game": game, "at": "a3", "piece": "BSCF"}),
+        );
+        assert_eq!(
+            placed["error"]["message"],
+            json!("illegal move a3 BSCF: BSCF is not free")
+        );
+
+        // A full board without a line is over too
+        let mut last = Quarto::try_from(
+            &[
+                "BSSF WTSF BSCF WSSH",
+                "WSCF BSSH BTCF WTCH",
+                "WSCH BTCH BTSF BSCH",
+                "BTSH WSSF WTSH ----",
+            ]
+            .join("\n"),
+        )
+        .unwrap();
+        assert!(last.pick_piece(&Piece::try_from("WTCF".to_string()).unwrap()));
+        let game: GameId = game.parse().unwrap();
+        server.games.insert(game.clone(), last);
+        let drawn = request(
+            &mut server,
+            5,
+            "apply_move",
+            json!({"game": game, "at": "d1"}),
+        );
+        assert_eq!(drawn["result"]["quarto"], json!(false), "{}", drawn);
+        assert_eq!(server.games[&game].status(), GameStatus::Drawn);
+        let after = request(
+            &mut server,
+            6,
+            "apply_move",
+            json!({"game": game, "piece": "WTCF"}),
+        );
+        assert_eq!(
+            after["error"]["message"],
+            json!("illegal move WTCF: the game is over; WTCF is not free")
+        );
+    }
+
+    #[test]
+    fn test_rpc_errors_keep_serving() {
+        let responses = call(concat!(
+            "not json\n",
+            "{\"id\":1}\n",
+            "{\"id\":2,\"method\":\"castle\"}\n",
+            "{\"id\":3,\"method\":\"get_state\",\"params\":{\"game\":\"nope\"}}\n",
+            "{\"id\":4,\"method\":\"apply_move\",\"params\":",
+            "{\"game\":\"1a2b3c4d-0000-4000-8000-000000000001\",\"x\":0}}\n",
+            "{\"id\":5,\"method\":\"shutdown\"}\n",
+            "{\"id\":6,\"method\":\"list_games\"}\n",
+        ));
+        let codes: Vec<Value> = responses
+            .iter()
+            .map(|r| r["error"]["code"].clone())
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                json!(PARSE_ERROR),
+                json!(INVALID_REQUEST),
+                json!(METHOD_NOT_FOUND),
+                json!(INVALID_PARAMS),
+                json!(UNKNOWN_GAME),
+                Value::Null,
+            ]
+        );
+        // Nothing is answered after shutdown
+        assert_eq!(responses.len(), 6);
+        assert_eq!(responses[5]["result"], Value::Null);
+        assert_eq!(responses[5]["id"], json!(5));
+    }
+
+    #[test]
+    fn test_rpc_rejects_illegal_moves_atomically() {
+        let mut server = Server::new();
+        let created = request(&mut server, 1, "new_game", json!({"variant": "Advanced"}));
+        assert_eq!(
+            created["result"]["state"]["rules"]["variant"],
+            json!("Advanced")
+        );
+        let game = created["result"]["game"].as_str().unwrap().to_string();
+
+        request(
+            &mut server,
+            2,
+            "apply_move",
+            json!({"game": game, "piece": "BSCF"}),
+        );
+        // Placing succeeds but handing over a piece that does not exist fails, so nothing changes
+        let bad = request(
+            &mut server,
+            3,
+            "apply_move",
+            json!({"game": game, "x": 0, "y": 0, "piece": "XXXX"}),
+        );
+        assert_eq!(bad["error"]["code"], json!(INVALID_PARAMS));
+        let state = request(&mut server, 4, "get_state", json!({"game": game}));
+        assert_eq!(state["result"]["state"]["next_piece"], json!("BSCF"));
+
+        let twice = request(
+            &mut server,
+            5,
+            "apply_move",
+            json!({"game": game, "piece": "WSCF"}),
+        );
+        assert_eq!(twice["error"]["code"], json!(ILLEGAL_MOVE));
+        let taken = request(
+            &mut server,
+            6,
+            "apply_move",
+            json!({"game": game, "x": 0, "y": 0, "piece": "BSCF"}),
+        );
+        assert_eq!(taken["error"]["code"], json!(ILLEGAL_MOVE));
+        for params in [
+            json!({"game": game, "at": "e5"}),
+            json!({"game": game, "at": "a4", "x": 0, "y": 0}),
+        ] {
+            let response = request(&mut server, 7, "check_move", params);
+            assert_eq!(response["error"]["code"], json!(INVALID_PARAMS));
+        }
+    }
+}
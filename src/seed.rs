@@ -0,0 +1,30 @@
+use crate::quarto::{GameRng, Quarto};
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite};
+
+/* Games created before seeds were kept have none */
+pub async fn load(db: &Pool<Sqlite>, uuid: &str) -> Result<Option<GameRng>, SqlxError> {
+    Ok(
+        sqlx::query_as::<_, (Option<i64>,)>("SELECT seed FROM game WHERE uuid = ?1")
+            .bind(uuid)
+            .fetch_optional(db)
+            .await?
+            .and_then(|(seed,)| seed)
+            .map(|seed| GameRng(seed as u64)),
+    )
+}
+
+/* The seed for `hint` and `analyze`: the one given, else the game's seed at this
+   move, else 0 as for games without one
+*/
+pub async fn for_move(
+    db: &Pool<Sqlite>,
+    uuid: &str,
+    quarto: &Quarto,
+    given: Option<u64>,
+) -> Result<u64, SqlxError> {
+    Ok(match given {
+        Some(seed) => seed,
+        None => load(db, uuid).await?.map_or(0, |rng| rng.seed_for(quarto)),
+    })
+}
@@ -0,0 +1,65 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::warn;
+
+/* Ctrl-C for long-running commands. The first asks the command to stop at its
+   next safe point, which it checks with `requested`: generate writes the games
+   it has played and watch closes the pool between polls. A second Ctrl-C exits
+   at once.
+
+   `play` keeps its session file after every command, so it is left to the
+   default handling and loses nothing when interrupted.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+/* Exit status of a forced exit, as a shell reports death by SIGINT */
+pub const FORCED_EXIT: i32 = 130;
+
+impl Shutdown {
+    /* Listens for Ctrl-C; `stopping` says what the command does before it stops */
+    pub fn on_ctrl_c(stopping: &'static str) -> Shutdown {
+        let shutdown = Shutdown::default();
+        let listener = shutdown.clone();
+        tokio::spawn(async move {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if listener.interrupt() {
+                    warn!("interrupted again, exiting");
+                    std::process::exit(FORCED_EXIT);
+                }
+                warn!("interrupted, {}; Ctrl-C again to exit at once", stopping);
+            }
+        });
+        shutdown
+    }
+
+    /* Asks the command to stop; true when it had been asked already */
+    pub fn interrupt(&self) -> bool {
+        self.0.swap(true, Ordering::Relaxed)
+    }
+
+    pub fn requested(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /* For work on other threads, which checks the flag itself */
+    pub fn flag(&self) -> &AtomicBool {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_second_interrupt_forces() {
+        let shutdown = Shutdown::default();
+        let command = shutdown.clone();
+        assert!(!command.requested());
+        assert!(!shutdown.interrupt());
+        assert!(command.requested());
+        assert!(command.flag().load(Ordering::Relaxed));
+        assert!(shutdown.interrupt());
+    }
+}
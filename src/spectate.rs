@@ -0,0 +1,159 @@
+use crate::clock::{format_amount, parse_amount};
+use crate::quarto::QuartoError;
+use sqlx::sqlite::SqliteQueryResult;
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite, SqliteConnection};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/* How far spectators lag behind the players, written like 30s or 2m.
+   It belongs to the game, so every watcher sees the same delayed stream.
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SpectatorDelay(pub Duration);
+
+impl FromStr for SpectatorDelay {
+    type Err = QuartoError;
+    fn from_str(s: &str) -> Result<SpectatorDelay, QuartoError> {
+        parse_amount(s)
+            .map(SpectatorDelay)
+            .ok_or_else(|| QuartoError::InvalidSpectatorDelay(s.to_string()))
+    }
+}
+
+impl fmt::Display for SpectatorDelay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&format_amount(self.0))
+    }
+}
+
+/* Events held back until `delay` after they happened, then handed out in the order
+   they were pushed. Once the game is over there is nothing left to protect, so
+   finishing releases everything still held.
+*/
+pub struct DelayedFeed<T> {
+    delay: i64,
+    held: VecDeque<(i64, T)>,
+    finished: bool,
+}
+
+impl<T> DelayedFeed<T> {
+    pub fn new(delay: SpectatorDelay) -> DelayedFeed<T> {
+        DelayedFeed {
+            delay: delay.0.as_millis() as i64,
+            held: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    /* `now` is milliseconds since the Unix epoch */
+    pub fn push(&mut self, now: i64, event: T) {
+        self.held.push_back((now + self.delay, event));
+    }
+
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /* Events due by `now`, oldest first */
+    pub fn release(&mut self, now: i64) -> Vec<T> {
+        let mut due = Vec::new();
+        while let Some((at, _)) = self.held.front() {
+            if !self.finished && *at > now {
+                break;
+            }
+            due.extend(self.held.pop_front().map(|(_, event)| event));
+        }
+        due
+    }
+}
+
+/* Pass the transaction which creates the game */
+pub async fn save(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    delay: SpectatorDelay,
+) -> Result<SqliteQueryResult, SqlxError> {
+    sqlx::query("UPDATE game SET spectator_delay_secs = ?2 WHERE uuid = ?1;")
+        .bind(uuid)
+        .bind(delay.0.as_secs() as i64)
+        .execute(conn)
+        .await
+}
+
+/* Games created without a delay are shown as they are played */
+pub async fn load(db: &Pool<Sqlite>, uuid: &str) -> Result<SpectatorDelay, SqlxError> {
+    let secs = sqlx::query_as::<_, (Option<i64>,)>(
+        "SELECT spectator_delay_secs FROM game WHERE uuid = ?1",
+    )
+    .bind(uuid)
+    .fetch_optional(db)
+    .await?
+    .and_then(|(secs,)| secs);
+    Ok(SpectatorDelay(
+        Duration::from_secs(secs.unwrap_or(0) as u64),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn delay(s: &str) -> SpectatorDelay {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_spectator_delay() {
+        assert_eq!(delay("30s").0, Duration::from_secs(30));
+        assert_eq!(delay("1m30s").to_string(), "90s");
+        assert_eq!(delay("0s"), SpectatorDelay::default());
+        for invalid in ["", "30", "1d", "-5s"] {
+            assert!(
+                matches!(
+                    invalid.parse::<SpectatorDelay>(),
+                    Err(QuartoError::InvalidSpectatorDelay(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_feed_releases_in_order_after_delay() {
+        let mut feed = DelayedFeed::new(delay("30s"));
+        feed.push(0, "a");
+        feed.push(10_000, "b");
+        feed.push(10_000, "c");
+        assert!(feed.release(29_999).is_empty());
+        assert_eq!(feed.release(30_000), vec!["a"]);
+        feed.push(35_000, "d");
+        assert_eq!(feed.release(40_000), vec!["b", "c"]);
+        assert!(feed.release(40_000).is_empty());
+        assert_eq!(feed.release(65_000), vec!["d"]);
+
+        // Without a delay events go out as soon as they are asked for
+        let mut live = DelayedFeed::new(SpectatorDelay::default());
+        live.push(5, 1);
+        assert_eq!(live.release(5), vec![1]);
+    }
+
+    #[test]
+    fn test_feed_releases_everything_at_game_end() {
+        let mut feed = DelayedFeed::new(delay("1m"));
+        feed.push(0, "move");
+        feed.push(1_000, "last move");
+        assert!(feed.release(2_000).is_empty());
+        feed.finish();
+        assert!(feed.is_finished());
+        assert_eq!(feed.release(2_000), vec!["move", "last move"]);
+        assert!(feed.release(2_000).is_empty());
+    }
+}
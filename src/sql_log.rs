@@ -0,0 +1,66 @@
+#[cfg(not(feature = "init"))]
+use std::fmt;
+#[cfg(not(feature = "init"))]
+use tracing::debug;
+
+/* --debug-sql, or debug_sql = "true" under [database]: every statement sqlx runs is
+   logged at debug level on the sqlx::query target, with the rows it affected and
+   returned and how long it took, and the writes that decide a game log their binds
+   on this module's target. Both are let through whatever RUST_LOG says.
+*/
+pub const DIRECTIVES: [&str; 2] = ["sqlx::query=debug", "quarto::sql_log=debug"];
+
+/* Player tokens are the only secret a statement can carry; a bind named like one
+   is logged by its length alone. The binds are logged by store.rs's queries, which
+   the init feature leaves out.
+*/
+#[cfg(not(feature = "init"))]
+fn is_secret(name: &str) -> bool {
+    name.contains("token")
+}
+
+/* Binds by name, written like uuid=d3b07384-… piece=WSCF */
+#[cfg(not(feature = "init"))]
+pub struct Binds<'a>(pub &'a [(&'a str, &'a dyn fmt::Display)]);
+
+#[cfg(not(feature = "init"))]
+impl fmt::Display for Binds<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (n, (name, value)) in self.0.iter().enumerate() {
+            if n > 0 {
+                f.write_str(" ")?;
+            }
+            if is_secret(name) {
+                let len = value.to_string().chars().count();
+                write!(f, "{}=<redacted, {} chars>", name, len)?;
+            } else {
+                write!(f, "{}={}", name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "init"))]
+pub fn binds(statement: &str, binds: &[(&str, &dyn fmt::Display)]) {
+    debug!(statement, binds = %Binds(binds), "bound statement");
+}
+
+#[cfg(all(test, not(feature = "init")))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tokens_are_redacted_from_binds() {
+        let logged = Binds(&[
+            ("uuid", &"d3b07384"),
+            ("player_token", &"s3cret"),
+            ("ply", &3),
+        ])
+        .to_string();
+        assert_eq!(
+            logged,
+            "uuid=d3b07384 player_token=<redacted, 6 chars> ply=3"
+        );
+    }
+}
@@ -0,0 +1,538 @@
+use crate::clock;
+use crate::compat;
+use crate::event::{self, Record};
+use crate::game_id;
+use crate::quarto::{GameStatus, Piece, Quarto, QuartoError, Rules};
+use crate::replay;
+use crate::summary::GameSummary;
+use serde::Serialize;
+use sqlx::sqlite::SqliteQueryResult;
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite};
+use std::collections::BTreeMap;
+use std::fmt;
+use tracing::warn;
+
+/* Aggregates over finished games: a quarto on the board, all 16 pieces placed, a
+   resignation or a draw by agreement. Only final boards are stored, so every figure
+   is read off the last position.
+*/
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct GameStats {
+    pub games: usize,
+    /* Games that ended with a piece on each cell, indexed like board_state */
+    pub cells: [[usize; 4]; 4],
+    /* Games in which each piece was part of a winning line, by piece code */
+    pub winning_pieces: BTreeMap<String, usize>,
+    /* Games by number of pieces placed */
+    pub lengths: BTreeMap<usize, usize>,
+    /* Games by how they ended */
+    pub endings: BTreeMap<String, usize>,
+    /* How long the counted games' moves took, where the events recorded it */
+    pub think: ThinkStats,
+    /* Who handed over what in the counted games, where the events recorded it */
+    pub handoffs: HandoffStats,
+    /* The summaries `analyze --all` stored, for `stats --analysis` */
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub analysis: Option<Vec<GameSummary>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ThinkStats {
+    /* Timed moves and their total think time in ms, by seat */
+    pub moves: [usize; 2],
+    pub total_ms: [i64; 2],
+    pub longest: Option<LongestThink>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LongestThink {
+    pub uuid: String,
+    pub seat: usize,
+    pub ms: i64,
+}
+
+impl ThinkStats {
+    pub fn add_game(&mut self, uuid: &str, records: &[Record]) -> Result<(), QuartoError> {
+        let plies = replay::plies(records)?;
+        for ((quarto, _), ms) in plies.iter().zip(replay::think_times(records)) {
+            let Some(ms) = ms else { continue };
+            let seat = clock::seat_to_move(quarto);
+            self.moves[seat] += 1;
+            self.total_ms[seat] += ms;
+            if self.longest.as_ref().is_none_or(|longest| ms > longest.ms) {
+                self.longest = Some(LongestThink {
+                    uuid: uuid.to_string(),
+                    seat,
+                    ms,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn average_ms(&self, seat: usize) -> Option<i64> {
+        match self.moves[seat] {
+            0 => None,
+            moves => Some(self.total_ms[seat] / moves as i64),
+        }
+    }
+}
+
+/* A losing hand-off gives the other seat a piece it wins with on its next move */
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct HandoffStats {
+    /* Pieces handed over and losing hand-offs, by the seat handing them */
+    pub handed: [usize; 2],
+    pub losing: [usize; 2],
+    /* Losing hand-offs by piece code */
+    pub losing_pieces: BTreeMap<String, usize>,
+}
+
+impl HandoffStats {
+    pub fn add_game(&mut self, records: &[Record]) -> Result<(), QuartoError> {
+        let plies = replay::plies(records)?;
+        let seats = replay::handed_by(records);
+        for (i, ((_, ply), seat)) in plies.iter().zip(seats).enumerate() {
+            let (Some(piece), Some(seat)) = (ply.hand, seat) else {
+                continue;
+            };
+            self.handed[seat] += 1;
+            let Some((before, reply)) = plies.get(i + 1) else {
+                continue;
+            };
+            let mut after = before.clone();
+            if after.apply_move(reply) && after.status() == GameStatus::Won {
+                self.losing[seat] += 1;
+                *self.losing_pieces.entry(piece.into()).or_default() += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ending {
+    Quarto,
+    FullBoard,
+    Resigned,
+    DrawAgreed,
+    Aborted,
+}
+
+impl Ending {
+    pub fn name(self) -> &'static str {
+        match self {
+            Ending::Quarto => "quarto",
+            Ending::FullBoard => "full board",
+            Ending::Resigned => "resignation",
+            Ending::DrawAgreed => "agreed draw",
+            Ending::Aborted => "abort",
+        }
+    }
+}
+
+impl GameStats {
+    /* Counts a game if the board has ended it */
+    pub fn add(&mut self, quarto: &Quarto) -> bool {
+        let ending = if !quarto.winning_lines().is_empty() {
+            Ending::Quarto
+        } else if quarto.placed_pieces() == 16 {
+            Ending::FullBoard
+        } else {
+            return false;
+        };
+        self.add_ended(quarto, ending);
+        true
+    }
+
+    pub fn add_ended(&mut self, quarto: &Quarto, ending: Ending) {
+        let winning_lines = quarto.winning_lines();
+        let placed = quarto.placed_pieces();
+        self.games += 1;
+        for (x, row) in quarto.board_state.cells().iter().enumerate() {
+            for (y, cell) in row.iter().enumerate() {
+                if cell.is_some() {
+                    self.cells[x][y] += 1;
+                }
+            }
+        }
+        let mut winners: Vec<Piece> = winning_lines
+            .iter()
+            .flatten()
+            .filter_map(|(x, y)| quarto.board_state.cells()[*x][*y])
+            .collect();
+        winners.sort_by_key(|p| p.to_index());
+        winners.dedup();
+        for p in winners {
+            *self.winning_pieces.entry(p.into()).or_default() += 1;
+        }
+        *self.lengths.entry(placed).or_default() += 1;
+        *self.endings.entry(ending.name().to_string()).or_default() += 1;
+    }
+
+    pub fn cell_percentage(&self, x: usize, y: usize) -> f64 {
+        if self.games == 0 {
+            return 0.0;
+        }
+        100.0 * self.cells[x][y] as f64 / self.games as f64
+    }
+
+    /* Cell usage as a 4x4 grid of percentages, laid out and labelled like the board */
+    pub fn heatmap(&self) -> String {
+        let mut grid = String::new();
+        for x in 0..4 {
+            grid.push_str(&(4 - x).to_string());
+            for y in 0..4 {
+                grid.push_str(&format!(
+                    " {:>4}",
+                    format!("{:.0}%", self.cell_percentage(x, y))
+                ));
+            }
+            grid.push('\n');
+        }
+        grid.push_str("     a    b    c    d\n");
+        grid
+    }
+}
+
+impl fmt::Display for GameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "finished games: {}", self.games)?;
+        writeln!(f, "endings:")?;
+        for (ending, count) in &self.endings {
+            writeln!(f, "  {} {}", ending, count)?;
+        }
+        writeln!(f, "winning pieces:")?;
+        for (piece, count) in &self.winning_pieces {
+            writeln!(f, "  {} {}", piece, count)?;
+        }
+        writeln!(f, "game lengths:")?;
+        for (length, count) in &self.lengths {
+            writeln!(f, "  {:>2} {}", length, count)?;
+        }
+        if let Some(longest) = &self.think.longest {
+            writeln!(f, "think times:")?;
+            for seat in 0..2 {
+                if let Some(average) = self.think.average_ms(seat) {
+                    writeln!(
+                        f,
+                        "  {} average {} over {} moves",
+                        clock::seat_name(seat),
+                        clock::format_think(average),
+                        self.think.moves[seat]
+                    )?;
+                }
+            }
+            writeln!(
+                f,
+                "  longest {} by {}",
+                clock::format_think(longest.ms),
+                clock::seat_name(longest.seat)
+            )?;
+        }
+        let handoffs = &self.handoffs;
+        if handoffs.handed.iter().any(|handed| *handed > 0) {
+            writeln!(f, "losing hand-offs:")?;
+            for seat in 0..2 {
+                writeln!(
+                    f,
+                    "  {} {} of {}",
+                    clock::seat_name(seat),
+                    handoffs.losing[seat],
+                    handoffs.handed[seat]
+                )?;
+            }
+            for (piece, count) in &handoffs.losing_pieces {
+                writeln!(f, "  {} {}", piece, count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/* Positions entered with --from-code are not real games and stay out of the statistics */
+pub async fn mark_setup(db: &Pool<Sqlite>, uuid: &str) -> Result<SqliteQueryResult, SqlxError> {
+    sqlx::query("UPDATE game SET setup = true WHERE uuid = ?1;")
+        .bind(uuid)
+        .execute(db)
+        .await
+}
+
+/* Aborted games have no result and are left out unless `include_aborted` */
+pub async fn collect(db: &Pool<Sqlite>, include_aborted: bool) -> Result<GameStats, SqlxError> {
+    let rows = sqlx::query_as::<_, (String, String, Option<i64>, bool, Option<i64>, bool)>(
+        r#"
+        SELECT uuid, board_state, resigned, draw_agreed, board_format, aborted IS NOT NULL
+        FROM game
+        WHERE board_state IS NOT NULL AND setup = false AND (?1 OR aborted IS NULL)
+        ORDER BY id
+        "#,
+    )
+    .bind(include_aborted)
+    .fetch_all(db)
+    .await?;
+    let mut stats = GameStats::default();
+    for (uuid, board_state, resigned, draw_agreed, board_format, aborted) in rows {
+        let board = compat::read_board(board_format, &board_state);
+        let counted =
+            match board.and_then(|board| Quarto::from_parts(board, None, Rules::default())) {
+                Ok(quarto) if aborted => {
+                    stats.add_ended(&quarto, Ending::Aborted);
+                    true
+                }
+                Ok(quarto) if resigned.is_some() => {
+                    stats.add_ended(&quarto, Ending::Resigned);
+                    true
+                }
+                Ok(quarto) if draw_agreed => {
+                    stats.add_ended(&quarto, Ending::DrawAgreed);
+                    true
+                }
+                Ok(quarto) => stats.add(&quarto),
+                Err(e) => {
+                    warn!(uuid = %game_id::logged(&uuid), ?e, "skipping unreadable game");
+                    false
+                }
+            };
+        if counted {
+            let records = event::load(db, &uuid, 0).await?;
+            if let Err(e) = stats.think.add_game(&uuid, &records) {
+                warn!(uuid = %game_id::logged(&uuid), ?e, "skipping think times");
+            }
+            if let Err(e) = stats.handoffs.add_game(&records) {
+                warn!(uuid = %game_id::logged(&uuid), ?e, "skipping hand-offs");
+            }
+        }
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::event::Event;
+    use crate::memory_db;
+    use indoc::indoc;
+
+    fn game(board_text: &str) -> Quarto {
+        Quarto::try_from(&board_text.to_string()).unwrap()
+    }
+
+    fn won() -> Quarto {
+        game(indoc! {
+        r#"BSCF BSCH BSSF BTSH
+           ---- WTCH ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#})
+    }
+
+    fn unfinished() -> Quarto {
+        game(indoc! {
+        r#"BSCF ---- ---- ----
+           ---- WTCH ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#})
+    }
+
+    #[test]
+    fn test_stats_count_finished_games() {
+        let mut stats = GameStats::default();
+        assert!(stats.add(&won()));
+        assert!(!stats.add(&unfinished()));
+        assert!(stats.add(&won()));
+
+        assert_eq!(stats.games, 2);
+        assert_eq!(stats.cells[0], [2, 2, 2, 2]);
+        assert_eq!(stats.cells[1], [0, 2, 0, 0]);
+        assert_eq!(stats.cell_percentage(1, 1), 100.0);
+        assert_eq!(stats.cell_percentage(2, 2), 0.0);
+        let winners: Vec<(&str, usize)> = stats
+            .winning_pieces
+            .iter()
+            .map(|(p, n)| (p.as_str(), *n))
+            .collect();
+        assert_eq!(
+            winners,
+            vec![("BSCF", 2), ("BSCH", 2), ("BSSF", 2), ("BTSH", 2)]
+        );
+        assert_eq!(stats.lengths.iter().collect::<Vec<_>>(), vec![(&5, &2)]);
+
+        // Conceded games count, apart from those the board decided
+        stats.add_ended(&unfinished(), Ending::Resigned);
+        stats.add_ended(&unfinished(), Ending::DrawAgreed);
+        assert_eq!(stats.games, 4);
+        assert_eq!(stats.winning_pieces["BSCF"], 2);
+        assert_eq!(
+            stats.endings.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("agreed draw".to_string(), 1),
+                ("quarto".to_string(), 2),
+                ("resignation".to_string(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stats_report() {
+        let mut stats = GameStats::default();
+        stats.add(&won());
+        stats.add(&game(indoc! {
+        r#"BSCF ---- ---- ----
+           BSCH ---- ---- ----
+           BSSF ---- ---- ----
+           BTSH ---- ---- ----"#}));
+        assert_eq!(
+            stats.heatmap(),
+            indoc! {"
+            4 100%  50%  50%  50%
+            3  50%  50%   0%   0%
+            2  50%   0%   0%   0%
+            1  50%   0%   0%   0%
+                 a    b    c    d
+            "}
+        );
+        assert_eq!(
+            stats.to_string(),
+            indoc! {"
+            finished games: 2
+            endings:
+              quarto 2
+            winning pieces:
+              BSCF 2
+              BSCH 2
+              BSSF 2
+              BTSH 2
+            game lengths:
+               4 1
+               5 1
+            "}
+        );
+    }
+
+    #[test]
+    fn test_think_stats_by_seat() {
+        let mut opening = Quarto::new();
+        opening.apply_move(&"BSCF".parse().unwrap());
+        let created = Event::Created {
+            position: opening.to_share_code(),
+        };
+        let moves = ["a1 BTSH", "b1 WSCH", "c1 WTSF"].map(|ply| Event::Move {
+            ply: ply.parse().unwrap(),
+        });
+        let think = [None, Some(4_000), Some(1_500), None];
+        let records: Vec<Record> = std::iter::once(created)
+            .chain(moves)
+            .zip(think)
+            .zip(1..)
+            .map(|((event, think_ms), seq)| Record {
+                seq,
+                event,
+                created_at: 1_000 * seq,
+                think_ms,
+                handed_by: None,
+            })
+            .collect();
+
+        let mut stats = GameStats::default();
+        stats.think.add_game("game-a", &records).unwrap();
+        stats.think.add_game("game-b", &records[..2]).unwrap();
+        assert_eq!(stats.think.moves, [1, 2]);
+        assert_eq!(stats.think.average_ms(0), Some(1_500));
+        assert_eq!(stats.think.average_ms(1), Some(4_000));
+        assert_eq!(
+            stats.think.longest,
+            Some(LongestThink {
+                uuid: "game-a".to_string(),
+                seat: 1,
+                ms: 4_000
+            })
+        );
+        assert!(stats.to_string().ends_with(indoc! {"
+            think times:
+              1st average 1.5s over 1 moves
+              2nd average 4.0s over 2 moves
+              longest 4.0s by 2nd
+            "}));
+    }
+
+    #[test]
+    fn test_losing_handoffs_by_seat_and_piece() {
+        let mut opening = Quarto::new();
+        opening.apply_move(&"BSCF".parse().unwrap());
+        let created = Event::Created {
+            position: opening.to_share_code(),
+        };
+        let moves = ["a1 BSCH", "b1 BSSF", "c1 BTSH", "d1"].map(|ply| Event::Move {
+            ply: ply.parse().unwrap(),
+        });
+        let handed_by = [None, Some(1), Some(0), Some(1), None];
+        let records: Vec<Record> = std::iter::once(created)
+            .chain(moves)
+            .zip(handed_by)
+            .zip(1..)
+            .map(|((event, handed_by), seq)| Record {
+                seq,
+                event,
+                created_at: 0,
+                think_ms: None,
+                handed_by,
+            })
+            .collect();
+
+        let mut stats = GameStats::default();
+        stats.handoffs.add_game(&records).unwrap();
+        // Moves recorded without the seat are left out
+        stats.handoffs.add_game(&records[..3]).unwrap();
+        let mut unknown = records.clone();
+        unknown[3].handed_by = None;
+        stats.handoffs.add_game(&unknown).unwrap();
+        assert_eq!(stats.handoffs.handed, [3, 4]);
+        assert_eq!(stats.handoffs.losing, [0, 1]);
+        assert!(stats.to_string().ends_with(indoc! {"
+            losing hand-offs:
+              1st 0 of 3
+              2nd 1 of 4
+              BTSH 1
+            "}));
+    }
+
+    #[tokio::test]
+    async fn test_collect_skips_setup_positions() {
+        let db = memory_db().await;
+        for (uuid, quarto) in [("a", won()), ("b", unfinished()), ("c", won())] {
+            let board_state: String = quarto.board_state.into();
+            sqlx::query("INSERT INTO game (uuid, board_state) VALUES (?1, ?2);")
+                .bind(uuid)
+                .bind(board_state)
+                .execute(&db)
+                .await
+                .unwrap();
+        }
+        mark_setup(&db, "c").await.unwrap();
+
+        let stats = collect(&db, false).await.unwrap();
+        assert_eq!(stats.games, 1);
+        assert_eq!(stats.lengths.into_iter().collect::<Vec<_>>(), vec![(5, 1)]);
+
+        sqlx::query("UPDATE game SET resigned = 1 WHERE uuid = 'b';")
+            .execute(&db)
+            .await
+            .unwrap();
+        let stats = collect(&db, false).await.unwrap();
+        assert_eq!(stats.games, 2);
+        assert_eq!(stats.endings["resignation"], 1);
+
+        // Aborted games have no result, even one the board had decided
+        sqlx::query("UPDATE game SET aborted = 2 WHERE uuid = 'a';")
+            .execute(&db)
+            .await
+            .unwrap();
+        let stats = collect(&db, false).await.unwrap();
+        assert_eq!(stats.games, 1);
+        assert!(stats.winning_pieces.is_empty());
+        let stats = collect(&db, true).await.unwrap();
+        assert_eq!(stats.games, 2);
+        assert_eq!(stats.endings["abort"], 1);
+    }
+}
@@ -0,0 +1,520 @@
+use crate::clock::{self, Clock};
+use crate::compat;
+use crate::deadline;
+use crate::event::{self, Event};
+use crate::game_id::{self, GameId};
+use crate::idempotency;
+#[cfg(not(feature = "init"))]
+use crate::quarto;
+use crate::quarto::{GameRng, GameStatus, Move, Piece, Quarto, QuartoError, Rules, Variant};
+use crate::spectate;
+#[cfg(not(feature = "init"))]
+use crate::sql_log;
+use crate::template::Settings;
+use crate::verify::{self, Outcome};
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite, SqliteConnection};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use tracing::{error, info, warn};
+
+/* Games as rows of the game table. Quarto knows nothing of the database: a game is
+   written from a Quarto and its uuid, and read back through from_row, which every
+   query of the table shares.
+*/
+
+/* A second game under an existing uuid is refused by the unique index */
+#[cfg_attr(feature = "init", allow(dead_code))]
+pub fn insert_error(e: SqlxError, uuid: &GameId) -> QuartoError {
+    match e.as_database_error() {
+        Some(db_error) if db_error.is_unique_violation() => {
+            error!(uuid = %game_id::logged(&uuid), "a game with this uuid already exists");
+            QuartoError::DuplicateGame(uuid.to_string())
+        }
+        _ => {
+            error!(uuid = %game_id::logged(&uuid), ?e, "cannot insert game");
+            QuartoError::AnyOther
+        }
+    }
+}
+
+/* A new game has a piece in hand, picked by the 1st player before it is stored.
+   The seed is kept as the INTEGER with the same bits.
+*/
+#[cfg_attr(feature = "init", allow(unused_variables))]
+#[tracing::instrument(level = "debug", skip(db, quarto))]
+pub async fn insert_game(
+    db: &mut SqliteConnection,
+    quarto: &Quarto,
+    uuid: &GameId,
+    rng: GameRng,
+) -> Result<(), QuartoError> {
+    let Some(piece) = quarto.next_piece else {
+        error!("a new game needs a piece in hand");
+        return Err(QuartoError::CorruptRecord(
+            "next_piece is missing".to_string(),
+        ));
+    };
+    #[cfg(not(feature = "init"))]
+    {
+        let piece: String = piece.into();
+        let board_state: String = quarto.board_state.clone().into();
+        let advanced = quarto.rules.variant == Variant::Advanced;
+        let engine_version = quarto::engine_version();
+        let seed = rng.0 as i64;
+        sql_log::binds(
+            "INSERT INTO game",
+            &[
+                ("uuid", uuid),
+                ("next_piece", &piece),
+                ("board_state", &board_state),
+                ("advanced", &advanced),
+                ("board_format", &compat::BOARD_FORMAT),
+                ("engine_version", &engine_version),
+                ("seed", &seed),
+            ],
+        );
+        let result = sqlx::query!(
+            r#"
+            INSERT INTO game (uuid, next_piece, board_state, advanced, board_format, engine_version, seed)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);
+            "#,
+            uuid,
+            piece,
+            board_state,
+            advanced,
+            compat::BOARD_FORMAT,
+            engine_version,
+            seed
+        )
+        .execute(&mut *db)
+        .await
+        .map_err(|e| insert_error(e, uuid))?;
+        info!(rows = result.rows_affected(), "inserted game");
+    }
+
+    Ok(())
+}
+
+#[cfg_attr(feature = "init", allow(unused_variables))]
+#[tracing::instrument(level = "debug", skip(db, quarto))]
+pub async fn update_game(
+    db: &mut SqliteConnection,
+    quarto: &Quarto,
+    uuid: &GameId,
+) -> Result<(), SqlxError> {
+    #[cfg(not(feature = "init"))]
+    {
+        let piece: Option<String> = quarto.next_piece.map(Into::into);
+        let board_state: String = quarto.board_state.clone().into();
+        sql_log::binds(
+            "UPDATE game",
+            &[
+                ("uuid", uuid),
+                ("next_piece", &piece.as_deref().unwrap_or("NULL")),
+                ("board_state", &board_state),
+                ("board_format", &compat::BOARD_FORMAT),
+            ],
+        );
+        let result = sqlx::query!(
+            r#"
+            UPDATE game SET next_piece = ?2, board_state = ?3, board_format = ?4
+            WHERE uuid = ?1;
+            "#,
+            uuid,
+            piece,
+            board_state,
+            compat::BOARD_FORMAT
+        )
+        .execute(&mut *db)
+        .await?;
+        info!(rows = result.rows_affected(), "updated game");
+    }
+
+    Ok(())
+}
+
+#[cfg_attr(feature = "init", allow(unused_variables))]
+#[tracing::instrument(level = "debug", skip(db))]
+pub async fn find_game(db: &Pool<Sqlite>, uuid: &GameId) -> Result<Option<Quarto>, Box<dyn Error>> {
+    #[cfg(not(feature = "init"))]
+    {
+        let Some(result) = sqlx::query!(
+            r#"
+             SELECT uuid, next_piece, board_state, assigned_1st, assigned_2nd, advanced,
+                    board_format
+             FROM game
+             WHERE uuid = ?1
+             "#,
+            uuid
+        )
+        .fetch_optional(db)
+        .await?
+        else {
+            return Ok(None);
+        };
+        let quarto = from_row(
+            &result.board_state,
+            &result.next_piece,
+            result.advanced,
+            result.board_format,
+        )?;
+        Ok(Some(quarto))
+    }
+    #[cfg(feature = "init")]
+    Ok(None)
+}
+
+/* Games still being played: nobody has won, lost on time, forfeited, resigned,
+   agreed a draw or aborted
+*/
+#[tracing::instrument(level = "debug", skip(db))]
+pub async fn games_in_progress(db: &Pool<Sqlite>) -> Result<Vec<(GameId, Quarto)>, SqlxError> {
+    let rows = sqlx::query_as::<_, (String, Option<String>, Option<String>, bool, Option<i64>)>(
+        r#"
+        SELECT uuid, board_state, next_piece, advanced, board_format
+        FROM game
+        WHERE flagged IS NULL AND forfeited IS NULL AND resigned IS NULL AND draw_agreed = false
+              AND aborted IS NULL
+        ORDER BY id
+        "#,
+    )
+    .fetch_all(db)
+    .await?;
+    let mut games = Vec::new();
+    for (uuid, board_state, next_piece, advanced, board_format) in rows {
+        let read = GameId::parse(&uuid).and_then(|game| {
+            let quarto = from_row(&board_state, &next_piece, advanced, board_format)?;
+            Ok((game, quarto))
+        });
+        match read {
+            Ok((game, quarto)) => {
+                if quarto.status() == GameStatus::InProgress && quarto.next_piece.is_some() {
+                    games.push((game, quarto));
+                }
+            }
+            Err(e) => warn!(uuid = %game_id::logged(&uuid), ?e, "skipping unreadable game"),
+        }
+    }
+    Ok(games)
+}
+
+/* Inserts the game with its seed, its clock, deadline and spectator delay, and
+   the event opening its history in one transaction
+*/
+pub async fn create_game(
+    db: &Pool<Sqlite>,
+    quarto: &mut Quarto,
+    uuid: &GameId,
+    piece: &Piece,
+    rng: GameRng,
+    settings: &Settings,
+    now: i64,
+) -> Result<(), Box<dyn Error>> {
+    if quarto.next_piece != Some(*piece) && !quarto.pick_piece(piece) {
+        let piece = String::from(*piece);
+        error!(piece, "the piece is not free");
+        return Err(QuartoError::IllegalMove(format!("{} is not free", piece)))?;
+    }
+    let mut tx = db.begin().await?;
+    insert_game(&mut tx, quarto, uuid, rng).await?;
+    if let Some(control) = settings.clock {
+        clock::save(&mut tx, uuid.as_str(), &Clock::start(control, now)).await?;
+    }
+    if let Some(deadline) = settings.deadline {
+        deadline::start(&mut tx, uuid.as_str(), deadline, now).await?;
+    }
+    if let Some(delay) = settings.spectator_delay {
+        spectate::save(&mut tx, uuid.as_str(), delay).await?;
+    }
+    let created = Event::Created {
+        position: quarto.to_share_code(),
+    };
+    event::append(&mut tx, uuid.as_str(), &created, now).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/* save_game for `move`, recording the clock, the next deadline and the client's
+   key with the reply in the same transaction. Returns the reply, e.g. move 5: b3
+   WTCH
+*/
+pub async fn save_move(
+    db: &Pool<Sqlite>,
+    quarto: &Quarto,
+    uuid: &GameId,
+    ply: &Move,
+    key: Option<&str>,
+    game_clock: Option<&Clock>,
+    now: i64,
+) -> Result<String, Box<dyn Error>> {
+    let mut tx = db.begin().await?;
+    update_game(&mut tx, quarto, uuid).await?;
+    if let Some(game_clock) = game_clock {
+        clock::save(&mut tx, uuid.as_str(), game_clock).await?;
+    }
+    let event = Event::Move { ply: *ply };
+    let seq = event::append(&mut tx, uuid.as_str(), &event, now).await?;
+    let reply = format!("move {}: {}", seq, ply);
+    if let Some(key) = key {
+        let game_over = quarto.status() != GameStatus::InProgress;
+        idempotency::record(&mut tx, uuid.as_str(), key, seq, &reply, game_over).await?;
+    }
+    deadline::renew(&mut tx, uuid.as_str(), now).await?;
+    tx.commit().await?;
+    Ok(reply)
+}
+
+/* What finishing a game wrote, or found written by an earlier try */
+#[derive(Clone, Debug, PartialEq)]
+pub struct FinalizedGame {
+    pub outcome: Outcome,
+    pub result_hash: String,
+    /* The reply to the final move, e.g. move 8: d4 */
+    pub reply: String,
+    /* An earlier try had finished the game, and this one wrote nothing */
+    pub repeated: bool,
+}
+
+impl fmt::Display for FinalizedGame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.outcome {
+            Outcome::Won(seat) => write!(f, "won by the {} player", clock::seat_name(seat))?,
+            Outcome::Drawn => f.write_str("drawn")?,
+        }
+        write!(f, ", result {}", self.result_hash)
+    }
+}
+
+/* save_move for the move which finishes the game, `quarto` being the game after
+   it. One transaction writes, in this order, the final board, the clock, the
+   move's event, the client's key, the end of the deadline and the result hash, so
+   a step which fails leaves the game as it was before the move. The hash is only
+   ever sealed once: a try on a game already finished writes nothing and gets back
+   what the first one wrote.
+*/
+pub async fn finalize_game(
+    db: &Pool<Sqlite>,
+    quarto: &Quarto,
+    uuid: &GameId,
+    ply: &Move,
+    key: Option<&str>,
+    game_clock: Option<&Clock>,
+    now: i64,
+) -> Result<FinalizedGame, Box<dyn Error>> {
+    let outcome = Outcome::of(quarto).expect("the game is finished");
+    let result_hash = verify::result_hash(quarto, outcome);
+    let mut tx = db.begin().await?;
+    update_game(&mut tx, quarto, uuid).await?;
+    if let Some(game_clock) = game_clock {
+        clock::save(&mut tx, uuid.as_str(), game_clock).await?;
+    }
+    let event = Event::Move { ply: *ply };
+    let seq = event::append(&mut tx, uuid.as_str(), &event, now).await?;
+    let reply = format!("move {}: {}", seq, ply);
+    if let Some(key) = key {
+        idempotency::record(&mut tx, uuid.as_str(), key, seq, &reply, true).await?;
+    }
+    deadline::stop(&mut tx, uuid.as_str()).await?;
+    if !verify::seal(&mut tx, uuid.as_str(), &result_hash).await? {
+        tx.rollback().await?;
+        info!("game was already finished");
+        return finalized(db, uuid).await;
+    }
+    tx.commit().await?;
+    info!(?outcome, hash = %result_hash, "sealed result");
+    Ok(FinalizedGame {
+        outcome,
+        result_hash,
+        reply,
+        repeated: false,
+    })
+}
+
+/* The result a game was finished with, from its board, sealed hash and last move */
+async fn finalized(db: &Pool<Sqlite>, uuid: &GameId) -> Result<FinalizedGame, Box<dyn Error>> {
+    let outcome = find_game(db, uuid).await?.as_ref().and_then(Outcome::of);
+    let result_hash = verify::load(db, uuid.as_str()).await?;
+    let last_move = event::load(db, uuid.as_str(), 0)
+        .await?
+        .into_iter()
+        .rev()
+        .find_map(|record| match record.event {
+            Event::Move { ply } => Some(format!("move {}: {}", record.seq, ply)),
+            _ => None,
+        });
+    match (outcome, result_hash, last_move) {
+        (Some(outcome), Some(result_hash), Some(reply)) => Ok(FinalizedGame {
+            outcome,
+            result_hash,
+            reply,
+            repeated: true,
+        }),
+        _ => {
+            error!("sealed game has no final move");
+            Err(QuartoError::CorruptRecord(
+                "sealed game has no final move".to_string(),
+            ))?
+        }
+    }
+}
+
+/* Stores the new position together with the event that led to it */
+pub async fn save_game(
+    db: &Pool<Sqlite>,
+    quarto: &Quarto,
+    uuid: &GameId,
+    event: &Event,
+    now: i64,
+) -> Result<(), Box<dyn Error>> {
+    let mut tx = db.begin().await?;
+    update_game(&mut tx, quarto, uuid).await?;
+    event::append(&mut tx, uuid.as_str(), event, now).await?;
+    Ok(tx.commit().await?)
+}
+
+/* A finished game has no piece in hand, so next_piece may be NULL. board_state is
+   read as its board_format says, or as inferred for rows from before that column.
+   Stray whitespace is put right with a warning; update_game writes the board back
+   canonical.
+*/
+pub fn from_row(
+    board_state: &Option<String>,
+    next_piece: &Option<String>,
+    advanced: bool,
+    board_format: Option<i64>,
+) -> Result<Quarto, QuartoError> {
+    let Some(board_state) = board_state else {
+        return Err(QuartoError::CorruptRecord(
+            "board_state is missing".to_string(),
+        ));
+    };
+    let (board, whitespace) = compat::read_board_noting_whitespace(board_format, board_state)?;
+    if !whitespace.is_empty() {
+        let fixed: Vec<String> = whitespace.iter().map(ToString::to_string).collect();
+        warn!(fixed = %fixed.join("; "),
+              "board_state has stray whitespace, rewritten on the next save");
+    }
+    let hand = match next_piece {
+        Some(np) => Some(Piece::try_from(np.to_string()).map_err(|_| {
+            QuartoError::CorruptRecord(format!("next_piece: invalid piece {}", np))
+        })?),
+        None => None,
+    };
+    let variant = if advanced {
+        Variant::Advanced
+    } else {
+        Variant::Classic
+    };
+    Quarto::from_parts(
+        board,
+        hand,
+        Rules {
+            variant,
+            ..Rules::default()
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory_db;
+    #[cfg(not(feature = "init"))]
+    use crate::seed;
+    use indoc::indoc;
+
+    const GAME: &str = "d3b07384-0000-4000-8000-000000000001";
+
+    #[cfg(not(feature = "init"))]
+    #[tokio::test]
+    async fn test_games_read_back_as_written() {
+        let db = memory_db().await;
+        let game = GameId::parse(GAME).unwrap();
+        let mut quarto = Quarto::new();
+        quarto.rules.variant = Variant::Advanced;
+        let rng = GameRng(u64::MAX);
+        let e = insert_game(&mut db.acquire().await.unwrap(), &quarto, &game, rng)
+            .await
+            .unwrap_err();
+        assert!(matches!(e, QuartoError::CorruptRecord(_)), "{:?}", e);
+
+        quarto.pick_piece(&Piece::try_from("BSCF".to_string()).unwrap());
+        insert_game(&mut db.acquire().await.unwrap(), &quarto, &game, rng)
+            .await
+            .unwrap();
+        assert_eq!(find_game(&db, &game).await.unwrap(), Some(quarto.clone()));
+        // The seed went in with the row, every bit of it
+        assert_eq!(seed::load(&db, GAME).await.unwrap(), Some(rng));
+
+        quarto.play_legal(&"a4 WTCH".parse().unwrap());
+        update_game(&mut db.acquire().await.unwrap(), &quarto, &game)
+            .await
+            .unwrap();
+        assert_eq!(find_game(&db, &game).await.unwrap(), Some(quarto.clone()));
+        let other = GameId::random();
+        assert_eq!(find_game(&db, &other).await.unwrap(), None);
+
+        // Rows which cannot be read are left out of the games in progress
+        sqlx::query(
+            "INSERT INTO game (uuid, next_piece, board_state) VALUES (?1, 'WSCF', 'BSCF');",
+        )
+        .bind(&other)
+        .execute(&db)
+        .await
+        .unwrap();
+        assert_eq!(games_in_progress(&db).await.unwrap(), vec![(game, quarto)]);
+    }
+
+    #[test]
+    fn test_corrupt_rows_are_reported() {
+        let board_state = Some(
+            indoc! {
+            r#"BSCF ---- ---- ----
+               ---- WTCH ---- ----
+               ---- ---- ---- ----
+               ---- ---- ---- ----"#}
+            .to_string(),
+        );
+        let loaded = from_row(&board_state, &Some("WSSF".to_string()), true, None).unwrap();
+        assert_eq!(loaded.rules.variant, Variant::Advanced);
+        assert_eq!(loaded.placed_pieces(), 2);
+        // A finished game has nothing in hand
+        let finished = from_row(&board_state, &None, false, Some(1)).unwrap();
+        assert_eq!(finished.next_piece, None);
+
+        // The stored next piece is also on the board
+        assert!(matches!(
+            from_row(&board_state, &Some("BSCF".to_string()), false, None),
+            Err(QuartoError::CorruptRecord(reason))
+                if reason == "piece in hand BSCF is also on the board"
+        ));
+        assert!(matches!(
+            from_row(&board_state, &Some("BSCX".to_string()), false, None),
+            Err(QuartoError::CorruptRecord(reason)) if reason == "next_piece: invalid piece BSCX"
+        ));
+        assert!(matches!(
+            from_row(&None, &None, false, None),
+            Err(QuartoError::CorruptRecord(_))
+        ));
+        assert!(matches!(
+            from_row(&board_state, &None, false, Some(compat::BOARD_FORMAT + 1)),
+            Err(QuartoError::CorruptRecord(reason)) if reason == "board_format 2 has no reader"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_uuid_is_refused() {
+        let db = memory_db().await;
+
+        let game = GameId::parse(GAME).unwrap();
+        let insert = || sqlx::query("INSERT INTO game (uuid) VALUES (?1);").bind(game.clone());
+        insert().execute(&db).await.unwrap();
+        let e = insert().execute(&db).await.unwrap_err();
+        assert!(matches!(
+            insert_error(e, &game),
+            QuartoError::DuplicateGame(uuid) if uuid == GAME
+        ));
+    }
+}
@@ -0,0 +1,381 @@
+use crate::clock;
+use crate::db_policy::DbPolicy;
+use crate::event::{self, Event, Record};
+use crate::game_id;
+use crate::progress::Progress;
+use crate::quarto::{GameStatus, Move, Quarto, QuartoError, Variant};
+use crate::replay::{self, Annotated};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::Serialize;
+use sqlx::sqlite::SqliteQueryResult;
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use tracing::{info, warn};
+
+/* `analyze --all` runs `replay --evaluate` over every matching game, which also
+   fills the analysis cache, and keeps a row per game in analysis_summary for
+   `stats --analysis`. A game already summarized at least as deep as asked is
+   skipped, so a run which was stopped picks up where it left off.
+*/
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GameSummary {
+    pub uuid: String,
+    pub depth: i64,
+    pub moves: usize,
+    /* By seat, 1st player first */
+    pub blunders: [usize; 2],
+    /* How far each move moved its player's search score, averaged over the game */
+    pub average_swing: f64,
+}
+
+impl GameSummary {
+    pub fn of(uuid: &str, depth: u32, annotated: &[Annotated]) -> GameSummary {
+        let swings: f64 = annotated
+            .iter()
+            .map(|a| (a.before.score() - a.after.score()).abs())
+            .sum();
+        GameSummary {
+            uuid: uuid.to_string(),
+            depth: depth.into(),
+            moves: annotated.len(),
+            blunders: replay::blunders(annotated),
+            average_swing: match annotated.len() {
+                0 => 0.0,
+                moves => swings / moves as f64,
+            },
+        }
+    }
+
+    /* e.g. 6b1f2a3c: depth 5, 12 moves, blunders 1st 1, 2nd 0, average swing 0.083 */
+    pub fn line(&self, known: &[String]) -> String {
+        format!(
+            "{}: depth {}, {} moves, blunders {} {}, {} {}, average swing {:.3}",
+            game_id::short(&self.uuid, known),
+            self.depth,
+            self.moves,
+            clock::seat_name(0),
+            self.blunders[0],
+            clock::seat_name(1),
+            self.blunders[1],
+            self.average_swing
+        )
+    }
+}
+
+/* Which games `analyze --all` takes, as key=value: status=finished or
+   status=unfinished, variant=classic or variant=advanced
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Finished(bool),
+    Variant(Variant),
+}
+
+impl FromStr for Filter {
+    type Err = QuartoError;
+    fn from_str(s: &str) -> Result<Filter, QuartoError> {
+        let invalid = || QuartoError::InvalidFilter(s.to_string());
+        match s.split_once('=').ok_or_else(invalid)? {
+            ("status", "finished") => Ok(Filter::Finished(true)),
+            ("status", "unfinished") => Ok(Filter::Finished(false)),
+            ("variant", variant) => variant.parse().map(Filter::Variant).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Filter {
+    /* `last` is where the events leave the game */
+    pub fn matches(&self, records: &[Record], last: &Quarto) -> bool {
+        match self {
+            Filter::Finished(finished) => is_finished(records, last) == *finished,
+            Filter::Variant(variant) => last.rules.variant == *variant,
+        }
+    }
+}
+
+/* Over on the board, or ended by a resignation, forfeit, agreed draw or abort */
+fn is_finished(records: &[Record], last: &Quarto) -> bool {
+    last.status() != GameStatus::InProgress
+        || records.iter().any(|record| {
+            matches!(
+                record.event,
+                Event::Resigned { .. }
+                    | Event::Forfeited { .. }
+                    | Event::DrawAgreed { .. }
+                    | Event::Aborted { .. }
+            )
+        })
+}
+
+pub async fn init_summary(db: &Pool<Sqlite>) -> Result<SqliteQueryResult, SqlxError> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS analysis_summary
+        (
+              uuid VARCHAR PRIMARY KEY,
+              depth INTEGER NOT NULL,
+              moves INTEGER NOT NULL,
+              blunders_1st INTEGER NOT NULL,
+              blunders_2nd INTEGER NOT NULL,
+              average_swing REAL NOT NULL
+        );"#,
+    )
+    .execute(db)
+    .await
+}
+
+/* Shallower summaries never replace deeper ones */
+async fn store(db: &Pool<Sqlite>, summary: &GameSummary) -> Result<SqliteQueryResult, SqlxError> {
+    sqlx::query(
+        r#"
+        INSERT INTO analysis_summary
+            (uuid, depth, moves, blunders_1st, blunders_2nd, average_swing)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT (uuid) DO UPDATE
+        SET depth = excluded.depth, moves = excluded.moves,
+            blunders_1st = excluded.blunders_1st, blunders_2nd = excluded.blunders_2nd,
+            average_swing = excluded.average_swing
+        WHERE excluded.depth >= analysis_summary.depth;
+        "#,
+    )
+    .bind(&summary.uuid)
+    .bind(summary.depth)
+    .bind(summary.moves as i64)
+    .bind(summary.blunders[0] as i64)
+    .bind(summary.blunders[1] as i64)
+    .bind(summary.average_swing)
+    .execute(db)
+    .await
+}
+
+/* Every summary, in the order the games were created; none before the first
+   `analyze --all`
+*/
+pub async fn load(db: &Pool<Sqlite>) -> Result<Vec<GameSummary>, SqlxError> {
+    init_summary(db).await?;
+    let rows = sqlx::query_as::<_, (String, i64, i64, i64, i64, f64)>(
+        r#"
+        SELECT s.uuid, s.depth, s.moves, s.blunders_1st, s.blunders_2nd, s.average_swing
+        FROM analysis_summary s LEFT JOIN game g ON g.uuid = s.uuid
+        ORDER BY g.id, s.uuid
+        "#,
+    )
+    .fetch_all(db)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(
+            |(uuid, depth, moves, first, second, average_swing)| GameSummary {
+                uuid,
+                depth,
+                moves: moves as usize,
+                blunders: [first as usize, second as usize],
+                average_swing,
+            },
+        )
+        .collect())
+}
+
+/* What `analyze --all` did */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Batch {
+    pub summarized: Vec<GameSummary>,
+    /* Matching games summarized at least as deep before */
+    pub skipped: usize,
+}
+
+/* Summarizes the games matching every filter, `jobs` of them at a time */
+pub async fn run(
+    db: &Pool<Sqlite>,
+    policy: &DbPolicy,
+    filters: &[Filter],
+    depth: u32,
+    jobs: usize,
+    progress: &dyn Progress,
+) -> Result<Batch, Box<dyn Error>> {
+    let done: HashMap<String, i64> = policy
+        .run("load summaries", || load(db))
+        .await?
+        .into_iter()
+        .map(|summary| (summary.uuid, summary.depth))
+        .collect();
+    let uuids = policy.run("list games", || event::games(db)).await?;
+    progress.phase("selecting games", Some(uuids.len() as u64));
+    let mut pending: Vec<(String, Vec<(Quarto, Move)>)> = Vec::new();
+    let mut skipped = 0;
+    for uuid in uuids {
+        let records = policy
+            .run("load events", || event::load(db, &uuid, 0))
+            .await?;
+        let game = replay::plies(&records).and_then(|plies| {
+            let last = event::replay(&records)?.ok_or_else(|| {
+                QuartoError::CorruptRecord("the game was never created".to_string())
+            })?;
+            Ok((plies, last))
+        });
+        progress.advance(1);
+        let (plies, last) = match game {
+            Ok(game) => game,
+            Err(e) => {
+                warn!(uuid = %game_id::logged(&uuid), ?e, "skipping unreadable game");
+                continue;
+            }
+        };
+        if !filters.iter().all(|filter| filter.matches(&records, &last)) {
+            continue;
+        }
+        if done.get(&uuid).is_some_and(|done| *done >= depth.into()) {
+            skipped += 1;
+        } else {
+            pending.push((uuid, plies));
+        }
+    }
+    progress.phase("analysing games", Some((pending.len() + skipped) as u64));
+    progress.advance(skipped as u64);
+    let mut summarized: Vec<(usize, GameSummary)> = stream::iter(pending.iter().enumerate())
+        .map(|(n, (uuid, plies))| async move {
+            let annotated = replay::evaluate_game(db, policy, plies, depth).await?;
+            let summary = GameSummary::of(uuid, depth, &annotated);
+            policy.run("save summary", || store(db, &summary)).await?;
+            progress.advance(1);
+            Ok::<_, Box<dyn Error>>((n, summary))
+        })
+        .buffer_unordered(jobs.max(1))
+        .try_collect()
+        .await?;
+    progress.finish();
+    summarized.sort_by_key(|(n, _)| *n);
+    info!(games = summarized.len(), skipped, depth, "summarized games");
+    Ok(Batch {
+        summarized: summarized.into_iter().map(|(_, summary)| summary).collect(),
+        skipped,
+    })
+}
+
+impl fmt::Display for Batch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "summarized {} games, skipped {} summarized as deep before",
+            self.summarized.len(),
+            self.skipped
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cache;
+    use crate::progress::test::Recording;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    /* Three short games from BSCF in hand: a quarto after the 2nd player hands
+       over a fourth brown piece, a resignation and a game still in progress
+    */
+    const FIXTURE: &str = include_str!("../tests/fixtures/three-short-games.sql");
+
+    const WON: &str = "a0000000-0000-4000-8000-000000000001";
+    const RESIGNED: &str = "a0000000-0000-4000-8000-000000000002";
+    const UNFINISHED: &str = "a0000000-0000-4000-8000-000000000003";
+
+    async fn fixture_db() -> Pool<Sqlite> {
+        // A single connection keeps the in-memory database alive for the test.
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        for statement in FIXTURE.split(";\n").filter(|s| !s.trim().is_empty()) {
+            sqlx::query(statement).execute(&db).await.unwrap();
+        }
+        event::init_events(&db).await.unwrap();
+        cache::init_cache(&db).await.unwrap();
+        db
+    }
+
+    #[tokio::test]
+    async fn test_finished_games_are_summarized_once() {
+        let db = fixture_db().await;
+        let finished = ["status=finished".parse().unwrap()];
+        let recording = Recording::default();
+        let batch = run(&db, &DbPolicy::default(), &finished, 2, 2, &recording)
+            .await
+            .unwrap();
+        assert_eq!(batch.skipped, 0);
+        assert_eq!(
+            batch.summarized,
+            vec![
+                GameSummary {
+                    uuid: WON.to_string(),
+                    depth: 2,
+                    moves: 4,
+                    blunders: [0, 1],
+                    average_swing: 0.125,
+                },
+                GameSummary {
+                    uuid: RESIGNED.to_string(),
+                    depth: 2,
+                    moves: 2,
+                    blunders: [0, 0],
+                    average_swing: 0.0,
+                },
+            ]
+        );
+        assert_eq!(
+            recording.phases(),
+            vec![
+                ("selecting games".to_string(), Some(3), 3),
+                ("analysing games".to_string(), Some(2), 2)
+            ]
+        );
+        assert!(recording.finished());
+        assert_eq!(load(&db).await.unwrap(), batch.summarized);
+        let (engine, _, max_depth) = cache::stats(&db).await.unwrap().remove(0);
+        assert_eq!((engine.as_str(), max_depth), (replay::ENGINE, 2));
+
+        // Resumed: only what is not yet summarized this deep is searched
+        let again = run(
+            &db,
+            &DbPolicy::default(),
+            &finished,
+            2,
+            2,
+            &Recording::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!((again.summarized.len(), again.skipped), (0, 2));
+        let all = run(&db, &DbPolicy::default(), &[], 1, 1, &Recording::default())
+            .await
+            .unwrap();
+        assert_eq!(all.skipped, 2);
+        assert_eq!(all.summarized.len(), 1);
+        assert_eq!(all.summarized[0].uuid, UNFINISHED);
+        assert_eq!(
+            all.summarized[0].line(&[]),
+            "a0000000: depth 1, 2 moves, blunders 1st 0, 2nd 0, average swing 0.000"
+        );
+        assert_eq!(load(&db).await.unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_filters() {
+        assert_eq!(
+            "status=unfinished".parse::<Filter>().ok(),
+            Some(Filter::Finished(false))
+        );
+        assert_eq!(
+            "variant=advanced".parse::<Filter>().ok(),
+            Some(Filter::Variant(Variant::Advanced))
+        );
+        for invalid in ["status", "status=over", "variant=huge", "seat=1st"] {
+            assert!(invalid.parse::<Filter>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use crate::analysis::CpuShare;
+use crate::clock::TimeControl;
+use crate::db_policy::DbTimeout;
+use crate::deadline::Deadline;
+use crate::notation::{Letters, Notation, SLOTS};
+use crate::quarto::{QuartoError, Variant};
+use crate::spectate::SpectatorDelay;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/* Named new-game settings, read from a config file like
+
+       [templates.blitz]
+       variant = "advanced"
+       clock = "3m+2s"
+
+       [database]
+       timeout = "5s"
+       debug_sql = "true"
+
+       [games]
+       abort_before = "2"
+
+       [analysis]
+       cpu = "50%"
+
+       [notation]
+       color = ["F", "C"]
+       empty = "...."
+
+   Only [templates.<name>], [database], [games], [analysis] and [notation] tables are understood; other
+   tables are skipped. Values are quoted strings, and pairs of letters for the
+   [notation] slots. The file is QUARTO_CONFIG, or quarto.toml in the current
+   directory when that is unset and the file exists.
+*/
+pub const CONFIG_ENV: &str = "QUARTO_CONFIG";
+
+const DEFAULT_CONFIG: &str = "quarto.toml";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Config {
+    pub templates: BTreeMap<String, Template>,
+    /* Overridden by --db-timeout */
+    pub db_timeout: Option<DbTimeout>,
+    /* Turned on, not off, by --debug-sql */
+    pub debug_sql: bool,
+    /* How pieces are typed and shown; stored games stay canonical */
+    pub notation: Option<Letters>,
+    /* Plies from which `abort` takes both players */
+    pub abort_before: Option<usize>,
+    /* How much of a core `play` analyses with in the background */
+    pub analysis_cpu: Option<CpuShare>,
+}
+
+/* The table the lines being read belong to */
+enum Table {
+    Template(String),
+    Database,
+    Games,
+    Analysis,
+    Notation,
+    Other,
+}
+
+/* Every option is left unset unless the template names it */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Template {
+    pub variant: Option<Variant>,
+    pub clock: Option<TimeControl>,
+    pub deadline: Option<Deadline>,
+    pub spectator_delay: Option<SpectatorDelay>,
+}
+
+/* What a new game is created with: built-in defaults, overridden by a template,
+   overridden by explicit flags
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Settings {
+    pub variant: Variant,
+    pub clock: Option<TimeControl>,
+    pub deadline: Option<Deadline>,
+    pub spectator_delay: Option<SpectatorDelay>,
+}
+
+impl Settings {
+    pub fn resolve(template: Option<&Template>, flags: &Template) -> Settings {
+        let mut settings = Settings::default();
+        for layer in template.into_iter().chain([flags]) {
+            settings.variant = layer.variant.unwrap_or(settings.variant);
+            settings.clock = layer.clock.or(settings.clock);
+            settings.deadline = layer.deadline.or(settings.deadline);
+            settings.spectator_delay = layer.spectator_delay.or(settings.spectator_delay);
+        }
+        settings
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "variant {}", self.variant)?;
+        if let Some(clock) = self.clock {
+            write!(f, ", clock {}", clock)?;
+        }
+        if let Some(deadline) = self.deadline {
+            write!(f, ", deadline {}", deadline)?;
+        }
+        if let Some(delay) = self.spectator_delay {
+            write!(f, ", spectator delay {}", delay)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn parse(text: &str) -> Result<Config, QuartoError> {
+    let mut config = Config::default();
+    let mut current = Table::Other;
+    // The [notation] header line, and the scheme read so far
+    let mut notation: Option<(usize, [[char; 2]; 4], String)> = None;
+    for (n, raw) in text.lines().enumerate() {
+        let content = raw.split('#').next().unwrap_or_default().trim();
+        if content.is_empty() {
+            continue;
+        }
+        let error = |reason: String| QuartoError::ParseError {
+            line: n + 1,
+            column: raw.len() - raw.trim_start().len() + 1,
+            reason,
+        };
+        if let Some(header) = content.strip_prefix('[') {
+            let header = header
+                .strip_suffix(']')
+                .ok_or_else(|| error("unclosed table header".to_string()))?;
+            current = match header.trim() {
+                "database" => Table::Database,
+                "games" => Table::Games,
+                "analysis" => Table::Analysis,
+                "notation" => {
+                    let canonical = Letters::default();
+                    notation.get_or_insert((n + 1, canonical.letters(), canonical.empty().into()));
+                    Table::Notation
+                }
+                header => match header.strip_prefix("templates.") {
+                    Some(name) if !name.is_empty() => {
+                        if config
+                            .templates
+                            .insert(name.to_string(), Template::default())
+                            .is_some()
+                        {
+                            return Err(error(format!("template {} is defined twice", name)));
+                        }
+                        Table::Template(name.to_string())
+                    }
+                    _ => Table::Other,
+                },
+            };
+            continue;
+        }
+        let (key, value) = content
+            .split_once('=')
+            .ok_or_else(|| error("expected key = \"value\"".to_string()))?;
+        if matches!(current, Table::Other) {
+            continue;
+        }
+        let (key, value) = (key.trim(), value.trim());
+        if let (Table::Notation, Some((_, letters, _))) = (&current, &mut notation) {
+            if let Some(slot) = SLOTS.iter().position(|slot| *slot == key) {
+                letters[slot] = letter_pair(value).ok_or_else(|| {
+                    error(format!("{}: expected two letters like [\"B\", \"W\"]", key))
+                })?;
+                continue;
+            }
+        }
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .ok_or_else(|| error(format!("{}: expected a quoted string", key)))?;
+        let invalid = |_| error(format!("{}: invalid value {}", key, value));
+        match (&current, key) {
+            (Table::Database, "timeout") => {
+                config.db_timeout = Some(value.parse().map_err(invalid)?)
+            }
+            (Table::Database, "debug_sql") => {
+                let on = value.parse::<bool>().ok();
+                config.debug_sql =
+                    on.ok_or_else(|| error(format!("{}: invalid value {}", key, value)))?
+            }
+            (Table::Games, "abort_before") => {
+                let plies = value.parse::<usize>().ok();
+                config.abort_before =
+                    Some(plies.ok_or_else(|| error(format!("{}: invalid value {}", key, value)))?)
+            }
+            (Table::Analysis, "cpu") => {
+                config.analysis_cpu = Some(value.parse().map_err(invalid)?)
+            }
+            (Table::Notation, "empty") => {
+                if let Some((_, _, empty)) = &mut notation {
+                    *empty = value.to_string();
+                }
+            }
+            (Table::Template(name), _) => {
+                let template = config.templates.get_mut(name).unwrap();
+                match key {
+                    "variant" => template.variant = Some(value.parse().map_err(invalid)?),
+                    "clock" => template.clock = Some(value.parse().map_err(invalid)?),
+                    "deadline" => template.deadline = Some(value.parse().map_err(invalid)?),
+                    "spectator_delay" => {
+                        template.spectator_delay = Some(value.parse().map_err(invalid)?)
+                    }
+                    _ => return Err(error(format!("unknown key {}", key))),
+                }
+            }
+            _ => return Err(error(format!("unknown key {}", key))),
+        }
+    }
+    // Checked once the whole table is read, since the empty cell depends on the letters
+    if let Some((line, letters, empty)) = notation {
+        let letters = Letters::new(letters, &empty).map_err(|reason| QuartoError::ParseError {
+            line,
+            column: 1,
+            reason,
+        })?;
+        config.notation = Some(letters);
+    }
+    Ok(config)
+}
+
+/* ["B", "W"]: one letter for each value of a property */
+fn letter_pair(value: &str) -> Option<[char; 2]> {
+    let list = value.strip_prefix('[')?.strip_suffix(']')?;
+    let letters: Vec<char> = list
+        .split(',')
+        .map(|item| {
+            let mut chars = item.trim().strip_prefix('"')?.strip_suffix('"')?.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c),
+                _ => None,
+            }
+        })
+        .collect::<Option<_>>()?;
+    letters.try_into().ok()
+}
+
+pub fn load() -> Result<Config, QuartoError> {
+    let (path, required) = match env::var(CONFIG_ENV) {
+        Ok(path) => (PathBuf::from(path), true),
+        Err(_) => (PathBuf::from(DEFAULT_CONFIG), false),
+    };
+    match fs::read_to_string(&path) {
+        Ok(text) => parse(&text),
+        Err(e) if !required && e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(QuartoError::Io(e)),
+    }
+}
+
+pub fn lookup<'a>(
+    templates: &'a BTreeMap<String, Template>,
+    name: &str,
+) -> Result<&'a Template, QuartoError> {
+    templates
+        .get(name)
+        .ok_or_else(|| QuartoError::UnknownTemplate {
+            name: name.to_string(),
+            known: templates.keys().cloned().collect(),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use indoc::indoc;
+
+    const CONFIG: &str = indoc! {r#"
+        # house rules
+        [server]
+        port = "8080"
+
+        [templates.blitz]
+        variant = "advanced"
+        clock = "3m+2s"
+
+        [templates.correspondence]
+        deadline = "72h"   # a move every three days
+        spectator_delay = "30s"
+
+        [database]
+        timeout = "10s"
+        debug_sql = "true"
+
+        [games]
+        abort_before = "4"
+
+        [analysis]
+        cpu = "25%"
+        "#};
+
+    #[test]
+    fn test_parse_templates() {
+        let config = parse(CONFIG).unwrap();
+        assert_eq!(config.db_timeout, Some("10s".parse().unwrap()));
+        assert!(config.debug_sql);
+        assert_eq!(config.abort_before, Some(4));
+        assert_eq!(config.analysis_cpu, Some("25%".parse().unwrap()));
+        let templates = config.templates;
+        assert_eq!(
+            templates.keys().collect::<Vec<_>>(),
+            vec!["blitz", "correspondence"]
+        );
+        let blitz = lookup(&templates, "blitz").unwrap();
+        assert_eq!(blitz.variant, Some(Variant::Advanced));
+        assert_eq!(blitz.clock, Some("3m+2s".parse().unwrap()));
+        assert_eq!(blitz.deadline, None);
+        assert_eq!(
+            Settings::resolve(Some(blitz), &Template::default()).to_string(),
+            "variant advanced, clock 3m+2s"
+        );
+        let correspondence = &templates["correspondence"];
+        assert_eq!(
+            Settings::resolve(Some(correspondence), &Template::default()).to_string(),
+            "variant classic, deadline 72h, spectator delay 30s"
+        );
+
+        for (text, line) in [
+            ("[templates.a]\nclock = 3m", 2),
+            ("[templates.a]\nclock = \"3x\"", 2),
+            ("[templates.a]\n\n  colour = \"red\"", 3),
+            ("[templates.a]\n[templates.a]", 2),
+            ("[templates.a", 1),
+            ("[database]\ntimeout = \"0s\"", 2),
+            ("[database]\nretries = \"3\"", 2),
+            ("[database]\ndebug_sql = \"yes\"", 2),
+            ("[games]\nabort_before = \"two\"", 2),
+            ("[analysis]\ncpu = \"0%\"", 2),
+        ] {
+            let e = parse(text).unwrap_err();
+            assert!(
+                matches!(e, QuartoError::ParseError { line: l, .. } if l == line),
+                "{}: {:?}",
+                text,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_notation() {
+        assert_eq!(parse(CONFIG).unwrap().notation, None);
+        let config = parse(indoc! {r#"
+            [notation]
+            color = ["F", "C"]   # foncé, clair
+            height = [ "P","G" ]
+            shape = ["R", "C"]
+            top = ["P", "T"]
+            empty = "...."
+            "#})
+        .unwrap();
+        let notation = config.notation.unwrap();
+        assert_eq!(notation.letters()[0], ['F', 'C']);
+        assert_eq!(notation.letters()[3], ['P', 'T']);
+        assert_eq!(notation.empty(), "....");
+        assert_eq!(
+            parse("[notation]\nshape = [\"O\", \"Q\"]")
+                .unwrap()
+                .notation,
+            Some(Letters::new([['B', 'W'], ['S', 'T'], ['O', 'Q'], ['F', 'H']], "----").unwrap())
+        );
+
+        for (text, line) in [
+            ("[notation]\ncolor = [\"B\", \"B\"]", 1),
+            ("\n[notation]\ntop = [\"F\", \"F\"]\nempty = \"----\"", 2),
+            ("[notation]\nempty = \"BSCF\"", 1),
+            ("[notation]\ncolor = \"BW\"", 2),
+            ("[notation]\ncolor = [\"B\", \"W\", \"G\"]", 2),
+            ("[notation]\ncolor = [\"Br\", \"W\"]", 2),
+            ("[notation]\ncolour = \"B\"", 2),
+        ] {
+            let e = parse(text).unwrap_err();
+            assert!(
+                matches!(e, QuartoError::ParseError { line: l, .. } if l == line),
+                "{}: {:?}",
+                text,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_template_lists_known_ones() {
+        let templates = parse(CONFIG).unwrap().templates;
+        assert!(matches!(
+            lookup(&templates, "bullet"),
+            Err(QuartoError::UnknownTemplate { name, known })
+                if name == "bullet" && known == ["blitz", "correspondence"]
+        ));
+    }
+
+    #[test]
+    fn test_flags_override_template_over_defaults() {
+        let template = Template {
+            variant: Some(Variant::Advanced),
+            clock: Some("3m+2s".parse().unwrap()),
+            deadline: None,
+            spectator_delay: Some("30s".parse().unwrap()),
+        };
+        let flags = Template {
+            clock: Some("10m+5s".parse().unwrap()),
+            deadline: Some("72h".parse().unwrap()),
+            ..Template::default()
+        };
+
+        // Built-in defaults alone
+        assert_eq!(
+            Settings::resolve(None, &Template::default()),
+            Settings::default()
+        );
+        assert_eq!(Settings::default().variant, Variant::Classic);
+        // Flags without a template
+        let settings = Settings::resolve(None, &flags);
+        assert_eq!(settings.variant, Variant::Classic);
+        assert_eq!(settings.deadline, flags.deadline);
+        // The template fills what the flags leave out, the flags win where both are set
+        let settings = Settings::resolve(Some(&template), &flags);
+        assert_eq!(settings.variant, Variant::Advanced);
+        assert_eq!(settings.clock, flags.clock);
+        assert_eq!(settings.deadline, flags.deadline);
+        assert_eq!(settings.spectator_delay, template.spectator_delay);
+        let classic = Template {
+            variant: Some(Variant::Classic),
+            ..Template::default()
+        };
+        assert_eq!(
+            Settings::resolve(Some(&template), &classic).variant,
+            Variant::Classic
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use futures::future::{BoxFuture, FutureExt};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/* Where commands get the time from. Clocks, deadlines, sweeps and think times
+   all work in milliseconds since the Unix epoch, and `watch` waits between
+   polls with `sleep`, so a test can run any of them on a clock it moves itself.
+*/
+pub trait Clock: Send + Sync {
+    fn now(&self) -> i64;
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64)
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        tokio::time::sleep(duration).boxed()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use futures::future;
+    use std::sync::atomic::{AtomicI64, Ordering};
+    use std::sync::Arc;
+
+    /* Stands still until advanced; sleeping advances it and returns at once */
+    #[derive(Clone, Debug, Default)]
+    pub(crate) struct FakeClock(Arc<AtomicI64>);
+
+    impl FakeClock {
+        pub(crate) fn at(now: i64) -> FakeClock {
+            FakeClock(Arc::new(AtomicI64::new(now)))
+        }
+
+        pub(crate) fn advance(&self, duration: Duration) {
+            self.0
+                .fetch_add(duration.as_millis() as i64, Ordering::SeqCst);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> i64 {
+            self.0.load(Ordering::SeqCst)
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+            self.advance(duration);
+            future::ready(()).boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fake_clock_moves_only_when_told() {
+        let clock = FakeClock::at(1_000);
+        let shared = clock.clone();
+        assert_eq!(clock.now(), 1_000);
+        shared.advance(Duration::from_secs(2));
+        assert_eq!(clock.now(), 3_000);
+        clock.sleep(Duration::from_millis(500)).await;
+        assert_eq!(shared.now(), 3_500);
+    }
+}
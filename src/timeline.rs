@@ -0,0 +1,209 @@
+use crate::clock::{format_clock, seat_name, seat_to_move};
+use crate::event::{Event, Record};
+use crate::export::date;
+use crate::quarto::{GameStatus, Quarto, QuartoError};
+use crate::verify::Outcome;
+use clap::ValueEnum;
+
+/* `log` prints everything that happened to a game, a line per event:
+
+       2025-10-16
+       23:10 created with BSCF in hand
+       23:20 move 1: bob places at a1 and hands BTSH
+       23:30 alice offered a draw
+       2025-10-17
+       00:20 move 6: alice places at d1 — quarto, alice wins
+
+   Absolute times are UTC, with the date on a line of its own whenever it changes;
+   relative ones count from the first event. Players are named where their seat
+   records a name.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum TimeStyle {
+    Absolute,
+    Relative,
+}
+
+/* hh:mm in UTC */
+fn time_of_day(millis: i64) -> String {
+    let minutes = millis.div_euclid(60_000).rem_euclid(24 * 60);
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/* The line for a move's result, once the board has decided one */
+fn ending(quarto: &Quarto, who: &dyn Fn(usize) -> String) -> Option<String> {
+    let how = match quarto.status() {
+        GameStatus::Adjudicated(_) => "dead position",
+        GameStatus::Drawn => "the board is full",
+        _ => "quarto",
+    };
+    match Outcome::of(quarto)? {
+        Outcome::Won(seat) => Some(format!("{}, {} wins", how, who(seat))),
+        Outcome::Drawn => Some(format!("{}, drawn", how)),
+    }
+}
+
+pub fn format_events(
+    records: &[Record],
+    style: TimeStyle,
+    players: &[Option<String>; 2],
+) -> Result<Vec<String>, QuartoError> {
+    let who = |seat: usize| {
+        players[seat]
+            .clone()
+            .unwrap_or_else(|| format!("{} player", seat_name(seat)))
+    };
+    let start = records.first().map_or(0, |record| record.created_at);
+    let mut quarto: Option<Quarto> = None;
+    let mut moves = 0;
+    let mut day = None;
+    let mut lines = Vec::new();
+    for record in records {
+        let text = match &record.event {
+            Event::Created { position } => {
+                let opening = Quarto::from_share_code(position)?;
+                let text = match (opening.placed_pieces(), opening.next_piece) {
+                    (0, Some(piece)) => format!("created with {} in hand", piece),
+                    _ => format!("created at {}", position),
+                };
+                quarto = Some(opening);
+                text
+            }
+            Event::Position { position } => {
+                quarto = Some(Quarto::from_share_code(position)?);
+                format!("board set to {}", position)
+            }
+            Event::Move { ply } => {
+                let Some(quarto) = quarto.as_mut() else {
+                    return Err(QuartoError::CorruptRecord(format!(
+                        "event {}: move before the game was created",
+                        record.seq
+                    )));
+                };
+                let mover = who(seat_to_move(quarto));
+                if !quarto.apply_move(ply) {
+                    return Err(QuartoError::CorruptRecord(format!(
+                        "event {}: illegal move {}",
+                        record.seq, ply
+                    )));
+                }
+                moves += 1;
+                let played = match (ply.place, ply.hand) {
+                    (Some(at), Some(piece)) => format!("places at {} and hands {}", at, piece),
+                    (Some(at), None) => format!("places at {}", at),
+                    (None, Some(piece)) => format!("hands {}", piece),
+                    (None, None) => "passes".to_string(),
+                };
+                let mut text = format!("move {}: {} {}", moves, mover, played);
+                if let Some(ending) = ending(quarto, &who) {
+                    text.push_str(&format!(" — {}", ending));
+                }
+                text
+            }
+            Event::Forfeited { seat, reason } => format!("{} forfeited: {}", who(*seat), reason),
+            Event::Resigned { seat } => format!("{} resigned", who(*seat)),
+            Event::DrawOffered { seat } => format!("{} offered a draw", who(*seat)),
+            Event::DrawAgreed { seat } => format!("{} accepted the draw", who(*seat)),
+            Event::AbortRequested { seat } => format!("{} asked to abort", who(*seat)),
+            Event::Aborted { seat } => format!("{} aborted the game", who(*seat)),
+        };
+        let at = match style {
+            TimeStyle::Absolute => {
+                let today = date(record.created_at);
+                if day.as_ref() != Some(&today) {
+                    lines.push(today.clone());
+                    day = Some(today);
+                }
+                time_of_day(record.created_at)
+            }
+            TimeStyle::Relative => format!("+{}", format_clock(record.created_at - start)),
+        };
+        lines.push(format!("{} {}", at, text));
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GOLDEN: &str = include_str!("../tests/fixtures/timeline.txt");
+
+    fn ply(text: &str) -> Event {
+        Event::Move {
+            ply: text.parse().unwrap(),
+        }
+    }
+
+    /* Every kind of event, an hour before midnight and on into the next day */
+    fn records() -> Vec<Record> {
+        let mut opening = Quarto::new();
+        opening.apply_move(&"BSCF".parse().unwrap());
+        let mut set = opening.clone();
+        set.apply_move(&"a1 BTSH".parse().unwrap());
+        let events = vec![
+            Event::Created {
+                position: opening.to_share_code(),
+            },
+            ply("a1 BTSH"),
+            Event::DrawOffered { seat: 0 },
+            Event::AbortRequested { seat: 1 },
+            Event::Position {
+                position: set.to_share_code(),
+            },
+            ply("b1 WSCH"),
+            Event::DrawAgreed { seat: 1 },
+            Event::Resigned { seat: 0 },
+            Event::Forfeited {
+                seat: 1,
+                reason: "missed the deadline".to_string(),
+            },
+            Event::Aborted { seat: 0 },
+            ply("d4 BTCF"),
+            ply("c1 WTSF"),
+            ply("a4 BSSH"),
+            ply("d1"),
+        ];
+        events
+            .into_iter()
+            .zip(1..)
+            .map(|(event, seq)| Record {
+                seq,
+                event,
+                created_at: 1_760_655_600_000 + seq * 600_000,
+                think_ms: None,
+                handed_by: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_timeline_matches_golden_file() {
+        let players = [Some("alice".to_string()), None];
+        let lines = format_events(&records(), TimeStyle::Absolute, &players).unwrap();
+        assert_eq!(lines.join("\n") + "\n", GOLDEN);
+
+        let relative = format_events(&records(), TimeStyle::Relative, &[None, None]).unwrap();
+        assert_eq!(relative[0], "+0:00 created with BSCF in hand");
+        assert_eq!(
+            relative[13],
+            "+2:10:00 move 6: 1st player places at d1 — quarto, 1st player wins"
+        );
+        assert_eq!(relative.len(), records().len());
+    }
+
+    #[test]
+    fn test_moves_before_creation_are_refused() {
+        let records = vec![Record {
+            seq: 1,
+            event: ply("a1 BTSH"),
+            created_at: 0,
+            think_ms: None,
+            handed_by: None,
+        }];
+        assert!(matches!(
+            format_events(&records, TimeStyle::Absolute, &[None, None]),
+            Err(QuartoError::CorruptRecord(_))
+        ));
+    }
+}
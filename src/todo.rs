@@ -0,0 +1,222 @@
+use crate::clock::{self, format_clock};
+use crate::deadline;
+use crate::game_id;
+use crate::quarto::{square, GameStatus, Piece};
+use crate::store;
+use serde::Serialize;
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite};
+use tracing::warn;
+
+/* A game waiting on a move from the identity in use, as `todo` lists it. Times are
+   milliseconds: what is left on their clock, until the move deadline, and since the
+   Unix epoch for the game's last event.
+*/
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Pending {
+    pub uuid: String,
+    pub opponent: Option<String>,
+    pub hand: Option<Piece>,
+    pub clock_ms: Option<i64>,
+    pub due_in_ms: Option<i64>,
+    pub updated_at: Option<i64>,
+    /* Where the piece in hand completes a line */
+    pub wins_at: Option<String>,
+}
+
+impl Pending {
+    /* The sooner of the clock and the deadline, when the game has either */
+    pub fn time_left(&self) -> Option<i64> {
+        self.clock_ms.into_iter().chain(self.due_in_ms).min()
+    }
+
+    /* e.g. 6b1f2a3c  vs bob  hand WTCH  clock 4:32  due in 1:59:00, and on a second line
+       the warning when the piece in hand wins at once
+    */
+    pub fn line(&self, known: &[String]) -> String {
+        let mut line = format!(
+            "{}  vs {}  hand {}",
+            game_id::short(&self.uuid, known),
+            self.opponent.as_deref().unwrap_or("nobody yet"),
+            self.hand.map_or("none".to_string(), String::from)
+        );
+        if let Some(ms) = self.clock_ms {
+            line.push_str(&format!("  clock {}", format_clock(ms)));
+        }
+        match self.due_in_ms {
+            Some(ms) if ms > 0 => line.push_str(&format!("  due in {}", format_clock(ms))),
+            Some(_) => line.push_str("  overdue"),
+            None => {}
+        }
+        if let (Some(p), Some(at)) = (self.hand, &self.wins_at) {
+            line.push_str(&format!("\n  ! {} wins at {}", String::from(p), at));
+        }
+        line
+    }
+}
+
+type PendingRow = (
+    String,
+    Option<String>,
+    Option<String>,
+    bool,
+    Option<i64>,
+    Option<String>,
+    Option<String>,
+    Option<i64>,
+);
+
+/* In-progress games where `name` holds the seat to move, the least time left first
+   and then the longest waiting. Games without a clock or a deadline come last.
+*/
+pub async fn pending(db: &Pool<Sqlite>, name: &str, now: i64) -> Result<Vec<Pending>, SqlxError> {
+    let rows = sqlx::query_as::<_, PendingRow>(
+        r#"
+        SELECT uuid, board_state, next_piece, advanced, board_format, player_1st, player_2nd,
+               (SELECT MAX(created_at) FROM event WHERE event.uuid = game.uuid)
+        FROM game
+        WHERE uuid IS NOT NULL AND (player_1st = ?1 OR player_2nd = ?1)
+              AND flagged IS NULL AND forfeited IS NULL AND resigned IS NULL
+              AND draw_agreed = false AND aborted IS NULL
+        ORDER BY id
+        "#,
+    )
+    .bind(name)
+    .fetch_all(db)
+    .await?;
+    let mut games = Vec::new();
+    for (uuid, board_state, next_piece, advanced, board_format, first, second, updated_at) in rows {
+        let quarto = match store::from_row(&board_state, &next_piece, advanced, board_format) {
+            Ok(quarto) => quarto,
+            Err(e) => {
+                warn!(uuid = %game_id::logged(&uuid), ?e, "skipping unreadable game");
+                continue;
+            }
+        };
+        if quarto.status() != GameStatus::InProgress {
+            continue;
+        }
+        let seat = clock::seat_to_move(&quarto);
+        let players = [first, second];
+        if players[seat].as_deref() != Some(name) {
+            continue;
+        }
+        let clock_ms = clock::load(db, &uuid)
+            .await?
+            .map(|game_clock| game_clock.remaining_at(seat, seat, now));
+        let due_in_ms = deadline::load(db, &uuid)
+            .await?
+            .map(|deadline| deadline.due_at - now);
+        let wins_at = quarto
+            .next_piece
+            .and_then(|p| quarto.winning_cell(&p))
+            .map(|at| square(at).to_string());
+        games.push(Pending {
+            uuid,
+            opponent: players[1 - seat].clone(),
+            hand: quarto.next_piece,
+            clock_ms,
+            due_in_ms,
+            updated_at,
+            wins_at,
+        });
+    }
+    games.sort_by_key(|game| {
+        let left = game.time_left();
+        (left.is_none(), left, game.updated_at)
+    });
+    Ok(games)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory_db;
+
+    const EMPTY: &str = "---- ---- ---- ----\n---- ---- ---- ----";
+
+    /* alice's turn in game-1, game-2, game-4 and game-7; bob's in game-3; game-5 is
+       won, game-6 resigned and game-8 not alice's
+    */
+    async fn fixture_db() -> Pool<Sqlite> {
+        let db = memory_db().await;
+        // One piece placed and one in hand: the 1st player is to move
+        let one = format!("BSCF ---- ---- ----\n---- ---- ---- ----\n{}", EMPTY);
+        let three = format!("BSCF BSCH BSSF ----\n---- ---- ---- ----\n{}", EMPTY);
+        let won = format!("BSCF BSCH BSSF BTSH\n---- ---- ---- ----\n{}", EMPTY);
+        let empty = format!("{}\n{}", EMPTY, EMPTY);
+        for (uuid, board, hand, first, second, resigned) in [
+            ("game-1", &one, "WTCH", "alice", Some("bob"), None),
+            ("game-2", &three, "BTCH", "alice", Some("bob"), None),
+            ("game-3", &empty, "WTCH", "alice", Some("bob"), None),
+            ("game-4", &one, "WTCH", "alice", None, None),
+            ("game-5", &won, "WTCH", "alice", Some("bob"), None),
+            ("game-6", &one, "WTCH", "alice", Some("bob"), Some(1)),
+            ("game-7", &one, "WTCH", "alice", Some("carol"), None),
+            ("game-8", &one, "WTCH", "bob", Some("alice"), None),
+        ] {
+            sqlx::query(
+                r#"
+                INSERT INTO game
+                    (uuid, board_state, next_piece, board_format, player_1st, player_2nd, resigned)
+                VALUES (?1, ?2, ?3, 1, ?4, ?5, ?6)
+                "#,
+            )
+            .bind(uuid)
+            .bind(board)
+            .bind(hand)
+            .bind(first)
+            .bind(second)
+            .bind(resigned)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        for (uuid, created_at) in [("game-1", 500), ("game-4", 300), ("game-7", 100)] {
+            sqlx::query(
+                "INSERT INTO event (uuid, seq, kind, payload, created_at) VALUES (?1, 1, 'x', '{}', ?2)",
+            )
+            .bind(uuid)
+            .bind(created_at)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+        for statement in [
+            "UPDATE game SET deadline_secs = 7200, due_at = 7200000 WHERE uuid = 'game-2';",
+            r#"UPDATE game SET time_control = '10m+5s', clock_1st = 240000, clock_2nd = 600000,
+                               last_move_at = 0 WHERE uuid = 'game-4';"#,
+        ] {
+            sqlx::query(statement).execute(&db).await.unwrap();
+        }
+        db
+    }
+
+    #[tokio::test]
+    async fn test_pending_lists_only_games_to_move_in() {
+        let db = fixture_db().await;
+        let games = pending(&db, "alice", 60_000).await.unwrap();
+        let uuids: Vec<&str> = games.iter().map(|game| game.uuid.as_str()).collect();
+        // The clock runs out before the deadline; then the longest waiting
+        assert_eq!(uuids, ["game-4", "game-2", "game-7", "game-1"]);
+
+        let known: Vec<String> = uuids.iter().map(|uuid| uuid.to_string()).collect();
+        let lines: Vec<String> = games.iter().map(|game| game.line(&known)).collect();
+        assert_eq!(lines[0], "game-4  vs nobody yet  hand WTCH  clock 3:00");
+        assert_eq!(
+            lines[1],
+            "game-2  vs bob  hand BTCH  due in 1:59:00\n  ! BTCH wins at d4"
+        );
+        assert_eq!(lines[2], "game-7  vs carol  hand WTCH");
+        assert_eq!(games[1].time_left(), Some(7_140_000));
+        assert_eq!(games[3].updated_at, Some(500));
+
+        let bobs = pending(&db, "bob", 60_000).await.unwrap();
+        let bobs: Vec<&str> = bobs.iter().map(|game| game.uuid.as_str()).collect();
+        assert_eq!(bobs, ["game-3", "game-8"]);
+        assert!(pending(&db, "dave", 60_000).await.unwrap().is_empty());
+        let overdue = pending(&db, "alice", 8_000_000).await.unwrap();
+        assert_eq!(overdue[0].uuid, "game-2");
+        assert!(overdue[0].line(&known).contains("overdue"));
+    }
+}
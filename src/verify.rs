@@ -0,0 +1,188 @@
+use crate::clock::seat_to_move;
+use crate::quarto::{Adjudication, GameStatus, Piece, Quarto, Variant};
+use sqlx::Error as SqlxError;
+use sqlx::{Pool, Sqlite, SqliteConnection};
+
+/* A finished game's result_hash, so results edited after the fact can be spotted.
+   Only final boards are stored, so the hash covers the final position and the
+   result it decides. Version 1 hashes these bytes with 64-bit FNV-1a:
+
+       b"quarto-result"   13 bytes
+       version            1 byte, 1
+       variant            1 byte, 0 classic, 1 advanced
+       cells              16 bytes, row by row as in board_state: the piece's
+                          to_index, or 0xff for an empty cell
+       piece in hand      1 byte, to_index or 0xff
+       outcome            1 byte, 0 won by the 1st player, 1 won by the 2nd, 2 drawn
+
+   and writes it as "1:" followed by 16 lower case hex digits.
+*/
+pub const HASH_VERSION: u8 = 1;
+
+const EMPTY: u8 = 0xff;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /* Seat 0 is the 1st player, 1 the 2nd */
+    Won(usize),
+    Drawn,
+}
+
+impl Outcome {
+    /* The result the board decides: the last placement completed a line, the board
+       is full, or the rules adjudicated a dead position
+    */
+    pub fn of(quarto: &Quarto) -> Option<Outcome> {
+        match quarto.status() {
+            GameStatus::InProgress => None,
+            GameStatus::Won => Some(Outcome::Won(1 - seat_to_move(quarto))),
+            GameStatus::Drawn => Some(Outcome::Drawn),
+            GameStatus::Adjudicated(Adjudication::Loss) => Some(Outcome::Won(seat_to_move(quarto))),
+            GameStatus::Adjudicated(Adjudication::Draw) => Some(Outcome::Drawn),
+        }
+    }
+}
+
+pub fn canonical_bytes(quarto: &Quarto, outcome: Outcome) -> Vec<u8> {
+    let index = |piece: Option<Piece>| piece.map_or(EMPTY, |p| p.to_index());
+    let mut bytes = b"quarto-result".to_vec();
+    bytes.push(HASH_VERSION);
+    bytes.push(match quarto.rules.variant {
+        Variant::Classic => 0,
+        Variant::Advanced => 1,
+    });
+    bytes.extend(
+        quarto
+            .board_state
+            .cells()
+            .iter()
+            .flatten()
+            .map(|cell| index(*cell)),
+    );
+    bytes.push(index(quarto.next_piece));
+    bytes.push(match outcome {
+        Outcome::Won(seat) => seat as u8,
+        Outcome::Drawn => 2,
+    });
+    bytes
+}
+
+pub fn result_hash(quarto: &Quarto, outcome: Outcome) -> String {
+    let hash = canonical_bytes(quarto, outcome)
+        .iter()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+        });
+    format!("{}:{:016x}", HASH_VERSION, hash)
+}
+
+/* Seals the result once: false, leaving the hash as it was, when the game already
+   has one. Pass the transaction which finishes the game.
+*/
+pub async fn seal(conn: &mut SqliteConnection, uuid: &str, hash: &str) -> Result<bool, SqlxError> {
+    let result =
+        sqlx::query("UPDATE game SET result_hash = ?2 WHERE uuid = ?1 AND result_hash IS NULL;")
+            .bind(uuid)
+            .bind(hash)
+            .execute(conn)
+            .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn load(db: &Pool<Sqlite>, uuid: &str) -> Result<Option<String>, SqlxError> {
+    Ok(
+        sqlx::query_as::<_, (Option<String>,)>("SELECT result_hash FROM game WHERE uuid = ?1")
+            .bind(uuid)
+            .fetch_optional(db)
+            .await?
+            .and_then(|(hash,)| hash),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use indoc::indoc;
+    use std::convert::TryFrom;
+
+    fn game(board_text: &str) -> Quarto {
+        Quarto::try_from(&board_text.to_string()).unwrap()
+    }
+
+    /* The 2nd player placed BTSH last, on the fifth placement */
+    fn won() -> Quarto {
+        game(indoc! {
+        r#"BSCF BSCH BSSF BTSH
+           ---- WTCH ---- ----
+           ---- ---- ---- ----
+           ---- ---- ---- ----"#})
+    }
+
+    #[test]
+    fn test_outcome_of_board() {
+        assert_eq!(Outcome::of(&won()), Some(Outcome::Won(1)));
+        assert_eq!(Outcome::of(&Quarto::new()), None);
+    }
+
+    #[test]
+    fn test_canonical_bytes() {
+        let bytes = canonical_bytes(&won(), Outcome::Won(1));
+        assert_eq!(bytes.len(), 13 + 1 + 1 + 16 + 1 + 1);
+        assert_eq!(&bytes[..13], b"quarto-result");
+        assert_eq!(bytes[13..15], [HASH_VERSION, 0]);
+        assert_eq!(bytes[15..19], [0, 1, 2, 0b0111]);
+        assert_eq!(bytes[19], EMPTY);
+        assert_eq!(bytes[31..], [EMPTY, 1]);
+        // Stable across releases: a change here needs a new HASH_VERSION
+        assert_eq!(result_hash(&won(), Outcome::Won(1)), "1:7f19af100b186ce2");
+    }
+
+    #[test]
+    fn test_any_single_change_changes_the_hash() {
+        let quarto = won();
+        let sealed = result_hash(&quarto, Outcome::Won(1));
+        let cells: Vec<String> = quarto
+            .board_state
+            .to_display_string()
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+        let edited = |cells: &[String]| {
+            let rows: Vec<String> = cells.chunks(4).map(|row| row.join(" ")).collect();
+            game(&rows.join("\n"))
+        };
+        let codes: Vec<String> = (0..16)
+            .map(|i: usize| {
+                ["BW", "ST", "CS", "FH"]
+                    .iter()
+                    .enumerate()
+                    .map(|(bit, letters)| letters.as_bytes()[(i >> (3 - bit)) & 1] as char)
+                    .collect()
+            })
+            .collect();
+
+        // Every placed piece moved to each empty cell, or swapped for an unplaced piece
+        let placed: Vec<usize> = (0..16).filter(|i| cells[*i] != "----").collect();
+        for &from in &placed {
+            for to in (0..16).filter(|i| !placed.contains(i)) {
+                let mut moved = cells.clone();
+                moved.swap(from, to);
+                assert_ne!(result_hash(&edited(&moved), Outcome::Won(1)), sealed);
+            }
+            for code in codes.iter().filter(|code| !cells.contains(code)) {
+                let mut swapped = cells.clone();
+                swapped[from] = code.clone();
+                assert_ne!(result_hash(&edited(&swapped), Outcome::Won(1)), sealed);
+            }
+        }
+        assert_eq!(result_hash(&edited(&cells), Outcome::Won(1)), sealed);
+
+        // The claimed winner
+        assert_ne!(result_hash(&quarto, Outcome::Won(0)), sealed);
+        assert_ne!(result_hash(&quarto, Outcome::Drawn), sealed);
+        // The rules
+        let mut advanced = quarto.clone();
+        advanced.rules.variant = Variant::Advanced;
+        assert_ne!(result_hash(&advanced, Outcome::Won(1)), sealed);
+    }
+}
@@ -0,0 +1,91 @@
+/* Allocation counts of the engine's hot paths. The counting allocator is the
+   global one of this binary alone, and it runs without the test harness, so each
+   check has the process to itself and no other test's allocations are counted.
+
+   The crate has no library target, so the engine is built in here from its
+   sources. Their test modules come along without their tests.
+*/
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src/intersperse.rs"]
+mod intersperse;
+#[path = "../src/notation.rs"]
+mod notation;
+#[path = "../src/quarto.rs"]
+mod quarto;
+
+use intersperse::intersperse;
+use quarto::{BoardState, Move, Piece, Quarto};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let result = f();
+    (result, ALLOCATIONS.load(Ordering::Relaxed) - before)
+}
+
+fn is_quarto_does_not_allocate() {
+    let won = "BSCF BSCH BSSF WTSH\n---- ---- ---- ----\n---- ---- ---- ----\nBTCF BTCH BTSF BTSH";
+    let quarto = Quarto::try_from(&won.to_string()).unwrap();
+    assert_eq!(allocations(|| quarto.is_quarto()), (true, 0));
+    let open = won.replace("BTSF BTSH", "BTSF ----");
+    let quarto = Quarto::try_from(&open).unwrap();
+    assert_eq!(allocations(|| quarto.is_quarto()), (false, 0));
+}
+
+fn rendering_allocates_only_the_result() {
+    let text = "BSCF ---- BSSF ----\n---- WTCH ---- ----\n---- ---- ---- ----\n---- ---- ---- WTSH";
+    let board = BoardState::try_from(&text.to_string()).unwrap();
+    let (display, count) = allocations(|| board.to_display_string());
+    assert_eq!((display.as_str(), count), (text, 1));
+    let (stored, count) = allocations(|| String::from(board));
+    assert_eq!(stored, text.replace("----", "    "));
+    assert_eq!(count, 1);
+
+    let piece: Piece = "WTCH".parse().unwrap();
+    assert_eq!(allocations(|| String::from(piece)), ("WTCH".to_string(), 1));
+    let mv: Move = "b2 WTSH".parse().unwrap();
+    assert_eq!(allocations(|| mv.to_string()), ("b2 WTSH".to_string(), 1));
+}
+
+/* What generate writes for a game: the moves joined straight into one buffer */
+fn transcript_allocates_once() {
+    let moves: Vec<Move> = ["BSCF", "a1 WSCF", "b2 BTCH", "c3"]
+        .iter()
+        .map(|mv| mv.parse().unwrap())
+        .collect();
+    let (text, count) = allocations(|| {
+        let mut text = String::with_capacity(moves.len() * "a1 BSCF; ".len());
+        let _ = write!(text, "{}", intersperse(&moves, "; "));
+        text
+    });
+    assert_eq!((text.as_str(), count), ("BSCF; a1 WSCF; b2 BTCH; c3", 1));
+}
+
+fn main() {
+    // The line tables are built on first use
+    Quarto::new().is_quarto();
+    is_quarto_does_not_allocate();
+    rendering_allocates_only_the_result();
+    transcript_allocates_once();
+}
@@ -0,0 +1,61 @@
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/* A directory of its own for each test, holding the database, the identity file
+   and anything the binary writes. The binary runs inside it, so no config file
+   from the working tree is picked up.
+*/
+pub struct Scratch {
+    pub dir: PathBuf,
+}
+
+impl Scratch {
+    pub fn new(name: &str) -> Scratch {
+        let dir = env::temp_dir().join(format!("quarto-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Scratch { dir }
+    }
+
+    pub fn database_url(&self) -> String {
+        format!("sqlite://{}", self.dir.join("games.sqlite").display())
+    }
+
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_quarto"));
+        command
+            .args(args)
+            .current_dir(&self.dir)
+            .env("DATABASE_URL", self.database_url())
+            .env("QUARTO_IDENTITIES", self.dir.join("identities"))
+            .env_remove("QUARTO_CONFIG")
+            .env("RUST_LOG", "warn");
+        command
+    }
+
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    /* stdout of a run that must succeed */
+    pub fn ok(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "quarto {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
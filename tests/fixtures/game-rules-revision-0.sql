@@ -0,0 +1,19 @@
+-- Games as stamped by a build before rules revision 1, next to one from before
+-- games were stamped at all
+CREATE TABLE game
+(
+      id INTEGER PRIMARY KEY,
+      uuid VARCHAR,
+      next_piece VARCHAR,
+      board_state VARCHAR,
+      board_format INTEGER,
+      engine_version VARCHAR
+);
+INSERT INTO game (uuid, next_piece, board_state, board_format, engine_version) VALUES ('e1a2b3c4-0000-4000-8000-000000000000', 'BSCF', '---- ---- ---- ----
+---- ---- ---- ----
+---- ---- ---- ----
+---- ---- ---- ----', 1, '0.1.0+rules.0');
+INSERT INTO game (uuid, next_piece, board_state, board_format, engine_version) VALUES ('e1a2b3c4-0000-4000-8000-00000000000f', 'BSCF', '---- ---- ---- ----
+---- ---- ---- ----
+---- ---- ---- ----
+---- ---- ---- ----', 1, NULL);
@@ -0,0 +1,64 @@
+-- Written by quarto before game.board_format existed, with the schema `init`
+-- created then. Game boards are multi-line text with blank empty cells; some
+-- rows have dashed cells or lines with their trailing blanks stripped.
+CREATE TABLE game
+(
+      id INTEGER PRIMARY KEY,
+      uuid VARCHAR,
+      assigned_1st BOOLEAN NOT NULL default false,
+      assigned_2nd BOOLEAN NOT NULL default false,
+      next_piece VARCHAR,
+      board_state VARCHAR,
+      advanced BOOLEAN NOT NULL default false,
+      setup BOOLEAN NOT NULL default false,
+      time_control VARCHAR,
+      clock_1st INTEGER,
+      clock_2nd INTEGER,
+      last_move_at INTEGER,
+      flagged INTEGER,
+      deadline_secs INTEGER,
+      due_at INTEGER,
+      forfeited INTEGER,
+      forfeit_reason VARCHAR,
+      spectator_delay_secs INTEGER,
+      result_hash VARCHAR,
+      resigned INTEGER,
+      draw_offered_by INTEGER,
+      draw_offered_at INTEGER,
+      draw_agreed BOOLEAN NOT NULL default false
+);
+CREATE UNIQUE INDEX game_uuid ON game (uuid);
+CREATE TABLE event
+(
+      uuid VARCHAR NOT NULL,
+      seq INTEGER NOT NULL,
+      kind VARCHAR NOT NULL,
+      payload VARCHAR NOT NULL,
+      created_at INTEGER NOT NULL,
+      PRIMARY KEY (uuid, seq)
+);
+-- In progress, with its events; the second move has the cell as an [x, y] pair
+INSERT INTO game (uuid, next_piece, board_state) VALUES ('0f6a2a3e-5d0c-4c43-9a53-2f1f3c1a7b10', 'BTSF', 'BSCF               
+     WTCH          
+                   
+                   ');
+INSERT INTO event VALUES ('0f6a2a3e-5d0c-4c43-9a53-2f1f3c1a7b10', 1, 'created', '{"kind":"created","position":"AQAAAAAAAAAAAAAAAQ4C"}', 1700000000000);
+INSERT INTO event VALUES ('0f6a2a3e-5d0c-4c43-9a53-2f1f3c1a7b10', 2, 'move', '{"kind":"move","ply":{"place":"a4","hand":"WTCH"}}', 1700000060000);
+INSERT INTO event VALUES ('0f6a2a3e-5d0c-4c43-9a53-2f1f3c1a7b10', 3, 'move', '{"kind":"move","ply":{"place":[1,1],"hand":"BTSF"}}', 1700000120000);
+-- Won, so nothing is in hand
+INSERT INTO game (uuid, next_piece, board_state, result_hash) VALUES ('5b0e8c52-8f71-4d2e-b6a4-9c3d7e2f4a01', NULL, 'BSCF BSCH BSSF BTSH
+---- WTCH ---- ----
+---- ---- ---- WSSF
+---- ---- ---- ----', NULL);
+-- Advanced, on a clock
+INSERT INTO game (uuid, next_piece, board_state, advanced, time_control, clock_1st, clock_2nd, last_move_at) VALUES ('9d4c1b7a-2e3f-4a5b-8c6d-7e8f9a0b1c2d', 'WSCF', 'WSSH WTSF
+
+
+BTCF', true, '5m+3s', 281000, 300000, 1700000300000);
+-- Drawn on a full board
+INSERT INTO game (uuid, next_piece, board_state) VALUES ('c3e1f2a4-6b7d-4e8f-9a0b-1c2d3e4f5a6b', NULL, 'BSSF WTSF BSCF WSSH
+WSCF BSSH BTCF WTCH
+WSCH BTCH BTSF BSCH
+BTSH WSSF WTSH WTCF');
+-- Never got a board
+INSERT INTO game (uuid) VALUES ('e7f8a9b0-c1d2-4e3f-8a4b-5c6d7e8f9a0b');
@@ -0,0 +1,34 @@
+-- Three games from BSCF in hand, each created a minute apart with a move a
+-- minute. The first ends on a quarto along row 1 after the 2nd player hands
+-- over a fourth brown piece, the 1st player resigns the second, and the third
+-- is still in progress.
+CREATE TABLE game
+(
+      id INTEGER PRIMARY KEY,
+      uuid VARCHAR
+);
+CREATE TABLE event
+(
+      uuid VARCHAR NOT NULL,
+      seq INTEGER NOT NULL,
+      kind VARCHAR NOT NULL,
+      payload VARCHAR NOT NULL,
+      created_at INTEGER NOT NULL,
+      think_ms INTEGER,
+      PRIMARY KEY (uuid, seq)
+);
+INSERT INTO game (uuid) VALUES ('a0000000-0000-4000-8000-000000000001');
+INSERT INTO game (uuid) VALUES ('a0000000-0000-4000-8000-000000000002');
+INSERT INTO game (uuid) VALUES ('a0000000-0000-4000-8000-000000000003');
+INSERT INTO event (uuid, seq, kind, payload, created_at) VALUES ('a0000000-0000-4000-8000-000000000001', 1, 'created', '{"kind":"created","position":"AQAAAAAAAAAAAAAAAQ4C"}', 1700000000000);
+INSERT INTO event (uuid, seq, kind, payload, created_at) VALUES ('a0000000-0000-4000-8000-000000000001', 2, 'move', '{"kind":"move","ply":{"place":"a1","hand":"BSCH"}}', 1700000060000);
+INSERT INTO event (uuid, seq, kind, payload, created_at) VALUES ('a0000000-0000-4000-8000-000000000001', 3, 'move', '{"kind":"move","ply":{"place":"b1","hand":"BSSF"}}', 1700000120000);
+INSERT INTO event (uuid, seq, kind, payload, created_at) VALUES ('a0000000-0000-4000-8000-000000000001', 4, 'move', '{"kind":"move","ply":{"place":"c1","hand":"BTSH"}}', 1700000180000);
+INSERT INTO event (uuid, seq, kind, payload, created_at) VALUES ('a0000000-0000-4000-8000-000000000001', 5, 'move', '{"kind":"move","ply":{"place":"d1","hand":null}}', 1700000240000);
+INSERT INTO event (uuid, seq, kind, payload, created_at) VALUES ('a0000000-0000-4000-8000-000000000002', 1, 'created', '{"kind":"created","position":"AQAAAAAAAAAAAAAAAQ4C"}', 1700000060000);
+INSERT INTO event (uuid, seq, kind, payload, created_at) VALUES ('a0000000-0000-4000-8000-000000000002', 2, 'move', '{"kind":"move","ply":{"place":"a1","hand":"WTSH"}}', 1700000120000);
+INSERT INTO event (uuid, seq, kind, payload, created_at) VALUES ('a0000000-0000-4000-8000-000000000002', 3, 'move', '{"kind":"move","ply":{"place":"b2","hand":"WTCH"}}', 1700000180000);
+INSERT INTO event (uuid, seq, kind, payload, created_at) VALUES ('a0000000-0000-4000-8000-000000000002', 4, 'resigned', '{"kind":"resigned","seat":0}', 1700000240000);
+INSERT INTO event (uuid, seq, kind, payload, created_at) VALUES ('a0000000-0000-4000-8000-000000000003', 1, 'created', '{"kind":"created","position":"AQAAAAAAAAAAAAAAAQ4C"}', 1700000120000);
+INSERT INTO event (uuid, seq, kind, payload, created_at) VALUES ('a0000000-0000-4000-8000-000000000003', 2, 'move', '{"kind":"move","ply":{"place":"a1","hand":"WTSH"}}', 1700000180000);
+INSERT INTO event (uuid, seq, kind, payload, created_at) VALUES ('a0000000-0000-4000-8000-000000000003', 3, 'move', '{"kind":"move","ply":{"place":"d4","hand":"WSCF"}}', 1700000240000);
@@ -0,0 +1,83 @@
+mod common;
+
+use common::Scratch;
+
+const GAME: &str = "d3b07384-0000-4000-8000-000000000001";
+
+/* Bad pieces and squares are usage errors from clap, exit status 2, reported
+   before any database is opened
+*/
+#[test]
+fn test_bad_arguments_are_usage_errors() {
+    let scratch = Scratch::new("move-arguments");
+    for (args, expected) in [
+        (
+            ["move", GAME, "a1", "XXXX"],
+            "invalid value 'XXXX' for '<PIECE>': XXXX is not a color, height, shape or top",
+        ),
+        (
+            ["move", GAME, "e5", "WTCH"],
+            "invalid value 'e5' for '<SQUARE>...'",
+        ),
+        (
+            ["move", GAME, "a0", "WTCH"],
+            "invalid value 'a0' for '<SQUARE>...'",
+        ),
+    ] {
+        let output = scratch.run(&args);
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.starts_with(&format!("error: {}", expected)),
+            "{}",
+            stderr
+        );
+        assert!(stderr.contains("try '--help'"), "{}", stderr);
+    }
+    // The deprecated x y is held to the same range
+    let output = scratch.run(&["move", GAME, "4", "0", "WTCH"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("invalid value '4' for '<SQUARE>...'"),
+        "{}",
+        stderr
+    );
+    assert!(!scratch.dir.join("games.sqlite").exists());
+}
+
+/* A move breaking two rules reports both at once, as text and as JSON, and leaves
+   the game as it was
+*/
+#[cfg(not(feature = "init"))]
+#[test]
+fn test_every_broken_rule_is_reported_together() {
+    let scratch = Scratch::new("broken-rules");
+    scratch.ok(&["init"]);
+    let uuid = scratch.ok(&["new-game"]).trim().to_string();
+    scratch.ok(&["move", &uuid, "a1", "WTCH"]);
+    let before = scratch.ok(&["show", &uuid]);
+
+    // a1 is taken and BSCF is already on it
+    let output = scratch.run(&["move", &uuid, "a1", "BSCF"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("a1 BSCF: a1 is occupied; BSCF is not free"),
+        "{}",
+        stderr
+    );
+
+    let output = scratch.run(&["move", &uuid, "a1", "BSCF", "--format", "json"]);
+    assert!(!output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["move"], "a1 BSCF");
+    let rules: Vec<&str> = report["breaks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|b| b["rule"].as_str().unwrap())
+        .collect();
+    assert_eq!(rules, ["occupied", "not_free"]);
+    assert_eq!(scratch.ok(&["show", &uuid]), before);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::Scratch;
+use std::fs;
+
+/* The whole offline flow through the binary, with no DATABASE_URL set */
+#[test]
+fn test_file_store_plays_without_a_database() {
+    let scratch = Scratch::new("offline");
+    let quarto = |args: &[&str]| {
+        let mut command = scratch.command(&[&["--store", "file:games"], args].concat());
+        command.env_remove("DATABASE_URL");
+        command.output().unwrap()
+    };
+    let ok = |args: &[&str]| {
+        let output = quarto(args);
+        assert!(
+            output.status.success(),
+            "quarto {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let uuid = ok(&["new-game"]).trim().to_string();
+    let file = scratch.dir.join("games").join(format!("{}.qrt", uuid));
+    assert!(fs::read_to_string(&file).unwrap().starts_with("quarto 2\n"));
+    ok(&["move", &uuid, "a1", "WTCH"]);
+    let show = ok(&["show", &uuid]);
+    assert!(show.contains("1 BSCF ---- ---- ----"), "{}", show);
+    assert!(show.contains("next piece: WTCH"), "{}", show);
+
+    // A write cut short before its rename leaves the game as it was
+    let tmp = file.with_extension("qrt.tmp");
+    fs::write(&tmp, "quarto 2\nBSCF WT").unwrap();
+    assert!(ok(&["show", &uuid]).contains("next piece: WTCH"));
+    ok(&["move", &uuid, "b2", "WSCF"]);
+    assert!(!tmp.exists());
+    assert!(ok(&["show", &uuid]).contains("next piece: WSCF"));
+
+    // Another process holds the game: the move is refused and nothing changes
+    let lock = file.with_extension("qrt.lock");
+    fs::write(&lock, "1\n").unwrap();
+    let before = fs::read_to_string(&file).unwrap();
+    let locked = quarto(&["move", &uuid, "c3", "BTCH"]);
+    assert!(!locked.status.success());
+    let stderr = String::from_utf8_lossy(&locked.stderr);
+    assert!(stderr.contains("GameLocked"), "{}", stderr);
+    assert_eq!(fs::read_to_string(&file).unwrap(), before);
+    fs::remove_file(&lock).unwrap();
+    ok(&["move", &uuid, "c3", "BTCH"]);
+    assert!(!scratch.dir.join("games.sqlite").exists());
+}
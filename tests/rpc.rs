@@ -0,0 +1,83 @@
+mod common;
+
+use common::Scratch;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{ChildStdin, ChildStdout, Stdio};
+
+/* A frontend's end of `quarto rpc`: one request line in, one response line out */
+struct Frontend {
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Frontend {
+    fn send(&mut self, line: &str) -> Value {
+        writeln!(self.stdin, "{}", line).unwrap();
+        self.stdin.flush().unwrap();
+        let mut response = String::new();
+        self.stdout.read_line(&mut response).unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    fn call(&mut self, id: i64, method: &str, params: Value) -> Value {
+        let request = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        let response = self.send(&request.to_string());
+        assert_eq!(response["id"], json!(id), "{}", response);
+        response
+    }
+}
+
+/* The games are kept in memory, so no database is opened or needed */
+#[test]
+fn test_rpc_plays_a_scripted_game_over_stdio() {
+    let scratch = Scratch::new("rpc");
+    let mut child = scratch
+        .command(&["rpc"])
+        .env_remove("DATABASE_URL")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut frontend = Frontend {
+        stdin: child.stdin.take().unwrap(),
+        stdout: BufReader::new(child.stdout.take().unwrap()),
+    };
+
+    let created = frontend.call(1, "new_game", Value::Null);
+    let game = created["result"]["game"].as_str().unwrap().to_string();
+    // Four brown pieces along the first line
+    let moves = [
+        json!({"game": game, "piece": "BSCF"}),
+        json!({"game": game, "at": "a4", "piece": "WSCF"}),
+        json!({"game": game, "at": "a3", "piece": "BSCH"}),
+        json!({"game": game, "at": "b4", "piece": "WSCH"}),
+        json!({"game": game, "at": "b3", "piece": "BSSF"}),
+        json!({"game": game, "at": "c4", "piece": "WSSF"}),
+        json!({"game": game, "at": "c3", "piece": "BTSH"}),
+    ];
+    for (id, params) in (10..).zip(moves) {
+        let response = frontend.call(id, "apply_move", params);
+        assert_eq!(response["result"]["quarto"], json!(false), "{}", response);
+    }
+
+    // A malformed line is answered with an error, and the game goes on
+    let garbled = frontend.send("{\"id\": 20, \"method\":");
+    assert_eq!(garbled["error"]["code"], json!(-32700));
+    let hint = frontend.call(21, "hint", json!({"game": game}));
+    assert_eq!(hint["result"]["winning_cell"], json!("d4"));
+    let won = frontend.call(22, "apply_move", json!({"game": game, "at": "d4"}));
+    assert_eq!(won["result"]["quarto"], json!(true));
+    let state = frontend.call(23, "get_state", json!({"game": game}));
+    assert_eq!(state["result"], won["result"]);
+    let listed = frontend.call(24, "list_games", Value::Null);
+    assert_eq!(listed["result"], json!([game]));
+
+    // shutdown is answered, then the process exits and closes its end
+    let shutdown = frontend.call(25, "shutdown", Value::Null);
+    assert_eq!(shutdown["result"], Value::Null);
+    let mut rest = String::new();
+    assert_eq!(frontend.stdout.read_line(&mut rest).unwrap(), 0);
+    assert!(child.wait().unwrap().success());
+    assert!(!scratch.dir.join("games.sqlite").exists());
+}
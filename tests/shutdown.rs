@@ -0,0 +1,58 @@
+/* Games are only stored by builds without init, and signals are sent with kill */
+#![cfg(all(unix, not(feature = "init")))]
+
+mod common;
+
+use common::Scratch;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+
+/* Ctrl-C asks `watch` to stop between polls: it closes the pool and exits
+   cleanly, and doctor finds the database as it was left
+*/
+#[test]
+fn test_interrupted_watch_leaves_the_database_consistent() {
+    let scratch = Scratch::new("shutdown");
+    scratch.ok(&["init"]);
+    let uuid = scratch.ok(&["new-game"]).trim().to_string();
+    scratch.ok(&["move", &uuid, "a1", "WTCH"]);
+
+    let mut watch = scratch
+        .command(&["watch", &uuid])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The first position is printed after the handler is installed
+    let mut stdout = BufReader::new(watch.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert!(!line.is_empty());
+    let sent = Command::new("kill")
+        .args(["-INT", &watch.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(sent.success());
+    let output = watch.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output.status);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("interrupted, stopping after this poll"),
+        "{}",
+        stderr
+    );
+
+    let doctor = scratch.ok(&["doctor"]);
+    assert!(
+        doctor.contains("every game is stored in board format"),
+        "{}",
+        doctor
+    );
+    assert!(
+        doctor.contains("the position index covers every game"),
+        "{}",
+        doctor
+    );
+    let show = scratch.ok(&["show", &uuid]);
+    assert!(show.contains("WTCH"), "{}", show);
+}
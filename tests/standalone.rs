@@ -0,0 +1,29 @@
+mod common;
+
+use common::Scratch;
+
+/* Commands which never open the store run with no DATABASE_URL set */
+#[test]
+fn test_commands_without_a_store_run_without_a_database() {
+    let scratch = Scratch::new("standalone");
+    let quarto = |args: &[&str]| {
+        let output = scratch
+            .command(args)
+            .env_remove("DATABASE_URL")
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "quarto {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    quarto(&["generate", "--games", "2", "--out", "games", "--jobs", "1"]);
+    assert!(scratch.dir.join("games").join("manifest").exists());
+    let counts = quarto(&["count-positions", "--plies", "1"]);
+    assert!(counts.contains("ply  1: 16"), "{}", counts);
+    assert!(!scratch.dir.join("games.sqlite").exists());
+}